@@ -0,0 +1,34 @@
+//! Exposes build details to `rustlama version`.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    println!("cargo:rerun-if-changed=Cargo.lock");
+
+    let target = env::var("TARGET").unwrap_or_else(|_| "unknown".to_string());
+    let profile = env::var("PROFILE").unwrap_or_else(|_| "unknown".to_string());
+    println!("cargo:rustc-env=RUSTLAMA_TARGET={}", target);
+    println!("cargo:rustc-env=RUSTLAMA_PROFILE={}", profile);
+
+    // Cargo doesn't pass dependency versions to build scripts, so read the resolved one from the lockfile
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let bindings = fs::read_to_string(Path::new(&manifest_dir).join("Cargo.lock"))
+        .ok()
+        .and_then(|lock| locked_version(&lock, "llama-cpp-2"))
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=RUSTLAMA_LLAMA_CPP_2_VERSION={}", bindings);
+}
+
+fn locked_version(lock: &str, package: &str) -> Option<String> {
+    let name_line = format!("name = \"{}\"", package);
+    let mut lines = lock.lines();
+    lines.find(|line| line.trim() == name_line)?;
+    lines
+        .next()?
+        .trim()
+        .strip_prefix("version = \"")?
+        .strip_suffix('"')
+        .map(str::to_string)
+}
@@ -0,0 +1,114 @@
+/*!
+# Banned Words
+
+`--ban-word` forbids whole words or phrases. Each phrase is tokenized, and at
+every step the token that would complete a banned phrase is masked out of the
+candidates, so the model picks something else instead.
+
+Matching is done on token IDs. Each phrase is banned both as written and with
+a leading space, since most tokenizers merge the space into the first token.
+Partial overlaps are handled conservatively: whenever the most recent tokens
+equal a banned phrase minus its last token, that last token is masked, even if
+the text would have continued into an allowed word. A phrase the model spells
+with a different tokenization can still slip through.
+*/
+
+use anyhow::{anyhow, Result};
+use llama_cpp_2::model::{AddBos, LlamaModel};
+use llama_cpp_2::token::data::LlamaTokenData;
+use llama_cpp_2::token::LlamaToken;
+use std::collections::HashSet;
+
+/// Tokenized phrases that generation must not produce
+#[derive(Debug, Clone, Default)]
+pub struct BannedPhrases {
+    phrases: Vec<Vec<LlamaToken>>,
+}
+
+impl BannedPhrases {
+    /// Ban the given token sequences; empty sequences are ignored
+    pub fn new(phrases: Vec<Vec<LlamaToken>>) -> Self {
+        let mut unique = Vec::new();
+        for phrase in phrases {
+            if !phrase.is_empty() && !unique.contains(&phrase) {
+                unique.push(phrase);
+            }
+        }
+        Self { phrases: unique }
+    }
+
+    /// Tokenize each word both as written and with a leading space
+    pub fn from_words(model: &LlamaModel, words: &[String]) -> Result<Self> {
+        let mut phrases = Vec::new();
+        for word in words {
+            for variant in [word.clone(), format!(" {}", word.trim_start())] {
+                let tokens = model
+                    .str_to_token(&variant, AddBos::Never)
+                    .map_err(|e| anyhow!("Failed to tokenize banned word '{}': {}", word, e))?;
+                phrases.push(tokens);
+            }
+        }
+        Ok(Self::new(phrases))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.phrases.is_empty()
+    }
+
+    /// Tokens that would complete a banned phrase after `history`
+    pub fn banned_next_tokens(&self, history: &[LlamaToken]) -> HashSet<LlamaToken> {
+        self.phrases
+            .iter()
+            .filter(|phrase| history.ends_with(&phrase[..phrase.len() - 1]))
+            .map(|phrase| phrase[phrase.len() - 1])
+            .collect()
+    }
+
+    /// Mask the logits of every token that would complete a banned phrase
+    pub fn mask(&self, history: &[LlamaToken], candidates: &mut [LlamaTokenData]) {
+        let banned = self.banned_next_tokens(history);
+        if banned.is_empty() {
+            return;
+        }
+
+        for candidate in candidates.iter_mut() {
+            if banned.contains(&candidate.id()) {
+                candidate.set_logit(f32::NEG_INFINITY);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(ids: &[i32]) -> Vec<LlamaToken> {
+        ids.iter().map(|&id| LlamaToken::new(id)).collect()
+    }
+
+    #[test]
+    fn test_banned_next_tokens_follow_prefix() {
+        let banned = BannedPhrases::new(vec![tokens(&[7]), tokens(&[1, 2, 3])]);
+
+        // Single-token phrases are always banned
+        assert_eq!(banned.banned_next_tokens(&[]), tokens(&[7]).into_iter().collect());
+
+        // The last token of a multi-token phrase is banned only after its prefix
+        let next = banned.banned_next_tokens(&tokens(&[9, 1, 2]));
+        assert!(next.contains(&LlamaToken::new(3)));
+        assert!(!banned.banned_next_tokens(&tokens(&[1, 9])).contains(&LlamaToken::new(3)));
+    }
+
+    #[test]
+    fn test_mask_sets_banned_logits() {
+        let banned = BannedPhrases::new(vec![tokens(&[1, 2])]);
+        let mut candidates: Vec<LlamaTokenData> = (0..4)
+            .map(|i| LlamaTokenData::new(LlamaToken::new(i), 1.0, 0.0))
+            .collect();
+
+        banned.mask(&tokens(&[1]), &mut candidates);
+        assert_eq!(candidates[2].logit(), f32::NEG_INFINITY);
+        assert_eq!(candidates[1].logit(), 1.0);
+    }
+}
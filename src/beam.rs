@@ -0,0 +1,116 @@
+/*!
+# Beam Search
+
+Bookkeeping for `--beams`: a fixed number of candidate sequences is kept, each
+is expanded by its most likely next tokens, and the highest cumulative
+log-probability sequences survive to the next step.
+
+The decoding itself lives with the rest of the generation loop; this module
+only decides which sequences to keep.
+*/
+
+use crate::sampling::SampledToken;
+use llama_cpp_2::token::LlamaToken;
+
+/// One candidate sequence of generated tokens
+#[derive(Debug, Clone, Default)]
+pub struct Beam {
+    pub tokens: Vec<LlamaToken>,
+    /// Log-probability of each token in `tokens`
+    pub log_probs: Vec<f32>,
+    /// Sum of the log-probabilities, including a terminating stop token
+    pub score: f64,
+    /// Set once the beam has sampled a stop token; it then carries over unchanged
+    pub finished: bool,
+}
+
+impl Beam {
+    /// Number of sampled tokens contributing to `score`
+    pub fn scored_tokens(&self) -> usize {
+        self.tokens.len() + self.finished as usize
+    }
+}
+
+/// Expand every unfinished beam by its `expansions` (in the same order as `beams`) and keep the
+/// `width` highest-scoring sequences. Finished beams compete with the expanded ones unchanged.
+pub fn select_beams(
+    beams: &[Beam],
+    expansions: &[Vec<SampledToken>],
+    width: usize,
+    is_stop: impl Fn(LlamaToken) -> bool,
+) -> Vec<Beam> {
+    let mut next: Vec<Beam> = Vec::new();
+
+    for (beam, expansion) in beams.iter().zip(expansions) {
+        if beam.finished {
+            next.push(beam.clone());
+            continue;
+        }
+
+        for sampled in expansion {
+            let mut extended = beam.clone();
+            extended.score += sampled.log_prob as f64;
+            if is_stop(sampled.token) {
+                extended.finished = true;
+            } else {
+                extended.tokens.push(sampled.token);
+                extended.log_probs.push(sampled.log_prob);
+            }
+            next.push(extended);
+        }
+    }
+
+    next.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+    next.truncate(width);
+    next
+}
+
+/// The beam with the highest cumulative log-probability
+pub fn best_beam(beams: &[Beam]) -> Option<&Beam> {
+    beams
+        .iter()
+        .max_by(|a, b| a.score.partial_cmp(&b.score).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sampled(token: i32, prob: f32) -> SampledToken {
+        SampledToken {
+            token: LlamaToken::new(token),
+            log_prob: prob.ln(),
+        }
+    }
+
+    #[test]
+    fn test_select_beams_keeps_highest_cumulative_score() {
+        let start = vec![Beam::default()];
+        let beams = select_beams(&start, &[vec![sampled(1, 0.6), sampled(2, 0.4)]], 2, |_| false);
+        assert_eq!(beams.len(), 2);
+
+        // Token 1 looks best alone, but token 2 has a far more confident continuation
+        let expansions = vec![
+            vec![sampled(3, 0.3), sampled(4, 0.3)],
+            vec![sampled(5, 0.9), sampled(6, 0.1)],
+        ];
+        let beams = select_beams(&beams, &expansions, 2, |_| false);
+        let best = best_beam(&beams).unwrap();
+        assert_eq!(best.tokens, vec![LlamaToken::new(2), LlamaToken::new(5)]);
+        assert!((best.score - (0.4f64 * 0.9).ln()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_select_beams_finishes_on_stop_token() {
+        let start = vec![Beam::default()];
+        let beams = select_beams(&start, &[vec![sampled(0, 0.7), sampled(1, 0.3)]], 2, |t| t.0 == 0);
+        assert!(beams[0].finished);
+        assert!(beams[0].tokens.is_empty());
+        assert_eq!(beams[0].scored_tokens(), 1);
+
+        // The finished beam carries over without expansions
+        let beams = select_beams(&beams, &[Vec::new(), vec![sampled(2, 0.5)]], 2, |t| t.0 == 0);
+        assert!(beams[0].finished);
+        assert_eq!(beams[1].tokens, vec![LlamaToken::new(1), LlamaToken::new(2)]);
+    }
+}
@@ -0,0 +1,104 @@
+/*!
+# Benchmarking
+
+`rustlama bench` measures how fast a model runs on this machine, so
+quantizations and thread counts can be compared on numbers rather than feel.
+Each pass processes a fixed synthetic prompt and then greedily generates a
+fixed number of tokens, ignoring end-of-sequence, with prompt processing and
+generation timed separately:
+
+```text
+rustlama bench -m model.gguf --runs 3 --threads 8
+Prompt processing: 512 tokens at 1204.31 tokens/sec
+Generation:        128 tokens at 38.72 tokens/sec
+```
+
+A warmup pass runs first and isn't counted. Speeds are averaged over the
+runs as total tokens over total time.
+*/
+
+use crate::tokens_per_sec;
+use serde::Serialize;
+use std::time::Duration;
+
+/// Text repeated to build the synthetic prompt
+pub const BENCH_TEXT: &str = "The quick brown fox jumps over the lazy dog while the cat watches from the window. ";
+
+/// Timings of one counted pass
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct BenchRun {
+    pub prompt_secs: f64,
+    pub gen_secs: f64,
+    pub prompt_tokens_per_sec: f64,
+    pub gen_tokens_per_sec: f64,
+}
+
+/// The result of `rustlama bench`, printed as text or as JSON with `--format json`
+#[derive(Debug, Serialize)]
+pub struct BenchReport {
+    pub model: String,
+    pub prompt_tokens: usize,
+    pub gen_tokens: usize,
+    pub threads: Option<i32>,
+    pub batch_size: usize,
+    pub prompt_tokens_per_sec: f64,
+    pub gen_tokens_per_sec: f64,
+    pub runs: Vec<BenchRun>,
+}
+
+impl BenchRun {
+    pub fn new(prompt_tokens: usize, prompt_time: Duration, gen_tokens: usize, gen_time: Duration) -> Self {
+        Self {
+            prompt_secs: prompt_time.as_secs_f64(),
+            gen_secs: gen_time.as_secs_f64(),
+            prompt_tokens_per_sec: tokens_per_sec(prompt_tokens, prompt_time),
+            gen_tokens_per_sec: tokens_per_sec(gen_tokens, gen_time),
+        }
+    }
+}
+
+impl BenchReport {
+    /// Summarize the counted passes of a benchmark
+    pub fn new(
+        model: String,
+        prompt_tokens: usize,
+        gen_tokens: usize,
+        threads: Option<i32>,
+        batch_size: usize,
+        runs: Vec<BenchRun>,
+    ) -> Self {
+        let total = |secs: fn(&BenchRun) -> f64| Duration::from_secs_f64(runs.iter().map(secs).sum());
+        Self {
+            prompt_tokens_per_sec: tokens_per_sec(prompt_tokens * runs.len(), total(|run| run.prompt_secs)),
+            gen_tokens_per_sec: tokens_per_sec(gen_tokens * runs.len(), total(|run| run.gen_secs)),
+            model,
+            prompt_tokens,
+            gen_tokens,
+            threads,
+            batch_size,
+            runs,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bench_report_averages_over_total_time() {
+        let runs = vec![
+            BenchRun::new(100, Duration::from_millis(100), 10, Duration::from_millis(500)),
+            BenchRun::new(100, Duration::from_millis(300), 10, Duration::from_millis(500)),
+        ];
+        assert_eq!(runs[0].prompt_tokens_per_sec, 1000.0);
+
+        let report = BenchReport::new("m.gguf".to_string(), 100, 10, Some(4), 512, runs);
+        assert_eq!(report.prompt_tokens_per_sec, 500.0);
+        assert_eq!(report.gen_tokens_per_sec, 20.0);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["runs"].as_array().unwrap().len(), 2);
+        assert_eq!(json["threads"], 4);
+    }
+}
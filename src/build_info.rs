@@ -0,0 +1,60 @@
+/*!
+# Build Information
+
+What `rustlama version` reports: the crate version, the llama.cpp bindings it
+was built against, the target triple and build profile, and the compute
+backends llama.cpp found at runtime. `--json` prints it as one object for bug
+reports.
+*/
+
+use serde::Serialize;
+
+/// Build configuration and the backends available to llama.cpp
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    /// Version of the llama-cpp-2 bindings (which pin the bundled llama.cpp)
+    pub llama_cpp_bindings: &'static str,
+    pub target: &'static str,
+    pub profile: &'static str,
+    /// Distinct backends with at least one device, e.g. CPU, CUDA, Metal
+    pub backends: Vec<String>,
+    pub gpu_offload: bool,
+}
+
+impl BuildInfo {
+    /// Combine the compile-time details with the backends detected at runtime
+    pub fn new(device_backends: impl IntoIterator<Item = String>, gpu_offload: bool) -> Self {
+        let mut backends: Vec<String> = Vec::new();
+        for backend in device_backends {
+            if !backends.contains(&backend) {
+                backends.push(backend);
+            }
+        }
+
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            llama_cpp_bindings: env!("RUSTLAMA_LLAMA_CPP_2_VERSION"),
+            target: env!("RUSTLAMA_TARGET"),
+            profile: env!("RUSTLAMA_PROFILE"),
+            backends,
+            gpu_offload,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_info_dedups_backends() {
+        let info = BuildInfo::new(["CUDA", "CUDA", "CPU"].map(String::from), true);
+        assert_eq!(info.backends, vec!["CUDA", "CPU"]);
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["gpu_offload"], true);
+        assert!(json["target"].is_string());
+    }
+}
@@ -0,0 +1,274 @@
+/*!
+# Chat Templates
+
+Instruct models expect the conversation wrapped in their own markup. Most
+GGUF files embed that markup as a Jinja template in `tokenizer.chat_template`;
+it is rendered with minijinja (plus Python string-method compatibility, which
+Hugging Face templates rely on) using the same variables `transformers`
+provides: `messages`, `bos_token`, `eos_token` and `add_generation_prompt`.
+
+`--chat-template` overrides the embedded template, either with the name of a
+built-in template or with a Jinja template string; anything else is rejected. If the chosen template is
+missing or fails to render, the Llama 2 template is used instead.
+
+`--messages-file` supplies a whole conversation as an OpenAI-style JSON array
+of `{"role": ..., "content": ...}` objects instead of a single prompt.
+*/
+
+use anyhow::{anyhow, Result};
+use minijinja::{context, Environment, Error, ErrorKind};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Template used when none is embedded or the chosen one can't be rendered
+pub const DEFAULT_TEMPLATE: &str = "llama2";
+
+/// Roles accepted in a `--messages-file` conversation
+pub const MESSAGE_ROLES: [&str; 3] = ["system", "user", "assistant"];
+
+/// Built-in templates selectable by name with `--chat-template`
+pub const BUILTIN_TEMPLATES: &[(&str, &str)] = &[
+    (
+        "chatml",
+        "{% for message in messages %}{{ '<|im_start|>' + message['role'] + '\\n' + message['content'] + '<|im_end|>\\n' }}{% endfor %}\
+         {% if add_generation_prompt %}{{ '<|im_start|>assistant\\n' }}{% endif %}",
+    ),
+    (
+        "llama2",
+        "{{ bos_token }}{% for message in messages %}\
+         {% if message['role'] == 'system' %}{{ '[INST] <<SYS>>\\n' + message['content'] + '\\n<</SYS>>\\n\\n' }}\
+         {% elif message['role'] == 'user' %}\
+         {% if loop.index0 > 0 and messages[loop.index0 - 1]['role'] == 'system' %}{{ message['content'] + ' [/INST]' }}\
+         {% else %}{{ '[INST] ' + message['content'] + ' [/INST]' }}{% endif %}\
+         {% else %}{{ ' ' + message['content'] + eos_token }}{% endif %}{% endfor %}",
+    ),
+    (
+        "llama3",
+        "{{ bos_token }}{% for message in messages %}\
+         {{ '<|start_header_id|>' + message['role'] + '<|end_header_id|>\\n\\n' + message['content'] | trim + '<|eot_id|>' }}\
+         {% endfor %}{% if add_generation_prompt %}{{ '<|start_header_id|>assistant<|end_header_id|>\\n\\n' }}{% endif %}",
+    ),
+    (
+        "gemma",
+        "{{ bos_token }}{% for message in messages %}\
+         {% set role = 'model' if message['role'] == 'assistant' else 'user' %}\
+         {{ '<start_of_turn>' + role + '\\n' + message['content'] | trim + '<end_of_turn>\\n' }}\
+         {% endfor %}{% if add_generation_prompt %}{{ '<start_of_turn>model\\n' }}{% endif %}",
+    ),
+];
+
+/// One message of a conversation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn new(role: &str, content: &str) -> Self {
+        Self {
+            role: role.to_string(),
+            content: content.to_string(),
+        }
+    }
+}
+
+/// Parse an OpenAI-style `[{"role": ..., "content": ...}, ...]` conversation
+pub fn parse_messages(json: &str) -> Result<Vec<ChatMessage>> {
+    let messages: Vec<ChatMessage> = serde_json::from_str(json)
+        .map_err(|e| anyhow!("Expected a JSON array of {{\"role\", \"content\"}} messages: {}", e))?;
+
+    if messages.is_empty() {
+        return Err(anyhow!("The conversation has no messages"));
+    }
+
+    for (i, message) in messages.iter().enumerate() {
+        if !MESSAGE_ROLES.contains(&message.role.as_str()) {
+            return Err(anyhow!(
+                "Message {} has role '{}'; expected one of {}",
+                i + 1,
+                message.role,
+                MESSAGE_ROLES.join(", ")
+            ));
+        }
+    }
+
+    Ok(messages)
+}
+
+/// Read and parse a `--messages-file`
+pub fn load_messages(path: &Path) -> Result<Vec<ChatMessage>> {
+    let json = fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read messages file '{}': {}", path.display(), e))?;
+    parse_messages(&json).map_err(|e| anyhow!("Invalid messages file '{}': {}", path.display(), e))
+}
+
+/// A rendered conversation and where its template came from
+#[derive(Debug)]
+pub struct ChatPrompt {
+    pub text: String,
+    /// "embedded", a built-in name, or "custom"
+    pub template: String,
+    /// Why the chosen template was abandoned for the default, if it was
+    pub fallback_reason: Option<String>,
+}
+
+/// Source of a built-in template by name
+pub fn builtin_template(name: &str) -> Option<&'static str> {
+    BUILTIN_TEMPLATES
+        .iter()
+        .find(|(builtin, _)| builtin.eq_ignore_ascii_case(name))
+        .map(|(_, source)| *source)
+}
+
+/// Render a Jinja chat template
+pub fn render_template(
+    source: &str,
+    messages: &[ChatMessage],
+    bos_token: &str,
+    eos_token: &str,
+) -> Result<String> {
+    let mut env = Environment::new();
+    env.set_trim_blocks(true);
+    env.set_lstrip_blocks(true);
+    minijinja_contrib::add_to_environment(&mut env);
+    env.set_unknown_method_callback(minijinja_contrib::pycompat::unknown_method_callback);
+    env.add_function("raise_exception", |message: String| -> Result<String, Error> {
+        Err(Error::new(ErrorKind::InvalidOperation, message))
+    });
+
+    env.render_str(
+        source,
+        context! {
+            messages => messages,
+            bos_token => bos_token,
+            eos_token => eos_token,
+            add_generation_prompt => true,
+        },
+    )
+    .map_err(|e| anyhow!("Failed to render chat template: {}", e))
+}
+
+/// Render a conversation with the override template, else the embedded one, else the default.
+/// A leading BOS token is stripped because the tokenizer adds its own. An override that is
+/// neither a built-in name nor Jinja is an error rather than a prompt rendered verbatim.
+pub fn format_chat_prompt(
+    override_template: Option<&str>,
+    embedded_template: Option<&str>,
+    messages: &[ChatMessage],
+    bos_token: &str,
+    eos_token: &str,
+) -> Result<ChatPrompt> {
+    let (template, source) = match (override_template, embedded_template) {
+        (Some(name), _) => match builtin_template(name) {
+            Some(source) => (name.to_lowercase(), Some(source)),
+            None if name.contains("{{") || name.contains("{%") => ("custom".to_string(), Some(name)),
+            None => {
+                let builtins: Vec<&str> = BUILTIN_TEMPLATES.iter().map(|(builtin, _)| *builtin).collect();
+                return Err(anyhow!(
+                    "Unknown chat template '{}': use one of {} or a Jinja template string",
+                    name,
+                    builtins.join(", ")
+                ));
+            }
+        },
+        (None, Some(source)) if !source.trim().is_empty() => ("embedded".to_string(), Some(source)),
+        (None, _) => (DEFAULT_TEMPLATE.to_string(), builtin_template(DEFAULT_TEMPLATE)),
+    };
+
+    let (text, template, fallback_reason) = match source.map(|s| render_template(s, messages, bos_token, eos_token)) {
+        Some(Ok(text)) => (text, template, None),
+        Some(Err(e)) => {
+            let default = builtin_template(DEFAULT_TEMPLATE).unwrap_or_default();
+            let text = render_template(default, messages, bos_token, eos_token).unwrap_or_default();
+            (text, DEFAULT_TEMPLATE.to_string(), Some(format!("{} template: {}", template, e)))
+        }
+        None => (String::new(), template, None),
+    };
+
+    let text = match text.strip_prefix(bos_token) {
+        Some(rest) if !bos_token.is_empty() => rest.to_string(),
+        _ => text,
+    };
+
+    Ok(ChatPrompt {
+        text,
+        template,
+        fallback_reason,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conversation() -> Vec<ChatMessage> {
+        vec![
+            ChatMessage::new("system", "Be brief."),
+            ChatMessage::new("user", "Hi"),
+        ]
+    }
+
+    #[test]
+    fn test_builtin_templates_render() {
+        let chatml = render_template(builtin_template("chatml").unwrap(), &conversation(), "<s>", "</s>").unwrap();
+        assert_eq!(
+            chatml,
+            "<|im_start|>system\nBe brief.<|im_end|>\n<|im_start|>user\nHi<|im_end|>\n<|im_start|>assistant\n"
+        );
+
+        let llama2 = render_template(builtin_template("llama2").unwrap(), &conversation(), "<s>", "</s>").unwrap();
+        assert_eq!(llama2, "<s>[INST] <<SYS>>\nBe brief.\n<</SYS>>\n\nHi [/INST]");
+    }
+
+    #[test]
+    fn test_embedded_hf_style_template() {
+        // Shape of the templates shipped in Hugging Face tokenizer configs
+        let template = "{% if messages[0]['role'] == 'system' %}{% set system = messages[0]['content'] %}{% endif %}\
+            {% for message in messages %}{% if message['role'] == 'user' %}\
+            {{ bos_token + '[INST] ' + (system ~ '\\n' if system else '') + message['content'].strip() + ' [/INST]' }}\
+            {% endif %}{% endfor %}";
+
+        let prompt = format_chat_prompt(None, Some(template), &conversation(), "<s>", "</s>").unwrap();
+        assert_eq!(prompt.template, "embedded");
+        assert!(prompt.fallback_reason.is_none());
+        assert_eq!(prompt.text, "[INST] Be brief.\nHi [/INST]");
+    }
+
+    #[test]
+    fn test_parse_messages() {
+        let messages = parse_messages(
+            r#"[{"role": "system", "content": "Be brief."}, {"role": "user", "content": "Hi"}]"#,
+        )
+        .unwrap();
+        assert_eq!(messages, conversation());
+
+        assert!(parse_messages("[]").is_err());
+        assert!(parse_messages(r#"{"role": "user", "content": "Hi"}"#).is_err());
+        assert!(parse_messages(r#"[{"role": "user"}]"#).is_err());
+        let err = parse_messages(r#"[{"role": "user", "content": "Hi"}, {"role": "tool", "content": "{}"}]"#).unwrap_err();
+        assert!(err.to_string().contains("Message 2 has role 'tool'"));
+    }
+
+    #[test]
+    fn test_unparseable_template_falls_back() {
+        let prompt = format_chat_prompt(None, Some("{% for message in %}"), &conversation(), "", "").unwrap();
+        assert_eq!(prompt.template, DEFAULT_TEMPLATE);
+        assert!(prompt.fallback_reason.is_some());
+        assert!(prompt.text.starts_with("[INST] <<SYS>>\nBe brief."));
+    }
+
+    #[test]
+    fn test_unknown_template_name_rejected() {
+        let err = format_chat_prompt(Some("mistral"), None, &conversation(), "", "").unwrap_err();
+        assert!(err.to_string().contains("'mistral'"));
+        assert!(err.to_string().contains("chatml, llama2, llama3, gemma"));
+
+        let prompt = format_chat_prompt(Some("LLAMA3"), None, &conversation(), "", "").unwrap();
+        assert_eq!(prompt.template, "llama3");
+        let prompt = format_chat_prompt(Some("{{ messages[1]['content'] }}"), None, &conversation(), "", "").unwrap();
+        assert_eq!(prompt.template, "custom");
+        assert_eq!(prompt.text, "Hi");
+    }
+}
@@ -49,12 +49,34 @@ tasks:
 ```
 */
 
+use crate::interpolate::{interpolate, render_template};
+use crate::truncate::TruncateStrategy;
 use anyhow::{anyhow, Result};
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
 use std::path::Path;
 
+/// Format of a configuration file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Yaml,
+    Json,
+    Toml,
+}
+
+impl ConfigFormat {
+    /// JSON for `.json`, TOML for `.toml`, and YAML for `.yml`, `.yaml` or any other name
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()).map(str::to_ascii_lowercase).as_deref() {
+            Some("json") => Self::Json,
+            Some("toml") => Self::Toml,
+            _ => Self::Yaml,
+        }
+    }
+}
+
 /// Main YAML configuration structure
 #[derive(Debug, Serialize, Deserialize)]
 pub struct YamlConfig {
@@ -88,6 +110,10 @@ pub struct YamlConfig {
     /// Environment variables
     #[serde(default)]
     pub environment: HashMap<String, String>,
+    
+    /// Values for `{{name}}` placeholders in prompts
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
 }
 
 /// Default configuration settings
@@ -140,6 +166,10 @@ pub struct DefaultConfig {
     /// Default stats setting
     #[serde(default)]
     pub stats: Option<bool>,
+    
+    /// How many inference tasks may run at once (`--parallel` overrides it)
+    #[serde(default)]
+    pub parallel: Option<usize>,
 }
 
 /// Model management task
@@ -168,6 +198,14 @@ pub struct ModelTask {
     #[serde(default)]
     pub verbose: bool,
     
+    /// Times to retry the task after a failure
+    #[serde(default)]
+    pub retries: Option<u32>,
+    
+    /// Seconds to wait before the first retry, doubling for each one after
+    #[serde(default)]
+    pub retry_delay_secs: Option<u64>,
+    
     /// Task description
     #[serde(default)]
     pub description: Option<String>,
@@ -180,8 +218,13 @@ pub struct InferenceTask {
     pub name: String,
     
     /// Input prompt
+    #[serde(default)]
     pub prompt: String,
     
+    /// File to read the prompt from, instead of `prompt`
+    #[serde(default)]
+    pub prompt_file: Option<String>,
+    
     /// Model to use (overrides default)
     #[serde(default)]
     pub model: Option<String>,
@@ -238,6 +281,166 @@ pub struct InferenceTask {
     #[serde(default)]
     pub output_file: Option<String>,
     
+    /// KV cache type for keys (f16, q8_0, q4_0, ...)
+    #[serde(default)]
+    pub kv_cache_type_k: Option<String>,
+    
+    /// KV cache type for values (f16, q8_0, q4_0, ...)
+    #[serde(default)]
+    pub kv_cache_type_v: Option<String>,
+    
+    /// Enable flash attention
+    #[serde(default)]
+    pub flash_attn: bool,
+    
+    /// Extra token IDs that end generation
+    #[serde(default)]
+    pub stop_tokens: Vec<i32>,
+    
+    /// Words or phrases the model must not generate
+    #[serde(default)]
+    pub ban_words: Vec<String>,
+    
+    /// Shell command run after the task completes
+    #[serde(default)]
+    pub post_hook: Option<String>,
+    
+    /// Grow the context to fit the prompt plus max tokens
+    #[serde(default)]
+    pub auto_ctx: bool,
+    
+    /// Maximum run time in seconds before the task is counted as failed
+    #[serde(default)]
+    pub timeout: Option<u64>,
+    
+    /// Minimum tokens to generate before stop tokens are allowed
+    #[serde(default)]
+    pub min_tokens: Option<usize>,
+    
+    /// System message: placed by the chat template when `chat` is set, else prepended to the prompt
+    #[serde(default)]
+    pub system: Option<String>,
+    
+    /// Format the prompt with the model's chat template
+    #[serde(default)]
+    pub chat: bool,
+    
+    /// Chat template override (built-in name or Jinja string)
+    #[serde(default)]
+    pub chat_template: Option<String>,
+    
+    /// Checkpoint the output file every N tokens
+    #[serde(default)]
+    pub save_every: Option<usize>,
+    
+    /// Logit bias for tokens that appear in the prompt
+    #[serde(default)]
+    pub prompt_affinity: Option<f32>,
+    
+    /// HuggingFace revision (branch, tag or commit)
+    #[serde(default)]
+    pub revision: Option<String>,
+    
+    /// Reuse the commit recorded in metadata.json
+    #[serde(default)]
+    pub pin: bool,
+    
+    /// Beam search width
+    #[serde(default)]
+    pub beams: Option<usize>,
+    
+    /// Stop at the first newline
+    #[serde(default)]
+    pub single_line: bool,
+    
+    /// Truncation strategy for over-long prompts (head, tail or middle)
+    #[serde(default)]
+    pub truncate: Option<TruncateStrategy>,
+    
+    /// Stop once an n-gram repeats this many times in a row
+    #[serde(default)]
+    pub stop_on_repeat: Option<usize>,
+    
+    /// Dynamic temperature range (0 = off)
+    #[serde(default)]
+    pub dynatemp_range: Option<f32>,
+    
+    /// Dynamic temperature entropy exponent
+    #[serde(default)]
+    pub dynatemp_exponent: Option<f32>,
+    
+    /// Seed for the sampling RNG
+    #[serde(default)]
+    pub seed: Option<u64>,
+    
+    /// Repetition penalty (1.0 = off)
+    #[serde(default)]
+    pub repeat_penalty: Option<f32>,
+    
+    /// Repetition penalty window in generated tokens
+    #[serde(default)]
+    pub repeat_last_n: Option<usize>,
+    
+    /// Strings that end generation, left out of the output
+    #[serde(default)]
+    pub stop: Vec<String>,
+    
+    /// Prompt processing batch size
+    #[serde(default)]
+    pub batch_size: Option<usize>,
+    
+    /// Append to output_file instead of replacing it
+    #[serde(default)]
+    pub append: bool,
+    
+    /// Mirostat version (0 = off, 1 or 2)
+    #[serde(default)]
+    pub mirostat: Option<u8>,
+    
+    /// Mirostat target surprise in bits
+    #[serde(default)]
+    pub mirostat_tau: Option<f32>,
+    
+    /// Mirostat learning rate
+    #[serde(default)]
+    pub mirostat_eta: Option<f32>,
+    
+    /// GBNF grammar file the output must match
+    #[serde(default)]
+    pub grammar_file: Option<String>,
+    
+    /// JSON Schema file the output must conform to
+    #[serde(default)]
+    pub json_schema: Option<String>,
+    
+    /// Keep generating past end-of-sequence
+    #[serde(default)]
+    pub ignore_eos: bool,
+    
+    /// Fixed logit offsets by token ID or single-token string
+    #[serde(default)]
+    pub logit_bias: HashMap<String, f32>,
+    
+    /// Read the model into RAM instead of memory-mapping it
+    #[serde(default)]
+    pub no_mmap: bool,
+    
+    /// Lock the model's pages in RAM
+    #[serde(default)]
+    pub mlock: bool,
+    
+    /// Times to retry the task after a failure
+    #[serde(default)]
+    pub retries: Option<u32>,
+    
+    /// Seconds to wait before the first retry, doubling for each one after
+    #[serde(default)]
+    pub retry_delay_secs: Option<u64>,
+    
+    /// Run the task once per combination of these values, available as `{{name}}` placeholders
+    #[serde(default)]
+    pub matrix: IndexMap<String, Vec<String>>,
+    
     /// Task description
     #[serde(default)]
     pub description: Option<String>,
@@ -348,6 +551,23 @@ pub struct DatasetTask {
     pub custom_fields: HashMap<String, String>,
 }
 
+/// Every combination of one value per matrix entry, varying the last entry fastest. An empty
+/// matrix has a single, empty combination.
+fn matrix_combinations(matrix: &IndexMap<String, Vec<String>>) -> Vec<Vec<(&String, &String)>> {
+    matrix.iter().fold(vec![Vec::new()], |combinations, (name, values)| {
+        combinations
+            .iter()
+            .flat_map(|combination| {
+                values.iter().map(move |value| {
+                    let mut combination = combination.clone();
+                    combination.push((name, value));
+                    combination
+                })
+            })
+            .collect()
+    })
+}
+
 // Default value functions for dataset configuration
 fn default_dataset_type() -> String { "instruction".to_string() }
 fn default_sample_count() -> usize { 100 }
@@ -357,13 +577,13 @@ fn default_unlimited_context() -> u32 { 32768 }  // Very high context
 fn default_true() -> bool { true }
 
 impl YamlConfig {
-    /// Load configuration from YAML file
+    /// Load configuration from a YAML, JSON or TOML file
     pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let content = fs::read_to_string(&path)
-            .map_err(|e| anyhow!("Failed to read YAML file '{}': {}", path.as_ref().display(), e))?;
+        let mut config = Self::parse_file(path)?;
         
-        let config: YamlConfig = serde_yaml::from_str(&content)
-            .map_err(|e| anyhow!("Failed to parse YAML configuration: {}", e))?;
+        // Substitute ${VAR} references before anything reads the values
+        config.interpolate_env()?;
+        config.render_prompts()?;
         
         // Validate configuration
         config.validate()?;
@@ -371,13 +591,142 @@ impl YamlConfig {
         Ok(config)
     }
     
-    /// Save configuration to YAML file
+    /// Parse a configuration file without validating it
+    pub fn parse_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let content = fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Failed to read configuration file '{}': {}", path.as_ref().display(), e))?;
+        
+        match ConfigFormat::from_path(path.as_ref()) {
+            ConfigFormat::Yaml => serde_yaml::from_str(&content)
+                .map_err(|e| anyhow!("Failed to parse YAML configuration: {}", e)),
+            ConfigFormat::Json => serde_json::from_str(&content)
+                .map_err(|e| anyhow!("Failed to parse JSON configuration: {}", e)),
+            ConfigFormat::Toml => toml::from_str(&content)
+                .map_err(|e| anyhow!("Failed to parse TOML configuration: {}", e)),
+        }
+    }
+    
+    /// Substitute `${VAR}` and `${VAR:-default}` in model IDs, paths and prompts, looking
+    /// variables up in the `environment` section first and then the process environment.
+    /// Post hooks are left as written, since the shell expands them.
+    pub fn interpolate_env(&mut self) -> Result<()> {
+        // The environment section itself may build on the process environment
+        let mut environment = HashMap::with_capacity(self.environment.len());
+        for (name, value) in &self.environment {
+            let value = interpolate(value, |var| std::env::var(var).ok())
+                .map_err(|e| anyhow!("environment.{}: {}", name, e))?;
+            environment.insert(name.clone(), value);
+        }
+        self.environment = environment;
+
+        let environment = &self.environment;
+        let lookup = |name: &str| environment.get(name).cloned().or_else(|| std::env::var(name).ok());
+        let expand = |field: &mut Option<String>, context: &str| -> Result<()> {
+            if let Some(value) = field {
+                *value = interpolate(value, lookup).map_err(|e| anyhow!("{}: {}", context, e))?;
+            }
+            Ok(())
+        };
+
+        if let Some(defaults) = &mut self.defaults {
+            for field in [&mut defaults.model, &mut defaults.hf_filename, &mut defaults.cache_dir] {
+                expand(field, "defaults")?;
+            }
+        }
+
+        for (name, value) in &mut self.variables {
+            *value = interpolate(value, lookup).map_err(|e| anyhow!("variables.{}: {}", name, e))?;
+        }
+
+        for (i, task) in self.models.iter_mut().enumerate() {
+            let context = format!("Model task {}", i);
+            for field in [&mut task.model_id, &mut task.filename, &mut task.cache_dir] {
+                expand(field, &context)?;
+            }
+        }
+
+        for task in &mut self.tasks {
+            let context = format!("Task '{}'", task.name);
+            task.prompt = interpolate(&task.prompt, lookup).map_err(|e| anyhow!("{}: {}", context, e))?;
+            for field in [
+                &mut task.prompt_file,
+                &mut task.system,
+                &mut task.model,
+                &mut task.hf_filename,
+                &mut task.cache_dir,
+                &mut task.output_file,
+                &mut task.revision,
+            ] {
+                expand(field, &context)?;
+            }
+        }
+
+        for dataset in &mut self.datasets {
+            let context = format!("Dataset '{}'", dataset.name);
+            dataset.output_file = interpolate(&dataset.output_file, lookup).map_err(|e| anyhow!("{}: {}", context, e))?;
+            for field in [&mut dataset.model, &mut dataset.hf_filename, &mut dataset.cache_dir] {
+                expand(field, &context)?;
+            }
+        }
+
+        Ok(())
+    }
+    
+    /// Expand each task with a `matrix` into one task per combination of its values, named
+    /// `name[value,...]`, and fill in `{{name}}` placeholders in prompts and output files from
+    /// the matrix and the `variables` section
+    pub fn render_prompts(&mut self) -> Result<()> {
+        let mut tasks = Vec::with_capacity(self.tasks.len());
+        for task in std::mem::take(&mut self.tasks) {
+            let context = format!("Task '{}'", task.name);
+            if let Some((name, _)) = task.matrix.iter().find(|(_, values)| values.is_empty()) {
+                return Err(anyhow!("{}: matrix.{} has no values", context, name));
+            }
+
+            let mut output_files = HashSet::new();
+            for combination in matrix_combinations(&task.matrix) {
+                let mut expanded = task.clone();
+                if !combination.is_empty() {
+                    let values: Vec<&str> = combination.iter().map(|(_, value)| value.as_str()).collect();
+                    expanded.name = format!("{}[{}]", task.name, values.join(","));
+                    expanded.matrix.clear();
+                }
+
+                let mut variables = self.variables.clone();
+                variables.extend(combination.into_iter().map(|(name, value)| (name.clone(), value.clone())));
+                expanded.prompt =
+                    render_template(&task.prompt, &variables).map_err(|e| anyhow!("{}: {}", context, e))?;
+                if let Some(output_file) = &task.output_file {
+                    let output_file =
+                        render_template(output_file, &variables).map_err(|e| anyhow!("{}: {}", context, e))?;
+                    // Runs sharing a file would overwrite each other's output
+                    if !task.append && !output_files.insert(output_file.clone()) {
+                        return Err(anyhow!(
+                            "{}: several matrix runs would write to '{}'; use a matrix variable in output_file or set append",
+                            context,
+                            output_file
+                        ));
+                    }
+                    expanded.output_file = Some(output_file);
+                }
+                tasks.push(expanded);
+            }
+        }
+        self.tasks = tasks;
+        Ok(())
+    }
+    
+    /// Save configuration as YAML, JSON or TOML, depending on the file's extension
     pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
-        let content = serde_yaml::to_string(self)
-            .map_err(|e| anyhow!("Failed to serialize configuration: {}", e))?;
+        let content = match ConfigFormat::from_path(path.as_ref()) {
+            ConfigFormat::Yaml => serde_yaml::to_string(self).map_err(anyhow::Error::from),
+            ConfigFormat::Json => serde_json::to_string_pretty(self).map_err(anyhow::Error::from),
+            ConfigFormat::Toml => toml::to_string_pretty(self).map_err(anyhow::Error::from),
+        }
+        .map_err(|e| anyhow!("Failed to serialize configuration: {}", e))?;
         
         fs::write(&path, content)
-            .map_err(|e| anyhow!("Failed to write YAML file '{}': {}", path.as_ref().display(), e))?;
+            .map_err(|e| anyhow!("Failed to write configuration file '{}': {}", path.as_ref().display(), e))?;
         
         Ok(())
     }
@@ -413,8 +762,12 @@ impl YamlConfig {
                 return Err(anyhow!("Task {} must have a name", i));
             }
             
-            if task.prompt.is_empty() {
-                return Err(anyhow!("Task '{}' must have a prompt", task.name));
+            match (task.prompt.is_empty(), &task.prompt_file) {
+                (true, None) => return Err(anyhow!("Task '{}' must have a prompt or prompt_file", task.name)),
+                (false, Some(_)) => {
+                    return Err(anyhow!("Task '{}' has both prompt and prompt_file; use one", task.name))
+                }
+                _ => {}
             }
             
             // Validate parameter ranges
@@ -435,6 +788,30 @@ impl YamlConfig {
                     ));
                 }
             }
+
+            for kv_type in [&task.kv_cache_type_k, &task.kv_cache_type_v].into_iter().flatten() {
+                crate::kv_cache::parse_kv_cache_type(kv_type)
+                    .map_err(|e| anyhow!("Task '{}': {}", task.name, e))?;
+            }
+
+            if task.timeout == Some(0) {
+                return Err(anyhow!("Task '{}': timeout must be greater than 0", task.name));
+            }
+        }
+        
+        if self.defaults.as_ref().is_some_and(|defaults| defaults.parallel == Some(0)) {
+            return Err(anyhow!("defaults.parallel must be at least 1"));
+        }
+        
+        // Task names are used by --only-tasks/--skip-tasks, so each must identify one task
+        let mut seen_names: HashMap<&str, usize> = HashMap::new();
+        for (i, task) in self.tasks.iter().enumerate() {
+            if let Some(first) = seen_names.insert(task.name.as_str(), i) {
+                return Err(anyhow!(
+                    "Duplicate task name '{}' used by tasks {} and {}",
+                    task.name, first, i
+                ));
+            }
         }
         
         Ok(())
@@ -509,6 +886,28 @@ impl YamlConfig {
         }
     }
     
+    /// Serialized effective settings of every task, keyed `task:<name>`, `dataset:<name>` or
+    /// `models`, so two loads of a config can be compared to find what changed
+    pub fn task_fingerprints(&self) -> BTreeMap<String, String> {
+        let mut fingerprints = BTreeMap::new();
+
+        for task in &self.tasks {
+            let mut task = task.clone();
+            self.apply_defaults(&mut task);
+            fingerprints.insert(format!("task:{}", task.name), serde_json::to_string(&task).unwrap_or_default());
+        }
+        for dataset in &self.datasets {
+            let mut dataset = dataset.clone();
+            self.apply_dataset_defaults(&mut dataset);
+            fingerprints.insert(format!("dataset:{}", dataset.name), serde_json::to_string(&dataset).unwrap_or_default());
+        }
+        if !self.models.is_empty() {
+            fingerprints.insert("models".to_string(), serde_json::to_string(&self.models).unwrap_or_default());
+        }
+
+        fingerprints
+    }
+
     /// Generate a sample configuration file
     pub fn generate_sample() -> Self {
         let mut environment = HashMap::new();
@@ -531,6 +930,7 @@ impl YamlConfig {
                 verbose: Some(false),
                 no_color: Some(false),
                 stats: Some(false),
+                parallel: None,
             }),
             models: vec![
                 ModelTask {
@@ -540,6 +940,8 @@ impl YamlConfig {
                     cache_dir: None,
                     force: false,
                     verbose: true,
+                    retries: Some(2),
+                    retry_delay_secs: None,
                     description: Some("Download Llama 2 7B Chat model".to_string()),
                 }
             ],
@@ -547,6 +949,7 @@ impl YamlConfig {
                 InferenceTask {
                     name: "Creative Writing".to_string(),
                     prompt: "Write a short story about space exploration".to_string(),
+                    prompt_file: None,
                     model: None,
                     hf_filename: None,
                     cache_dir: None,
@@ -561,12 +964,53 @@ impl YamlConfig {
                     stats: true,
                     verbose: false,
                     output_file: Some("creative_story.txt".to_string()),
+                    kv_cache_type_k: None,
+                    kv_cache_type_v: None,
+                    flash_attn: false,
+                    stop_tokens: Vec::new(),
+                    ban_words: Vec::new(),
+                    post_hook: None,
+                    auto_ctx: false,
+                    timeout: None,
+                    min_tokens: None,
+                    system: None,
+                    chat: false,
+                    chat_template: None,
+                    save_every: None,
+                    prompt_affinity: None,
+                    revision: None,
+                    pin: false,
+                    beams: None,
+                    single_line: false,
+                    truncate: None,
+                    stop_on_repeat: None,
+                    dynatemp_range: None,
+                    dynatemp_exponent: None,
+                    seed: None,
+                    repeat_penalty: None,
+                    repeat_last_n: None,
+                    stop: Vec::new(),
+                    batch_size: None,
+                    append: false,
+                    mirostat: None,
+                    mirostat_tau: None,
+                    mirostat_eta: None,
+                    grammar_file: None,
+                    json_schema: None,
+                    ignore_eos: false,
+                    logit_bias: HashMap::new(),
+                    no_mmap: false,
+                    mlock: false,
+                    retries: None,
+                    retry_delay_secs: None,
+                    matrix: IndexMap::new(),
                     description: Some("Generate creative content".to_string()),
                     continue_on_error: false,
                 },
                 InferenceTask {
                     name: "Technical Explanation".to_string(),
                     prompt: "Explain how neural networks work in simple terms".to_string(),
+                    prompt_file: None,
                     model: None,
                     hf_filename: None,
                     cache_dir: None,
@@ -581,6 +1025,46 @@ impl YamlConfig {
                     stats: true,
                     verbose: true,
                     output_file: Some("neural_networks.txt".to_string()),
+                    kv_cache_type_k: None,
+                    kv_cache_type_v: None,
+                    flash_attn: false,
+                    stop_tokens: Vec::new(),
+                    ban_words: Vec::new(),
+                    post_hook: None,
+                    auto_ctx: false,
+                    timeout: None,
+                    min_tokens: None,
+                    system: None,
+                    chat: false,
+                    chat_template: None,
+                    save_every: None,
+                    prompt_affinity: None,
+                    revision: None,
+                    pin: false,
+                    beams: None,
+                    single_line: false,
+                    truncate: None,
+                    stop_on_repeat: None,
+                    dynatemp_range: None,
+                    dynatemp_exponent: None,
+                    seed: None,
+                    repeat_penalty: None,
+                    repeat_last_n: None,
+                    stop: Vec::new(),
+                    batch_size: None,
+                    append: false,
+                    mirostat: None,
+                    mirostat_tau: None,
+                    mirostat_eta: None,
+                    grammar_file: None,
+                    json_schema: None,
+                    ignore_eos: false,
+                    logit_bias: HashMap::new(),
+                    no_mmap: false,
+                    mlock: false,
+                    retries: None,
+                    retry_delay_secs: None,
+                    matrix: IndexMap::new(),
                     description: Some("Generate technical documentation".to_string()),
                     continue_on_error: false,
                 },
@@ -661,6 +1145,7 @@ impl YamlConfig {
                 },
             ],
             environment,
+            variables: HashMap::new(),
         }
     }
 }
@@ -673,8 +1158,113 @@ mod tests {
 
     #[test]
     fn test_yaml_config_validation() {
+        let mut config = YamlConfig::generate_sample();
+        assert!(config.validate().is_ok());
+
+        config.defaults.as_mut().unwrap().parallel = Some(0);
+        assert!(config.validate().unwrap_err().to_string().contains("parallel"));
+    }
+
+    #[test]
+    fn test_yaml_config_prompt_file() {
+        let mut config = YamlConfig::generate_sample();
+        config.tasks[0].prompt_file = Some("prompts/story.txt".to_string());
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("both prompt and prompt_file"));
+
+        config.tasks[0].prompt.clear();
+        assert!(config.validate().is_ok());
+
+        config.tasks[0].prompt_file = None;
+        assert!(config.validate().unwrap_err().to_string().contains("must have a prompt"));
+    }
+
+    #[test]
+    fn test_yaml_config_duplicate_task_names() {
+        let mut config = YamlConfig::generate_sample();
+        config.tasks[1].name = config.tasks[0].name.clone();
+        
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("Duplicate task name 'Creative Writing'"));
+        assert!(err.contains("tasks 0 and 1"));
+    }
+
+    #[test]
+    fn test_task_fingerprints_track_effective_settings() {
         let config = YamlConfig::generate_sample();
+        let before = config.task_fingerprints();
+
+        let mut edited = YamlConfig::generate_sample();
+        edited.tasks[0].max_tokens = Some(7);
+        let after = edited.task_fingerprints();
+        let changed: Vec<&String> = after.keys().filter(|key| before.get(*key) != after.get(*key)).collect();
+        assert_eq!(changed, vec![&format!("task:{}", config.tasks[0].name)]);
+
+        // A default affects every task that inherits it
+        let mut edited = YamlConfig::generate_sample();
+        edited.defaults.as_mut().unwrap().model = Some("other/model-GGUF".to_string());
+        let after = edited.task_fingerprints();
+        assert!(after.keys().filter(|key| key.starts_with("task:")).all(|key| before.get(key) != after.get(key)));
+    }
+
+    #[test]
+    fn test_config_interpolation() {
+        let mut config = YamlConfig::generate_sample();
+        config.environment.insert("RUSTLAMA_TEST_OUT".to_string(), "/tmp/${RUSTLAMA_TEST_UNSET:-out}".to_string());
+        config.tasks[0].prompt = "Write about ${RUSTLAMA_TEST_TOPIC:-space}".to_string();
+        config.tasks[0].output_file = Some("${RUSTLAMA_TEST_OUT}/story.txt".to_string());
+        config.interpolate_env().unwrap();
+        assert_eq!(config.tasks[0].prompt, "Write about space");
+        assert_eq!(config.tasks[0].output_file.as_deref(), Some("/tmp/out/story.txt"));
+
+        config.tasks[1].model = Some("${RUSTLAMA_TEST_UNSET}".to_string());
+        let err = config.interpolate_env().unwrap_err().to_string();
+        assert!(err.contains(&config.tasks[1].name) && err.contains("RUSTLAMA_TEST_UNSET"));
+    }
+
+    #[test]
+    fn test_config_prompt_variables() {
+        let mut config = YamlConfig::generate_sample();
+        config.environment.insert("RUSTLAMA_TEST_PLANET".to_string(), "Mars".to_string());
+        config.variables.insert("place".to_string(), "${RUSTLAMA_TEST_PLANET}".to_string());
+        config.tasks[0].prompt = "Write a short story set on {{ place }}".to_string();
+        config.interpolate_env().unwrap();
+        config.render_prompts().unwrap();
+        assert_eq!(config.tasks[0].prompt, "Write a short story set on Mars");
+
+        config.tasks[1].prompt = "Explain {{topic}}".to_string();
+        let err = config.render_prompts().unwrap_err().to_string();
+        assert!(err.contains(&config.tasks[1].name) && err.contains("'topic'"));
+    }
+
+    #[test]
+    fn test_config_matrix_expansion() {
+        let mut config = YamlConfig::generate_sample();
+        config.tasks.truncate(1);
+        let task = &mut config.tasks[0];
+        task.name = "story".to_string();
+        task.prompt = "A {{tone}} story about {{topic}}".to_string();
+        task.output_file = Some("out/{{topic}}-{{tone}}.txt".to_string());
+        task.matrix = IndexMap::from([
+            ("topic".to_string(), vec!["space".to_string(), "ocean".to_string()]),
+            ("tone".to_string(), vec!["formal".to_string(), "casual".to_string()]),
+        ]);
+        config.render_prompts().unwrap();
+
+        let names: Vec<&str> = config.tasks.iter().map(|task| task.name.as_str()).collect();
+        assert_eq!(names, ["story[space,formal]", "story[space,casual]", "story[ocean,formal]", "story[ocean,casual]"]);
+        assert_eq!(config.tasks[1].prompt, "A casual story about space");
+        assert_eq!(config.tasks[2].output_file.as_deref(), Some("out/ocean-formal.txt"));
+        assert!(config.tasks.iter().all(|task| task.matrix.is_empty()));
         assert!(config.validate().is_ok());
+
+        // Every run writing the same file is a mistake unless they append
+        for append in [false, true] {
+            let mut config = YamlConfig::generate_sample();
+            config.tasks[0].matrix = IndexMap::from([("n".to_string(), vec!["1".to_string(), "2".to_string()])]);
+            config.tasks[0].append = append;
+            assert_eq!(config.render_prompts().is_ok(), append);
+        }
     }
 
     #[test]
@@ -700,4 +1290,27 @@ mod tests {
         
         Ok(())
     }
+
+    #[test]
+    fn test_config_formats_by_extension() -> Result<()> {
+        assert_eq!(ConfigFormat::from_path(Path::new("tasks.JSON")), ConfigFormat::Json);
+        assert_eq!(ConfigFormat::from_path(Path::new("tasks.toml")), ConfigFormat::Toml);
+        assert_eq!(ConfigFormat::from_path(Path::new("tasks.yml")), ConfigFormat::Yaml);
+        assert_eq!(ConfigFormat::from_path(Path::new("tasks")), ConfigFormat::Yaml);
+
+        let dir = tempfile::tempdir()?;
+        let config = YamlConfig::generate_sample();
+        for name in ["tasks.yaml", "tasks.json", "tasks.toml"] {
+            let path = dir.path().join(name);
+            config.save_to_file(&path)?;
+            let loaded = YamlConfig::load_from_file(&path)?;
+            assert_eq!(config.task_fingerprints(), loaded.task_fingerprints(), "{}", name);
+        }
+
+        let path = dir.path().join("broken.json");
+        fs::write(&path, "version: 1.0")?;
+        assert!(YamlConfig::load_from_file(&path).unwrap_err().to_string().contains("JSON"));
+        
+        Ok(())
+    }
 }
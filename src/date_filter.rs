@@ -0,0 +1,86 @@
+/*!
+# Date Filters
+
+Parsing for the `--since` and `--before` options of `models ls`. A bound is
+either a duration counted back from now (`30m`, `12h`, `7d`, `2w`) or an
+absolute date (`2024-05-01`) or timestamp (`2024-05-01T12:00:00Z`).
+*/
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+
+/// Parse a duration-or-date bound relative to `now`
+pub fn parse_time_bound(value: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    let value = value.trim();
+
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+    }
+
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: i64 = amount
+        .parse()
+        .map_err(|_| anyhow!("'{}' is not a duration (e.g. 7d) or date (e.g. 2024-05-01)", value))?;
+
+    let duration = match unit {
+        "m" => Duration::minutes(amount),
+        "h" => Duration::hours(amount),
+        "d" => Duration::days(amount),
+        "w" => Duration::weeks(amount),
+        _ => {
+            return Err(anyhow!(
+                "Unknown duration unit in '{}'; use m, h, d or w (e.g. 7d)",
+                value
+            ))
+        }
+    };
+
+    Ok(now - duration)
+}
+
+/// Whether `date` falls in the window: at or after `since` and strictly before `before`
+pub fn in_window(date: DateTime<Utc>, since: Option<DateTime<Utc>>, before: Option<DateTime<Utc>>) -> bool {
+    since.map_or(true, |since| date >= since) && before.map_or(true, |before| date < before)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-05-10T12:00:00Z").unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_parse_time_bound() {
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+
+        assert_eq!(parse_time_bound("7d", now()).unwrap(), at("2024-05-03T12:00:00Z"));
+        assert_eq!(parse_time_bound("2w", now()).unwrap(), at("2024-04-26T12:00:00Z"));
+        assert_eq!(parse_time_bound("90m", now()).unwrap(), at("2024-05-10T10:30:00Z"));
+        assert_eq!(parse_time_bound("2024-05-01", now()).unwrap(), at("2024-05-01T00:00:00Z"));
+        assert_eq!(parse_time_bound("2024-05-01T08:00:00+02:00", now()).unwrap(), at("2024-05-01T06:00:00Z"));
+
+        assert!(parse_time_bound("7y", now()).is_err());
+        assert!(parse_time_bound("last week", now()).is_err());
+        assert!(parse_time_bound("d", now()).is_err());
+    }
+
+    #[test]
+    fn test_in_window() {
+        let since = parse_time_bound("7d", now()).ok();
+        let before = parse_time_bound("1d", now()).ok();
+        let days_ago = |days: i64| now() - Duration::days(days);
+
+        assert!(in_window(days_ago(3), since, before));
+        assert!(!in_window(days_ago(10), since, before));
+        assert!(!in_window(days_ago(0), since, before));
+        assert!(in_window(days_ago(0), since, None));
+        assert!(in_window(days_ago(100), None, None));
+    }
+}
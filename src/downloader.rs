@@ -1,33 +1,107 @@
 use anyhow::{anyhow, Result};
 use colored::*;
+use futures_util::future::join_all;
 use futures_util::StreamExt;
 use indicatif::{ProgressBar, ProgressStyle};
 use reqwest;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::fs::{self, File};
-use std::io::Write;
+use std::collections::HashMap;
+use std::io::{Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::metadata::{FileRecord, ModelMetadata};
+
+/// Default Hugging Face Hub endpoint
+pub const DEFAULT_HF_ENDPOINT: &str = "https://huggingface.co";
+
+/// Environment variables holding a Hugging Face access token, in order of preference
+pub const HF_TOKEN_ENV_VARS: &[&str] = &["HF_TOKEN", "HUGGING_FACE_HUB_TOKEN"];
+
+/// Environment variable holding comma-separated Hub endpoints, as read by `--hf-endpoint`
+pub const HF_ENDPOINT_ENV_VAR: &str = "HF_ENDPOINT";
+
+/// Hub endpoints from `HF_ENDPOINT`, for callers without an `--hf-endpoint` flag
+pub fn env_endpoints() -> Vec<String> {
+    std::env::var(HF_ENDPOINT_ENV_VAR)
+        .map(|value| value.split(',').map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+/// Environment variable holding comma-separated Hub endpoints, as read by `--hf-endpoint`
+pub const HF_ENDPOINT_ENV_VAR: &str = "HF_ENDPOINT";
+
+/// Hub endpoints from `HF_ENDPOINT`, for callers without an `--hf-endpoint` flag
+pub fn env_endpoints() -> Vec<String> {
+    std::env::var(HF_ENDPOINT_ENV_VAR)
+        .map(|value| value.split(',').map(str::to_string).collect())
+        .unwrap_or_default()
+}
 
 /// Hugging Face model information response
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct HfModelInfo {
     pub id: String,
+    /// Commit SHA of the revision the info describes
+    #[serde(default)]
+    pub sha: Option<String>,
     pub siblings: Vec<HfFile>,
 }
 
 /// Hugging Face file information
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct HfFile {
     pub rfilename: String,
     #[serde(rename = "size")]
     pub size: Option<u64>,
+    /// Git LFS details, present for large files such as GGUF weights
+    #[serde(default)]
+    pub lfs: Option<HfLfsInfo>,
+}
+
+impl HfFile {
+    /// Check a downloaded file's size and hex SHA256 against what the Hub reports. Only LFS
+    /// files have a published checksum; for others the size is all there is to check.
+    pub fn check_download(&self, size: u64, sha256: &str) -> Result<()> {
+        let expected_size = self.lfs.as_ref().map(|lfs| lfs.size).or(self.size);
+        if let Some(expected) = expected_size.filter(|&expected| expected != size) {
+            return Err(anyhow!("expected {} bytes but received {}", expected, size));
+        }
+
+        if let Some(lfs) = &self.lfs {
+            if !lfs.sha256.eq_ignore_ascii_case(sha256) {
+                return Err(anyhow!("SHA256 is {} but the Hub reports {}", sha256, lfs.sha256));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Git LFS details of a Hugging Face file
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HfLfsInfo {
+    pub sha256: String,
+    pub size: u64,
 }
 
+/// Model info responses shared between downloaders, keyed by model ID and revision
+pub type ModelInfoCache = Arc<Mutex<HashMap<String, HfModelInfo>>>;
+
 /// Model downloader for Hugging Face models
 pub struct ModelDownloader {
     client: reqwest::Client,
     cache_dir: PathBuf,
+    endpoints: Vec<String>,
+    verbose: bool,
+    quiet: bool,
+    revision: Option<String>,
+    pin_revision: bool,
+    verify: bool,
+    token: Option<String>,
+    info_cache: ModelInfoCache,
 }
 
 impl ModelDownloader {
@@ -51,16 +125,97 @@ impl ModelDownloader {
             .build()
             .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))?;
 
-        Ok(Self { client, cache_dir })
+        Ok(Self {
+            client,
+            cache_dir,
+            endpoints: vec![DEFAULT_HF_ENDPOINT.to_string()],
+            verbose: false,
+            quiet: false,
+            revision: None,
+            pin_revision: false,
+            verify: true,
+            token: default_hf_token(),
+            info_cache: ModelInfoCache::default(),
+        })
+    }
+
+    /// Use the given Hub mirrors, tried in order; an empty list keeps the default endpoint
+    pub fn with_endpoints(mut self, endpoints: Vec<String>) -> Self {
+        let endpoints: Vec<String> = endpoints
+            .into_iter()
+            .map(|e| e.trim().trim_end_matches('/').to_string())
+            .filter(|e| !e.is_empty())
+            .collect();
+
+        if !endpoints.is_empty() {
+            self.endpoints = endpoints;
+        }
+        self
+    }
+
+    /// Report which endpoint served each request
+    pub fn with_verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Suppress status messages on stdout, for modes where stdout carries machine-readable output
+    pub fn with_quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Download from a specific branch, tag or commit instead of the latest `main`
+    pub fn with_revision(mut self, revision: Option<String>) -> Self {
+        self.revision = revision;
+        self
+    }
+
+    /// Re-download from the commit recorded in metadata.json rather than resolving `main` again
+    pub fn with_pinned_revision(mut self, pin_revision: bool) -> Self {
+        self.pin_revision = pin_revision;
+        self
+    }
+
+    /// Authenticate with this access token instead of the one from the environment or token file
+    pub fn with_token(mut self, token: Option<String>) -> Self {
+        if let Some(token) = token {
+            self.token = Some(token);
+        }
+        self
+    }
+
+    /// Check downloads against the size and SHA256 the Hub reports (on by default)
+    pub fn with_verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Share model info responses with other downloaders, e.g. ones filled by `prefetch_model_info`
+    pub fn with_info_cache(mut self, info_cache: ModelInfoCache) -> Self {
+        self.info_cache = info_cache;
+        self
+    }
+
+    /// Fetch model info for several models concurrently so later lookups are served from the
+    /// cache. Failures are left for the individual lookups to report.
+    pub async fn prefetch_model_info(&self, model_ids: &[String]) {
+        join_all(model_ids.iter().map(|model_id| self.get_model_info(model_id))).await;
+    }
+
+    /// Get the directory holding all cached model directories
+    pub fn get_models_dir(&self) -> PathBuf {
+        self.cache_dir.join("models")
+    }
+
+    /// Get the cache directory for a single model
+    pub fn get_model_dir(&self, model_id: &str) -> PathBuf {
+        self.get_models_dir().join(normalize_model_id(model_id))
     }
 
     /// Get the local path for a model
     pub fn get_model_path(&self, model_id: &str, filename: &str) -> PathBuf {
-        let safe_model_id = model_id.replace('/', "--");
-        self.cache_dir
-            .join("models")
-            .join(safe_model_id)
-            .join(filename)
+        self.get_model_dir(model_id).join(filename)
     }
 
     /// Check if a model file exists locally
@@ -68,21 +223,58 @@ impl ModelDownloader {
         self.get_model_path(model_id, filename).exists()
     }
 
-    /// Get model information from Hugging Face Hub
+    /// Get model information from Hugging Face Hub, trying each endpoint in turn
     pub async fn get_model_info(&self, model_id: &str) -> Result<HfModelInfo> {
-        let url = format!("https://huggingface.co/api/models/{}", model_id);
+        self.get_model_info_at(model_id, self.revision.as_deref()).await
+    }
+
+    /// Get model information for a revision (`None` for the default branch)
+    pub async fn get_model_info_at(&self, model_id: &str, revision: Option<&str>) -> Result<HfModelInfo> {
+        let cache_key = format!("{}@{}", model_id, revision.unwrap_or_default());
+        if let Some(model_info) = self.info_cache.lock().unwrap().get(&cache_key) {
+            return Ok(model_info.clone());
+        }
+
+        let mut last_error = anyhow!("No Hugging Face endpoints configured");
+
+        for endpoint in &self.endpoints {
+            match self.get_model_info_from(endpoint, model_id, revision).await {
+                Ok(model_info) => {
+                    if self.verbose {
+                        println!("{} Model info fetched from {}", "Info:".blue().bold(), endpoint);
+                    }
+                    self.info_cache.lock().unwrap().insert(cache_key, model_info.clone());
+                    return Ok(model_info);
+                }
+                Err(e) => {
+                    if self.endpoints.len() > 1 {
+                        eprintln!("{} {}: {}", "Warning:".yellow().bold(), endpoint, e);
+                    }
+                    last_error = e;
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+
+    async fn get_model_info_from(&self, endpoint: &str, model_id: &str, revision: Option<&str>) -> Result<HfModelInfo> {
+        // blobs=true adds file sizes and LFS checksums to the siblings
+        let url = match revision {
+            Some(revision) => format!("{}/api/models/{}/revision/{}?blobs=true", endpoint, model_id, revision),
+            None => format!("{}/api/models/{}?blobs=true", endpoint, model_id),
+        };
         
         let response = self
-            .client
-            .get(&url)
+            .authorized(self.client.get(&url))
             .send()
             .await
             .map_err(|e| anyhow!("Failed to fetch model info: {}", e))?;
 
         if !response.status().is_success() {
             return Err(anyhow!(
-                "Failed to fetch model info: HTTP {}",
-                response.status()
+                "Failed to fetch model info: {}",
+                describe_status(response.status())
             ));
         }
 
@@ -94,6 +286,14 @@ impl ModelDownloader {
         Ok(model_info)
     }
 
+    /// Attach the access token, if there is one, to a Hub request
+    fn authorized(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
     /// Download a model file from Hugging Face Hub
     pub async fn download_model(
         &self,
@@ -105,6 +305,9 @@ impl ModelDownloader {
 
         // Check if file already exists
         if local_path.exists() && !force_download {
+            if self.quiet {
+                return Ok(local_path);
+            }
             println!(
                 "{} Model already exists: {}",
                 "Info:".blue().bold(),
@@ -119,15 +322,36 @@ impl ModelDownloader {
                 .map_err(|e| anyhow!("Failed to create model directory: {}", e))?;
         }
 
-        println!(
-            "{} Downloading model: {} (file: {})",
-            "Info:".blue().bold(),
-            model_id,
-            filename
-        );
+        if !self.quiet {
+            println!(
+                "{} Downloading model: {} (file: {})",
+                "Info:".blue().bold(),
+                model_id,
+                filename
+            );
+        }
+
+        // An explicit revision wins; otherwise --pin reuses the recorded commit, and a plain pull
+        // resolves the current `main` so the exact commit can be recorded
+        let model_dir = self.get_model_dir(model_id);
+        let mut model_metadata = ModelMetadata::load_or_warn(&model_dir);
+        let requested_revision = match (&self.revision, self.pin_revision) {
+            (Some(revision), _) => Some(revision.clone()),
+            (None, true) => model_metadata.revision.clone(),
+            (None, false) => None,
+        };
 
         // Get model info to find the file
-        let model_info = self.get_model_info(model_id).await?;
+        let model_info = self.get_model_info_at(model_id, requested_revision.as_deref()).await?;
+        let revision = model_info
+            .sha
+            .clone()
+            .or(requested_revision)
+            .unwrap_or_else(|| "main".to_string());
+
+        if !self.quiet {
+            println!("{} Model files pinned to {}", "Info:".blue().bold(), revision);
+        }
         
         let file_info = model_info
             .siblings
@@ -137,27 +361,6 @@ impl ModelDownloader {
 
         let file_size = file_info.size.unwrap_or(0);
 
-        // Download URL
-        let download_url = format!(
-            "https://huggingface.co/{}/resolve/main/{}",
-            model_id, filename
-        );
-
-        // Start download
-        let response = self
-            .client
-            .get(&download_url)
-            .send()
-            .await
-            .map_err(|e| anyhow!("Failed to start download: {}", e))?;
-
-        if !response.status().is_success() {
-            return Err(anyhow!(
-                "Failed to download file: HTTP {}",
-                response.status()
-            ));
-        }
-
         // Create progress bar
         let pb = ProgressBar::new(file_size);
         pb.set_style(
@@ -173,45 +376,147 @@ impl ModelDownloader {
             .map_err(|e| anyhow!("Failed to create temporary file: {}", e))?;
 
         let mut downloaded = 0u64;
-        let mut stream = response.bytes_stream();
         let mut hasher = Sha256::new();
+        let mut last_error = None;
+
+        // Try each endpoint in turn, resuming from the bytes already written where the mirror allows
+        for endpoint in &self.endpoints {
+            let download_url = format!("{}/{}/resolve/{}/{}", endpoint, model_id, revision, filename);
+
+            let mut request = self.authorized(self.client.get(&download_url));
+            if downloaded > 0 {
+                request = request.header(reqwest::header::RANGE, format!("bytes={}-", downloaded));
+            }
+
+            let response = match request.send().await {
+                Ok(response) if response.status().is_success() => response,
+                Ok(response) => {
+                    last_error = Some(anyhow!("Failed to download file from {}: {}", endpoint, describe_status(response.status())));
+                    continue;
+                }
+                Err(e) => {
+                    last_error = Some(anyhow!("Failed to start download from {}: {}", endpoint, e));
+                    continue;
+                }
+            };
+
+            // A mirror that ignores the range request sends the whole file again
+            if downloaded > 0 && response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+                file.set_len(0)
+                    .and_then(|_| file.seek(SeekFrom::Start(0)).map(|_| ()))
+                    .map_err(|e| anyhow!("Failed to reset temporary file: {}", e))?;
+                downloaded = 0;
+                hasher = Sha256::new();
+                pb.set_position(0);
+            } else if downloaded > 0 && self.verbose {
+                println!("{} Resuming at byte {} from {}", "Info:".blue().bold(), downloaded, endpoint);
+            }
+
+            let mut stream = response.bytes_stream();
+            let mut interrupted = None;
+
+            while let Some(chunk) = stream.next().await {
+                let chunk = match chunk {
+                    Ok(chunk) => chunk,
+                    Err(e) => {
+                        interrupted = Some(anyhow!("Download from {} interrupted: {}", endpoint, e));
+                        break;
+                    }
+                };
+                
+                file.write_all(&chunk)
+                    .map_err(|e| anyhow!("Failed to write chunk: {}", e))?;
+                
+                hasher.update(&chunk);
+                downloaded += chunk.len() as u64;
+                pb.set_position(downloaded);
+            }
+
+            match interrupted {
+                None => {
+                    if self.verbose {
+                        println!("{} Downloaded from {}", "Info:".blue().bold(), endpoint);
+                    }
+                    last_error = None;
+                    break;
+                }
+                Some(e) => {
+                    eprintln!("{} {}", "Warning:".yellow().bold(), e);
+                    last_error = Some(e);
+                }
+            }
+        }
 
-        while let Some(chunk) = stream.next().await {
-            let chunk = chunk.map_err(|e| anyhow!("Failed to read chunk: {}", e))?;
-            
-            file.write_all(&chunk)
-                .map_err(|e| anyhow!("Failed to write chunk: {}", e))?;
-            
-            hasher.update(&chunk);
-            downloaded += chunk.len() as u64;
-            pb.set_position(downloaded);
+        if let Some(e) = last_error {
+            pb.abandon();
+            drop(file);
+            let _ = fs::remove_file(&temp_path);
+            return Err(e);
+        }
+
+        // A truncated or corrupted download would otherwise only fail when the model is loaded
+        drop(file);
+        let sha256 = hex::encode(hasher.finalize());
+        if self.verify {
+            if let Err(e) = file_info.check_download(downloaded, &sha256) {
+                pb.abandon();
+                let _ = fs::remove_file(&temp_path);
+                return Err(anyhow!("Download of '{}' failed verification: {}; retry, or pass --no-verify to keep it anyway", filename, e));
+            }
         }
 
         pb.finish_with_message("Download complete!".green().to_string());
 
-        // Close file and rename from temp
-        drop(file);
+        // Rename from temp
         fs::rename(&temp_path, &local_path)
             .map_err(|e| anyhow!("Failed to finalize download: {}", e))?;
 
-        println!(
-            "{} Model downloaded successfully: {}",
-            "Success:".green().bold(),
-            local_path.display()
+        // Record the true model ID, since the directory name can't always be mapped back to it,
+        // and the commit and checksum of the file
+        model_metadata.model_id.get_or_insert_with(|| model_id.to_string());
+        model_metadata.files.insert(
+            filename.to_string(),
+            FileRecord {
+                size: downloaded,
+                sha256,
+                downloaded_at: chrono::Utc::now(),
+                revision: Some(revision.clone()),
+            },
         );
+        model_metadata.revision = Some(revision);
+        model_metadata.save(&model_dir)?;
+
+        if !self.quiet {
+            println!(
+                "{} Model downloaded successfully: {}",
+                "Success:".green().bold(),
+                local_path.display()
+            );
+        }
 
         Ok(local_path)
     }
 
     /// List available files for a model
     pub async fn list_model_files(&self, model_id: &str) -> Result<Vec<String>> {
+        let gguf_files = self
+            .list_model_file_infos(model_id)
+            .await?
+            .into_iter()
+            .map(|f| f.rfilename)
+            .collect();
+
+        Ok(gguf_files)
+    }
+
+    /// List available GGUF files for a model, including their sizes when known
+    pub async fn list_model_file_infos(&self, model_id: &str) -> Result<Vec<HfFile>> {
         let model_info = self.get_model_info(model_id).await?;
-        
-        let gguf_files: Vec<String> = model_info
+
+        let gguf_files: Vec<HfFile> = model_info
             .siblings
             .into_iter()
             .filter(|f| f.rfilename.ends_with(".gguf"))
-            .map(|f| f.rfilename)
             .collect();
 
         Ok(gguf_files)
@@ -223,6 +528,54 @@ impl ModelDownloader {
     }
 }
 
+/// Access token from `HF_TOKEN`, `HUGGING_FACE_HUB_TOKEN` or the token file written by
+/// `huggingface-cli login` (`$HF_HOME/token`, by default `~/.cache/huggingface/token`)
+pub fn default_hf_token() -> Option<String> {
+    let from_env = HF_TOKEN_ENV_VARS
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .map(|token| token.trim().to_string())
+        .find(|token| !token.is_empty());
+    if from_env.is_some() {
+        return from_env;
+    }
+
+    let hf_home = std::env::var_os("HF_HOME")
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".cache").join("huggingface")))?;
+    read_token_file(&hf_home.join("token"))
+}
+
+/// Read an access token file, ignoring surrounding whitespace; `None` if missing or empty
+pub fn read_token_file(path: &Path) -> Option<String> {
+    let token = fs::read_to_string(path).ok()?;
+    let token = token.trim();
+    (!token.is_empty()).then(|| token.to_string())
+}
+
+/// Describe a failed Hub response, pointing at authentication for 401 and 403
+fn describe_status(status: reqwest::StatusCode) -> String {
+    match status {
+        reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => format!(
+            "access denied (HTTP {}); is this a gated model? Accept its license on the model page and pass --hf-token or set HF_TOKEN",
+            status.as_u16()
+        ),
+        _ => format!("HTTP {}", status),
+    }
+}
+
+/// Turn a model ID into its cache directory name (`owner/name` becomes `owner--name`)
+pub fn normalize_model_id(model_id: &str) -> String {
+    model_id.replace('/', "--")
+}
+
+/// Best-effort inverse of `normalize_model_id` for directories without recorded metadata.
+/// Only the first `--` is treated as the owner separator, so names containing `--` survive;
+/// owners containing `--` can't be recovered and need the ID stored in metadata.json.
+pub fn denormalize_model_id(dir_name: &str) -> String {
+    dir_name.replacen("--", "/", 1)
+}
+
 /// Check if a string looks like a Hugging Face model ID
 pub fn is_hf_model_id(model: &str) -> bool {
     // HF model IDs are typically in the format "username/modelname" or "organization/modelname"
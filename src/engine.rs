@@ -0,0 +1,903 @@
+/*!
+# Inference Engine
+
+[`InferenceEngine`] is the embedding API: it loads a GGUF model once and
+generates completions for any number of prompts, so another program can use
+RustLlama without shelling out to the CLI. [`InferenceEngine::generate_stream`]
+yields the completion piece by piece, for rendering output as it arrives or
+applying stop conditions of your own.
+
+Each call gets a fresh context, so prompts never see each other's KV cache.
+Loading the weights is the expensive part and happens only once. llama.cpp's
+backend can only be initialized once at a time, so engines that are alive
+together share it.
+
+Everything that shapes a completion is in [`SamplingParams`], applied each
+step in this order: prompt affinity, repeat penalty, banned phrases, logit
+bias, the stop-token floor (`min_tokens`, `ignore_eos`), the grammar, then
+top-k and top-p (or Mirostat) and a draw at the (dynamic) temperature. A
+context that fills up is shifted, keeping the prompt, instead of failing.
+
+The CLI drives the same engine at a lower level: it creates contexts of its
+own (KV cache types, flash attention, an image or a saved state already in
+them), generates in them with [`InferenceEngine::stream_in`] one token at a
+time through [`TokenStream::next_token`], and runs `--beams` with
+[`BeamSearch`].
+*/
+
+use crate::ban_words::BannedPhrases;
+use crate::beam::{self, Beam};
+use crate::grammar;
+use crate::logit_bias::{LogitBias, LogitBiasEntry};
+use crate::repetition;
+use crate::sampling::{self, FilterTrace, Mirostat, SampledToken};
+use crate::stop_strings;
+use crate::stop_tokens::StopTokens;
+use anyhow::{anyhow, Result};
+use llama_cpp_2::context::params::LlamaContextParams;
+use llama_cpp_2::context::LlamaContext;
+use llama_cpp_2::llama_backend::LlamaBackend;
+use llama_cpp_2::llama_batch::LlamaBatch;
+use llama_cpp_2::model::params::LlamaModelParams;
+use llama_cpp_2::model::{AddBos, LlamaModel, Special};
+use llama_cpp_2::sampling::LlamaSampler;
+use llama_cpp_2::token::data::LlamaTokenData;
+use llama_cpp_2::token::data_array::LlamaTokenDataArray;
+use llama_cpp_2::token::LlamaToken;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::borrow::{Borrow, BorrowMut};
+use std::collections::HashSet;
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::sync::{Arc, Mutex, Weak};
+
+/// Context size used unless [`InferenceEngine::with_ctx_size`] says otherwise
+pub const DEFAULT_CTX_SIZE: u32 = 2048;
+
+/// Tokens decoded per batch unless [`InferenceEngine::with_batch_size`] says otherwise
+pub const DEFAULT_BATCH_SIZE: usize = 512;
+
+/// Sampling settings for one generation, with the CLI's defaults
+#[derive(Debug, Clone, PartialEq)]
+pub struct SamplingParams {
+    pub max_tokens: usize,
+    /// Stop tokens are masked until this many tokens have been generated
+    pub min_tokens: usize,
+    /// Mask stop tokens for the whole generation
+    pub ignore_eos: bool,
+    /// 0 is greedy
+    pub temperature: f32,
+    /// Vary the temperature per token by up to this much, by the entropy of the distribution;
+    /// 0 turns it off
+    pub dynatemp_range: f32,
+    pub dynatemp_exponent: f32,
+    /// 0 keeps the whole vocabulary
+    pub top_k: usize,
+    /// 1.0 turns the nucleus filter off
+    pub top_p: f32,
+    /// 1.0 turns the penalty off
+    pub repeat_penalty: f32,
+    pub repeat_last_n: usize,
+    /// Logit bias for tokens that appear in the prompt; 0 turns it off
+    pub prompt_affinity: f32,
+    /// Mirostat version 1 or 2 in place of top-k and top-p; 0 turns it off
+    pub mirostat: u8,
+    pub mirostat_tau: f32,
+    pub mirostat_eta: f32,
+    /// Random if unset
+    pub seed: Option<u64>,
+    /// Strings that end generation, left out of the output
+    pub stop: Vec<String>,
+    /// Token IDs that end generation besides EOS and the model's end-of-turn tokens
+    pub stop_tokens: Vec<i32>,
+    /// Words and phrases that are never generated
+    pub ban_words: Vec<String>,
+    pub logit_bias: Vec<LogitBiasEntry>,
+    /// GBNF grammar with a `root` rule that the completion must match
+    pub grammar: Option<String>,
+    /// End at the first newline, which is left out
+    pub single_line: bool,
+    /// End once an n-gram repeats this many times in a row
+    pub stop_on_repeat: Option<usize>,
+    /// Positions kept at the start of a full context when it is shifted; the prompt by default
+    pub n_keep: Option<usize>,
+    /// Shift a full context instead of failing
+    pub context_shift: bool,
+}
+
+impl Default for SamplingParams {
+    fn default() -> Self {
+        Self {
+            max_tokens: 1024,
+            min_tokens: 0,
+            ignore_eos: false,
+            temperature: 0.8,
+            dynatemp_range: 0.0,
+            dynatemp_exponent: 1.0,
+            top_k: 40,
+            top_p: 0.95,
+            repeat_penalty: 1.1,
+            repeat_last_n: 64,
+            prompt_affinity: 0.0,
+            mirostat: 0,
+            mirostat_tau: 5.0,
+            mirostat_eta: 0.1,
+            seed: None,
+            stop: Vec::new(),
+            stop_tokens: Vec::new(),
+            ban_words: Vec::new(),
+            logit_bias: Vec::new(),
+            grammar: None,
+            single_line: false,
+            stop_on_repeat: None,
+            n_keep: None,
+            context_shift: true,
+        }
+    }
+}
+
+impl SamplingParams {
+    /// Number of generated tokens below which stop tokens are masked
+    fn stop_floor(&self) -> usize {
+        if self.ignore_eos {
+            usize::MAX
+        } else {
+            self.min_tokens
+        }
+    }
+}
+
+/// The llama.cpp backend, initialized on first use and freed when the last engine using it is
+fn shared_backend() -> Result<Arc<LlamaBackend>> {
+    static BACKEND: Mutex<Weak<LlamaBackend>> = Mutex::new(Weak::new());
+
+    let mut shared = BACKEND.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(backend) = shared.upgrade() {
+        return Ok(backend);
+    }
+    let backend = Arc::new(LlamaBackend::init().map_err(|e| anyhow!("Failed to initialize llama backend: {}", e))?);
+    *shared = Arc::downgrade(&backend);
+    Ok(backend)
+}
+
+/// Split `tokens` into chunks of at most `batch_size`, each with its offset into `tokens`
+pub fn batch_chunks(tokens: &[LlamaToken], batch_size: usize) -> impl Iterator<Item = (usize, &[LlamaToken])> {
+    tokens.chunks(batch_size).enumerate().map(move |(i, chunk)| (i * batch_size, chunk))
+}
+
+/// Positions a context shift discards: half of those after the first `n_keep`
+pub fn context_shift_discard(n_cur: usize, n_keep: usize) -> usize {
+    n_cur.saturating_sub(n_keep) / 2
+}
+
+/// A loaded model that serves many prompts
+pub struct InferenceEngine {
+    model: LlamaModel,
+    // Declared after the model so that it outlives it
+    backend: Arc<LlamaBackend>,
+    ctx_size: u32,
+    batch_size: usize,
+    threads: Option<i32>,
+}
+
+impl InferenceEngine {
+    /// Initialize llama.cpp and load a GGUF model
+    pub fn load(model_path: impl AsRef<Path>) -> Result<Self> {
+        Self::load_with_params(model_path, &LlamaModelParams::default())
+    }
+
+    /// Like [`load`](Self::load), with llama.cpp model parameters such as mmap and mlock
+    pub fn load_with_params(model_path: impl AsRef<Path>, params: &LlamaModelParams) -> Result<Self> {
+        let model_path = model_path.as_ref();
+        let backend = shared_backend()?;
+        let model = LlamaModel::load_from_file(&backend, model_path, params)
+            .map_err(|e| anyhow!("Failed to load model '{}': {}", model_path.display(), e))?;
+
+        Ok(Self {
+            model,
+            backend,
+            ctx_size: DEFAULT_CTX_SIZE,
+            batch_size: DEFAULT_BATCH_SIZE,
+            threads: None,
+        })
+    }
+
+    /// Context size in tokens; each prompt must fit
+    pub fn with_ctx_size(mut self, ctx_size: u32) -> Self {
+        self.ctx_size = ctx_size;
+        self
+    }
+
+    /// Maximum number of prompt tokens decoded in one batch; contexts passed to
+    /// [`stream_in`](Self::stream_in) must allow batches this large
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Number of inference threads (auto-detected by default)
+    pub fn with_threads(mut self, threads: i32) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// The loaded model, e.g. for tokenizing or reading metadata
+    pub fn model(&self) -> &LlamaModel {
+        &self.model
+    }
+
+    /// Context parameters from the engine's context size, batch size and threads
+    pub fn context_params(&self) -> LlamaContextParams {
+        let mut ctx_params = LlamaContextParams::default()
+            .with_n_ctx(NonZeroU32::new(self.ctx_size))
+            .with_n_batch(self.batch_size as u32);
+        if let Some(threads) = self.threads {
+            ctx_params = ctx_params.with_n_threads(threads);
+        }
+        ctx_params
+    }
+
+    /// Create a context for the model
+    pub fn new_context(&self, params: LlamaContextParams) -> Result<LlamaContext<'_>> {
+        self.model
+            .new_context(&self.backend, params)
+            .map_err(|e| anyhow!("Failed to create context: {}", e))
+    }
+
+    /// Generate a completion of `prompt`, ending at an end-of-sequence or end-of-turn token,
+    /// a stop string or `max_tokens`
+    pub fn generate(&mut self, prompt: &str, params: &SamplingParams) -> Result<String> {
+        self.generate_stream(prompt, params)?.collect()
+    }
+
+    /// Like [`generate`](Self::generate), but yield the completion piece by piece as it is
+    /// generated. Dropping the stream stops generation.
+    pub fn generate_stream(&mut self, prompt: &str, params: &SamplingParams) -> Result<TokenStream<'_>> {
+        let engine: &Self = self;
+        let ctx = engine.new_context(engine.context_params())?;
+
+        let tokens = engine
+            .model
+            .str_to_token(prompt, AddBos::Always)
+            .map_err(|e| anyhow!("Failed to tokenize prompt: {}", e))?;
+        if tokens.is_empty() {
+            return Err(anyhow!("Prompt is empty and the model adds no BOS token"));
+        }
+        let n_ctx = ctx.n_ctx() as usize;
+        if tokens.len() >= n_ctx {
+            return Err(anyhow!(
+                "Prompt ({} tokens) does not fit in the context of {} tokens",
+                tokens.len(),
+                n_ctx
+            ));
+        }
+
+        engine.stream_in(ctx, &tokens, 0, params, |_| Ok(()))
+    }
+
+    /// Decode `tokens` into `ctx` after its first `n_past` positions, in batch-sized chunks,
+    /// calling `on_chunk` with the number of tokens decoded so far after each one. Returns the
+    /// batch index of the final token's logits.
+    pub fn decode_prompt(
+        &self,
+        ctx: &mut LlamaContext<'_>,
+        tokens: &[LlamaToken],
+        n_past: i32,
+        mut on_chunk: impl FnMut(usize) -> Result<()>,
+    ) -> Result<i32> {
+        if tokens.is_empty() {
+            return Err(anyhow!("There are no prompt tokens to decode"));
+        }
+
+        // Only the final token needs logits
+        let mut batch = LlamaBatch::new(self.batch_size, 1);
+        for (chunk_start, chunk) in batch_chunks(tokens, self.batch_size) {
+            batch.clear();
+            for (i, &token) in chunk.iter().enumerate() {
+                let pos = chunk_start + i;
+                batch
+                    .add(token, n_past + pos as i32, &[0], pos == tokens.len() - 1)
+                    .map_err(|e| anyhow!("Failed to add token to batch: {}", e))?;
+            }
+            ctx.decode(&mut batch).map_err(|e| anyhow!("Failed to process prompt: {}", e))?;
+            on_chunk(chunk_start + chunk.len())?;
+        }
+
+        // The final token is the last one added to the final chunk
+        Ok(batch.n_tokens() - 1)
+    }
+
+    /// Decode the prompt `tokens` into `ctx` after its first `n_past` positions (which the
+    /// caller may have filled, e.g. with an image or a restored state) and start generating
+    /// from them. `on_prompt_chunk` is called as for [`decode_prompt`](Self::decode_prompt).
+    /// The context can be passed by value or as `&mut`, to reuse it afterwards.
+    pub fn stream_in<'a, C: BorrowMut<LlamaContext<'a>>>(
+        &'a self,
+        mut ctx: C,
+        tokens: &[LlamaToken],
+        n_past: i32,
+        params: &SamplingParams,
+        on_prompt_chunk: impl FnMut(usize) -> Result<()>,
+    ) -> Result<TokenStream<'a, C>> {
+        let sampler = Sampler::new(&self.model, tokens, params)?;
+        let context: &mut LlamaContext<'a> = ctx.borrow_mut();
+        let logit_index = self.decode_prompt(context, tokens, n_past, on_prompt_chunk)?;
+        let prompt_end = n_past as usize + tokens.len();
+
+        Ok(TokenStream {
+            model: &self.model,
+            ctx,
+            batch: LlamaBatch::new(1, 1),
+            mirostat: (params.mirostat > 0)
+                .then(|| Mirostat::new(params.mirostat, params.mirostat_tau, params.mirostat_eta)),
+            rng: StdRng::seed_from_u64(params.seed.unwrap_or_else(rand::random)),
+            params: params.clone(),
+            sampler,
+            history: tokens.to_vec(),
+            candidates: Vec::with_capacity(self.model.n_vocab().max(0) as usize),
+            logit_index,
+            prompt_tokens: tokens.len(),
+            n_keep: params.n_keep.unwrap_or(prompt_end),
+            n_cur: prompt_end as i32,
+            tokens_generated: 0,
+            log_prob_sum: 0.0,
+            scored_tokens: 0,
+            text: String::new(),
+            emitted: 0,
+            explain: 0,
+            explain_top: 0,
+            stop_reason: None,
+            finished: false,
+        })
+    }
+
+    /// Decode the prompt as for [`stream_in`](Self::stream_in) and set up a beam search over
+    /// `width` sequences. Grammars and Mirostat follow a single sequence, so they can't be used.
+    pub fn beam_search_in<'a, C: BorrowMut<LlamaContext<'a>>>(
+        &'a self,
+        mut ctx: C,
+        tokens: &[LlamaToken],
+        n_past: i32,
+        params: &SamplingParams,
+        width: usize,
+        on_prompt_chunk: impl FnMut(usize) -> Result<()>,
+    ) -> Result<BeamSearch<'a, C>> {
+        if params.grammar.is_some() || params.mirostat > 0 {
+            return Err(anyhow!("Beam search can't be combined with a grammar or Mirostat"));
+        }
+
+        let sampler = Sampler::new(&self.model, tokens, params)?;
+        let context: &mut LlamaContext<'a> = ctx.borrow_mut();
+        let logit_index = self.decode_prompt(context, tokens, n_past, on_prompt_chunk)?;
+
+        Ok(BeamSearch {
+            model: &self.model,
+            ctx,
+            batch: LlamaBatch::new(self.batch_size, 1),
+            batch_size: self.batch_size,
+            params: params.clone(),
+            sampler,
+            prompt: tokens.to_vec(),
+            prompt_end: n_past + tokens.len() as i32,
+            width: width.max(1),
+            beams: vec![Beam::default()],
+            cached: Vec::new(),
+            logit_index,
+            candidates: Vec::with_capacity(self.model.n_vocab().max(0) as usize),
+            steps: 0,
+        })
+    }
+}
+
+/// The sampling stages of one generation that are resolved against the model up front
+struct Sampler {
+    stop_tokens: StopTokens,
+    banned: BannedPhrases,
+    logit_bias: LogitBias,
+    grammar: Option<LlamaSampler>,
+    /// The prompt's vocabulary for `prompt_affinity`, special tokens like BOS excluded
+    prompt_vocab: HashSet<LlamaToken>,
+}
+
+impl Sampler {
+    fn new(model: &LlamaModel, prompt: &[LlamaToken], params: &SamplingParams) -> Result<Self> {
+        let stop_tokens = StopTokens::for_model(model, &params.stop_tokens)?;
+        let grammar = match &params.grammar {
+            Some(text) => Some(
+                LlamaSampler::grammar(model, text, grammar::ROOT_RULE)
+                    .map_err(|e| anyhow!("Failed to load grammar: {}", e))?,
+            ),
+            None => None,
+        };
+        let prompt_vocab = prompt
+            .iter()
+            .copied()
+            .filter(|&token| token != model.token_bos() && !stop_tokens.contains(token))
+            .collect();
+
+        Ok(Self {
+            banned: BannedPhrases::from_words(model, &params.ban_words)?,
+            logit_bias: LogitBias::from_entries(model, &params.logit_bias)?,
+            stop_tokens,
+            grammar,
+            prompt_vocab,
+        })
+    }
+
+    /// Apply every stage before the top-k, top-p and temperature draw. `history` is the prompt
+    /// followed by the `generated` tokens of the sequence being extended.
+    fn shape(&self, candidates: &mut Vec<LlamaTokenData>, history: &[LlamaToken], generated: usize, params: &SamplingParams) {
+        if params.prompt_affinity != 0.0 {
+            sampling::apply_token_bias(candidates, &self.prompt_vocab, params.prompt_affinity);
+        }
+        let window = generated.min(params.repeat_last_n);
+        if params.repeat_penalty != 1.0 && window > 0 {
+            let recent_vocab: HashSet<LlamaToken> = history[history.len() - window..].iter().copied().collect();
+            sampling::apply_repeat_penalty(candidates, &recent_vocab, params.repeat_penalty);
+        }
+        if !self.banned.is_empty() {
+            self.banned.mask(history, candidates);
+        }
+        if !self.logit_bias.is_empty() {
+            self.logit_bias.apply(candidates);
+        }
+        self.stop_tokens.suppress_below_floor(generated, params.stop_floor(), candidates);
+        if let Some(grammar) = &self.grammar {
+            let mut constrained = LlamaTokenDataArray::new(std::mem::take(candidates), false);
+            constrained.apply_sampler(grammar);
+            *candidates = constrained.data;
+        }
+    }
+}
+
+/// Why a [`TokenStream`] ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// `max_tokens` were generated
+    MaxTokens,
+    /// An end-of-sequence, end-of-turn or extra stop token was sampled
+    StopToken(LlamaToken),
+    /// The text reached a stop string, which is left out
+    StopString,
+    /// With `single_line`, the text reached a newline
+    EndOfLine,
+    /// An n-gram of this many tokens repeated `stop_on_repeat` times
+    Repetition(usize),
+}
+
+/// One sampled token of a [`TokenStream`]
+#[derive(Debug, Clone)]
+pub struct GeneratedToken {
+    pub token: LlamaToken,
+    /// Natural-log probability under the full distribution, before top-k and top-p
+    pub log_prob: f32,
+    /// The token's text: empty for a stop token, cut at the newline with `single_line`
+    pub piece: String,
+    /// Text that is ready to show: what can no longer be the start of a stop string, and
+    /// everything left once generation ends
+    pub text: String,
+    /// Positions freed by shifting the full context before this token was decoded
+    pub shifted: usize,
+    /// How the candidates were filtered, for the tokens asked for with [`TokenStream::explain`]
+    pub trace: Option<FilterTrace>,
+}
+
+/// A completion being generated; see [`InferenceEngine::generate_stream`] and
+/// [`InferenceEngine::stream_in`]. As an iterator it yields the text piece by piece.
+pub struct TokenStream<'a, C: BorrowMut<LlamaContext<'a>> = LlamaContext<'a>> {
+    model: &'a LlamaModel,
+    ctx: C,
+    batch: LlamaBatch,
+    params: SamplingParams,
+    sampler: Sampler,
+    mirostat: Option<Mirostat>,
+    rng: StdRng,
+    /// The prompt followed by the generated tokens
+    history: Vec<LlamaToken>,
+    /// Vocab-sized candidate buffer, refilled in place each step
+    candidates: Vec<LlamaTokenData>,
+    /// Batch index of the logits to sample from next
+    logit_index: i32,
+    prompt_tokens: usize,
+    n_keep: usize,
+    n_cur: i32,
+    tokens_generated: usize,
+    log_prob_sum: f64,
+    scored_tokens: usize,
+    /// Everything generated so far; the first `emitted` bytes have been handed out
+    text: String,
+    emitted: usize,
+    /// Number of tokens to trace, and candidates listed per trace
+    explain: usize,
+    explain_top: usize,
+    stop_reason: Option<StopReason>,
+    finished: bool,
+}
+
+impl<'a, C: BorrowMut<LlamaContext<'a>>> TokenStream<'a, C> {
+    /// Trace the filter stages of the first `steps` tokens, listing the `top` most likely
+    /// survivors of each
+    pub fn explain(mut self, steps: usize, top: usize) -> Self {
+        self.explain = steps;
+        self.explain_top = top;
+        self
+    }
+
+    /// Number of prompt tokens decoded for this stream, including BOS
+    pub fn prompt_tokens(&self) -> usize {
+        self.prompt_tokens
+    }
+
+    /// Number of tokens generated so far
+    pub fn tokens_generated(&self) -> usize {
+        self.tokens_generated
+    }
+
+    /// The generated tokens in the context, excluding a stop token or the one a stop string or
+    /// newline ended generation in
+    pub fn generated(&self) -> &[LlamaToken] {
+        &self.history[self.prompt_tokens..]
+    }
+
+    /// Everything generated so far, including text still held back
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Sum of the log-probabilities of every sampled token, including a terminating stop token
+    pub fn log_prob_sum(&self) -> f64 {
+        self.log_prob_sum
+    }
+
+    /// Number of sampled tokens contributing to [`log_prob_sum`](Self::log_prob_sum)
+    pub fn scored_tokens(&self) -> usize {
+        self.scored_tokens
+    }
+
+    /// Positions kept at the start of the context when it is shifted
+    pub fn n_keep(&self) -> usize {
+        self.n_keep
+    }
+
+    /// Why generation ended, once it has
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        self.stop_reason
+    }
+
+    /// Whether generation ended because it reached `max_tokens`, rather than at an
+    /// end-of-sequence token or a stop string
+    pub fn reached_max_tokens(&self) -> bool {
+        self.stop_reason == Some(StopReason::MaxTokens)
+    }
+
+    /// Sample, check and decode the next token. Returns `None` once generation has ended;
+    /// the token that ends it is still returned, with [`stop_reason`](Self::stop_reason) set.
+    pub fn next_token(&mut self) -> Result<Option<GeneratedToken>> {
+        if self.finished {
+            return Ok(None);
+        }
+        let step = self.step();
+        if !matches!(step, Ok(Some(_))) {
+            self.finished = true;
+        }
+        step
+    }
+
+    fn step(&mut self) -> Result<Option<GeneratedToken>> {
+        if self.tokens_generated >= self.params.max_tokens {
+            self.stop_reason = Some(StopReason::MaxTokens);
+            return Ok(None);
+        }
+
+        let ctx: &LlamaContext<'a> = self.ctx.borrow();
+        self.candidates.clear();
+        self.candidates.extend(ctx.candidates_ith(self.logit_index));
+        self.sampler.shape(&mut self.candidates, &self.history, self.tokens_generated, &self.params);
+
+        // Scores are under the full distribution, so normalize before filtering
+        let log_norm = sampling::log_sum_exp(&self.candidates);
+        let unfiltered = (self.tokens_generated < self.explain).then(|| self.candidates.clone());
+
+        // Keep the top-k, then the top-p nucleus (or Mirostat's cut instead), and draw from the
+        // temperature-scaled distribution (greedy at temperature 0)
+        if self.mirostat.is_none() {
+            sampling::apply_top_k(&mut self.candidates, self.params.top_k);
+            sampling::apply_top_p(&mut self.candidates, self.params.top_p);
+        }
+        let temperature = sampling::dynamic_temperature(
+            &self.candidates,
+            self.params.temperature,
+            self.params.dynatemp_range,
+            self.params.dynatemp_exponent,
+        );
+        let sampled = match self.mirostat.as_mut() {
+            Some(mirostat) => mirostat.sample(&mut self.candidates, temperature, log_norm, &mut self.rng),
+            None => sampling::sample_temperature(&self.candidates, temperature, log_norm, &mut self.rng),
+        };
+        // Nothing left to draw from ends generation like an end-of-sequence token
+        let sampled = sampled.unwrap_or(SampledToken {
+            token: self.model.token_eos(),
+            log_prob: 0.0,
+        });
+        let token = sampled.token;
+        self.log_prob_sum += sampled.log_prob as f64;
+        self.scored_tokens += 1;
+
+        let mut generated = GeneratedToken {
+            token,
+            log_prob: sampled.log_prob,
+            piece: String::new(),
+            text: String::new(),
+            shifted: 0,
+            trace: unfiltered.map(|unfiltered| {
+                sampling::trace_filters(&unfiltered, self.params.top_k, self.params.top_p, temperature, self.explain_top)
+            }),
+        };
+
+        if self.sampler.stop_tokens.contains(token) {
+            return Ok(Some(self.finish(generated, StopReason::StopToken(token))));
+        }
+        if let Some(grammar) = self.sampler.grammar.as_mut() {
+            grammar.accept(token);
+        }
+
+        let mut line_done = false;
+        if let Ok(mut piece) = self.model.token_to_str(token, Special::Tokenize) {
+            if let Some(newline) = piece.find('\n').filter(|_| self.params.single_line) {
+                piece.truncate(newline);
+                line_done = true;
+            }
+            self.text.push_str(&piece);
+            generated.piece = piece;
+
+            // Stop strings can't start in text already handed out, since that is held back
+            if let Some(stop) = stop_strings::find_stop(&self.text[self.emitted..], &self.params.stop) {
+                self.text.truncate(self.emitted + stop);
+                return Ok(Some(self.finish(generated, StopReason::StopString)));
+            }
+        }
+        if line_done {
+            self.tokens_generated += 1;
+            return Ok(Some(self.finish(generated, StopReason::EndOfLine)));
+        }
+
+        self.history.push(token);
+
+        let ctx: &mut LlamaContext<'a> = self.ctx.borrow_mut();
+        if self.n_cur as u32 >= ctx.n_ctx() {
+            generated.shifted = shift_context(ctx, self.n_cur as usize, self.n_keep, self.params.context_shift)?;
+            self.n_cur -= generated.shifted as i32;
+        }
+
+        self.batch.clear();
+        self.batch
+            .add(token, self.n_cur, &[0], true)
+            .map_err(|e| anyhow!("Failed to add generated token to batch: {}", e))?;
+        ctx.decode(&mut self.batch).map_err(|e| anyhow!("Failed to decode batch: {}", e))?;
+        self.logit_index = self.batch.n_tokens() - 1;
+        self.n_cur += 1;
+        self.tokens_generated += 1;
+
+        if let Some(repeats) = self.params.stop_on_repeat {
+            if let Some(ngram) = repetition::repeating_ngram(self.generated(), repeats) {
+                return Ok(Some(self.finish(generated, StopReason::Repetition(ngram))));
+            }
+        }
+        if self.tokens_generated == self.params.max_tokens {
+            return Ok(Some(self.finish(generated, StopReason::MaxTokens)));
+        }
+
+        // Hold back a tail that could still become a stop string
+        let ready = self.text.len() - stop_strings::partial_stop_len(&self.text[self.emitted..], &self.params.stop);
+        generated.text = self.text[self.emitted..ready].to_string();
+        self.emitted = ready;
+        Ok(Some(generated))
+    }
+
+    /// End generation with `token`, handing out the rest of the text with it
+    fn finish(&mut self, mut token: GeneratedToken, reason: StopReason) -> GeneratedToken {
+        self.stop_reason = Some(reason);
+        self.finished = true;
+        token.text = self.text[self.emitted..].to_string();
+        self.emitted = self.text.len();
+        token
+    }
+}
+
+impl<'a, C: BorrowMut<LlamaContext<'a>>> Iterator for TokenStream<'a, C> {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.next_token() {
+                Ok(Some(token)) if token.text.is_empty() => continue,
+                Ok(Some(token)) => return Some(Ok(token.text)),
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Free room in a full context by discarding the oldest half of the positions after the first
+/// `n_keep` and moving the rest down to close the gap, returning how many positions were freed
+fn shift_context(ctx: &mut LlamaContext<'_>, n_cur: usize, n_keep: usize, enabled: bool) -> Result<usize> {
+    if !enabled {
+        return Err(anyhow!("The context of {} tokens is full and context shifting is off", n_cur));
+    }
+
+    let n_discard = context_shift_discard(n_cur, n_keep);
+    if n_discard == 0 {
+        return Err(anyhow!(
+            "The context of {} tokens is full and keeping the first {} leaves nothing to discard",
+            n_cur,
+            n_keep
+        ));
+    }
+
+    ctx.clear_kv_cache_seq(Some(0), Some(n_keep as u32), Some((n_keep + n_discard) as u32))
+        .map_err(|e| anyhow!("Failed to discard context: {}", e))?;
+    ctx.kv_cache_seq_add(0, Some((n_keep + n_discard) as u32), Some(n_cur as u32), -(n_discard as i32))
+        .map_err(|e| anyhow!("Failed to shift context: {}", e))?;
+    Ok(n_discard)
+}
+
+/// Beam search over sequences that share KV sequence 0; see [`InferenceEngine::beam_search_in`].
+/// Before a beam is expanded, the cache is rewound to where it diverges from the beam decoded
+/// last and only the rest is re-decoded.
+pub struct BeamSearch<'a, C: BorrowMut<LlamaContext<'a>> = LlamaContext<'a>> {
+    model: &'a LlamaModel,
+    ctx: C,
+    batch: LlamaBatch,
+    batch_size: usize,
+    params: SamplingParams,
+    sampler: Sampler,
+    prompt: Vec<LlamaToken>,
+    prompt_end: i32,
+    width: usize,
+    beams: Vec<Beam>,
+    /// Generated tokens currently in the cache, and the batch index holding their final logits
+    cached: Vec<LlamaToken>,
+    logit_index: i32,
+    candidates: Vec<LlamaTokenData>,
+    steps: usize,
+}
+
+impl<'a, C: BorrowMut<LlamaContext<'a>>> BeamSearch<'a, C> {
+    /// Expand every unfinished beam by its most likely tokens and keep the best sequences.
+    /// Returns false once every beam is finished or `max_tokens` steps have been taken.
+    pub fn step(&mut self) -> Result<bool> {
+        if self.steps == self.params.max_tokens || self.beams.iter().all(|beam| beam.finished) {
+            return Ok(false);
+        }
+
+        let mut expansions = Vec::with_capacity(self.beams.len());
+        for beam in &self.beams {
+            if beam.finished {
+                expansions.push(Vec::new());
+                continue;
+            }
+
+            let ctx: &mut LlamaContext<'a> = self.ctx.borrow_mut();
+            if beam.tokens != self.cached {
+                self.logit_index =
+                    decode_beam(ctx, &mut self.batch, &self.cached, &beam.tokens, self.prompt_end, self.batch_size)?;
+                self.cached.clone_from(&beam.tokens);
+            }
+
+            self.candidates.clear();
+            self.candidates.extend(ctx.candidates_ith(self.logit_index));
+            let history: Vec<LlamaToken> = self.prompt.iter().chain(&beam.tokens).copied().collect();
+            self.sampler.shape(&mut self.candidates, &history, beam.tokens.len(), &self.params);
+
+            expansions.push(sampling::top_candidates(&self.candidates, self.width));
+        }
+
+        let stop_tokens = &self.sampler.stop_tokens;
+        self.beams = beam::select_beams(&self.beams, &expansions, self.width, |token| stop_tokens.contains(token));
+        self.steps += 1;
+        Ok(self.steps < self.params.max_tokens && !self.beams.iter().all(|beam| beam.finished))
+    }
+
+    /// The sequences kept so far
+    pub fn beams(&self) -> &[Beam] {
+        &self.beams
+    }
+
+    /// The sequence with the highest cumulative log-probability
+    pub fn best(&self) -> Beam {
+        beam::best_beam(&self.beams).cloned().unwrap_or_default()
+    }
+
+    /// Each of a beam's tokens as text with its log-probability, ending at the first newline
+    /// with `single_line`
+    pub fn pieces(&self, beam: &Beam) -> Vec<(String, f32)> {
+        let mut pieces = Vec::with_capacity(beam.tokens.len());
+        for (&token, &log_prob) in beam.tokens.iter().zip(&beam.log_probs) {
+            if let Ok(mut piece) = self.model.token_to_str(token, Special::Tokenize) {
+                let newline = piece.find('\n').filter(|_| self.params.single_line);
+                if let Some(newline) = newline {
+                    piece.truncate(newline);
+                }
+                pieces.push((piece, log_prob));
+                if newline.is_some() {
+                    break;
+                }
+            }
+        }
+        pieces
+    }
+
+    /// A beam's text. Beams aren't steered by stop strings, but the text is still cut at the
+    /// first one.
+    pub fn text(&self, beam: &Beam) -> String {
+        let mut text: String = self.pieces(beam).into_iter().map(|(piece, _)| piece).collect();
+        if let Some(stop) = stop_strings::find_stop(&text, &self.params.stop) {
+            text.truncate(stop);
+        }
+        text
+    }
+}
+
+/// Rewind the cache from the `cached` generated tokens to the common prefix with `target` and
+/// decode the rest of `target`, returning the batch index of its last token's logits
+fn decode_beam(
+    ctx: &mut LlamaContext<'_>,
+    batch: &mut LlamaBatch,
+    cached: &[LlamaToken],
+    target: &[LlamaToken],
+    prompt_end: i32,
+    batch_size: usize,
+) -> Result<i32> {
+    let mut common = cached.iter().zip(target).take_while(|(a, b)| a == b).count();
+    // The last token must be decoded again to get its logits back
+    if common == target.len() {
+        common = common.saturating_sub(1);
+    }
+
+    ctx.clear_kv_cache_seq(Some(0), Some((prompt_end as usize + common) as u32), None)
+        .map_err(|e| anyhow!("Failed to rewind context: {}", e))?;
+
+    let suffix = &target[common..];
+    for (chunk_offset, chunk) in batch_chunks(suffix, batch_size) {
+        batch.clear();
+        let chunk_start = common + chunk_offset;
+        for (i, &token) in chunk.iter().enumerate() {
+            let pos = chunk_start + i;
+            batch
+                .add(token, prompt_end + pos as i32, &[0], pos == target.len() - 1)
+                .map_err(|e| anyhow!("Failed to add beam token to batch: {}", e))?;
+        }
+        ctx.decode(batch).map_err(|e| anyhow!("Failed to decode batch: {}", e))?;
+    }
+
+    Ok(batch.n_tokens() - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_chunks_cover_long_prompt() {
+        // A 650-token prompt doesn't fit one 512-token batch
+        let tokens: Vec<LlamaToken> = (0..650).map(LlamaToken::new).collect();
+        let chunks: Vec<(usize, &[LlamaToken])> = batch_chunks(&tokens, 512).collect();
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!((chunks[0].0, chunks[0].1.len()), (0, 512));
+        assert_eq!((chunks[1].0, chunks[1].1.len()), (512, 138));
+        assert_eq!(chunks[1].1[0], tokens[512]);
+        assert!(batch_chunks(&tokens, 64).all(|(_, chunk)| chunk.len() <= 64));
+    }
+
+    #[test]
+    fn test_context_shift_discard() {
+        // A 2048-token context holding a 100-token prompt drops half of the 1948 generated tokens
+        assert_eq!(context_shift_discard(2048, 100), 974);
+        assert_eq!(context_shift_discard(2048, 0), 1024);
+        assert_eq!(context_shift_discard(2048, 2047), 0);
+        assert_eq!(context_shift_discard(2048, 4096), 0);
+    }
+}
@@ -0,0 +1,111 @@
+/*!
+# Named Pipes
+
+`--prompt-file` and `--output-file` accept FIFOs so RustLlama can sit in a
+pipeline without temp files. A FIFO prompt is read until the writer closes
+it. A FIFO output can't be replaced atomically (renaming over it would swap
+the pipe for a regular file), so it is opened once and streamed to instead.
+
+`--prompt -` reads the prompt from stdin the same way, for
+`cat prompt.txt | rustlama run ... --prompt -`.
+*/
+
+use anyhow::{anyhow, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read};
+use std::path::Path;
+
+/// Whether `path` exists and is a named pipe
+#[cfg(unix)]
+pub fn is_fifo(path: &Path) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_fifo())
+}
+
+/// Named pipes are only detected on Unix
+#[cfg(not(unix))]
+pub fn is_fifo(_path: &Path) -> bool {
+    false
+}
+
+/// `--prompt` value that reads the prompt from stdin
+pub const STDIN_PROMPT: &str = "-";
+
+/// Read a prompt from a regular file or FIFO, dropping one trailing newline
+pub fn read_prompt_file(path: &Path) -> Result<String> {
+    File::open(path)
+        .and_then(read_prompt)
+        .map_err(|e| anyhow!("Failed to read prompt file '{}': {}", path.display(), e))
+}
+
+/// Read a prompt from stdin until EOF, dropping one trailing newline
+pub fn read_prompt_stdin() -> Result<String> {
+    read_prompt(io::stdin().lock()).map_err(|e| anyhow!("Failed to read prompt from stdin: {}", e))
+}
+
+fn read_prompt(mut reader: impl Read) -> io::Result<String> {
+    // Read to EOF rather than trusting the file length, which is 0 for a pipe
+    let mut prompt = String::new();
+    reader.read_to_string(&mut prompt)?;
+
+    if prompt.ends_with('\n') {
+        prompt.pop();
+        if prompt.ends_with('\r') {
+            prompt.pop();
+        }
+    }
+    Ok(prompt)
+}
+
+/// Open a FIFO for streaming output; blocks until a reader opens the other end
+pub fn open_writer(path: &Path) -> Result<BufWriter<File>> {
+    OpenOptions::new()
+        .write(true)
+        .open(path)
+        .map(BufWriter::new)
+        .map_err(|e| anyhow!("Failed to open output pipe '{}': {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_read_prompt_file_regular() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("prompt.txt");
+        fs::write(&path, "Hello\nworld\r\n").unwrap();
+
+        assert_eq!(read_prompt_file(&path).unwrap(), "Hello\nworld");
+        assert!(!is_fifo(&path));
+        assert!(!is_fifo(&dir.path().join("missing")));
+
+        // stdin goes through the same reader
+        assert_eq!(read_prompt(&b"piped prompt\n"[..]).unwrap(), "piped prompt");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fifo_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("prompt.fifo");
+        if !std::process::Command::new("mkfifo").arg(&path).status().is_ok_and(|status| status.success()) {
+            return; // mkfifo unavailable
+        }
+        assert!(is_fifo(&path));
+
+        let writer_path = path.clone();
+        let writer = std::thread::spawn(move || {
+            let mut pipe = open_writer(&writer_path).unwrap();
+            pipe.write_all(b"streamed ").unwrap();
+            pipe.flush().unwrap();
+            pipe.write_all(b"prompt\n").unwrap();
+        });
+
+        assert_eq!(read_prompt_file(&path).unwrap(), "streamed prompt");
+        writer.join().unwrap();
+    }
+}
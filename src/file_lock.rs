@@ -0,0 +1,85 @@
+/*!
+# Output File Locking
+
+Config tasks write their `output_file` under an exclusive advisory lock, so
+two RustLlama processes running configs that share an output path can't
+interleave their writes. By default a locked file is an error; `--lock-wait`
+blocks until the other process is done.
+
+The lock lives on the open file handle, so it is released whenever the handle
+is dropped, including on errors and task cancellation. A task that streams its
+output holds the lock for the whole generation.
+*/
+
+use anyhow::{anyhow, Result};
+use fs2::FileExt;
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Replace (or with `append`, extend) the contents of `path` while holding an exclusive lock on it
+pub fn write_locked(path: &Path, contents: &str, append: bool, wait: bool) -> Result<()> {
+    let mut file = open_locked(path, append, wait)?;
+    file.write_all(contents.as_bytes())?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Open `path` for writing under an exclusive lock, truncated or positioned at the end for
+/// `append`; the lock is held until the file is dropped
+pub fn open_locked(path: &Path, append: bool, wait: bool) -> Result<File> {
+    // Truncate only once the lock is held, so a concurrent writer's output isn't cut short
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(path)
+        .map_err(|e| anyhow!("Failed to open '{}': {}", path.display(), e))?;
+
+    if wait {
+        file.lock_exclusive()
+            .map_err(|e| anyhow!("Failed to lock '{}': {}", path.display(), e))?;
+    } else if let Err(e) = file.try_lock_exclusive() {
+        if e.kind() == fs2::lock_contended_error().kind() {
+            return Err(anyhow!(
+                "'{}' is locked by another process; use --lock-wait to wait for it",
+                path.display()
+            ));
+        }
+        return Err(anyhow!("Failed to lock '{}': {}", path.display(), e));
+    }
+
+    if append {
+        file.seek(SeekFrom::End(0))?;
+    } else {
+        file.set_len(0)?;
+    }
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_write_locked_respects_held_lock() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("output.txt");
+        fs::write(&path, "a much longer earlier output").unwrap();
+
+        let holder = File::open(&path).unwrap();
+        holder.lock_exclusive().unwrap();
+        let err = write_locked(&path, "new", false, false).unwrap_err();
+        assert!(err.to_string().contains("--lock-wait"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a much longer earlier output");
+
+        drop(holder);
+        write_locked(&path, "new", false, false).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+
+        write_locked(&path, " and more", true, false).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new and more");
+    }
+}
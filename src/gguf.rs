@@ -0,0 +1,364 @@
+/*!
+# GGUF Model Information
+
+Helpers for turning the metadata of a GGUF model into a short human-readable
+summary, either from a model llama.cpp has loaded or straight from a file's
+header, which is much cheaper than loading the weights.
+*/
+
+use anyhow::{anyhow, Result};
+use llama_cpp_2::model::LlamaModel;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+/// Magic number at the start of every GGUF file
+const GGUF_MAGIC: &[u8; 4] = b"GGUF";
+
+/// Longest metadata string read, so a corrupt length can't exhaust memory
+const MAX_STRING_LEN: u64 = 64 * 1024 * 1024;
+
+/// Condensed description of a loaded model
+#[derive(Debug, Clone)]
+pub struct ModelSummary {
+    pub name: Option<String>,
+    pub architecture: String,
+    pub parameters: u64,
+    pub quantization: String,
+    pub vocab_size: i32,
+    pub context_length: u32,
+    pub size_bytes: u64,
+}
+
+impl ModelSummary {
+    /// Collect a summary from a loaded model's GGUF metadata
+    pub fn from_model(model: &LlamaModel) -> Self {
+        let quantization = quantization_name(
+            model
+                .meta_val_str("general.file_type")
+                .ok()
+                .and_then(|ftype| ftype.parse::<u32>().ok()),
+        );
+
+        Self {
+            name: model.meta_val_str("general.name").ok(),
+            architecture: model
+                .meta_val_str("general.architecture")
+                .unwrap_or_else(|_| "unknown".to_string()),
+            parameters: model.n_params(),
+            quantization,
+            vocab_size: model.n_vocab(),
+            context_length: model.n_ctx_train(),
+            size_bytes: model.size(),
+        }
+    }
+
+    /// Collect a summary from a GGUF file's header, without loading the model. The size is the
+    /// file's size.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let file = File::open(path).map_err(|e| anyhow!("Failed to open '{}': {}", path.display(), e))?;
+        let size_bytes = file.metadata()?.len();
+        let header = HeaderReader { reader: BufReader::new(file) }
+            .read()
+            .map_err(|e| anyhow!("Failed to read GGUF header of '{}': {}", path.display(), e))?;
+
+        let string = |key: &str| match header.metadata.get(key) {
+            Some(MetaValue::Str(value)) => Some(value.clone()),
+            _ => None,
+        };
+        let uint = |key: &str| match header.metadata.get(key) {
+            Some(MetaValue::Uint(value)) => Some(*value),
+            Some(MetaValue::Int(value)) => u64::try_from(*value).ok(),
+            _ => None,
+        };
+
+        let architecture = string("general.architecture").unwrap_or_else(|| "unknown".to_string());
+        let vocab_size = match header.metadata.get("tokenizer.ggml.tokens") {
+            Some(MetaValue::Array(len)) => Some(*len),
+            _ => uint(&format!("{}.vocab_size", architecture)),
+        };
+
+        Ok(Self {
+            name: string("general.name"),
+            parameters: header.parameters,
+            quantization: quantization_name(uint("general.file_type").and_then(|ftype| u32::try_from(ftype).ok())),
+            vocab_size: vocab_size.unwrap_or(0) as i32,
+            context_length: uint(&format!("{}.context_length", architecture)).unwrap_or(0) as u32,
+            architecture,
+            size_bytes,
+        })
+    }
+}
+
+/// The parts of a GGUF header a summary needs
+struct Header {
+    metadata: HashMap<String, MetaValue>,
+    /// Total elements across all tensors
+    parameters: u64,
+}
+
+/// A metadata value; arrays keep only their length, and floats and bools aren't needed
+enum MetaValue {
+    Uint(u64),
+    Int(i64),
+    Str(String),
+    Array(u64),
+    Other,
+}
+
+/// Reads the little-endian GGUF header: magic, version, counts, metadata, then tensor infos
+struct HeaderReader<R> {
+    reader: R,
+}
+
+impl<R: Read> HeaderReader<R> {
+    fn read(mut self) -> Result<Header> {
+        let mut magic = [0u8; 4];
+        self.reader.read_exact(&mut magic)?;
+        if &magic != GGUF_MAGIC {
+            return Err(anyhow!("not a GGUF file"));
+        }
+        let version = self.u32()?;
+        if version < 2 {
+            return Err(anyhow!("GGUF version {} is not supported", version));
+        }
+
+        let tensor_count = self.u64()?;
+        let metadata_count = self.u64()?;
+
+        let mut metadata = HashMap::new();
+        for _ in 0..metadata_count {
+            let key = self.string()?;
+            let kind = self.u32()?;
+            metadata.insert(key, self.value(kind)?);
+        }
+
+        let mut parameters = 0u64;
+        for _ in 0..tensor_count {
+            self.skip_string()?;
+            let dimensions = self.u32()?;
+            let mut elements = 1u64;
+            for _ in 0..dimensions {
+                elements = elements.saturating_mul(self.u64()?);
+            }
+            // Tensor type and data offset
+            self.skip(4 + 8)?;
+            parameters = parameters.saturating_add(elements);
+        }
+
+        Ok(Header { metadata, parameters })
+    }
+
+    fn value(&mut self, kind: u32) -> Result<MetaValue> {
+        Ok(match kind {
+            0 => MetaValue::Uint(self.bytes::<1>()?[0] as u64),
+            1 => MetaValue::Int(i8::from_le_bytes(self.bytes()?) as i64),
+            2 => MetaValue::Uint(u16::from_le_bytes(self.bytes()?) as u64),
+            3 => MetaValue::Int(i16::from_le_bytes(self.bytes()?) as i64),
+            4 => MetaValue::Uint(self.u32()? as u64),
+            5 => MetaValue::Int(i32::from_le_bytes(self.bytes()?) as i64),
+            8 => MetaValue::Str(self.string()?),
+            9 => {
+                let element_kind = self.u32()?;
+                let len = self.u64()?;
+                self.skip_array(element_kind, len)?;
+                MetaValue::Array(len)
+            }
+            10 => MetaValue::Uint(self.u64()?),
+            11 => MetaValue::Int(i64::from_le_bytes(self.bytes()?)),
+            kind => {
+                self.skip(scalar_size(kind)?)?;
+                MetaValue::Other
+            }
+        })
+    }
+
+    fn skip_array(&mut self, element_kind: u32, len: u64) -> Result<()> {
+        match element_kind {
+            8 => (0..len).try_for_each(|_| self.skip_string()),
+            9 => (0..len).try_for_each(|_| {
+                let element_kind = self.u32()?;
+                let len = self.u64()?;
+                self.skip_array(element_kind, len)
+            }),
+            kind => self.skip(scalar_size(kind)?.saturating_mul(len)),
+        }
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut bytes = [0u8; N];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes()?))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.string_len()?;
+        let mut bytes = vec![0u8; len as usize];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    fn skip_string(&mut self) -> Result<()> {
+        let len = self.string_len()?;
+        self.skip(len)
+    }
+
+    fn string_len(&mut self) -> Result<u64> {
+        let len = self.u64()?;
+        if len > MAX_STRING_LEN {
+            return Err(anyhow!("string of {} bytes is too long", len));
+        }
+        Ok(len)
+    }
+
+    fn skip(&mut self, len: u64) -> Result<()> {
+        let skipped = io::copy(&mut (&mut self.reader).take(len), &mut io::sink())?;
+        if skipped < len {
+            return Err(anyhow!("file ends inside the header"));
+        }
+        Ok(())
+    }
+}
+
+/// Size in bytes of a fixed-size GGUF metadata value
+fn scalar_size(kind: u32) -> Result<u64> {
+    match kind {
+        0 | 1 | 7 => Ok(1),
+        2 | 3 => Ok(2),
+        4..=6 => Ok(4),
+        10..=12 => Ok(8),
+        kind => Err(anyhow!("unknown metadata value type {}", kind)),
+    }
+}
+
+/// Quantization name for a `general.file_type`, or `unknown`
+fn quantization_name(ftype: Option<u32>) -> String {
+    ftype.map_or("unknown", file_type_name).to_string()
+}
+
+/// Name of a llama.cpp `llama_ftype` value as stored in `general.file_type`
+pub fn file_type_name(ftype: u32) -> &'static str {
+    match ftype {
+        0 => "F32",
+        1 => "F16",
+        2 => "Q4_0",
+        3 => "Q4_1",
+        7 => "Q8_0",
+        8 => "Q5_0",
+        9 => "Q5_1",
+        10 => "Q2_K",
+        11 => "Q3_K_S",
+        12 => "Q3_K_M",
+        13 => "Q3_K_L",
+        14 => "Q4_K_S",
+        15 => "Q4_K_M",
+        16 => "Q5_K_S",
+        17 => "Q5_K_M",
+        18 => "Q6_K",
+        19 => "IQ2_XXS",
+        20 => "IQ2_XS",
+        21 => "Q2_K_S",
+        22 => "IQ3_XS",
+        23 => "IQ3_XXS",
+        24 => "IQ1_S",
+        25 => "IQ4_NL",
+        26 => "IQ3_S",
+        27 => "IQ3_M",
+        28 => "IQ2_S",
+        29 => "IQ2_M",
+        30 => "IQ4_XS",
+        31 => "IQ1_M",
+        32 => "BF16",
+        36 => "TQ1_0",
+        37 => "TQ2_0",
+        38 => "MXFP4_MOE",
+        _ => "unknown",
+    }
+}
+
+/// Format a parameter count as e.g. `6.7B` or `125M`
+pub fn format_param_count(parameters: u64) -> String {
+    let n = parameters as f64;
+    if n >= 1e9 {
+        format!("{:.1}B", n / 1e9)
+    } else if n >= 1e6 {
+        format!("{:.0}M", n / 1e6)
+    } else if n >= 1e3 {
+        format!("{:.0}K", n / 1e3)
+    } else {
+        parameters.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_type_name() {
+        assert_eq!(file_type_name(15), "Q4_K_M");
+        assert_eq!(file_type_name(7), "Q8_0");
+        assert_eq!(file_type_name(4), "unknown");
+    }
+
+    #[test]
+    fn test_summary_from_file_header() {
+        let string = |text: &str| [&(text.len() as u64).to_le_bytes()[..], text.as_bytes()].concat();
+        let mut gguf = [&GGUF_MAGIC[..], &3u32.to_le_bytes(), &2u64.to_le_bytes(), &5u64.to_le_bytes()].concat();
+        for (key, kind, value) in [
+            ("general.architecture", 8u32, string("llama")),
+            ("general.file_type", 4, 15u32.to_le_bytes().to_vec()),
+            ("llama.context_length", 4, 4096u32.to_le_bytes().to_vec()),
+            ("llama.rope.freq_base", 6, 10000f32.to_le_bytes().to_vec()),
+            (
+                "tokenizer.ggml.tokens",
+                9,
+                [&8u32.to_le_bytes()[..], &3u64.to_le_bytes(), &string("<s>"), &string("a"), &string("b")].concat(),
+            ),
+        ] {
+            gguf.extend(string(key));
+            gguf.extend(kind.to_le_bytes());
+            gguf.extend(value);
+        }
+        for (name, dims) in [("token_embd.weight", &[4u64, 3][..]), ("output_norm.weight", &[4u64][..])] {
+            gguf.extend(string(name));
+            gguf.extend((dims.len() as u32).to_le_bytes());
+            dims.iter().for_each(|dim| gguf.extend(dim.to_le_bytes()));
+            gguf.extend([0u8; 12]);
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.gguf");
+        std::fs::write(&path, &gguf).unwrap();
+
+        let summary = ModelSummary::from_file(&path).unwrap();
+        assert_eq!(summary.architecture, "llama");
+        assert_eq!(summary.quantization, "Q4_K_M");
+        assert_eq!(summary.context_length, 4096);
+        assert_eq!(summary.vocab_size, 3);
+        assert_eq!(summary.parameters, 16);
+        assert_eq!(summary.size_bytes, gguf.len() as u64);
+        assert_eq!(summary.name, None);
+
+        std::fs::write(&path, &gguf[..gguf.len() - 4]).unwrap();
+        assert!(ModelSummary::from_file(&path).is_err());
+        std::fs::write(&path, b"not a model").unwrap();
+        assert!(ModelSummary::from_file(&path).unwrap_err().to_string().contains("not a GGUF file"));
+    }
+
+    #[test]
+    fn test_format_param_count() {
+        assert_eq!(format_param_count(6_738_415_616), "6.7B");
+        assert_eq!(format_param_count(124_000_000), "124M");
+        assert_eq!(format_param_count(512), "512");
+    }
+}
@@ -0,0 +1,141 @@
+/*!
+# Grammars
+
+`--grammar-file` (`grammar_file` in YAML) constrains generation to a
+[GBNF](https://github.com/ggml-org/llama.cpp/blob/master/grammars/README.md)
+grammar, llama.cpp's BNF dialect. Each step, tokens that can't continue a
+string the grammar accepts are masked before sampling, so the output is
+guaranteed to match it:
+
+```text
+root   ::= answer ("," answer)*
+answer ::= "yes" | "no" | [0-9]+
+```
+
+The masking itself is done by llama.cpp. Grammars are checked here first,
+when they are loaded, so that a typo is reported with a clear error instead of
+llama.cpp's log output: there must be a `root` rule, every rule that is
+referenced must be defined, and strings, character classes and parentheses
+must be closed.
+*/
+
+use anyhow::{anyhow, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// The rule generation starts from
+pub const ROOT_RULE: &str = "root";
+
+/// Read a GBNF grammar from `path` and check it
+pub fn load_grammar(path: &Path) -> Result<String> {
+    let grammar = fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read grammar file '{}': {}", path.display(), e))?;
+    check_grammar(&grammar).map_err(|e| anyhow!("Invalid grammar in '{}': {}", path.display(), e))?;
+    Ok(grammar)
+}
+
+/// Check that `grammar` is well-formed GBNF with a `root` rule and no undefined rules
+pub fn check_grammar(grammar: &str) -> Result<()> {
+    let mut defined = HashSet::new();
+    let mut referenced = Vec::new();
+    let mut depth = 0usize;
+
+    let mut chars = grammar.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '#' => {
+                while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+            }
+            '"' => skip_delimited(&mut chars, '"').ok_or_else(|| anyhow!("unterminated string at byte {}", start))?,
+            '[' => skip_delimited(&mut chars, ']')
+                .ok_or_else(|| anyhow!("unterminated character class at byte {}", start))?,
+            '(' => depth += 1,
+            ')' => {
+                depth = depth
+                    .checked_sub(1)
+                    .ok_or_else(|| anyhow!("unmatched ')' at byte {}", start))?;
+            }
+            '{' => {
+                while chars.next_if(|&(_, c)| c.is_ascii_digit() || c == ',' || c == ' ').is_some() {}
+                if chars.next().map(|(_, c)| c) != Some('}') {
+                    return Err(anyhow!("malformed repetition at byte {}", start));
+                }
+            }
+            c if is_name_char(c) => {
+                let mut end = start + c.len_utf8();
+                while let Some((i, c)) = chars.next_if(|&(_, c)| is_name_char(c)) {
+                    end = i + c.len_utf8();
+                }
+                let name = &grammar[start..end];
+                if grammar[end..].trim_start_matches([' ', '\t']).starts_with("::=") {
+                    if depth > 0 {
+                        return Err(anyhow!("rule '{}' is defined inside parentheses", name));
+                    }
+                    defined.insert(name);
+                    while chars.next_if(|&(_, c)| c != '=').is_some() {}
+                    chars.next();
+                } else {
+                    referenced.push(name);
+                }
+            }
+            '|' | '*' | '+' | '?' | '.' => {}
+            c if c.is_whitespace() => {}
+            c => return Err(anyhow!("unexpected '{}' at byte {}", c, start)),
+        }
+    }
+
+    if depth > 0 {
+        return Err(anyhow!("unclosed '('"));
+    }
+    if !defined.contains(ROOT_RULE) {
+        return Err(anyhow!("no '{}' rule; generation starts from 'root ::= ...'", ROOT_RULE));
+    }
+    if let Some(name) = referenced.iter().find(|name| !defined.contains(*name)) {
+        return Err(anyhow!("rule '{}' is used but never defined", name));
+    }
+    Ok(())
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+/// Advance past the closing `end`, honoring backslash escapes; `None` if it never comes
+fn skip_delimited(chars: &mut impl Iterator<Item = (usize, char)>, end: char) -> Option<()> {
+    while let Some((_, c)) = chars.next() {
+        if c == '\\' {
+            chars.next()?;
+        } else if c == end {
+            return Some(());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_grammar() {
+        // Only digits
+        assert!(check_grammar("root ::= [0-9]+\n").is_ok());
+
+        let grammar = r##"
+# yes/no answers or numbers, comma-separated
+root   ::= answer ("," answer)*
+answer ::= "yes" | "no" | "\"#]" | number
+number ::= [0-9]{1,3} | [^\]a-z]
+"##;
+        assert!(check_grammar(grammar).is_ok());
+
+        let error = |grammar: &str| check_grammar(grammar).unwrap_err().to_string();
+        assert!(error("digits ::= [0-9]+").contains("no 'root' rule"));
+        assert!(error("root ::= digit+").contains("'digit' is used but never defined"));
+        assert!(error("root ::= \"yes").contains("unterminated string"));
+        assert!(error("root ::= [0-9").contains("unterminated character class"));
+        assert!(error("root ::= (\"a\" | \"b\"").contains("unclosed"));
+        assert!(error("root ::= \"a\" ; \"b\"").contains("unexpected ';'"));
+    }
+}
@@ -0,0 +1,73 @@
+/*!
+# Post-Generation Hooks
+
+A hook is a shell command run after a generation completes. It receives the
+result through environment variables:
+
+- `RUSTLAMA_OUTPUT_FILE`: path the output was saved to (empty if none)
+- `RUSTLAMA_TOKENS`: number of tokens generated
+- `RUSTLAMA_DURATION`: wall-clock time of the run in seconds
+
+A hook that can't be started or exits with a non-zero status is an error.
+*/
+
+use anyhow::{anyhow, Result};
+use std::process::Command;
+use std::time::Duration;
+
+/// Run a hook command through the platform shell with the generation results in its environment
+pub fn run_post_hook(
+    command: &str,
+    output_file: Option<&str>,
+    tokens_generated: usize,
+    duration: Duration,
+) -> Result<()> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+
+    let status = shell
+        .arg(command)
+        .env("RUSTLAMA_OUTPUT_FILE", output_file.unwrap_or_default())
+        .env("RUSTLAMA_TOKENS", tokens_generated.to_string())
+        .env("RUSTLAMA_DURATION", format!("{:.3}", duration.as_secs_f64()))
+        .status()
+        .map_err(|e| anyhow!("Failed to run post hook '{}': {}", command, e))?;
+
+    if !status.success() {
+        return Err(anyhow!("Post hook '{}' failed with {}", command, status));
+    }
+
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_post_hook_receives_environment() -> Result<()> {
+        let dir = TempDir::new()?;
+        let record = dir.path().join("hook.txt");
+        let command = format!(
+            "echo \"$RUSTLAMA_OUTPUT_FILE $RUSTLAMA_TOKENS $RUSTLAMA_DURATION\" > {}",
+            record.display()
+        );
+
+        run_post_hook(&command, Some("out.txt"), 42, Duration::from_millis(1500))?;
+        assert_eq!(std::fs::read_to_string(&record)?.trim(), "out.txt 42 1.500");
+        Ok(())
+    }
+
+    #[test]
+    fn test_post_hook_failure_is_error() {
+        assert!(run_post_hook("exit 3", None, 0, Duration::ZERO).is_err());
+    }
+}
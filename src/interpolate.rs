@@ -0,0 +1,158 @@
+/*!
+# Variable Interpolation
+
+Config files can reference variables instead of hard-coding secrets and
+machine-specific paths:
+
+```yaml
+environment:
+  MODELS: "${HOME}/models"
+
+tasks:
+  - name: "summary"
+    model: "${MODELS}/llama-2-7b-chat.Q4_K_M.gguf"
+    prompt: "Summarize ${TOPIC:-the news}"
+    output_file: "${OUT_DIR}/summary.txt"
+```
+
+`${VAR}` is replaced by the variable's value and `${VAR:-default}` falls back
+to `default` when the variable is unset or empty. A variable that is neither
+set nor given a default is an error. `$${` produces a literal `${`, and a `$`
+not followed by `{` is left alone.
+
+Prompts can also use `{{name}}` placeholders, filled in from the config's
+top-level `variables` section after `${VAR}` substitution, so near-identical
+prompts can share one template. Only `{{` followed by a name made of letters,
+digits, `_`, `-` and `.` and then `}}` is a placeholder, with spaces allowed
+around the name; anything else is left as written. `\{{` produces a literal
+`{{`.
+*/
+
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+
+/// Substitute every `${VAR}` and `${VAR:-default}` in `text`, looking names up with `lookup`
+pub fn interpolate(text: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        if let Some(escaped) = after.strip_prefix("${") {
+            result.push_str("${");
+            rest = escaped;
+        } else if let Some(body) = after.strip_prefix('{') {
+            let end = body
+                .find('}')
+                .ok_or_else(|| anyhow!("Unterminated '${{' in '{}'", text))?;
+            let expression = &body[..end];
+            let (name, default) = match expression.split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (expression, None),
+            };
+            if name.is_empty() {
+                return Err(anyhow!("Empty variable name in '{}'", text));
+            }
+
+            let value = match (lookup(name).filter(|value| !value.is_empty()), default) {
+                (Some(value), _) => value,
+                (None, Some(default)) => default.to_string(),
+                (None, None) => {
+                    return Err(anyhow!(
+                        "Undefined variable '{}' (set it in the environment or the config's environment section, or use ${{{}:-default}})",
+                        name,
+                        name
+                    ))
+                }
+            };
+            result.push_str(&value);
+            rest = &body[end + 1..];
+        } else {
+            result.push('$');
+            rest = after;
+        }
+    }
+
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Fill in every `{{name}}` placeholder in `text` from `variables`
+pub fn render_template(text: &str, variables: &HashMap<String, String>) -> Result<String> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        if let Some(before) = rest[..start].strip_suffix('\\') {
+            result.push_str(before);
+            result.push_str("{{");
+            rest = after;
+            continue;
+        }
+        result.push_str(&rest[..start]);
+
+        let name = after.find("}}").map(|end| (after[..end].trim(), end));
+        match name {
+            Some((name, end)) if is_template_name(name) => {
+                let value = variables.get(name).ok_or_else(|| {
+                    anyhow!("Undefined template variable '{}' (define it in the config's variables section)", name)
+                })?;
+                result.push_str(value);
+                rest = &after[end + 2..];
+            }
+            _ => {
+                result.push_str("{{");
+                rest = after;
+            }
+        }
+    }
+
+    result.push_str(rest);
+    Ok(result)
+}
+
+fn is_template_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpolate() {
+        let lookup = |name: &str| match name {
+            "HOME" => Some("/home/me".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+
+        assert_eq!(interpolate("${HOME}/models/a.gguf", lookup).unwrap(), "/home/me/models/a.gguf");
+        assert_eq!(interpolate("about ${TOPIC:-cats} and ${EMPTY:-dogs}", lookup).unwrap(), "about cats and dogs");
+        assert_eq!(interpolate("${HOME:-/tmp}", lookup).unwrap(), "/home/me");
+        assert_eq!(interpolate("costs $5, literally $${HOME}", lookup).unwrap(), "costs $5, literally ${HOME}");
+        assert_eq!(interpolate("no variables", lookup).unwrap(), "no variables");
+
+        assert!(interpolate("${MISSING}", lookup).unwrap_err().to_string().contains("'MISSING'"));
+        assert!(interpolate("${HOME", lookup).is_err());
+        assert!(interpolate("${}", lookup).is_err());
+    }
+
+    #[test]
+    fn test_render_template() {
+        let variables = HashMap::from([
+            ("noun".to_string(), "cats".to_string()),
+            ("tone.style".to_string(), "formal".to_string()),
+        ]);
+
+        assert_eq!(render_template("About {{noun}}, {{ tone.style }}", &variables).unwrap(), "About cats, formal");
+        assert_eq!(render_template("fn f() {{ }} {x}", &variables).unwrap(), "fn f() {{ }} {x}");
+        assert_eq!(render_template("\\{{noun}} {{noun", &variables).unwrap(), "{{noun}} {{noun");
+
+        let err = render_template("About {{topic}}", &variables).unwrap_err().to_string();
+        assert!(err.contains("'topic'"));
+    }
+}
@@ -0,0 +1,59 @@
+/*!
+# Interrupts
+
+Ctrl-C while a completion is being generated stops generation at the next
+token instead of killing the process: the partial text is flushed and written
+to `--output-file`, `--stats` are printed, remaining config tasks are skipped
+and the process exits with status 130. A second Ctrl-C, or one while nothing
+is being generated (loading a model, downloading, waiting on stdin), exits
+right away, after resetting the terminal's colors.
+*/
+
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Exit status of a process stopped by SIGINT
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static GENERATING: AtomicUsize = AtomicUsize::new(0);
+
+/// Handle Ctrl-C for the rest of the process; must be called inside the Tokio runtime
+pub fn install() {
+    tokio::spawn(async {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if GENERATING.load(Ordering::SeqCst) == 0 || INTERRUPTED.swap(true, Ordering::SeqCst) {
+                reset_terminal();
+                std::process::exit(EXIT_INTERRUPTED);
+            }
+        }
+    });
+}
+
+/// Whether Ctrl-C was pressed during generation
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Marks generation as in progress until dropped, so Ctrl-C stops it instead of exiting
+pub struct Generating(());
+
+pub fn generating() -> Generating {
+    GENERATING.fetch_add(1, Ordering::SeqCst);
+    Generating(())
+}
+
+impl Drop for Generating {
+    fn drop(&mut self) {
+        GENERATING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Clear any color left active by output cut off mid-sequence
+fn reset_terminal() {
+    let mut stdout = io::stdout();
+    if stdout.is_terminal() {
+        let _ = writeln!(stdout, "\x1b[0m");
+        let _ = stdout.flush();
+    }
+}
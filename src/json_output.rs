@@ -0,0 +1,121 @@
+/*!
+# JSON Output
+
+`rustlama run --format json` prints a single JSON object once generation is
+done instead of streaming colored text, so the result can be piped straight
+into `jq`:
+
+```text
+{"prompt":"The capital of France is","text":" Paris.","tokens_generated":3,"elapsed_secs":0.21,"tokens_per_sec":14.3,"sampling":{"seed":42,...}}
+```
+
+With `--temp-range` the objects of the sweep are printed together as one
+array, each with the `temperature` it was generated at:
+
+```text
+[{"temperature":0.2,"prompt":"The capital of France is","text":" Paris.",...},{"temperature":0.7,...}]
+```
+
+Nothing else is written to stdout; warnings still go to stderr. `prompt` is
+the text the model saw, i.e. after any chat template was applied.
+*/
+
+use crate::{tokens_per_sec, Generation, RunConfig};
+use anyhow::Result;
+use serde::Serialize;
+use std::time::Duration;
+
+/// The object printed by `run --format json`
+#[derive(Debug, Serialize)]
+pub struct RunOutput<'a> {
+    pub prompt: &'a str,
+    pub text: &'a str,
+    pub tokens_generated: usize,
+    pub elapsed_secs: f64,
+    pub tokens_per_sec: f64,
+    /// Cumulative log-probability of the winning beam (only with `--beams`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub beam_score: Option<f64>,
+    pub sampling: SamplingSettings<'a>,
+    /// Each generated piece with its log-probability (only with `--show-probs`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_log_probs: Option<Vec<TokenLogProb<'a>>>,
+}
+
+/// One completion of a `--temp-range` sweep
+#[derive(Debug, Serialize)]
+pub struct SweepOutput<'a> {
+    pub temperature: f32,
+    #[serde(flatten)]
+    pub output: RunOutput<'a>,
+}
+
+/// The sampling parameters a run actually used, including the settled seed
+#[derive(Debug, Serialize)]
+pub struct SamplingSettings<'a> {
+    pub seed: Option<u64>,
+    pub max_tokens: usize,
+    pub min_tokens: usize,
+    pub temperature: f32,
+    pub dynatemp_range: f32,
+    pub top_k: usize,
+    pub top_p: f32,
+    pub repeat_penalty: f32,
+    pub repeat_last_n: usize,
+    pub best_of: usize,
+    pub beams: usize,
+    pub stop: &'a [String],
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenLogProb<'a> {
+    pub text: &'a str,
+    pub log_prob: f32,
+}
+
+impl<'a> RunOutput<'a> {
+    /// Describe the winning generation of a run
+    pub fn new(
+        generation: &'a Generation,
+        tokens_generated: usize,
+        duration: Duration,
+        beam_score: Option<f64>,
+        cli: &'a RunConfig,
+    ) -> Self {
+        let elapsed_secs = duration.as_secs_f64();
+        Self {
+            prompt: &cli.prompt,
+            text: &generation.text,
+            tokens_generated,
+            elapsed_secs,
+            tokens_per_sec: tokens_per_sec(tokens_generated, duration),
+            beam_score,
+            sampling: SamplingSettings {
+                seed: cli.seed,
+                max_tokens: cli.max_tokens,
+                min_tokens: cli.min_tokens,
+                temperature: cli.temperature,
+                dynatemp_range: cli.dynatemp_range,
+                top_k: cli.top_k,
+                top_p: cli.top_p,
+                repeat_penalty: cli.repeat_penalty,
+                repeat_last_n: cli.repeat_last_n,
+                best_of: cli.best_of,
+                beams: cli.beams,
+                stop: &cli.stop,
+            },
+            token_log_probs: cli.show_probs.then(|| {
+                generation
+                    .token_log_probs
+                    .iter()
+                    .map(|(text, log_prob)| TokenLogProb { text, log_prob: *log_prob })
+                    .collect()
+            }),
+        }
+    }
+
+    /// Serialize as one line
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+}
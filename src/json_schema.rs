@@ -0,0 +1,429 @@
+/*!
+# JSON Schema
+
+`--json-schema` (`json_schema` in YAML) makes the output a JSON document that
+conforms to a [JSON Schema](https://json-schema.org/). The schema is converted
+into a GBNF grammar and applied the same way as `--grammar-file`:
+
+```json
+{
+  "type": "object",
+  "properties": {
+    "name": { "type": "string" },
+    "age": { "type": "integer" },
+    "tags": { "type": "array", "items": { "enum": ["admin", "user"] } }
+  },
+  "required": ["name"]
+}
+```
+
+Supported are `type` (including lists of types), `properties` and `required`,
+`items`, `enum`, `const`, `anyOf`/`oneOf` and local `$ref`s (`#/$defs/...`).
+Properties are generated in the order the schema lists them, required ones
+first. Length and range limits (`minLength`, `maxItems`, `minimum`, ...) can't
+be expressed in the grammar; they are only checked by [`validate`], which runs
+on the finished text and reports anything the grammar let through.
+*/
+
+use crate::grammar::ROOT_RULE;
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// Rules for arbitrary JSON, shared by every converted schema
+const JSON_RULES: &str = r#"value   ::= object | array | string | number | boolean | null
+object  ::= "{" ws ( string ":" ws value ( "," ws string ":" ws value )* )? "}" ws
+array   ::= "[" ws ( value ( "," ws value )* )? "]" ws
+string  ::= "\"" ( [^"\\\x7F\x00-\x1F] | "\\" ( ["\\/bfnrt] | "u" [0-9a-fA-F]{4} ) )* "\"" ws
+number  ::= integer ( "." [0-9]+ )? ( [eE] [-+]? [0-9]+ )? ws
+integer ::= "-"? ( "0" | [1-9] [0-9]{0,15} ) ws
+boolean ::= ( "true" | "false" ) ws
+null    ::= "null" ws
+ws      ::= | " " | "\n" [ \t]{0,20}
+"#;
+
+/// Names of the rules in [`JSON_RULES`], which generated rules must not reuse
+const JSON_RULE_NAMES: [&str; 9] = ["value", "object", "array", "string", "number", "integer", "boolean", "null", "ws"];
+
+/// Read a JSON Schema from `path`
+pub fn load_schema(path: &Path) -> Result<Value> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read JSON schema '{}': {}", path.display(), e))?;
+    serde_json::from_str(&contents).map_err(|e| anyhow!("Invalid JSON schema '{}': {}", path.display(), e))
+}
+
+/// Convert `schema` into a GBNF grammar whose `root` rule matches the conforming documents
+pub fn schema_to_grammar(schema: &Value) -> Result<String> {
+    let mut converter = Converter {
+        root: schema,
+        rules: Vec::new(),
+        names: HashSet::new(),
+        refs: HashMap::new(),
+    };
+    converter.rule(ROOT_RULE, schema)?;
+
+    let mut grammar = String::new();
+    for (name, expr) in &converter.rules {
+        grammar.push_str(&format!("{} ::= {}\n", name, expr));
+    }
+    grammar.push_str(JSON_RULES);
+    Ok(grammar)
+}
+
+/// Check generated `text` against `schema`, naming the first place it doesn't conform
+pub fn validate(schema: &Value, text: &str) -> Result<()> {
+    let instance: Value = serde_json::from_str(text.trim()).map_err(|e| anyhow!("not valid JSON: {}", e))?;
+    check(schema, schema, &instance, "")
+}
+
+struct Converter<'a> {
+    root: &'a Value,
+    /// Rules in the order they were added, `root` first
+    rules: Vec<(String, String)>,
+    names: HashSet<String>,
+    /// `$ref` targets already converted, so recursive schemas terminate
+    refs: HashMap<String, String>,
+}
+
+impl Converter<'_> {
+    /// Add a rule for `schema` named after `name` (made unique) and return its name
+    fn rule(&mut self, name: &str, schema: &Value) -> Result<String> {
+        let name = self.unique_name(name);
+        let index = self.rules.len();
+        self.rules.push((name.clone(), String::new()));
+        self.rules[index].1 = self.expr(schema, &name)?;
+        Ok(name)
+    }
+
+    fn unique_name(&mut self, name: &str) -> String {
+        let base: String = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '-' })
+            .collect();
+        let mut name = base.clone();
+        let mut n = 1;
+        while JSON_RULE_NAMES.contains(&name.as_str()) || !self.names.insert(name.clone()) {
+            n += 1;
+            name = format!("{}{}", base, n);
+        }
+        name
+    }
+
+    fn expr(&mut self, schema: &Value, name: &str) -> Result<String> {
+        let schema = match schema {
+            Value::Bool(true) => return Ok("value".to_string()),
+            Value::Bool(false) => return Err(anyhow!("schema 'false' at '{}' matches nothing", name)),
+            Value::Object(schema) => schema,
+            _ => return Err(anyhow!("schema at '{}' must be an object or a boolean", name)),
+        };
+
+        for keyword in ["allOf", "not", "if", "patternProperties"] {
+            if schema.contains_key(keyword) {
+                return Err(anyhow!("'{}' is not supported (at '{}')", keyword, name));
+            }
+        }
+
+        if let Some(reference) = schema.get("$ref") {
+            let reference = reference.as_str().ok_or_else(|| anyhow!("'$ref' must be a string"))?;
+            return self.reference(reference);
+        }
+        if let Some(value) = schema.get("const") {
+            return Ok(format!("{} ws", literal(value)));
+        }
+        if let Some(values) = schema.get("enum") {
+            let values = values.as_array().ok_or_else(|| anyhow!("'enum' at '{}' must be an array", name))?;
+            if values.is_empty() {
+                return Err(anyhow!("empty 'enum' at '{}' matches nothing", name));
+            }
+            let alternatives: Vec<String> = values.iter().map(literal).collect();
+            return Ok(format!("( {} ) ws", alternatives.join(" | ")));
+        }
+        if let Some(options) = schema.get("anyOf").or_else(|| schema.get("oneOf")) {
+            let options = options.as_array().ok_or_else(|| anyhow!("'anyOf' at '{}' must be an array", name))?;
+            let alternatives = options
+                .iter()
+                .enumerate()
+                .map(|(i, option)| self.rule(&format!("{}-{}", name, i), option))
+                .collect::<Result<Vec<_>>>()?;
+            return Ok(format!("( {} )", alternatives.join(" | ")));
+        }
+
+        match schema.get("type") {
+            Some(Value::String(kind)) => self.type_expr(kind, schema, name),
+            Some(Value::Array(kinds)) => {
+                let alternatives = kinds
+                    .iter()
+                    .map(|kind| {
+                        let kind = kind.as_str().ok_or_else(|| anyhow!("'type' at '{}' must list strings", name))?;
+                        self.type_expr(kind, schema, name)
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(format!("( {} )", alternatives.join(" | ")))
+            }
+            Some(_) => Err(anyhow!("'type' at '{}' must be a string or a list of strings", name)),
+            None if schema.contains_key("properties") => self.type_expr("object", schema, name),
+            None if schema.contains_key("items") => self.type_expr("array", schema, name),
+            None => Ok("value".to_string()),
+        }
+    }
+
+    fn type_expr(&mut self, kind: &str, schema: &serde_json::Map<String, Value>, name: &str) -> Result<String> {
+        match kind {
+            "string" | "number" | "integer" | "boolean" | "null" => Ok(kind.to_string()),
+            "array" => match schema.get("items") {
+                Some(items) => {
+                    let item = self.rule(&format!("{}-item", name), items)?;
+                    Ok(format!("\"[\" ws ( {item} ( \",\" ws {item} )* )? \"]\" ws"))
+                }
+                None => Ok("array".to_string()),
+            },
+            "object" => match schema.get("properties").and_then(Value::as_object) {
+                Some(properties) => self.object_expr(properties, schema.get("required"), name),
+                None => Ok("object".to_string()),
+            },
+            _ => Err(anyhow!("unknown type '{}' at '{}'", kind, name)),
+        }
+    }
+
+    /// `{` required properties, then each optional property in order, `}`
+    fn object_expr(
+        &mut self,
+        properties: &serde_json::Map<String, Value>,
+        required: Option<&Value>,
+        name: &str,
+    ) -> Result<String> {
+        let required: HashSet<&str> = required
+            .and_then(Value::as_array)
+            .map(|names| names.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+
+        let mut mandatory = Vec::new();
+        let mut optional = Vec::new();
+        for (key, property) in properties {
+            let rule = self.rule(&format!("{}-{}", name, key), property)?;
+            let pair = format!("{} ws \":\" ws {}", literal(&Value::String(key.clone())), rule);
+            if required.contains(key.as_str()) {
+                mandatory.push(pair);
+            } else {
+                optional.push(pair);
+            }
+        }
+
+        let optional_after = |start: usize| -> String {
+            optional[start..]
+                .iter()
+                .map(|pair| format!(" ( \",\" ws {} )?", pair))
+                .collect()
+        };
+        let body = if !mandatory.is_empty() {
+            format!("{}{}", mandatory.join(" \",\" ws "), optional_after(0))
+        } else if !optional.is_empty() {
+            // Without a required property, whichever optional one comes first has no leading comma
+            let firsts: Vec<String> = optional
+                .iter()
+                .enumerate()
+                .map(|(i, pair)| format!("{}{}", pair, optional_after(i + 1)))
+                .collect();
+            format!("( {} )?", firsts.join(" | "))
+        } else {
+            String::new()
+        };
+        Ok(format!("\"{{\" ws {} \"}}\" ws", body))
+    }
+
+    fn reference(&mut self, reference: &str) -> Result<String> {
+        if let Some(name) = self.refs.get(reference) {
+            return Ok(name.clone());
+        }
+        let target = resolve(self.root, reference)?;
+        let name = format!("ref-{}", reference.rsplit('/').next().unwrap_or_default());
+        let name = self.unique_name(&name);
+        self.refs.insert(reference.to_string(), name.clone());
+
+        let index = self.rules.len();
+        self.rules.push((name.clone(), String::new()));
+        self.rules[index].1 = self.expr(target, &name)?;
+        Ok(name)
+    }
+}
+
+/// A GBNF string literal matching `value` serialized as JSON
+fn literal(value: &Value) -> String {
+    let json = value.to_string();
+    format!("\"{}\"", json.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Look up a local `$ref` such as `#/$defs/address`
+fn resolve<'a>(root: &'a Value, reference: &str) -> Result<&'a Value> {
+    let pointer = reference
+        .strip_prefix('#')
+        .ok_or_else(|| anyhow!("only local '$ref's are supported, not '{}'", reference))?;
+    root.pointer(pointer)
+        .ok_or_else(|| anyhow!("'$ref' '{}' doesn't point into the schema", reference))
+}
+
+fn check(root: &Value, schema: &Value, instance: &Value, path: &str) -> Result<()> {
+    let at = || if path.is_empty() { "/".to_string() } else { path.to_string() };
+    let schema = match schema {
+        Value::Bool(true) => return Ok(()),
+        Value::Bool(false) => return Err(anyhow!("{}: no value is allowed here", at())),
+        Value::Object(schema) => schema,
+        _ => return Ok(()),
+    };
+
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        return check(root, resolve(root, reference)?, instance, path);
+    }
+    if let Some(value) = schema.get("const") {
+        if value != instance {
+            return Err(anyhow!("{}: expected {}", at(), value));
+        }
+    }
+    if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+        if !values.contains(instance) {
+            return Err(anyhow!("{}: {} is not one of the allowed values", at(), instance));
+        }
+    }
+    if let Some(options) = schema.get("anyOf").and_then(Value::as_array) {
+        if !options.iter().any(|option| check(root, option, instance, path).is_ok()) {
+            return Err(anyhow!("{}: matches none of 'anyOf'", at()));
+        }
+    }
+    if let Some(options) = schema.get("oneOf").and_then(Value::as_array) {
+        let matches = options.iter().filter(|option| check(root, option, instance, path).is_ok()).count();
+        if matches != 1 {
+            return Err(anyhow!("{}: matches {} of 'oneOf' instead of exactly one", at(), matches));
+        }
+    }
+
+    let kinds: Vec<&str> = match schema.get("type") {
+        Some(Value::String(kind)) => vec![kind.as_str()],
+        Some(Value::Array(kinds)) => kinds.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    if !kinds.is_empty() && !kinds.iter().any(|kind| has_type(instance, kind)) {
+        return Err(anyhow!("{}: expected {}, got {}", at(), kinds.join(" or "), instance));
+    }
+
+    let limit = |keyword: &str| schema.get(keyword).and_then(Value::as_f64);
+    match instance {
+        Value::String(text) => {
+            let length = text.chars().count() as f64;
+            if limit("minLength").is_some_and(|min| length < min) || limit("maxLength").is_some_and(|max| length > max)
+            {
+                return Err(anyhow!("{}: string length {} is out of range", at(), length));
+            }
+        }
+        Value::Number(number) => {
+            let number = number.as_f64().unwrap_or_default();
+            if limit("minimum").is_some_and(|min| number < min) || limit("maximum").is_some_and(|max| number > max) {
+                return Err(anyhow!("{}: {} is out of range", at(), number));
+            }
+        }
+        Value::Array(items) => {
+            let length = items.len() as f64;
+            if limit("minItems").is_some_and(|min| length < min) || limit("maxItems").is_some_and(|max| length > max) {
+                return Err(anyhow!("{}: {} items is out of range", at(), items.len()));
+            }
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    check(root, item_schema, item, &format!("{}/{}", path, i))?;
+                }
+            }
+        }
+        Value::Object(object) => {
+            for key in schema.get("required").and_then(Value::as_array).into_iter().flatten() {
+                if let Some(key) = key.as_str().filter(|key| !object.contains_key(*key)) {
+                    return Err(anyhow!("{}: missing required property '{}'", at(), key));
+                }
+            }
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for (key, value) in object {
+                match properties.and_then(|properties| properties.get(key)) {
+                    Some(property) => check(root, property, value, &format!("{}/{}", path, key))?,
+                    None if schema.get("additionalProperties") == Some(&Value::Bool(false)) => {
+                        return Err(anyhow!("{}: unexpected property '{}'", at(), key));
+                    }
+                    None => {}
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn has_type(instance: &Value, kind: &str) -> bool {
+    match kind {
+        "string" => instance.is_string(),
+        "number" => instance.is_number(),
+        "integer" => instance.is_i64() || instance.is_u64() || instance.as_f64().is_some_and(|n| n.fract() == 0.0),
+        "boolean" => instance.is_boolean(),
+        "null" => instance.is_null(),
+        "array" => instance.is_array(),
+        "object" => instance.is_object(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::check_grammar;
+    use serde_json::json;
+
+    fn person_schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string", "maxLength": 10 },
+                "age": { "type": ["integer", "null"] },
+                "role": { "enum": ["admin", "user"] },
+                "address": { "$ref": "#/$defs/address" }
+            },
+            "required": ["name", "role"],
+            "$defs": {
+                "address": {
+                    "type": "object",
+                    "properties": {
+                        "city": { "type": "string" },
+                        "lines": { "type": "array", "items": { "type": "string" } }
+                    }
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn test_schema_to_grammar() {
+        let grammar = schema_to_grammar(&person_schema()).unwrap();
+        check_grammar(&grammar).unwrap();
+        assert!(grammar
+            .starts_with("root ::= \"{\" ws \"\\\"name\\\"\" ws \":\" ws root-name \",\" ws \"\\\"role\\\"\""));
+        assert!(grammar.contains("root-role ::= ( \"\\\"admin\\\"\" | \"\\\"user\\\"\" ) ws\n"));
+        assert!(grammar.contains("root-address ::= ref-address\n"));
+        // Neither property of the address is required, so either may come first
+        assert!(grammar.contains("ref-address ::= \"{\" ws ( \"\\\"city\\\"\""));
+
+        check_grammar(&schema_to_grammar(&json!({})).unwrap()).unwrap();
+        check_grammar(&schema_to_grammar(&json!({"type": "array", "items": {"const": 1}})).unwrap()).unwrap();
+        assert!(schema_to_grammar(&json!({"allOf": []})).unwrap_err().to_string().contains("'allOf'"));
+        assert!(schema_to_grammar(&json!({"$ref": "#/$defs/missing"})).is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let schema = person_schema();
+        validate(&schema, r#"{"name": "Ada", "role": "admin", "address": {"lines": ["1 Main St"]}}"#).unwrap();
+        validate(&schema, "{\"name\": \"Ada\", \"age\": null, \"role\": \"user\"}\n").unwrap();
+
+        let error = |text: &str| validate(&schema, text).unwrap_err().to_string();
+        assert!(error(r#"{"name": "Ada""#).contains("not valid JSON"));
+        assert!(error(r#"{"name": "Ada"}"#).contains("missing required property 'role'"));
+        assert!(error(r#"{"name": "Ada", "role": "guest"}"#).contains("not one of the allowed values"));
+        assert!(error(r#"{"name": "Ada Lovelace Byron", "role": "user"}"#).contains("/name"));
+        assert!(error(r#"{"name": "Ada", "role": "user", "address": {"lines": [1]}}"#).contains("/address/lines/0"));
+    }
+}
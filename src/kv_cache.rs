@@ -0,0 +1,78 @@
+/*!
+# KV Cache Types
+
+The KV cache defaults to f16. Quantized types (q8_0, q4_0, ...) trade a little
+accuracy for a much smaller cache, which allows longer contexts in the same
+memory.
+*/
+
+use anyhow::{anyhow, Result};
+use llama_cpp_2::context::params::KvCacheType;
+
+/// Cache types accepted by `--kv-cache-type-k` / `--kv-cache-type-v`
+pub const SUPPORTED_KV_CACHE_TYPES: &[&str] = &[
+    "f32", "f16", "bf16", "q8_0", "q4_0", "q4_1", "iq4_nl", "q5_0", "q5_1",
+];
+
+/// Map a cache type name to the llama.cpp cache type
+pub fn parse_kv_cache_type(name: &str) -> Result<KvCacheType> {
+    match name.to_lowercase().as_str() {
+        "f32" => Ok(KvCacheType::F32),
+        "f16" => Ok(KvCacheType::F16),
+        "bf16" => Ok(KvCacheType::BF16),
+        "q8_0" => Ok(KvCacheType::Q8_0),
+        "q4_0" => Ok(KvCacheType::Q4_0),
+        "q4_1" => Ok(KvCacheType::Q4_1),
+        "iq4_nl" => Ok(KvCacheType::IQ4_NL),
+        "q5_0" => Ok(KvCacheType::Q5_0),
+        "q5_1" => Ok(KvCacheType::Q5_1),
+        _ => Err(anyhow!(
+            "Unsupported KV cache type '{}': must be one of: {}",
+            name,
+            SUPPORTED_KV_CACHE_TYPES.join(", ")
+        )),
+    }
+}
+
+/// Storage cost of one cache element in bits, including block scales
+pub fn bits_per_element(name: &str) -> Option<f32> {
+    match name.to_lowercase().as_str() {
+        "f32" => Some(32.0),
+        "f16" | "bf16" => Some(16.0),
+        "q8_0" => Some(8.5),
+        "q4_0" | "iq4_nl" => Some(4.5),
+        "q4_1" => Some(5.0),
+        "q5_0" => Some(5.5),
+        "q5_1" => Some(6.0),
+        _ => None,
+    }
+}
+
+/// Estimated fraction of memory saved versus an f16 K and V cache
+pub fn savings_vs_f16(type_k: &str, type_v: &str) -> Option<f32> {
+    let bits = bits_per_element(type_k)? + bits_per_element(type_v)?;
+    Some(1.0 - bits / 32.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_supported_types_parse() {
+        for name in SUPPORTED_KV_CACHE_TYPES {
+            assert!(parse_kv_cache_type(name).is_ok(), "{} should parse", name);
+            assert!(bits_per_element(name).is_some());
+        }
+        assert!(parse_kv_cache_type("Q8_0").is_ok());
+        assert!(parse_kv_cache_type("q3_k").is_err());
+    }
+
+    #[test]
+    fn test_savings_vs_f16() {
+        assert_eq!(savings_vs_f16("f16", "f16"), Some(0.0));
+        let q8 = savings_vs_f16("q8_0", "q8_0").unwrap();
+        assert!((q8 - 0.46875).abs() < 1e-6);
+        assert!(savings_vs_f16("f32", "f16").unwrap() < 0.0);
+    }
+}
@@ -0,0 +1,55 @@
+/*!
+# RustLlama
+
+The library behind the `rustlama` CLI. [`InferenceEngine`] loads a GGUF model
+once and generates completions for any number of prompts, for embedding
+RustLlama in another Rust program:
+
+```no_run
+use rustlama::{InferenceEngine, SamplingParams};
+
+let mut engine = InferenceEngine::load("llama-2-7b-chat.Q4_K_M.gguf")?.with_ctx_size(4096);
+let params = SamplingParams {
+    max_tokens: 64,
+    stop: vec!["\n\n".to_string()],
+    ..SamplingParams::default()
+};
+println!("{}", engine.generate("The capital of France is", &params)?);
+# Ok::<(), anyhow::Error>(())
+```
+
+The other modules are the building blocks the CLI is made of (sampling, stop
+conditions, chat templates, the model cache and downloader) and can be used
+on their own.
+*/
+
+pub mod ban_words;
+pub mod beam;
+pub mod build_info;
+pub mod chat;
+pub mod config;
+pub mod date_filter;
+pub mod downloader;
+pub mod engine;
+pub mod fifo;
+pub mod file_lock;
+pub mod gguf;
+pub mod grammar;
+pub mod hooks;
+pub mod interpolate;
+pub mod json_schema;
+pub mod kv_cache;
+pub mod lint;
+pub mod logit_bias;
+pub mod metadata;
+pub mod prune;
+pub mod quant;
+pub mod repetition;
+pub mod sampling;
+pub mod state;
+pub mod stop_strings;
+pub mod stop_tokens;
+pub mod truncate;
+pub mod vision;
+
+pub use engine::{InferenceEngine, SamplingParams, StopReason, TokenStream};
@@ -0,0 +1,239 @@
+/*!
+# Configuration Linting
+
+Static checks for a configuration file that go beyond `YamlConfig::validate`:
+whether referenced models can be resolved, whether output files can be
+written, whether grammar files and JSON schemas load, whether prompt files exist, and whether task names are unique.
+Nothing is downloaded or run.
+*/
+
+use crate::config::YamlConfig;
+use crate::downloader::is_hf_model_id;
+use crate::grammar::load_grammar;
+use crate::json_schema::{load_schema, schema_to_grammar};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Findings from linting a configuration
+#[derive(Debug, Default)]
+pub struct LintReport {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl LintReport {
+    /// Whether the configuration passed without errors
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// Run all lint checks against a parsed (not yet validated) configuration
+pub fn lint_config(config: &YamlConfig) -> LintReport {
+    let mut report = LintReport::default();
+
+    if let Err(e) = config.validate() {
+        report.errors.push(e.to_string());
+    }
+
+    check_models(config, &mut report);
+    check_output_paths(config, &mut report);
+    check_grammars(config, &mut report);
+    check_prompt_files(config, &mut report);
+    check_dataset_names(config, &mut report);
+
+    report
+}
+
+/// A model reference is resolvable if it is a well-formed HF ID or an existing local file
+fn is_resolvable_model(model: &str) -> bool {
+    if Path::new(model).exists() {
+        return true;
+    }
+
+    is_hf_model_id(model) && model.split('/').all(|part| !part.is_empty())
+}
+
+fn check_models(config: &YamlConfig, report: &mut LintReport) {
+    for (i, model_task) in config.models.iter().enumerate() {
+        if let Some(model_id) = &model_task.model_id {
+            if matches!(model_task.action.as_str(), "pull" | "remove") && !is_resolvable_model(model_id) {
+                report.errors.push(format!(
+                    "Model task {}: '{}' is not a valid Hugging Face model ID",
+                    i, model_id
+                ));
+            }
+        }
+    }
+
+    for task in &config.tasks {
+        let mut task = task.clone();
+        config.apply_defaults(&mut task);
+        match &task.model {
+            Some(model) if !is_resolvable_model(model) => report.errors.push(format!(
+                "Task '{}': model '{}' is neither an existing file nor a valid Hugging Face model ID",
+                task.name, model
+            )),
+            Some(_) => {}
+            None => report.errors.push(format!(
+                "Task '{}': no model specified and no default model configured",
+                task.name
+            )),
+        }
+    }
+
+    for dataset in &config.datasets {
+        let mut dataset = dataset.clone();
+        config.apply_dataset_defaults(&mut dataset);
+        match &dataset.model {
+            Some(model) if !is_resolvable_model(model) => report.errors.push(format!(
+                "Dataset '{}': model '{}' is neither an existing file nor a valid Hugging Face model ID",
+                dataset.name, model
+            )),
+            Some(_) => {}
+            None => report.errors.push(format!(
+                "Dataset '{}': no model specified and no default model configured",
+                dataset.name
+            )),
+        }
+    }
+}
+
+/// Check that the directory an output file would be written into exists and is writable
+fn check_output_path(owner: &str, output_file: &str, report: &mut LintReport) {
+    let path = Path::new(output_file);
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    match fs::metadata(parent) {
+        Ok(metadata) if !metadata.is_dir() => report.errors.push(format!(
+            "{}: output directory '{}' is not a directory",
+            owner,
+            parent.display()
+        )),
+        Ok(metadata) if metadata.permissions().readonly() => report.errors.push(format!(
+            "{}: output directory '{}' is read-only",
+            owner,
+            parent.display()
+        )),
+        Ok(_) => {
+            if path.exists() {
+                report.warnings.push(format!(
+                    "{}: output file '{}' already exists and will be overwritten",
+                    owner, output_file
+                ));
+            }
+        }
+        Err(_) => report.errors.push(format!(
+            "{}: output directory '{}' does not exist",
+            owner,
+            parent.display()
+        )),
+    }
+}
+
+fn check_output_paths(config: &YamlConfig, report: &mut LintReport) {
+    for task in &config.tasks {
+        if let Some(output_file) = &task.output_file {
+            check_output_path(&format!("Task '{}'", task.name), output_file, report);
+        }
+    }
+
+    for dataset in &config.datasets {
+        check_output_path(&format!("Dataset '{}'", dataset.name), &dataset.output_file, report);
+    }
+}
+
+fn check_grammars(config: &YamlConfig, report: &mut LintReport) {
+    for task in &config.tasks {
+        if let Some(Err(e)) = task.grammar_file.as_deref().map(|path| load_grammar(Path::new(path))) {
+            report.errors.push(format!("Task '{}': {}", task.name, e));
+        }
+
+        if let Some(path) = &task.json_schema {
+            if task.grammar_file.is_some() {
+                report
+                    .errors
+                    .push(format!("Task '{}': json_schema and grammar_file can't be combined", task.name));
+            }
+            if let Err(e) = load_schema(Path::new(path)).and_then(|schema| schema_to_grammar(&schema)) {
+                report.errors.push(format!("Task '{}': {}", task.name, e));
+            }
+        }
+    }
+}
+
+fn check_prompt_files(config: &YamlConfig, report: &mut LintReport) {
+    for task in &config.tasks {
+        if let Some(path) = &task.prompt_file {
+            if let Err(e) = fs::File::open(path) {
+                report.errors.push(format!("Task '{}': can't read prompt_file '{}': {}", task.name, path, e));
+            }
+        }
+    }
+}
+
+/// Dataset names share the --only-tasks/--skip-tasks namespace with task names, which
+/// `validate` already checks for duplicates among themselves
+fn check_dataset_names(config: &YamlConfig, report: &mut LintReport) {
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    for task in &config.tasks {
+        seen.insert(task.name.as_str(), 1);
+    }
+
+    for dataset in &config.datasets {
+        let count = seen.entry(dataset.name.as_str()).or_insert(0);
+        *count += 1;
+        if *count == 2 {
+            report.errors.push(format!(
+                "Dataset name '{}' is already used by another task or dataset",
+                dataset.name
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_config_lints_clean() {
+        let config = YamlConfig::generate_sample();
+        let report = lint_config(&config);
+        assert!(report.is_ok(), "unexpected errors: {:?}", report.errors);
+    }
+
+    #[test]
+    fn test_lint_reports_bad_model_and_output_dir() {
+        let mut config = YamlConfig::generate_sample();
+        config.tasks[0].model = Some("not a model".to_string());
+        config.tasks[1].output_file = Some("/nonexistent-rustlama-dir/out.txt".to_string());
+        config.tasks[1].grammar_file = Some("/nonexistent-rustlama-dir/digits.gbnf".to_string());
+        config.tasks[1].json_schema = Some("/nonexistent-rustlama-dir/person.json".to_string());
+
+        let report = lint_config(&config);
+        assert!(report.errors.iter().any(|e| e.contains("not a model")));
+        assert!(report.errors.iter().any(|e| e.contains("does not exist")));
+        assert!(report.errors.iter().any(|e| e.contains("Failed to read grammar file")));
+        assert!(report.errors.iter().any(|e| e.contains("Failed to read JSON schema")));
+        assert!(report.errors.iter().any(|e| e.contains("can't be combined")));
+    }
+
+    #[test]
+    fn test_lint_reports_duplicate_names() {
+        let mut config = YamlConfig::generate_sample();
+        config.tasks[1].name = config.tasks[0].name.clone();
+
+        let report = lint_config(&config);
+        assert!(report.errors.iter().any(|e| e.contains("Duplicate task name")));
+
+        config.tasks[1].name = "Unique".to_string();
+        config.datasets[0].name = config.tasks[0].name.clone();
+        let report = lint_config(&config);
+        assert!(report.errors.iter().any(|e| e.contains("Dataset name")));
+    }
+}
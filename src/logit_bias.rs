@@ -0,0 +1,156 @@
+/*!
+# Logit Bias
+
+`--logit-bias TOKEN=BIAS` adds a fixed amount to a token's logit at every
+step, before sampling. A positive bias makes the token more likely, a negative
+one less likely, and `-inf` bans it outright. TOKEN is either a token ID or a
+string that the tokenizer turns into exactly one token:
+
+```text
+rustlama run -m model.gguf -p "..." --logit-bias 13=-inf --logit-bias " however"=-5
+```
+
+Unlike `--ban-word`, a bias works on single tokens only, but it applies
+wherever the token occurs.
+*/
+
+use anyhow::{anyhow, Result};
+use llama_cpp_2::model::{AddBos, LlamaModel};
+use llama_cpp_2::token::data::LlamaTokenData;
+use llama_cpp_2::token::LlamaToken;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// One `TOKEN=BIAS` argument, with the token not yet resolved
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogitBiasEntry {
+    pub token: String,
+    pub bias: f32,
+}
+
+impl FromStr for LogitBiasEntry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Split at the last '=' so a token string may itself contain one
+        let (token, bias) = s
+            .rsplit_once('=')
+            .ok_or_else(|| format!("expected TOKEN=BIAS, got '{}'", s))?;
+        if token.is_empty() {
+            return Err(format!("no token before '=' in '{}'", s));
+        }
+        let bias = bias
+            .trim()
+            .parse::<f32>()
+            .map_err(|_| format!("'{}' is not a number (use -inf to ban a token)", bias))?;
+        if bias.is_nan() {
+            return Err("the bias can't be NaN".to_string());
+        }
+        Ok(Self {
+            token: token.to_string(),
+            bias,
+        })
+    }
+}
+
+/// Fixed logit offsets per token
+#[derive(Debug, Clone, Default)]
+pub struct LogitBias {
+    biases: HashMap<LlamaToken, f32>,
+}
+
+impl LogitBias {
+    /// Bias the given tokens; biases for the same token add up
+    pub fn new(biases: impl IntoIterator<Item = (LlamaToken, f32)>) -> Self {
+        let mut combined = HashMap::new();
+        for (token, bias) in biases {
+            *combined.entry(token).or_insert(0.0) += bias;
+        }
+        Self { biases: combined }
+    }
+
+    /// Resolve each entry's token, as an ID if it parses as one and through the tokenizer otherwise
+    pub fn from_entries(model: &LlamaModel, entries: &[LogitBiasEntry]) -> Result<Self> {
+        let n_vocab = model.n_vocab();
+        let mut biases = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let token = match entry.token.parse::<i32>() {
+                Ok(id) if (0..n_vocab).contains(&id) => LlamaToken::new(id),
+                Ok(id) => {
+                    return Err(anyhow!(
+                        "Logit bias token {} is out of range for a vocabulary of {} tokens",
+                        id,
+                        n_vocab
+                    ))
+                }
+                Err(_) => {
+                    let tokens = model
+                        .str_to_token(&entry.token, AddBos::Never)
+                        .map_err(|e| anyhow!("Failed to tokenize logit bias token '{}': {}", entry.token, e))?;
+                    match tokens[..] {
+                        [token] => token,
+                        _ => {
+                            return Err(anyhow!(
+                                "Logit bias token '{}' is {} tokens, not one; use its token IDs or --ban-word",
+                                entry.token,
+                                tokens.len()
+                            ))
+                        }
+                    }
+                }
+            };
+            biases.push((token, entry.bias));
+        }
+        Ok(Self::new(biases))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.biases.is_empty()
+    }
+
+    /// Add each token's bias to its logit
+    pub fn apply(&self, candidates: &mut [LlamaTokenData]) {
+        for candidate in candidates.iter_mut() {
+            if let Some(bias) = self.biases.get(&candidate.id()) {
+                candidate.set_logit(candidate.logit() + bias);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_logit_bias_entry() {
+        let entry: LogitBiasEntry = "13=-inf".parse().unwrap();
+        assert_eq!(entry.token, "13");
+        assert_eq!(entry.bias, f32::NEG_INFINITY);
+
+        let entry: LogitBiasEntry = "a=b=2.5".parse().unwrap();
+        assert_eq!(entry.token, "a=b");
+        assert_eq!(entry.bias, 2.5);
+
+        assert!("13".parse::<LogitBiasEntry>().is_err());
+        assert!("=1".parse::<LogitBiasEntry>().is_err());
+        assert!("13=high".parse::<LogitBiasEntry>().is_err());
+    }
+
+    #[test]
+    fn test_apply_logit_bias() {
+        let bias = LogitBias::new([
+            (LlamaToken::new(1), 2.0),
+            (LlamaToken::new(1), 0.5),
+            (LlamaToken::new(2), f32::NEG_INFINITY),
+        ]);
+        let mut candidates: Vec<LlamaTokenData> = (0..3)
+            .map(|i| LlamaTokenData::new(LlamaToken::new(i), 1.0, 0.0))
+            .collect();
+
+        bias.apply(&mut candidates);
+        assert_eq!(candidates[0].logit(), 1.0);
+        assert_eq!(candidates[1].logit(), 3.5);
+        assert_eq!(candidates[2].logit(), f32::NEG_INFINITY);
+    }
+}
@@ -21,27 +21,57 @@ rustlama --model model.gguf --prompt "Hello, world!"
 */
 
 use anyhow::Result;
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
 use colored::*;
 use indicatif::{ProgressBar, ProgressStyle};
 use llama_cpp_2::context::params::LlamaContextParams;
+use llama_cpp_2::context::LlamaContext;
 use llama_cpp_2::llama_backend::LlamaBackend;
-use llama_cpp_2::llama_batch::LlamaBatch;
 use llama_cpp_2::model::params::LlamaModelParams;
 use llama_cpp_2::model::{AddBos, LlamaModel, Special};
+use llama_cpp_2::token::LlamaToken;
+use llama_cpp_2::{send_logs_to_tracing, LogOptions};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::ffi::OsString;
 use std::fs;
-use std::io::{self, Write};
+use std::io::{self, BufRead, IsTerminal, Write};
 use std::num::NonZeroU32;
-use std::path::PathBuf;
-use std::time::Instant;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Once};
+use std::time::{Instant, SystemTime};
+use tracing_subscriber::filter::LevelFilter;
 
 #[cfg(test)]
 mod tests;
-mod downloader;
-mod config;
-
-use downloader::{is_hf_model_id, ModelDownloader};
+mod bench;
+mod interrupt;
+mod json_output;
+mod pipe;
+mod progress;
+mod server;
+
+use rustlama::{
+    build_info, chat, config, date_filter, downloader, engine, fifo, file_lock, gguf, grammar, hooks, json_schema,
+    kv_cache, lint, logit_bias, metadata, prune, quant, sampling, state, stop_tokens, truncate, vision,
+};
+
+use downloader::{is_hf_model_id, HfFile, ModelDownloader, ModelInfoCache};
+use gguf::{format_param_count, ModelSummary};
+use chat::ChatMessage;
 use config::{YamlConfig, InferenceTask, ModelTask, DatasetTask};
+use metadata::{CacheProblem, ModelMetadata, Verification, METADATA_FILE};
+use prune::PruneKind;
+use json_output::{RunOutput, SweepOutput};
+use logit_bias::LogitBiasEntry;
+use pipe::{PipeRequest, PipeResponse};
+use progress::LiveProgress;
+use build_info::BuildInfo;
+use engine::{InferenceEngine, SamplingParams, StopReason};
+use sampling::{FilterTrace, SampledToken, TempRange};
+use truncate::TruncateStrategy;
+use stop_tokens::StopTokens;
 
 #[derive(Parser)]
 #[command(
@@ -66,6 +96,10 @@ use config::{YamlConfig, InferenceTask, ModelTask, DatasetTask};
     author = "Sangam Biradar"
 )]
 struct Cli {
+    /// Assume "yes" for every confirmation prompt
+    #[arg(short = 'y', long, global = true, help = "Assume yes for every confirmation prompt (required for destructive commands when stdin is not a terminal)")]
+    yes: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -83,7 +117,7 @@ enum Commands {
         hf_filename: Option<String>,
 
         /// Models cache directory
-        #[arg(long, help = "Directory to cache downloaded models (default: ~/.cache/rustlama)")]
+        #[arg(long, env = "RUSTLAMA_CACHE_DIR", help = "Directory to cache downloaded models (default: ~/.cache/rustlama)")]
         cache_dir: Option<String>,
 
         /// Force re-download even if model exists
@@ -95,14 +129,28 @@ enum Commands {
             short,
             long,
             value_name = "TEXT",
-            help = "Input prompt for text generation"
+            required_unless_present_any = ["prompt_file", "prompts_file", "messages_file"],
+            help = "Input prompt for text generation ('-' reads it from stdin)"
         )]
-        prompt: String,
+        prompt: Option<String>,
+
+        /// Read the prompt from a file or named pipe
+        #[arg(long, value_name = "PATH", conflicts_with = "prompt", help = "Read the prompt from a file or FIFO (read until the writer closes it; one trailing newline is dropped)")]
+        prompt_file: Option<PathBuf>,
+
+        /// Generate a completion for each line of a file
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["prompt", "prompt_file", "messages_file", "interactive_first"], help = "Read one prompt per line and generate a completion for each with a single model load; with --output-file the n-th is written to <stem>.<n>.<ext>")]
+        prompts_file: Option<PathBuf>,
+
+        /// Document to prepend to the prompt
+        #[arg(long, value_name = "PATH", conflicts_with = "messages_file", help = "Prepend this file's contents to the prompt (before chat formatting), e.g. a document to answer questions about; applies to every --prompts-file line")]
+        context_file: Option<PathBuf>,
 
         /// Maximum number of tokens to generate
         #[arg(
             short = 'n',
             long,
+            env = "RUSTLAMA_MAX_TOKENS",
             default_value = "1024",
             help = "Maximum number of tokens to generate"
         )]
@@ -112,43 +160,280 @@ enum Commands {
         #[arg(
             short,
             long,
+            env = "RUSTLAMA_TEMPERATURE",
             default_value = "0.8",
             help = "Sampling temperature (0.1-2.0)"
         )]
         temperature: f32,
 
         /// Top-k sampling: limit to k most likely tokens
-        #[arg(long, default_value = "40", help = "Top-k sampling parameter")]
+        #[arg(long, env = "RUSTLAMA_TOP_K", default_value = "40", help = "Top-k sampling parameter")]
         top_k: usize,
 
         /// Top-p (nucleus) sampling: cumulative probability threshold
         #[arg(
             long,
+            env = "RUSTLAMA_TOP_P",
             default_value = "0.95",
             help = "Top-p sampling parameter (0.0-1.0)"
         )]
         top_p: f32,
 
+        /// Generate N candidates and keep the best one
+        #[arg(
+            long,
+            env = "RUSTLAMA_BEST_OF",
+            default_value = "1",
+            value_name = "N",
+            help = "Generate N candidates and output the one with the highest average token log-probability"
+        )]
+        best_of: usize,
+
         /// Context size (number of tokens the model can remember)
         #[arg(
             short = 'c',
             long,
+            env = "RUSTLAMA_CTX_SIZE",
             help = "Context size in tokens (default: model's default)"
         )]
         ctx_size: Option<u32>,
 
         /// Number of threads to use
-        #[arg(short = 'j', long, help = "Number of threads for inference")]
+        #[arg(short = 'j', long, env = "RUSTLAMA_THREADS", help = "Number of threads for inference")]
         threads: Option<i32>,
 
         /// Disable colored output
-        #[arg(long, help = "Disable colored output")]
+        #[arg(long, env = "RUSTLAMA_NO_COLOR", help = "Disable colored output")]
         no_color: bool,
 
         /// Show generation statistics
-        #[arg(short, long, help = "Show detailed generation statistics")]
+        #[arg(short, long, env = "RUSTLAMA_STATS", help = "Show detailed generation statistics")]
         stats: bool,
 
+        /// Suppress llama.cpp's perf timings
+        #[arg(long, help = "Suppress llama.cpp's internal timing/perf output (always on unless --verbose; its warnings and errors are still shown)")]
+        no_perf: bool,
+
+        /// Image to pass to a multimodal (vision) model
+        #[arg(long, value_name = "PATH", help = "Image file for multimodal models (requires --mmproj)")]
+        image: Option<String>,
+
+        /// Multimodal projector for vision models
+        #[arg(long, value_name = "PATH", help = "Multimodal projector (mmproj) GGUF file used to encode --image")]
+        mmproj: Option<String>,
+
+        /// KV cache type for keys
+        #[arg(long, env = "RUSTLAMA_KV_CACHE_TYPE_K", value_name = "TYPE", help = "KV cache type for keys: f32, f16, bf16, q8_0, q4_0, q4_1, iq4_nl, q5_0, q5_1 (default: f16)")]
+        kv_cache_type_k: Option<String>,
+
+        /// KV cache type for values
+        #[arg(long, env = "RUSTLAMA_KV_CACHE_TYPE_V", value_name = "TYPE", help = "KV cache type for values: f32, f16, bf16, q8_0, q4_0, q4_1, iq4_nl, q5_0, q5_1 (default: f16)")]
+        kv_cache_type_v: Option<String>,
+
+        /// Enable flash attention
+        #[arg(long, env = "RUSTLAMA_FLASH_ATTN", help = "Enable flash attention (falls back to standard attention if unsupported)")]
+        flash_attn: bool,
+
+        /// Annotate each generated token with its probability
+        #[arg(long, env = "RUSTLAMA_SHOW_PROBS", help = "Show each generated token's probability inline, e.g. word(0.83)")]
+        show_probs: bool,
+
+        /// Derive the RNG seed from the prompt and sampling parameters
+        #[arg(long, help = "Derive a deterministic seed from a hash of the prompt and sampling parameters")]
+        seed_from_prompt: bool,
+
+        /// Print a short model summary after loading
+        #[arg(long, help = "Print architecture, parameter count, quantization, vocab size and trained context after loading (implied by --verbose)")]
+        show_model_info: bool,
+
+        /// Hugging Face Hub endpoints to try in order
+        #[arg(long = "hf-endpoint", env = "HF_ENDPOINT", value_delimiter = ',', value_name = "URL", help = "Hugging Face Hub endpoint or mirror; repeat or comma-separate to fall back in order")]
+        hf_endpoints: Vec<String>,
+
+        /// Write raw token IDs to a file during generation
+        #[arg(long, value_name = "PATH", help = "Write prompt and generated token IDs to a file, one per line (prompt IDs, a blank line, then generated IDs)")]
+        dump_tokens_file: Option<String>,
+
+        /// Shell command to run after generation
+        #[arg(long, value_name = "COMMAND", help = "Shell command run after generation with RUSTLAMA_OUTPUT_FILE, RUSTLAMA_TOKENS and RUSTLAMA_DURATION set")]
+        post_hook: Option<String>,
+
+        /// Additional token IDs that end generation
+        #[arg(long = "stop-token", value_name = "ID", help = "Token ID that ends generation, in addition to EOS and detected end-of-turn tokens (repeatable)")]
+        stop_tokens: Vec<i32>,
+
+        /// Words or phrases the model must not generate
+        #[arg(long = "ban-word", value_name = "STRING", help = "Word or phrase the model must not generate (repeatable)")]
+        ban_words: Vec<String>,
+
+        /// Pick the largest quant that fits in available memory
+        #[arg(long, conflicts_with = "hf_filename", help = "Pick the largest GGUF quant that fits in available memory (Hugging Face models only)")]
+        auto_quant: bool,
+
+        /// Grow the context to fit the prompt plus max tokens
+        #[arg(long, env = "RUSTLAMA_AUTO_CTX", help = "Grow the context to fit the prompt plus --max-tokens, up to the model's trained context length")]
+        auto_ctx: bool,
+
+        /// Minimum number of tokens before generation may stop
+        #[arg(long, env = "RUSTLAMA_MIN_TOKENS", default_value = "0", value_name = "N", help = "Suppress EOS and other stop tokens until at least N tokens have been generated")]
+        min_tokens: usize,
+
+        /// System message for chat models
+        #[arg(long, value_name = "TEXT", help = "System message; placed by the chat template with --chat, else prepended to the prompt")]
+        system: Option<String>,
+
+        /// Format the prompt as a chat turn
+        #[arg(long, help = "Format the prompt as a user message with the model's chat template")]
+        chat: bool,
+
+        /// Chat template override
+        #[arg(long, value_name = "NAME_OR_JINJA", help = "Chat template to use instead of the model's embedded one: chatml, llama2, llama3, gemma, or a Jinja template string")]
+        chat_template: Option<String>,
+
+        /// Write the generated text to a file
+        #[arg(short = 'o', long, value_name = "PATH", help = "Write the generated text to a file")]
+        output_file: Option<String>,
+
+        /// Checkpoint the output every N tokens
+        #[arg(long, value_name = "N", requires = "output_file", help = "Also write the text generated so far to --output-file every N tokens")]
+        save_every: Option<usize>,
+
+        /// Bias toward (positive) or away from (negative) tokens used in the prompt
+        #[arg(long, env = "RUSTLAMA_PROMPT_AFFINITY", default_value = "0.0", value_name = "BIAS", allow_negative_numbers = true, help = "Logit bias added to tokens that appear in the prompt; positive reuses prompt vocabulary, negative avoids it")]
+        prompt_affinity: f32,
+
+        /// Wait for a line of input after ingesting the prompt
+        #[arg(long, help = "Ingest the prompt, then wait for one line on stdin that is appended before generation starts")]
+        interactive_first: bool,
+
+        /// Hugging Face revision to download
+        #[arg(long, value_name = "REV", help = "Branch, tag or commit to download from (default: the current commit of main, recorded in metadata.json)")]
+        revision: Option<String>,
+
+        /// Reuse the recorded commit instead of resolving main again
+        #[arg(long, conflicts_with = "revision", help = "Download from the commit recorded in metadata.json instead of resolving main again")]
+        pin: bool,
+
+        /// Trace the sampling decision for the first K tokens
+        #[arg(long, value_name = "K", num_args = 0..=1, default_missing_value = "16", requires = "verbose", help = "With --verbose, print the top-k/top-p/temperature filter stages and the chosen token for the first K tokens (default 16) to stderr")]
+        explain: Option<usize>,
+
+        /// Beam search width
+        #[arg(long, default_value = "1", value_name = "N", help = "Keep the N most likely sequences at each step and print the best (1 = greedy)")]
+        beams: usize,
+
+        /// Sweep the temperature over a range
+        #[arg(long, value_name = "START:STOP:STEP", help = "Generate one completion per temperature from START to STOP (inclusive) in STEP increments, each labelled with its temperature")]
+        temp_range: Option<TempRange>,
+
+        /// Stop at the first newline
+        #[arg(long, help = "Stop generating at the first newline and leave the newline out, for single-line completions")]
+        single_line: bool,
+
+        /// Truncate an over-long prompt instead of failing
+        #[arg(long, env = "RUSTLAMA_TRUNCATE", value_enum, value_name = "STRATEGY", help = "Drop prompt tokens from the head, tail or middle when the prompt doesn't fit in the context minus --max-tokens (default: fail)")]
+        truncate: Option<TruncateStrategy>,
+
+        /// Save the context state after generation
+        #[arg(long, value_name = "PATH", help = "After generation, save the KV cache and token history to PATH (plus a PATH.json model fingerprint)")]
+        dump_state: Option<PathBuf>,
+
+        /// Restore a saved context state before generation
+        #[arg(long, value_name = "PATH", help = "Restore a state saved with --dump-state instead of processing a prompt; --prompt is appended as a continuation (may be empty)")]
+        load_state: Option<PathBuf>,
+
+        /// Stop when the output loops
+        #[arg(long, env = "RUSTLAMA_STOP_ON_REPEAT", value_name = "N", help = "Stop generating once an n-gram of up to 8 tokens repeats N times in a row")]
+        stop_on_repeat: Option<usize>,
+
+        /// Dynamic temperature range
+        #[arg(long, env = "RUSTLAMA_DYNATEMP_RANGE", default_value = "0.0", value_name = "RANGE", help = "Vary the temperature per token between --temperature minus and plus RANGE by the entropy of the distribution (0 = off)")]
+        dynatemp_range: f32,
+
+        /// Dynamic temperature exponent
+        #[arg(long, env = "RUSTLAMA_DYNATEMP_EXPONENT", default_value = "1.0", value_name = "EXP", help = "Exponent applied to the normalized entropy before mapping it into the --dynatemp-range window")]
+        dynatemp_exponent: f32,
+
+        /// Read a conversation from a JSON file
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["prompt", "prompt_file", "system"], help = "Read an OpenAI-style [{\"role\", \"content\"}, ...] conversation (roles: system, user, assistant) and format it with the chat template instead of --prompt")]
+        messages_file: Option<PathBuf>,
+
+        /// Seed for the sampling RNG
+        #[arg(long, value_name = "N", conflicts_with = "seed_from_prompt", help = "Seed for the sampling RNG, for reproducible output (random if unset)")]
+        seed: Option<u64>,
+
+        /// Repetition penalty
+        #[arg(long, env = "RUSTLAMA_REPEAT_PENALTY", default_value = "1.1", value_name = "PENALTY", help = "Penalize tokens generated within the last --repeat-last-n tokens (1.0 = off)")]
+        repeat_penalty: f32,
+
+        /// Repetition penalty window
+        #[arg(long, env = "RUSTLAMA_REPEAT_LAST_N", default_value = "64", value_name = "N", help = "How many recently generated tokens --repeat-penalty looks back over (0 = off)")]
+        repeat_last_n: usize,
+
+        /// Strings that end generation
+        #[arg(long, value_name = "TEXT", help = "Stop generating once the output contains TEXT, leaving it out (repeatable)")]
+        stop: Vec<String>,
+
+        /// Prompt processing batch size
+        #[arg(long, env = "RUSTLAMA_BATCH_SIZE", default_value = "512", value_name = "N", help = "Maximum number of tokens decoded in one batch; longer prompts are processed in chunks of N")]
+        batch_size: usize,
+
+        /// Skip checksum verification of downloads
+        #[arg(long, help = "Keep a downloaded model even if its size or SHA256 doesn't match what the Hub reports")]
+        no_verify: bool,
+
+        /// Hugging Face access token
+        #[arg(long, value_name = "TOKEN", help = "Hugging Face access token for gated or private models (default: HF_TOKEN, HUGGING_FACE_HUB_TOKEN or ~/.cache/huggingface/token)")]
+        hf_token: Option<String>,
+
+        /// Append to the output file
+        #[arg(long, requires = "output_file", conflicts_with = "save_every", help = "Append the generated text to --output-file instead of replacing it")]
+        append: bool,
+
+        /// Output format
+        #[arg(long, value_enum, default_value = "text", conflicts_with_all = ["verbose", "interactive_first", "show_model_info"], help = "text streams colored output; json prints a single JSON object with the prompt, text, --stats figures and sampling parameters once generation is done (an array of them, one per temperature, with --temp-range; implies --no-color)")]
+        format: OutputFormat,
+
+        /// Tokens kept when the context fills up
+        #[arg(long, env = "RUSTLAMA_N_KEEP", value_name = "N", help = "When prompt plus generation fills the context, keep the first N tokens and discard the oldest half of the rest to continue (default: the whole prompt)")]
+        n_keep: Option<usize>,
+
+        /// Mirostat sampling version
+        #[arg(long, env = "RUSTLAMA_MIROSTAT", default_value = "0", value_name = "0|1|2", value_parser = clap::value_parser!(u8).range(0..=2), help = "Mirostat adaptive sampling, which keeps the surprise of each token near --mirostat-tau in place of top-k and top-p (0 = off, 1 = Mirostat, 2 = Mirostat 2.0)")]
+        mirostat: u8,
+
+        /// Mirostat target surprise
+        #[arg(long, env = "RUSTLAMA_MIROSTAT_TAU", default_value = "5.0", value_name = "TAU", help = "Target surprise for --mirostat, in bits; lower is more focused and coherent")]
+        mirostat_tau: f32,
+
+        /// Mirostat learning rate
+        #[arg(long, env = "RUSTLAMA_MIROSTAT_ETA", default_value = "0.1", value_name = "ETA", help = "How quickly --mirostat corrects towards --mirostat-tau")]
+        mirostat_eta: f32,
+
+        /// Constrain output to a GBNF grammar
+        #[arg(long, value_name = "PATH", help = "Only generate text matching the GBNF grammar in this file (llama.cpp's grammar format; generation starts from its root rule)")]
+        grammar_file: Option<PathBuf>,
+
+        /// Constrain output to a JSON Schema
+        #[arg(long, value_name = "PATH", help = "Only generate JSON conforming to the JSON Schema in this file (converted to a GBNF grammar)")]
+        json_schema: Option<PathBuf>,
+
+        /// Keep generating past end-of-sequence
+        #[arg(long, env = "RUSTLAMA_IGNORE_EOS", help = "Mask EOS and other stop tokens so generation always runs to --max-tokens (output may turn incoherent past the natural end)")]
+        ignore_eos: bool,
+
+        /// Fixed logit offsets for specific tokens
+        #[arg(long = "logit-bias", value_name = "TOKEN=BIAS", help = "Add BIAS to a token's logit before sampling; TOKEN is an ID or a string that is a single token, and -inf bans it (repeatable)")]
+        logit_bias: Vec<LogitBiasEntry>,
+
+        /// Load the model into RAM instead of memory-mapping it
+        #[arg(long, env = "RUSTLAMA_NO_MMAP", help = "Read the whole model into RAM instead of memory-mapping the file")]
+        no_mmap: bool,
+
+        /// Lock the model in RAM
+        #[arg(long, env = "RUSTLAMA_MLOCK", help = "Lock the model's pages in RAM so they are never swapped out")]
+        mlock: bool,
+
         /// Verbose output
         #[arg(short, long, help = "Enable verbose output")]
         verbose: bool,
@@ -162,12 +447,12 @@ enum Commands {
 
     /// Run tasks from YAML configuration file
     Config {
-        /// Path to YAML configuration file
-        #[arg(short, long, help = "Path to YAML configuration file")]
+        /// Path to the configuration file
+        #[arg(short, long, help = "Path to the configuration file: YAML, or JSON/TOML when it ends in .json/.toml")]
         file: Option<PathBuf>,
 
         /// Dry run - show what would be executed without running
-        #[arg(long, help = "Show what would be executed without actually running")]
+        #[arg(long, help = "Show what would be executed, with every resolved task setting, without actually running")]
         dry_run: bool,
 
         /// Generate sample configuration file
@@ -175,7 +460,7 @@ enum Commands {
         generate_sample: bool,
 
         /// Output file for sample generation
-        #[arg(long, default_value = "rustlama.yml", help = "Output file for sample configuration")]
+        #[arg(long, default_value = "rustlama.yml", help = "Output file for sample configuration (.json and .toml write those formats, anything else YAML)")]
         output: PathBuf,
 
         /// Continue execution on errors
@@ -190,9 +475,313 @@ enum Commands {
         #[arg(long, help = "Skip specific tasks (comma-separated names)")]
         skip_tasks: Option<String>,
 
+        /// Default per-task timeout
+        #[arg(long, value_name = "SECONDS", help = "Fail any task that runs longer than this (tasks can override with `timeout`)")]
+        task_timeout: Option<u64>,
+
+        /// Re-run changed tasks whenever the file is saved
+        #[arg(long, help = "After the first run, watch the config file and re-run the tasks whose settings changed on each save")]
+        watch: bool,
+
+        /// Wait for locked output files
+        #[arg(long, help = "Wait for another process to release a task's output_file instead of failing")]
+        lock_wait: bool,
+
+        /// Run inference tasks concurrently
+        #[arg(long, value_name = "N", help = "Run up to N inference tasks at once; tasks that use the same model run one after another (default: defaults.parallel, else 1)")]
+        parallel: Option<usize>,
+
         /// Verbose output
         #[arg(short, long, help = "Enable verbose output")]
         verbose: bool,
+
+        #[command(subcommand)]
+        command: Option<ConfigCommands>,
+    },
+
+    /// Serve newline-delimited JSON requests on stdin with the model kept loaded
+    Pipe {
+        /// Path to the GGUF model file or Hugging Face model ID
+        #[arg(short, long, value_name = "FILE_OR_HF_ID", help = "Path to GGUF model file or Hugging Face model ID")]
+        model: String,
+
+        /// Hugging Face model filename (for HF models)
+        #[arg(long, help = "Specific filename to download from HF model (auto-detected if not specified)")]
+        hf_filename: Option<String>,
+
+        /// Models cache directory
+        #[arg(long, env = "RUSTLAMA_CACHE_DIR", help = "Directory to cache downloaded models (default: ~/.cache/rustlama)")]
+        cache_dir: Option<String>,
+
+        /// Default maximum number of tokens per request
+        #[arg(short = 'n', long, env = "RUSTLAMA_MAX_TOKENS", default_value = "1024", help = "Default maximum number of tokens to generate per request")]
+        max_tokens: usize,
+
+        /// Default sampling temperature
+        #[arg(short, long, env = "RUSTLAMA_TEMPERATURE", default_value = "0.8", help = "Default sampling temperature (0.1-2.0)")]
+        temperature: f32,
+
+        /// Default top-k
+        #[arg(long, env = "RUSTLAMA_TOP_K", default_value = "40", help = "Default top-k sampling parameter")]
+        top_k: usize,
+
+        /// Default top-p
+        #[arg(long, env = "RUSTLAMA_TOP_P", default_value = "0.95", help = "Default top-p sampling parameter (0.0-1.0)")]
+        top_p: f32,
+
+        /// Context size shared by all requests
+        #[arg(short = 'c', long, env = "RUSTLAMA_CTX_SIZE", help = "Context size in tokens; each request's prompt plus max_tokens must fit")]
+        ctx_size: Option<u32>,
+
+        /// Number of threads to use
+        #[arg(short = 'j', long, env = "RUSTLAMA_THREADS", help = "Number of threads for inference")]
+        threads: Option<i32>,
+
+        /// Enable flash attention
+        #[arg(long, env = "RUSTLAMA_FLASH_ATTN", help = "Enable flash attention")]
+        flash_attn: bool,
+
+        /// Load the model into RAM instead of memory-mapping it
+        #[arg(long, env = "RUSTLAMA_NO_MMAP", help = "Read the whole model into RAM instead of memory-mapping the file")]
+        no_mmap: bool,
+
+        /// Lock the model in RAM
+        #[arg(long, env = "RUSTLAMA_MLOCK", help = "Lock the model's pages in RAM so they are never swapped out")]
+        mlock: bool,
+
+        /// Hugging Face Hub endpoints to try in order
+        #[arg(long = "hf-endpoint", env = "HF_ENDPOINT", value_delimiter = ',', value_name = "URL", help = "Hugging Face Hub endpoint or mirror; repeat or comma-separate to fall back in order")]
+        hf_endpoints: Vec<String>,
+
+        /// Hugging Face access token
+        #[arg(long, value_name = "TOKEN", help = "Hugging Face access token for gated or private models (default: HF_TOKEN, HUGGING_FACE_HUB_TOKEN or ~/.cache/huggingface/token)")]
+        hf_token: Option<String>,
+
+        /// Verify the model cache before starting
+        #[arg(long, help = "Check every cached model against its recorded checksum before loading, and refuse to start if any is corrupt (see 'models doctor')")]
+        check_cache: bool,
+
+        /// Log progress to stderr
+        #[arg(short, long, help = "Log loading progress and each request to stderr")]
+        verbose: bool,
+    },
+
+    /// Serve an OpenAI-compatible HTTP API with the model kept loaded
+    Serve {
+        /// Path to the GGUF model file or Hugging Face model ID
+        #[arg(short, long, value_name = "FILE_OR_HF_ID", help = "Path to GGUF model file or Hugging Face model ID")]
+        model: String,
+
+        /// Hugging Face model filename (for HF models)
+        #[arg(long, help = "Specific filename to download from HF model (auto-detected if not specified)")]
+        hf_filename: Option<String>,
+
+        /// Models cache directory
+        #[arg(long, env = "RUSTLAMA_CACHE_DIR", help = "Directory to cache downloaded models (default: ~/.cache/rustlama)")]
+        cache_dir: Option<String>,
+
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1", help = "Address to listen on (use 0.0.0.0 to accept connections from other machines)")]
+        host: String,
+
+        /// Port to listen on
+        #[arg(long, default_value = "8080", help = "Port to listen on")]
+        port: u16,
+
+        /// Default maximum number of tokens per request
+        #[arg(short = 'n', long, env = "RUSTLAMA_MAX_TOKENS", default_value = "1024", help = "Maximum number of tokens to generate when a request doesn't set max_tokens")]
+        max_tokens: usize,
+
+        /// Default sampling temperature
+        #[arg(short, long, env = "RUSTLAMA_TEMPERATURE", default_value = "0.8", help = "Sampling temperature when a request doesn't set one")]
+        temperature: f32,
+
+        /// Top-k for every request
+        #[arg(long, env = "RUSTLAMA_TOP_K", default_value = "40", help = "Top-k sampling parameter (not settable per request)")]
+        top_k: usize,
+
+        /// Default top-p
+        #[arg(long, env = "RUSTLAMA_TOP_P", default_value = "0.95", help = "Top-p sampling parameter when a request doesn't set one (0.0-1.0)")]
+        top_p: f32,
+
+        /// Context size shared by all requests
+        #[arg(short = 'c', long, env = "RUSTLAMA_CTX_SIZE", help = "Context size in tokens; each request's prompt must fit (default: 2048)")]
+        ctx_size: Option<u32>,
+
+        /// Number of threads to use
+        #[arg(short = 'j', long, env = "RUSTLAMA_THREADS", help = "Number of threads for inference")]
+        threads: Option<i32>,
+
+        /// Prompt processing batch size
+        #[arg(long, env = "RUSTLAMA_BATCH_SIZE", default_value = "512", value_name = "N", help = "Maximum number of tokens decoded in one batch")]
+        batch_size: usize,
+
+        /// Hugging Face Hub endpoints to try in order
+        #[arg(long = "hf-endpoint", env = "HF_ENDPOINT", value_delimiter = ',', value_name = "URL", help = "Hugging Face Hub endpoint or mirror; repeat or comma-separate to fall back in order")]
+        hf_endpoints: Vec<String>,
+
+        /// Hugging Face access token
+        #[arg(long, value_name = "TOKEN", help = "Hugging Face access token for gated or private models (default: HF_TOKEN, HUGGING_FACE_HUB_TOKEN or ~/.cache/huggingface/token)")]
+        hf_token: Option<String>,
+
+        /// Verify the model cache before starting
+        #[arg(long, help = "Check every cached model against its recorded checksum before loading, and refuse to start if any is corrupt (see 'models doctor')")]
+        check_cache: bool,
+
+        /// Log progress to stderr
+        #[arg(short, long, help = "Log loading progress and each request to stderr")]
+        verbose: bool,
+    },
+
+    /// Print the tokens a prompt is split into
+    Tokenize {
+        /// Path to the GGUF model file or Hugging Face model ID
+        #[arg(short, long, value_name = "FILE_OR_HF_ID", help = "Path to GGUF model file or Hugging Face model ID")]
+        model: String,
+
+        /// Hugging Face model filename (for HF models)
+        #[arg(long, help = "Specific filename to download from HF model (auto-detected if not specified)")]
+        hf_filename: Option<String>,
+
+        /// Models cache directory
+        #[arg(long, env = "RUSTLAMA_CACHE_DIR", help = "Directory to cache downloaded models (default: ~/.cache/rustlama)")]
+        cache_dir: Option<String>,
+
+        /// Text to tokenize
+        #[arg(short, long, value_name = "TEXT", help = "Text to tokenize ('-' reads it from stdin)")]
+        prompt: String,
+
+        /// Whether to prepend the BOS token
+        #[arg(long, value_name = "BOOL", default_value_t = true, action = clap::ArgAction::Set, help = "Prepend the beginning-of-sequence token, as run does (true or false)")]
+        add_bos: bool,
+
+        /// Show special tokens' text
+        #[arg(long, help = "Show the text of special tokens (BOS, EOS, chat markup) instead of empty pieces")]
+        special: bool,
+
+        /// Output format
+        #[arg(long, value_enum, default_value = "text", help = "text prints one token per line; json prints a single JSON object with the tokens and their count")]
+        format: OutputFormat,
+
+        /// Hugging Face Hub endpoints to try in order
+        #[arg(long = "hf-endpoint", env = "HF_ENDPOINT", value_delimiter = ',', value_name = "URL", help = "Hugging Face Hub endpoint or mirror; repeat or comma-separate to fall back in order")]
+        hf_endpoints: Vec<String>,
+
+        /// Hugging Face access token
+        #[arg(long, value_name = "TOKEN", help = "Hugging Face access token for gated or private models (default: HF_TOKEN, HUGGING_FACE_HUB_TOKEN or ~/.cache/huggingface/token)")]
+        hf_token: Option<String>,
+
+        /// Log progress to stderr
+        #[arg(short, long, help = "Log model loading to stderr")]
+        verbose: bool,
+    },
+
+    /// Measure prompt processing and generation speed
+    Bench {
+        /// Path to the GGUF model file or Hugging Face model ID
+        #[arg(short, long, value_name = "FILE_OR_HF_ID", help = "Path to GGUF model file or Hugging Face model ID")]
+        model: String,
+
+        /// Hugging Face model filename (for HF models)
+        #[arg(long, help = "Specific filename to download from HF model (auto-detected if not specified)")]
+        hf_filename: Option<String>,
+
+        /// Models cache directory
+        #[arg(long, env = "RUSTLAMA_CACHE_DIR", help = "Directory to cache downloaded models (default: ~/.cache/rustlama)")]
+        cache_dir: Option<String>,
+
+        /// Number of counted passes
+        #[arg(long, default_value = "3", value_name = "N", help = "Number of timed passes to average, after one untimed warmup pass")]
+        runs: usize,
+
+        /// Length of the synthetic prompt
+        #[arg(long, default_value = "512", value_name = "N", help = "Number of prompt tokens processed per pass")]
+        prompt_tokens: usize,
+
+        /// Number of tokens generated per pass
+        #[arg(long, default_value = "128", value_name = "N", help = "Number of tokens generated per pass")]
+        gen_tokens: usize,
+
+        /// Number of threads to use
+        #[arg(short = 'j', long, env = "RUSTLAMA_THREADS", help = "Number of threads for inference")]
+        threads: Option<i32>,
+
+        /// Prompt processing batch size
+        #[arg(long, env = "RUSTLAMA_BATCH_SIZE", default_value = "512", value_name = "N", help = "Maximum number of tokens decoded in one batch")]
+        batch_size: usize,
+
+        /// Output format
+        #[arg(long, value_enum, default_value = "text", help = "text prints a summary; json prints a single JSON object with every pass's timings")]
+        format: OutputFormat,
+
+        /// Load the model into RAM instead of memory-mapping it
+        #[arg(long, env = "RUSTLAMA_NO_MMAP", help = "Read the whole model into RAM instead of memory-mapping the file")]
+        no_mmap: bool,
+
+        /// Lock the model in RAM
+        #[arg(long, env = "RUSTLAMA_MLOCK", help = "Lock the model's pages in RAM so they are never swapped out")]
+        mlock: bool,
+
+        /// Hugging Face Hub endpoints to try in order
+        #[arg(long = "hf-endpoint", env = "HF_ENDPOINT", value_delimiter = ',', value_name = "URL", help = "Hugging Face Hub endpoint or mirror; repeat or comma-separate to fall back in order")]
+        hf_endpoints: Vec<String>,
+
+        /// Hugging Face access token
+        #[arg(long, value_name = "TOKEN", help = "Hugging Face access token for gated or private models (default: HF_TOKEN, HUGGING_FACE_HUB_TOKEN or ~/.cache/huggingface/token)")]
+        hf_token: Option<String>,
+
+        /// Log progress to stderr
+        #[arg(short, long, help = "Log model loading and each pass's timings to stderr")]
+        verbose: bool,
+    },
+
+    /// Print a shell completion script to stdout
+    Completions {
+        /// Shell to generate completions for
+        #[arg(value_enum, help = "Shell to generate completions for (bash, zsh, fish, powershell, elvish)")]
+        shell: clap_complete::Shell,
+    },
+
+    /// List compute backends and devices available to llama.cpp
+    #[command(alias = "list-backends")]
+    Devices {
+        /// Disable colored output
+        #[arg(long, help = "Disable colored output")]
+        no_color: bool,
+    },
+
+    /// Print version, build and backend information
+    Version {
+        /// Print a JSON object instead of text
+        #[arg(long, help = "Print the version, bindings, target, profile and backends as a JSON object (for bug reports)")]
+        json: bool,
+    },
+}
+
+/// Order of `models ls` output
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum ListSort {
+    Name,
+    Size,
+    Date,
+}
+
+/// How `run` presents its result
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    /// Colored text, streamed as it is generated
+    Text,
+    /// One JSON object once generation is done
+    Json,
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Check a configuration file for problems without executing it
+    Lint {
+        /// Path to the configuration file
+        #[arg(short, long, help = "Path to the configuration file: YAML, or JSON/TOML when it ends in .json/.toml")]
+        file: PathBuf,
     },
 }
 
@@ -216,6 +805,34 @@ enum ModelCommands {
         #[arg(short, long, help = "Force re-download model even if it exists locally")]
         force: bool,
 
+        /// Hugging Face Hub endpoints to try in order
+        #[arg(long = "hf-endpoint", env = "HF_ENDPOINT", value_delimiter = ',', value_name = "URL", help = "Hugging Face Hub endpoint or mirror; repeat or comma-separate to fall back in order")]
+        hf_endpoints: Vec<String>,
+
+        /// Hugging Face access token
+        #[arg(long, value_name = "TOKEN", help = "Hugging Face access token for gated or private models (default: HF_TOKEN, HUGGING_FACE_HUB_TOKEN or ~/.cache/huggingface/token)")]
+        hf_token: Option<String>,
+
+        /// Never prompt for a file choice
+        #[arg(long, help = "Fail instead of showing an interactive file picker when multiple files are available")]
+        no_interactive: bool,
+
+        /// Pick the largest quant that fits in available memory
+        #[arg(long, conflicts_with = "filename", help = "Pick the largest GGUF quant that fits in available memory")]
+        auto_quant: bool,
+
+        /// Hugging Face revision to download
+        #[arg(long, value_name = "REV", help = "Branch, tag or commit to download from (default: the current commit of main, recorded in metadata.json)")]
+        revision: Option<String>,
+
+        /// Reuse the recorded commit instead of resolving main again
+        #[arg(long, conflicts_with = "revision", help = "Download from the commit recorded in metadata.json instead of resolving main again")]
+        pin: bool,
+
+        /// Skip checksum verification of the download
+        #[arg(long, help = "Keep the download even if its size or SHA256 doesn't match what the Hub reports")]
+        no_verify: bool,
+
         /// Verbose output
         #[arg(short, long, help = "Enable verbose output")]
         verbose: bool,
@@ -228,6 +845,18 @@ enum ModelCommands {
         #[arg(long, help = "Directory to check for cached models (default: ~/.cache/rustlama)")]
         cache_dir: Option<String>,
 
+        /// Only models downloaded at or after this point
+        #[arg(long, value_name = "DURATION|DATE", help = "Only list models downloaded within a duration (30m, 12h, 7d, 2w) or since a date (2024-05-01)")]
+        since: Option<String>,
+
+        /// Only models downloaded before this point
+        #[arg(long, value_name = "DURATION|DATE", help = "Only list models downloaded more than a duration ago (e.g. 30d) or before a date")]
+        before: Option<String>,
+
+        /// Sort order
+        #[arg(long, value_enum, default_value = "name", help = "Sort by name, size (largest first) or download date (newest first)")]
+        sort: ListSort,
+
         /// Show detailed information
         #[arg(short, long, help = "Show detailed model information")]
         verbose: bool,
@@ -260,55 +889,336 @@ enum ModelCommands {
         #[arg(long, help = "Directory to check for cached models (default: ~/.cache/rustlama)")]
         cache_dir: Option<String>,
     },
-}
 
-fn main() -> Result<()> {
-    tokio::runtime::Runtime::new()?.block_on(async_main())
-}
+    /// Delete leftover partial downloads and, optionally, long-unused models
+    Prune {
+        /// Also remove models unused for this many days
+        #[arg(long, value_name = "DAYS", value_parser = clap::value_parser!(u64).range(1..), help = "Also remove models whose files haven't been read or written in DAYS days (pinned models are kept)")]
+        older_than: Option<u64>,
 
-async fn async_main() -> Result<()> {
-    let cli = Cli::parse();
+        /// Only list what would be removed
+        #[arg(long, help = "List what would be removed and how much space it would free, without deleting anything")]
+        dry_run: bool,
 
-    match cli.command {
-        Commands::Run {
-            model,
-            hf_filename,
-            cache_dir,
-            force_download,
-            prompt,
-            max_tokens,
-            temperature,
-            top_k,
-            top_p,
-            ctx_size,
-            threads,
-            no_color,
-            stats,
-            verbose,
-        } => {
-            // Create a compatible structure for the existing inference logic
-            let run_config = RunConfig {
-                model,
-                hf_filename,
-                cache_dir,
-                force_download,
-                prompt,
-                max_tokens,
-                temperature,
-                top_k,
-                top_p,
-                ctx_size,
-                threads,
-                no_color,
-                stats,
-                verbose,
-            };
-            let _generated_text = run_inference(run_config).await?;
-            Ok(())
-        }
-        Commands::Models { command } => {
-            handle_model_commands(command).await
-        }
+        /// Models cache directory
+        #[arg(long, help = "Directory to check for cached models (default: ~/.cache/rustlama)")]
+        cache_dir: Option<String>,
+    },
+
+    /// Pin a cached model so cleanup commands never remove it
+    Pin {
+        /// Hugging Face model ID
+        #[arg(help = "Model ID to pin (e.g., TheBloke/Llama-2-7B-Chat-GGUF)")]
+        model_id: String,
+
+        /// Models cache directory
+        #[arg(long, help = "Directory to check for cached models (default: ~/.cache/rustlama)")]
+        cache_dir: Option<String>,
+    },
+
+    /// Unpin a previously pinned model
+    Unpin {
+        /// Hugging Face model ID
+        #[arg(help = "Model ID to unpin (e.g., TheBloke/Llama-2-7B-Chat-GGUF)")]
+        model_id: String,
+
+        /// Models cache directory
+        #[arg(long, help = "Directory to check for cached models (default: ~/.cache/rustlama)")]
+        cache_dir: Option<String>,
+    },
+
+    /// Change the ID of a cached model
+    #[command(alias = "mv")]
+    Rename {
+        /// Current model ID
+        #[arg(help = "Current model ID (e.g., TheBloke/Llama-2-7B-Chat-GGUF)")]
+        old_id: String,
+
+        /// New model ID
+        #[arg(help = "New model ID in owner/name form")]
+        new_id: String,
+
+        /// Models cache directory
+        #[arg(long, help = "Directory to check for cached models (default: ~/.cache/rustlama)")]
+        cache_dir: Option<String>,
+    },
+
+    /// Rebuild corrupt or missing metadata.json files from the cached files
+    RepairMetadata {
+        /// Model ID
+        #[arg(help = "Model ID to rebuild unconditionally (default: every model whose metadata is missing or unreadable)")]
+        model_id: Option<String>,
+
+        /// Models cache directory
+        #[arg(long, help = "Directory to check for cached models (default: ~/.cache/rustlama)")]
+        cache_dir: Option<String>,
+
+        /// Don't contact the Hub
+        #[arg(long, help = "Skip looking up the revision on Hugging Face; only recompute sizes and checksums")]
+        offline: bool,
+    },
+
+    /// Show detailed information about each file of a cached model
+    Stat {
+        /// Model ID
+        #[arg(help = "Model ID (e.g., TheBloke/Llama-2-7B-Chat-GGUF)")]
+        model_id: String,
+
+        /// Models cache directory
+        #[arg(long, help = "Directory to check for cached models (default: ~/.cache/rustlama)")]
+        cache_dir: Option<String>,
+
+        /// Skip checksum verification
+        #[arg(long, help = "Don't re-hash files to check them against their recorded SHA256")]
+        no_verify: bool,
+    },
+
+    /// Show architecture, size and quantization read from GGUF headers
+    Info {
+        /// Model ID or GGUF file
+        #[arg(help = "Model ID (e.g., TheBloke/Llama-2-7B-Chat-GGUF) or path to a GGUF file")]
+        model: String,
+
+        /// Models cache directory
+        #[arg(long, help = "Directory to check for cached models (default: ~/.cache/rustlama)")]
+        cache_dir: Option<String>,
+    },
+
+    /// Check every cached model file against its recorded checksum
+    Doctor {
+        /// Models cache directory
+        #[arg(long, help = "Directory to check for cached models (default: ~/.cache/rustlama)")]
+        cache_dir: Option<String>,
+
+        /// Move bad files aside
+        #[arg(long, help = "Move corrupt and incomplete files into a .corrupt/ directory inside their model directory so they aren't loaded")]
+        quarantine: bool,
+    },
+}
+
+fn main() -> Result<()> {
+    tokio::runtime::Runtime::new()?.block_on(async_main())?;
+    // Generation stopped early on Ctrl-C; the output was still saved
+    if interrupt::interrupted() {
+        std::process::exit(interrupt::EXIT_INTERRUPTED);
+    }
+    Ok(())
+}
+
+/// Insert the `run` subcommand when the arguments start with an option instead of a
+/// subcommand, so `rustlama --model m.gguf --prompt Hi` works as documented
+fn default_to_run(mut args: Vec<OsString>) -> Vec<OsString> {
+    // Skip the global --yes, which may come before the subcommand
+    let first = args
+        .iter()
+        .skip(1)
+        .position(|arg| arg != "-y" && arg != "--yes")
+        .map(|i| i + 1);
+    if let Some(i) = first {
+        let arg = args[i].to_string_lossy();
+        let is_top_level = ["-h", "--help", "-V", "--version"].contains(&arg.as_ref());
+        if arg.starts_with('-') && !is_top_level {
+            args.insert(i, OsString::from("run"));
+        }
+    }
+    args
+}
+
+async fn async_main() -> Result<()> {
+    let cli = Cli::parse_from(default_to_run(std::env::args_os().collect()));
+    interrupt::install();
+
+    match cli.command {
+        Commands::Run {
+            model,
+            hf_filename,
+            cache_dir,
+            force_download,
+            prompt,
+            prompt_file,
+            prompts_file,
+            context_file,
+            max_tokens,
+            temperature,
+            top_k,
+            top_p,
+            best_of,
+            ctx_size,
+            threads,
+            no_color,
+            stats,
+            no_perf,
+            image,
+            mmproj,
+            kv_cache_type_k,
+            kv_cache_type_v,
+            flash_attn,
+            show_probs,
+            seed_from_prompt,
+            show_model_info,
+            hf_endpoints,
+            dump_tokens_file,
+            stop_tokens,
+            ban_words,
+            auto_quant,
+            post_hook,
+            auto_ctx,
+            min_tokens,
+            system,
+            chat,
+            chat_template,
+            output_file,
+            save_every,
+            prompt_affinity,
+            interactive_first,
+            revision,
+            pin,
+            explain,
+            beams,
+            temp_range,
+            single_line,
+            truncate,
+            dump_state,
+            load_state,
+            stop_on_repeat,
+            dynatemp_range,
+            dynatemp_exponent,
+            messages_file,
+            seed,
+            repeat_penalty,
+            repeat_last_n,
+            stop,
+            batch_size,
+            no_verify,
+            hf_token,
+            append,
+            format,
+            n_keep,
+            mirostat,
+            mirostat_tau,
+            mirostat_eta,
+            grammar_file,
+            json_schema,
+            ignore_eos,
+            logit_bias,
+            no_mmap,
+            mlock,
+            verbose,
+        } => {
+            // Create a compatible structure for the existing inference logic
+            let prompt = match (prompt, prompt_file) {
+                (Some(prompt), _) if prompt == fifo::STDIN_PROMPT => {
+                    if interactive_first {
+                        return Err(anyhow::anyhow!("--prompt - reads stdin, so it can't be combined with --interactive-first"));
+                    }
+                    fifo::read_prompt_stdin()?
+                }
+                (Some(prompt), _) => prompt,
+                (None, Some(path)) => fifo::read_prompt_file(&path)?,
+                // --messages-file: the prompt is rendered from the conversation once the model is
+                // loaded, and each --prompts-file line is filled in per run
+                (None, None) => String::new(),
+            };
+            let prompts = match &prompts_file {
+                Some(path) => Some(read_prompt_lines(path)?),
+                None => None,
+            };
+            let context = match &context_file {
+                Some(path) => Some(
+                    fs::read_to_string(path)
+                        .map_err(|e| anyhow::anyhow!("Failed to read context file '{}': {}", path.display(), e))?,
+                ),
+                None => None,
+            };
+
+            let run_config = RunConfig {
+                model,
+                hf_filename,
+                cache_dir,
+                force_download,
+                prompt,
+                max_tokens,
+                temperature,
+                top_k,
+                top_p,
+                best_of,
+                ctx_size,
+                threads,
+                no_color: no_color || format == OutputFormat::Json,
+                stats,
+                no_perf,
+                image,
+                mmproj,
+                kv_cache_type_k,
+                kv_cache_type_v,
+                flash_attn,
+                show_probs,
+                seed_from_prompt,
+                show_model_info,
+                hf_endpoints,
+                dump_tokens_file,
+                stop_tokens,
+                ban_words,
+                auto_quant,
+                auto_ctx,
+                deadline: None,
+                min_tokens,
+                system,
+                chat,
+                chat_template,
+                output_file,
+                save_every,
+                prompt_affinity,
+                interactive_first,
+                revision,
+                pin,
+                explain,
+                beams,
+                temp_range,
+                single_line,
+                truncate,
+                dump_state,
+                load_state,
+                stop_on_repeat,
+                dynatemp_range,
+                dynatemp_exponent,
+                messages_file,
+                seed,
+                repeat_penalty,
+                repeat_last_n,
+                stop,
+                batch_size,
+                no_verify,
+                hf_token,
+                append,
+                output_lock_wait: false,
+                stream_output_file: false,
+                format,
+                quiet: format == OutputFormat::Json,
+                n_keep,
+                mirostat,
+                mirostat_tau,
+                mirostat_eta,
+                grammar_file,
+                json_schema,
+                ignore_eos,
+                logit_bias,
+                no_mmap,
+                mlock,
+                context,
+                verbose,
+            };
+            match prompts {
+                Some(prompts) => run_prompts(run_config, &prompts, append, post_hook.as_deref()).await,
+                None => run_once(run_config, &mut None, append, post_hook.as_deref()).await,
+            }
+        }
+        Commands::Models { command } => {
+            handle_model_commands(command, cli.yes).await
+        }
+        Commands::Config { command: Some(ConfigCommands::Lint { file }), .. } => {
+            lint_config_file(file)
+        }
         Commands::Config { 
             file, 
             dry_run, 
@@ -317,23 +1227,428 @@ async fn async_main() -> Result<()> {
             continue_on_error, 
             only_tasks, 
             skip_tasks, 
-            verbose 
+            task_timeout,
+            watch,
+            lock_wait,
+            parallel,
+            verbose,
+            command: None,
         } => {
-            handle_config_command(
-                file, 
-                dry_run, 
-                generate_sample, 
-                output, 
-                continue_on_error, 
-                only_tasks, 
-                skip_tasks, 
-                verbose
-            ).await
+            let options = ConfigRunOptions {
+                dry_run,
+                continue_on_error,
+                task_timeout,
+                watch,
+                lock_wait,
+                parallel,
+                verbose,
+            };
+            handle_config_command(file, generate_sample, output, only_tasks, skip_tasks, &options).await
+        }
+        Commands::Pipe {
+            model,
+            hf_filename,
+            cache_dir,
+            max_tokens,
+            temperature,
+            top_k,
+            top_p,
+            ctx_size,
+            threads,
+            flash_attn,
+            no_mmap,
+            mlock,
+            hf_endpoints,
+            hf_token,
+            check_cache,
+            verbose,
+        } => {
+            if check_cache {
+                check_cache_before_start(cache_dir.clone())?;
+            }
+
+            let defaults = RunConfig {
+                model,
+                hf_filename,
+                cache_dir,
+                force_download: false,
+                prompt: String::new(),
+                max_tokens,
+                temperature,
+                top_k,
+                top_p,
+                best_of: 1,
+                ctx_size,
+                threads,
+                no_color: true,
+                stats: false,
+                no_perf: true,
+                image: None,
+                mmproj: None,
+                kv_cache_type_k: None,
+                kv_cache_type_v: None,
+                flash_attn,
+                show_probs: false,
+                seed_from_prompt: false,
+                show_model_info: false,
+                hf_endpoints,
+                dump_tokens_file: None,
+                stop_tokens: Vec::new(),
+                ban_words: Vec::new(),
+                auto_quant: false,
+                auto_ctx: false,
+                deadline: None,
+                min_tokens: 0,
+                system: None,
+                chat: false,
+                chat_template: None,
+                output_file: None,
+                save_every: None,
+                prompt_affinity: 0.0,
+                interactive_first: false,
+                revision: None,
+                pin: false,
+                explain: None,
+                beams: 1,
+                temp_range: None,
+                single_line: false,
+                truncate: None,
+                dump_state: None,
+                load_state: None,
+                stop_on_repeat: None,
+                dynatemp_range: 0.0,
+                dynatemp_exponent: 1.0,
+                messages_file: None,
+                seed: None,
+                repeat_penalty: 1.1,
+                repeat_last_n: 64,
+                stop: Vec::new(),
+                batch_size: DEFAULT_BATCH_SIZE,
+                no_verify: false,
+                hf_token,
+                append: false,
+                output_lock_wait: false,
+                stream_output_file: false,
+                format: OutputFormat::Text,
+                quiet: false,
+                n_keep: None,
+                mirostat: 0,
+                mirostat_tau: 5.0,
+                mirostat_eta: 0.1,
+                grammar_file: None,
+                json_schema: None,
+                ignore_eos: false,
+                logit_bias: Vec::new(),
+                no_mmap,
+                mlock,
+                context: None,
+                verbose: false, // stdout carries only JSON responses
+            };
+            run_pipe(defaults, verbose).await
+        }
+        Commands::Serve {
+            model,
+            hf_filename,
+            cache_dir,
+            host,
+            port,
+            max_tokens,
+            temperature,
+            top_k,
+            top_p,
+            ctx_size,
+            threads,
+            batch_size,
+            hf_endpoints,
+            hf_token,
+            check_cache,
+            verbose,
+        } => {
+            if check_cache {
+                check_cache_before_start(cache_dir.clone())?;
+            }
+
+            let defaults = RunConfig {
+                model,
+                hf_filename,
+                cache_dir,
+                force_download: false,
+                prompt: String::new(),
+                max_tokens,
+                temperature,
+                top_k,
+                top_p,
+                best_of: 1,
+                ctx_size,
+                threads,
+                no_color: true,
+                stats: false,
+                no_perf: true,
+                image: None,
+                mmproj: None,
+                kv_cache_type_k: None,
+                kv_cache_type_v: None,
+                flash_attn: false,
+                show_probs: false,
+                seed_from_prompt: false,
+                show_model_info: false,
+                hf_endpoints,
+                dump_tokens_file: None,
+                stop_tokens: Vec::new(),
+                ban_words: Vec::new(),
+                auto_quant: false,
+                auto_ctx: false,
+                deadline: None,
+                min_tokens: 0,
+                system: None,
+                chat: false,
+                chat_template: None,
+                output_file: None,
+                save_every: None,
+                prompt_affinity: 0.0,
+                interactive_first: false,
+                revision: None,
+                pin: false,
+                explain: None,
+                beams: 1,
+                temp_range: None,
+                single_line: false,
+                truncate: None,
+                dump_state: None,
+                load_state: None,
+                stop_on_repeat: None,
+                dynatemp_range: 0.0,
+                dynatemp_exponent: 1.0,
+                messages_file: None,
+                seed: None,
+                repeat_penalty: 1.1,
+                repeat_last_n: 64,
+                stop: Vec::new(),
+                batch_size,
+                no_verify: false,
+                hf_token,
+                append: false,
+                output_lock_wait: false,
+                stream_output_file: false,
+                format: OutputFormat::Text,
+                quiet: true,
+                n_keep: None,
+                mirostat: 0,
+                mirostat_tau: 5.0,
+                mirostat_eta: 0.1,
+                grammar_file: None,
+                json_schema: None,
+                ignore_eos: false,
+                logit_bias: Vec::new(),
+                no_mmap: false,
+                mlock: false,
+                context: None,
+                verbose: false, // the server logs to stderr itself
+            };
+            run_server(defaults, &host, port, verbose).await
+        }
+        Commands::Tokenize {
+            model,
+            hf_filename,
+            cache_dir,
+            prompt,
+            add_bos,
+            special,
+            format,
+            hf_endpoints,
+            hf_token,
+            verbose,
+        } => {
+            let prompt = if prompt == fifo::STDIN_PROMPT { fifo::read_prompt_stdin()? } else { prompt };
+            let config = RunConfig {
+                model,
+                hf_filename,
+                cache_dir,
+                force_download: false,
+                prompt,
+                max_tokens: 0,
+                temperature: 0.8,
+                top_k: 40,
+                top_p: 0.95,
+                best_of: 1,
+                ctx_size: None,
+                threads: None,
+                no_color: true,
+                stats: false,
+                no_perf: true,
+                image: None,
+                mmproj: None,
+                kv_cache_type_k: None,
+                kv_cache_type_v: None,
+                flash_attn: false,
+                show_probs: false,
+                seed_from_prompt: false,
+                show_model_info: false,
+                hf_endpoints,
+                dump_tokens_file: None,
+                stop_tokens: Vec::new(),
+                ban_words: Vec::new(),
+                auto_quant: false,
+                auto_ctx: false,
+                deadline: None,
+                min_tokens: 0,
+                system: None,
+                chat: false,
+                chat_template: None,
+                output_file: None,
+                save_every: None,
+                prompt_affinity: 0.0,
+                interactive_first: false,
+                revision: None,
+                pin: false,
+                explain: None,
+                beams: 1,
+                temp_range: None,
+                single_line: false,
+                truncate: None,
+                dump_state: None,
+                load_state: None,
+                stop_on_repeat: None,
+                dynatemp_range: 0.0,
+                dynatemp_exponent: 1.0,
+                messages_file: None,
+                seed: None,
+                repeat_penalty: 1.1,
+                repeat_last_n: 64,
+                stop: Vec::new(),
+                batch_size: DEFAULT_BATCH_SIZE,
+                no_verify: false,
+                hf_token,
+                append: false,
+                output_lock_wait: false,
+                stream_output_file: false,
+                format: OutputFormat::Text,
+                quiet: true,
+                n_keep: None,
+                mirostat: 0,
+                mirostat_tau: 5.0,
+                mirostat_eta: 0.1,
+                grammar_file: None,
+                json_schema: None,
+                ignore_eos: false,
+                logit_bias: Vec::new(),
+                no_mmap: false,
+                mlock: false,
+                context: None,
+                verbose: false, // only the tokens are printed
+            };
+            run_tokenize(config, add_bos, special, format, verbose).await
+        }
+        Commands::Bench {
+            model,
+            hf_filename,
+            cache_dir,
+            runs,
+            prompt_tokens,
+            gen_tokens,
+            threads,
+            batch_size,
+            format,
+            no_mmap,
+            mlock,
+            hf_endpoints,
+            hf_token,
+            verbose,
+        } => {
+            let config = RunConfig {
+                model,
+                hf_filename,
+                cache_dir,
+                force_download: false,
+                prompt: String::new(),
+                max_tokens: gen_tokens,
+                temperature: 0.8,
+                top_k: 40,
+                top_p: 0.95,
+                best_of: 1,
+                ctx_size: Some((prompt_tokens + gen_tokens) as u32),
+                threads,
+                no_color: true,
+                stats: false,
+                no_perf: true,
+                image: None,
+                mmproj: None,
+                kv_cache_type_k: None,
+                kv_cache_type_v: None,
+                flash_attn: false,
+                show_probs: false,
+                seed_from_prompt: false,
+                show_model_info: false,
+                hf_endpoints,
+                dump_tokens_file: None,
+                stop_tokens: Vec::new(),
+                ban_words: Vec::new(),
+                auto_quant: false,
+                auto_ctx: false,
+                deadline: None,
+                min_tokens: 0,
+                system: None,
+                chat: false,
+                chat_template: None,
+                output_file: None,
+                save_every: None,
+                prompt_affinity: 0.0,
+                interactive_first: false,
+                revision: None,
+                pin: false,
+                explain: None,
+                beams: 1,
+                temp_range: None,
+                single_line: false,
+                truncate: None,
+                dump_state: None,
+                load_state: None,
+                stop_on_repeat: None,
+                dynatemp_range: 0.0,
+                dynatemp_exponent: 1.0,
+                messages_file: None,
+                seed: None,
+                repeat_penalty: 1.1,
+                repeat_last_n: 64,
+                stop: Vec::new(),
+                batch_size,
+                no_verify: false,
+                hf_token,
+                append: false,
+                output_lock_wait: false,
+                stream_output_file: false,
+                format: OutputFormat::Text,
+                quiet: true,
+                n_keep: None,
+                mirostat: 0,
+                mirostat_tau: 5.0,
+                mirostat_eta: 0.1,
+                grammar_file: None,
+                json_schema: None,
+                ignore_eos: false,
+                logit_bias: Vec::new(),
+                no_mmap,
+                mlock,
+                context: None,
+                verbose: false, // only the timings are printed
+            };
+            run_bench(config, runs, prompt_tokens, gen_tokens, format, verbose).await
+        }
+        Commands::Completions { shell } => {
+            clap_complete::generate(shell, &mut Cli::command(), "rustlama", &mut io::stdout());
+            Ok(())
+        }
+        Commands::Devices { no_color } => {
+            list_devices(no_color)
+        }
+        Commands::Version { json } => {
+            print_version(json)
         }
     }
 }
 
 // Helper struct to maintain compatibility with existing code
+#[derive(Clone)]
 pub struct RunConfig {
     model: String,
     hf_filename: Option<String>,
@@ -344,23 +1659,1036 @@ pub struct RunConfig {
     temperature: f32,
     top_k: usize,
     top_p: f32,
+    best_of: usize,
     ctx_size: Option<u32>,
     threads: Option<i32>,
     no_color: bool,
     stats: bool,
+    no_perf: bool,
+    image: Option<String>,
+    mmproj: Option<String>,
+    kv_cache_type_k: Option<String>,
+    kv_cache_type_v: Option<String>,
+    flash_attn: bool,
+    show_probs: bool,
+    seed_from_prompt: bool,
+    show_model_info: bool,
+    hf_endpoints: Vec<String>,
+    dump_tokens_file: Option<String>,
+    stop_tokens: Vec<i32>,
+    ban_words: Vec<String>,
+    auto_quant: bool,
+    auto_ctx: bool,
+    deadline: Option<Instant>,
+    min_tokens: usize,
+    system: Option<String>,
+    chat: bool,
+    chat_template: Option<String>,
+    output_file: Option<String>,
+    save_every: Option<usize>,
+    prompt_affinity: f32,
+    interactive_first: bool,
+    revision: Option<String>,
+    pin: bool,
+    explain: Option<usize>,
+    beams: usize,
+    temp_range: Option<TempRange>,
+    single_line: bool,
+    truncate: Option<TruncateStrategy>,
+    dump_state: Option<PathBuf>,
+    load_state: Option<PathBuf>,
+    stop_on_repeat: Option<usize>,
+    dynatemp_range: f32,
+    dynatemp_exponent: f32,
+    messages_file: Option<PathBuf>,
+    seed: Option<u64>,
+    repeat_penalty: f32,
+    repeat_last_n: usize,
+    stop: Vec<String>,
+    batch_size: usize,
+    no_verify: bool,
+    hf_token: Option<String>,
+    append: bool,
+    output_lock_wait: bool,
+    stream_output_file: bool,
+    format: OutputFormat,
+    /// Write nothing but warnings and verbose messages to stdout (JSON output, parallel config tasks)
+    quiet: bool,
+    n_keep: Option<usize>,
+    mirostat: u8,
+    mirostat_tau: f32,
+    mirostat_eta: f32,
+    grammar_file: Option<PathBuf>,
+    json_schema: Option<PathBuf>,
+    ignore_eos: bool,
+    logit_bias: Vec<LogitBiasEntry>,
+    no_mmap: bool,
+    mlock: bool,
+    context: Option<String>,
     verbose: bool,
 }
 
-async fn run_inference(cli: RunConfig) -> Result<String> {
+// Context size used when neither --ctx-size nor RUSTLAMA_CTX_SIZE is given, and the
+// default --batch-size, are the library's
+use engine::{DEFAULT_BATCH_SIZE, DEFAULT_CTX_SIZE};
+
+/// --auto-ctx rounds the context size up to a multiple of this
+const AUTO_CTX_ALIGN: usize = 256;
+
+/// Candidates listed per --explain step
+const EXPLAIN_TOP_CANDIDATES: usize = 5;
+
+/// Quiet period after a save before --watch reloads, so bursts of writes trigger one run
+const WATCH_DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(300);
+
+/// Run one generation for `run`, write it to --output-file and run the --post-hook
+async fn run_once(run_config: RunConfig, loaded: &mut Option<LoadedModel>, append: bool, post_hook: Option<&str>) -> Result<()> {
+    let start_time = Instant::now();
+    let output_file = run_config.output_file.clone();
+    let streamed = streams_output_file(&run_config);
+    let generation = run_inference(run_config, loaded).await?;
+
+    match &output_file {
+        Some(_) if streamed => {}
+        Some(path) if fifo::is_fifo(Path::new(path)) => {
+            let mut pipe = fifo::open_writer(Path::new(path))?;
+            pipe.write_all(generation.text.as_bytes())?;
+            pipe.flush()?;
+        }
+        Some(path) if append => file_lock::write_locked(Path::new(path), &generation.text, true, false)?,
+        Some(path) => write_atomic(path, &generation.text)?,
+        None => {}
+    }
+
+    if let Some(command) = post_hook {
+        hooks::run_post_hook(command, output_file.as_deref(), generation.tokens_generated, start_time.elapsed())?;
+    }
+    Ok(())
+}
+
+/// --prompts-file: generate a completion for each prompt with one model load. Each starts from a
+/// fresh context; the n-th goes to `<stem>.<n>.<ext>` with --output-file and is labelled on
+/// stdout otherwise.
+async fn run_prompts(run_config: RunConfig, prompts: &[String], append: bool, post_hook: Option<&str>) -> Result<()> {
+    let mut loaded = None;
+    for (i, prompt) in prompts.iter().enumerate() {
+        if interrupt::interrupted() {
+            break;
+        }
+        let mut run = run_config.clone();
+        run.prompt = prompt.clone();
+        run.output_file = run_config.output_file.as_deref().map(|path| numbered_output_path(path, i + 1));
+
+        // JSON output is one object per prompt, which carries the prompt itself
+        if run.format == OutputFormat::Text {
+            let label = format!("[{}/{}]", i + 1, prompts.len());
+            if !run.no_color {
+                println!("\n{}", label.cyan().bold());
+            } else {
+                println!("\n{}", label);
+            }
+        }
+
+        run_once(run, &mut loaded, append, post_hook).await?;
+    }
+    Ok(())
+}
+
+/// Put a --context-file document before the prompt, separated by a blank line
+pub fn with_context(context: &str, prompt: &str) -> String {
+    let context = context.trim_end();
+    if prompt.is_empty() {
+        context.to_string()
+    } else {
+        format!("{}\n\n{}", context, prompt)
+    }
+}
+
+/// Read the non-blank lines of a --prompts-file
+fn read_prompt_lines(path: &Path) -> Result<Vec<String>> {
+    let prompts: Vec<String> = fifo::read_prompt_file(path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(String::from)
+        .collect();
+    if prompts.is_empty() {
+        return Err(anyhow::anyhow!("Prompts file '{}' has no prompts", path.display()));
+    }
+    Ok(prompts)
+}
+
+/// `out.txt` becomes `out.<n>.txt`; without an extension, `.txt` is used
+pub fn numbered_output_path(path: &str, n: usize) -> String {
+    let path = Path::new(path);
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    let extension = path.extension().map(|ext| ext.to_string_lossy()).unwrap_or("txt".into());
+    path.with_file_name(format!("{}.{}.{}", stem, n, extension))
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// A model loaded for one or more runs
+struct LoadedModel {
+    path: PathBuf,
+    engine: InferenceEngine,
+}
+
+/// Run one generation. `loaded` holds the model of an earlier run, which is reused if this run
+/// asks for the same model file and replaced otherwise; either way the model is left in it.
+async fn run_inference(cli: RunConfig, loaded: &mut Option<LoadedModel>) -> Result<Generation> {
     // Validate inputs
     validate_args(&cli)?;
 
-    if cli.verbose {
-        print_banner(&cli);
+    if cli.verbose {
+        print_banner(&cli);
+    }
+
+    // Resolve model path (download if necessary)
+    let model_path = resolve_model_path(&cli, cli.quiet).await?;
+    let mut model = match loaded.take() {
+        Some(model) if model.path == model_path => {
+            if cli.verbose {
+                println!("{} Reusing loaded model: {}", "Info:".blue().bold(), model_path.display());
+            }
+            model
+        }
+        previous => {
+            // Free the previous model before loading the next one
+            drop(previous);
+            load_model(model_path, &cli)?
+        }
+    };
+    model.engine = model.engine.with_batch_size(cli.batch_size);
+
+    let generation = generate_with_model(&model, cli);
+    *loaded = Some(model);
+    generation
+}
+
+/// Initialize the backend and load the model at `model_path`
+fn load_model(model_path: PathBuf, cli: &RunConfig) -> Result<LoadedModel> {
+    if cli.verbose {
+        println!(
+            "{} Initializing llama.cpp backend...",
+            "Info:".blue().bold()
+        );
+    }
+
+    init_llama_logs(cli.verbose);
+
+    if cli.verbose {
+        println!("{} Loading model: {}", "Info:".blue().bold(), model_path.display());
+    }
+
+    // Set up model parameters
+    let model_params = build_model_params(cli);
+
+    // Load the model with progress indication
+    let loading_msg = format!("Loading model: {}", model_path.display());
+    let pb = if cli.quiet {
+        None
+    } else if !cli.no_color {
+        let pb = ProgressBar::new_spinner();
+        pb.set_style(
+            ProgressStyle::default_spinner()
+                .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ")
+                .template("{spinner:.green} {msg}")
+                .unwrap(),
+        );
+        pb.set_message(loading_msg);
+        pb.enable_steady_tick(std::time::Duration::from_millis(100));
+        Some(pb)
+    } else {
+        println!("Loading model...");
+        None
+    };
+
+    let engine = InferenceEngine::load_with_params(&model_path, &model_params)?;
+
+    if let Some(pb) = &pb {
+        pb.finish_with_message("Model loaded successfully ✓".green().to_string());
+    } else if !cli.quiet {
+        println!("Model loaded successfully");
+    }
+
+    if cli.show_model_info || cli.verbose {
+        print_model_summary(&ModelSummary::from_model(engine.model()), cli);
+    }
+
+    Ok(LoadedModel {
+        path: model_path,
+        engine,
+    })
+}
+
+/// Format the prompt and generate from it with an already loaded model, in a fresh context
+fn generate_with_model(loaded: &LoadedModel, mut cli: RunConfig) -> Result<Generation> {
+    let engine = &loaded.engine;
+    let model = engine.model();
+
+    // --context-file: the document is part of the prompt, so it counts toward the context and gets chat formatting
+    if let Some(context) = &cli.context {
+        cli.prompt = with_context(context, &cli.prompt);
+    }
+
+    // Without --chat the system message is plain text ahead of the prompt
+    if !cli.chat && cli.chat_template.is_none() && cli.messages_file.is_none() {
+        if let Some(system) = &cli.system {
+            cli.prompt = format!("{}\n\n{}", system, cli.prompt);
+        }
+    }
+
+    // Chat models: wrap the prompt in the conversation markup the model was trained on
+    if cli.chat || cli.chat_template.is_some() || cli.messages_file.is_some() {
+        let messages = match &cli.messages_file {
+            Some(path) => chat::load_messages(path)?,
+            None => {
+                let mut messages = Vec::new();
+                if let Some(system) = &cli.system {
+                    messages.push(ChatMessage::new("system", system));
+                }
+                messages.push(ChatMessage::new("user", &cli.prompt));
+                messages
+            }
+        };
+
+        let embedded_template = model.meta_val_str("tokenizer.chat_template").ok();
+        let bos_token = model.token_to_str(model.token_bos(), Special::Tokenize).unwrap_or_default();
+        let eos_token = model.token_to_str(model.token_eos(), Special::Tokenize).unwrap_or_default();
+
+        let chat_prompt = chat::format_chat_prompt(
+            cli.chat_template.as_deref(),
+            embedded_template.as_deref(),
+            &messages,
+            &bos_token,
+            &eos_token,
+        )?;
+
+        if let Some(reason) = &chat_prompt.fallback_reason {
+            eprintln!(
+                "{} Falling back to the {} chat template: {}",
+                "Warning:".yellow().bold(),
+                chat::DEFAULT_TEMPLATE,
+                reason
+            );
+        }
+
+        if cli.verbose {
+            println!("{} Chat template: {}", "Info:".blue().bold(), chat_prompt.template);
+        }
+
+        cli.prompt = chat_prompt.text;
+    }
+
+    // Tokenize up front so --auto-ctx can size the context; reused below for text-only prompts
+    let early_tokens = if cli.auto_ctx {
+        let tokens = model
+            .str_to_token(&cli.prompt, AddBos::Always)
+            .map_err(|e| anyhow::anyhow!("Failed to tokenize prompt: {}", e))?;
+
+        let needed = tokens.len() + cli.max_tokens;
+        let n_ctx_train = model.n_ctx_train();
+        let current = cli.ctx_size.unwrap_or(DEFAULT_CTX_SIZE);
+        let sized = auto_ctx_size(needed, n_ctx_train);
+
+        if needed > n_ctx_train as usize && n_ctx_train > 0 {
+            eprintln!(
+                "{} Prompt ({} tokens) plus --max-tokens ({}) exceeds the model's trained context of {} tokens",
+                "Warning:".yellow().bold(),
+                tokens.len(),
+                cli.max_tokens,
+                n_ctx_train
+            );
+        }
+
+        if sized > current {
+            if cli.verbose {
+                println!(
+                    "{} Auto-sized context from {} to {} tokens",
+                    "Info:".blue().bold(),
+                    current,
+                    sized
+                );
+            }
+            cli.ctx_size = Some(sized);
+        }
+
+        Some(tokens)
+    } else {
+        None
+    };
+
+    if cli.verbose {
+        println!("{} Creating context...", "Info:".blue().bold());
+    }
+
+    // Create context from model; not every model/backend supports flash attention
+    let mut flash_attn_active = cli.flash_attn;
+    let mut ctx = match engine.new_context(build_context_params(&cli, cli.flash_attn)?) {
+        Ok(ctx) => ctx,
+        Err(e) if cli.flash_attn => {
+            eprintln!(
+                "{} Flash attention unavailable ({}), falling back to standard attention",
+                "Warning:".yellow().bold(),
+                e
+            );
+            flash_attn_active = false;
+            engine.new_context(build_context_params(&cli, false)?)?
+        }
+        Err(e) => return Err(e),
+    };
+
+    if cli.verbose {
+        println!(
+            "{} Context created with {} tokens",
+            "Info:".blue().bold(),
+            ctx.n_ctx()
+        );
+        println!(
+            "{} Flash attention: {}",
+            "Info:".blue().bold(),
+            if flash_attn_active { "enabled" } else { "disabled" }
+        );
+
+        if cli.kv_cache_type_k.is_some() || cli.kv_cache_type_v.is_some() {
+            let type_k = cli.kv_cache_type_k.as_deref().unwrap_or("f16");
+            let type_v = cli.kv_cache_type_v.as_deref().unwrap_or("f16");
+            let savings = kv_cache::savings_vs_f16(type_k, type_v).unwrap_or(0.0);
+            println!(
+                "{} KV cache types: K={} V={} (~{:.0}% {} than f16)",
+                "Info:".blue().bold(),
+                type_k,
+                type_v,
+                savings.abs() * 100.0,
+                if savings >= 0.0 { "smaller" } else { "larger" }
+            );
+        }
+    }
+
+    // Vision models: the text before the image and the image itself go through the projector,
+    // leaving only the text after the image for the regular prompt batch
+    let (n_past, prompt_text, add_bos) = if let Some(image) = &cli.image {
+        let mmproj = cli.mmproj.as_deref().unwrap_or_default();
+        let (text_before, text_after) = vision::split_at_marker(&cli.prompt);
+
+        if cli.verbose {
+            println!("{} Encoding image: {}", "Info:".blue().bold(), image);
+        }
+
+        let n_past = vision::eval_image_prefix(model, &ctx, mmproj, image, text_before, cli.threads, cli.batch_size as i32)?;
+
+        if cli.verbose {
+            println!(
+                "{} Image encoded into {} context positions",
+                "Info:".blue().bold(),
+                n_past
+            );
+        }
+
+        // Generation needs at least one text token after the image to read logits from
+        let text_after = if text_after.is_empty() { "\n" } else { text_after };
+        (n_past, text_after, AddBos::Never)
+    } else {
+        (0, cli.prompt.as_str(), AddBos::Always)
+    };
+
+    // Tokenize the prompt
+    let tokens = match early_tokens {
+        Some(tokens) if cli.image.is_none() => tokens,
+        _ => model
+            .str_to_token(prompt_text, add_bos)
+            .map_err(|e| anyhow::anyhow!("Failed to tokenize prompt: {}", e))?,
+    };
+    let tokens = fit_prompt(model, tokens, ctx.n_ctx() as usize - n_past as usize, &cli)?;
+
+    if cli.verbose {
+        println!(
+            "{} Prompt tokenized: {} tokens",
+            "Info:".blue().bold(),
+            tokens.len()
+        );
+
+        let stop_ids: Vec<String> = StopTokens::for_model(model, &cli.stop_tokens)?
+            .ids()
+            .iter()
+            .map(|id| id.to_string())
+            .collect();
+        println!("{} Stop tokens: {}", "Info:".blue().bold(), stop_ids.join(", "));
+    }
+
+    // --load-state: the restored context replaces prompt processing and the prompt continues it
+    let fingerprint = state::model_fingerprint(&ModelSummary::from_model(model));
+    let (n_past, tokens, mut state_prefix) = match &cli.load_state {
+        Some(path) => {
+            let mut restored = state::load(&mut ctx, path, &fingerprint)?;
+            if cli.verbose {
+                println!(
+                    "{} Restored {} tokens of context from {}",
+                    "Info:".blue().bold(),
+                    restored.len(),
+                    path.display()
+                );
+            }
+
+            let continuation = model
+                .str_to_token(&cli.prompt, AddBos::Never)
+                .map_err(|e| anyhow::anyhow!("Failed to tokenize prompt: {}", e))?;
+            let continuation = fit_prompt(model, continuation, ctx.n_ctx() as usize - restored.len(), &cli)?;
+
+            if continuation.is_empty() {
+                // Generation needs at least one new token to read logits from, so re-decode the last one
+                let last = restored
+                    .pop()
+                    .ok_or_else(|| anyhow::anyhow!("State file '{}' holds no tokens", path.display()))?;
+                ctx.clear_kv_cache_seq(Some(0), Some(restored.len() as u32), None)
+                    .map_err(|e| anyhow::anyhow!("Failed to reset context: {}", e))?;
+                (restored.len() as i32, vec![last], restored)
+            } else {
+                (restored.len() as i32, continuation, restored)
+            }
+        }
+        None => (n_past, tokens, Vec::new()),
+    };
+
+    // --interactive-first: ingest the prompt now, then wait for one line to generate from
+    let (n_past, tokens) = if cli.interactive_first {
+        ingest_tokens(engine, &mut ctx, &tokens, n_past, &cli)?;
+        state_prefix.extend_from_slice(&tokens);
+
+        print_prompt(&cli);
+        println!();
+        print!("{} ", ">".green().bold());
+        io::stdout().flush()?;
+
+        let mut input = String::new();
+        io::stdin().read_line(&mut input)?;
+        // Generation needs at least one new token to read logits from
+        if input.is_empty() {
+            input.push('\n');
+        }
+
+        let input_tokens = model
+            .str_to_token(&input, AddBos::Never)
+            .map_err(|e| anyhow::anyhow!("Failed to tokenize input: {}", e))?;
+
+        // The line goes after the ingested prompt, so it only gets what the prompt left of the context
+        let n_past = n_past + tokens.len() as i32;
+        let available = ctx.n_ctx() as usize - n_past as usize;
+        if input_tokens.len() >= available && cli.truncate.is_none() {
+            return Err(anyhow::anyhow!(
+                "Input ({} tokens) does not fit in the {} tokens of context left after the prompt; use --truncate head|tail|middle or a larger --ctx-size",
+                input_tokens.len(),
+                available
+            ));
+        }
+        (n_past, fit_prompt(model, input_tokens, available, &cli)?)
+    } else {
+        (n_past, tokens)
+    };
+
+    // With --best-of or --beams, candidates are buffered and only the winner is printed
+    let stream_output = cli.best_of == 1 && cli.beams == 1;
+
+    // Print prompt if not verbose (so user sees what they're generating from)
+    if stream_output && !cli.interactive_first && !cli.quiet {
+        if !cli.verbose {
+            print_prompt(&cli);
+        }
+        println!(); // New line after prompt
+    }
+
+    // Start each run with an empty token dump
+    if let Some(path) = &cli.dump_tokens_file {
+        fs::File::create(path)
+            .map_err(|e| anyhow::anyhow!("Failed to create token dump file '{}': {}", path, e))?;
+    }
+
+    // Settle the seed once so it can be reported and reused to reproduce the run
+    if cli.seed.is_none() {
+        cli.seed = Some(sampling_seed(&cli));
+        if cli.seed_from_prompt && cli.verbose {
+            println!("{} Seed derived from prompt: {}", "Info:".blue().bold(), derive_prompt_seed(&cli));
+        }
+    }
+
+    let Some(range) = cli.temp_range else {
+        let generation = generate_and_report(engine, &mut ctx, &tokens, n_past, &cli, stream_output, None)?;
+
+        if let Some(path) = &cli.dump_state {
+            state_prefix.extend_from_slice(&tokens);
+            state_prefix.extend_from_slice(&generation.tokens);
+            state::save(&ctx, path, &state_prefix, &fingerprint)?;
+            if cli.verbose {
+                println!(
+                    "{} Saved {} tokens of context to {}",
+                    "Info:".blue().bold(),
+                    state_prefix.len(),
+                    path.display()
+                );
+            }
+        }
+
+        return Ok(generation);
+    };
+
+    // --temp-range: one labelled completion per temperature, all from the same prompt; with
+    // --format json their objects are printed together as one array
+    let mut outputs = Vec::new();
+    let mut sweep = Generation {
+        text: String::new(),
+        tokens_generated: 0,
+        log_prob_sum: 0.0,
+        scored_tokens: 0,
+        token_log_probs: Vec::new(),
+        tokens: Vec::new(),
+    };
+    for temperature in range.values() {
+        let label = format!("[temperature {:.2}]", temperature);
+        if cli.format == OutputFormat::Json {
+            // Each object carries its temperature instead
+        } else if !cli.no_color {
+            println!("\n{}", label.cyan().bold());
+        } else {
+            println!("\n{}", label);
+        }
+
+        let mut run = cli.clone();
+        run.temperature = temperature;
+        let generation =
+            generate_and_report(engine, &mut ctx, &tokens, n_past, &run, stream_output, Some(&mut outputs))?;
+
+        if !sweep.text.is_empty() {
+            sweep.text.push_str("\n\n");
+        }
+        sweep.text.push_str(&format!("{}\n{}", label, generation.text));
+        sweep.tokens_generated += generation.tokens_generated;
+        sweep.log_prob_sum += generation.log_prob_sum;
+        sweep.scored_tokens += generation.scored_tokens;
+        sweep.token_log_probs.extend(generation.token_log_probs);
+        sweep.tokens.extend(generation.tokens);
+    }
+
+    if cli.format == OutputFormat::Json {
+        println!("{}", serde_json::to_string(&outputs)?);
+    }
+
+    Ok(sweep)
+}
+
+/// Generate the --best-of candidates (or beams) from an ingested context, print the result
+/// and return the best one. In JSON mode a --temp-range sweep collects the result's object in
+/// `sweep_outputs` instead of printing it.
+fn generate_and_report<'a>(
+    engine: &'a InferenceEngine,
+    ctx: &mut LlamaContext<'a>,
+    tokens: &[LlamaToken],
+    n_past: i32,
+    cli: &RunConfig,
+    stream_output: bool,
+    sweep_outputs: Option<&mut Vec<serde_json::Value>>,
+) -> Result<Generation> {
+    let params = sampling_params(cli)?;
+    let seed = sampling_seed(cli);
+
+    // Generate tokens
+    let start_time = Instant::now();
+    let mut generations = Vec::with_capacity(cli.best_of);
+
+    for candidate in 0..cli.best_of {
+        if cli.verbose && cli.best_of > 1 {
+            println!(
+                "{} Generating candidate {}/{}",
+                "Info:".blue().bold(),
+                candidate + 1,
+                cli.best_of
+            );
+        }
+
+        // Every candidate starts from a freshly ingested prompt; an encoded image is kept
+        ctx.clear_kv_cache_seq(Some(0), Some(n_past as u32), None)
+            .map_err(|e| anyhow::anyhow!("Failed to reset context: {}", e))?;
+        // Each candidate draws from its own seed, so --best-of gets different completions
+        let params = SamplingParams {
+            seed: Some(seed.wrapping_add(candidate as u64)),
+            ..params.clone()
+        };
+        let generation = if cli.beams > 1 {
+            generate_beams(engine, ctx, tokens, n_past, cli, &params)?
+        } else {
+            generate_candidate(engine, ctx, tokens, n_past, cli, &params, stream_output)?
+        };
+        generations.push(generation);
+    }
+
+    let generation_time = start_time.elapsed();
+    let tokens_generated: usize = generations.iter().map(|g| g.tokens_generated).sum();
+
+    let best_index = best_generation_index(&generations).unwrap_or(0);
+    let beam_score = (cli.beams > 1).then(|| generations[best_index].log_prob_sum);
+
+    if cli.format == OutputFormat::Json {
+        let output = RunOutput::new(&generations[best_index], tokens_generated, generation_time, beam_score, cli);
+        match sweep_outputs {
+            Some(outputs) => outputs.push(serde_json::to_value(SweepOutput { temperature: cli.temperature, output })?),
+            None => println!("{}", output.to_json()?),
+        }
+        return Ok(generations.swap_remove(best_index));
+    }
+
+    if !stream_output && !cli.quiet {
+        if cli.verbose {
+            for (i, generation) in generations.iter().enumerate() {
+                println!(
+                    "\n{} Candidate {} (avg log-prob {:.4}){}",
+                    "Info:".blue().bold(),
+                    i + 1,
+                    generation.avg_log_prob(),
+                    if i == best_index { " ← best" } else { "" }
+                );
+                println!("{}", generation.text);
+            }
+            println!();
+        } else {
+            print_prompt(cli);
+            println!();
+        }
+
+        let best = &generations[best_index];
+        if cli.show_probs {
+            for (piece, log_prob) in &best.token_log_probs {
+                print_piece(piece, Some(*log_prob), cli);
+            }
+        } else {
+            print_piece(&best.text, None, cli);
+        }
+    }
+
+    if !cli.quiet {
+        println!(); // New line after generation
+    }
+
+    // Show statistics if requested
+    if cli.stats {
+        print_stats(tokens_generated, generation_time, beam_score, cli);
+    }
+
+    if cli.verbose {
+        if cli.best_of > 1 {
+            println!(
+                "{} Selected candidate {}/{} (avg log-prob {:.4})",
+                "Info:".blue().bold(),
+                best_index + 1,
+                cli.best_of,
+                generations[best_index].avg_log_prob()
+            );
+        }
+        println!("{} Generation completed!", "Success:".green().bold());
+    }
+
+    Ok(generations.swap_remove(best_index))
+}
+
+/// Load the model once and serve the OpenAI-compatible HTTP API until the process is stopped
+async fn run_server(defaults: RunConfig, host: &str, port: u16, verbose: bool) -> Result<()> {
+    validate_args(&defaults)?;
+
+    let addr: std::net::SocketAddr = format!("{}:{}", host, port)
+        .parse()
+        .map_err(|e| anyhow::anyhow!("Invalid listen address '{}:{}': {}", host, port, e))?;
+    let model_path = resolve_model_path(&defaults, true).await?;
+
+    init_llama_logs(verbose);
+
+    eprintln!("{} Loading model: {}", "Info:".blue().bold(), model_path.display());
+    let mut engine = InferenceEngine::load_with_params(&model_path, &build_model_params(&defaults))?
+        .with_ctx_size(defaults.ctx_size.unwrap_or(DEFAULT_CTX_SIZE))
+        .with_batch_size(defaults.batch_size);
+    if let Some(threads) = defaults.threads {
+        engine = engine.with_threads(threads);
+    }
+
+    // Requests are sampled like `run`; they can only override what the API exposes
+    let sampling = sampling_params(&defaults)?;
+    server::serve(engine, defaults.model, sampling, addr, verbose).await
+}
+
+/// Print the tokens `config.prompt` is split into, with their pieces and the total count
+async fn run_tokenize(config: RunConfig, add_bos: bool, special: bool, format: OutputFormat, verbose: bool) -> Result<()> {
+    let model_path = resolve_model_path(&config, true).await?;
+
+    init_llama_logs(verbose);
+    if verbose {
+        eprintln!("{} Loading model: {}", "Info:".blue().bold(), model_path.display());
+    }
+    let engine = InferenceEngine::load(&model_path)?;
+    let model = engine.model();
+
+    let tokens = model
+        .str_to_token(&config.prompt, if add_bos { AddBos::Always } else { AddBos::Never })
+        .map_err(|e| anyhow::anyhow!("Failed to tokenize prompt: {}", e))?;
+    let special = if special { Special::Tokenize } else { Special::Plaintext };
+    // Byte-fallback tokens can hold part of a UTF-8 character, which has no text of its own
+    let pieces: Vec<Option<String>> = tokens.iter().map(|&token| model.token_to_str(token, special).ok()).collect();
+
+    if format == OutputFormat::Json {
+        let tokens: Vec<_> = tokens
+            .iter()
+            .zip(&pieces)
+            .map(|(token, piece)| serde_json::json!({"id": token.0, "piece": piece}))
+            .collect();
+        println!("{}", serde_json::json!({"count": tokens.len(), "tokens": tokens}));
+        return Ok(());
+    }
+
+    for (token, piece) in tokens.iter().zip(&pieces) {
+        match piece {
+            Some(piece) => println!("{:>8} {:?}", token.0, piece),
+            None => println!("{:>8} <partial character>", token.0),
+        }
+    }
+    println!("{} {} tokens", "Total:".green().bold(), tokens.len());
+    Ok(())
+}
+
+/// Time prompt processing and generation on a synthetic prompt, after a warmup pass
+async fn run_bench(
+    config: RunConfig,
+    runs: usize,
+    prompt_tokens: usize,
+    gen_tokens: usize,
+    format: OutputFormat,
+    verbose: bool,
+) -> Result<()> {
+    if runs == 0 || prompt_tokens == 0 || gen_tokens == 0 {
+        return Err(anyhow::anyhow!("--runs, --prompt-tokens and --gen-tokens must be at least 1"));
+    }
+    validate_args(&config)?;
+
+    let model_path = resolve_model_path(&config, true).await?;
+
+    init_llama_logs(verbose);
+
+    if verbose {
+        eprintln!("{} Loading model: {}", "Info:".blue().bold(), model_path.display());
+    }
+
+    let engine = InferenceEngine::load_with_params(&model_path, &build_model_params(&config))?
+        .with_batch_size(config.batch_size);
+    let model = engine.model();
+    let mut ctx = engine.new_context(build_context_params(&config, false)?)?;
+
+    // Greedy and blind to end-of-sequence, so every pass generates the same tokens
+    let params = SamplingParams {
+        max_tokens: gen_tokens,
+        ignore_eos: true,
+        temperature: 0.0,
+        seed: Some(0),
+        ..sampling_params(&config)?
+    };
+
+    // Repeat the filler until there are enough tokens, then cut it to length
+    let mut prompt = model
+        .str_to_token(&bench::BENCH_TEXT.repeat(prompt_tokens / 4 + 1), AddBos::Always)
+        .map_err(|e| anyhow::anyhow!("Failed to tokenize prompt: {}", e))?;
+    prompt.truncate(prompt_tokens);
+
+    let mut timings = Vec::with_capacity(runs);
+    for pass in 0..=runs {
+        ctx.clear_kv_cache();
+
+        let start_time = Instant::now();
+        let mut stream = engine.stream_in(&mut ctx, &prompt, 0, &params, |_| check_deadline(&config))?;
+        let prompt_time = start_time.elapsed();
+
+        let start_time = Instant::now();
+        while stream.next_token()?.is_some() {}
+        let gen_time = start_time.elapsed();
+
+        let run = bench::BenchRun::new(prompt.len(), prompt_time, gen_tokens, gen_time);
+        if verbose {
+            eprintln!(
+                "{} {}: prompt {:.2} tokens/sec, generation {:.2} tokens/sec",
+                "Info:".blue().bold(),
+                if pass == 0 { "Warmup".to_string() } else { format!("Run {}/{}", pass, runs) },
+                run.prompt_tokens_per_sec,
+                run.gen_tokens_per_sec
+            );
+        }
+        if pass > 0 {
+            timings.push(run);
+        }
+    }
+
+    let report = bench::BenchReport::new(
+        model_path.display().to_string(),
+        prompt.len(),
+        gen_tokens,
+        config.threads,
+        config.batch_size,
+        timings,
+    );
+
+    if format == OutputFormat::Json {
+        println!("{}", serde_json::to_string(&report)?);
+        return Ok(());
+    }
+
+    println!(
+        "{} {} ({} runs, {} threads, batch size {})",
+        "Benchmark:".green().bold(),
+        report.model,
+        runs,
+        report.threads.map_or("auto".to_string(), |threads| threads.to_string()),
+        report.batch_size
+    );
+    println!("Prompt processing: {} tokens at {:.2} tokens/sec", report.prompt_tokens, report.prompt_tokens_per_sec);
+    println!("Generation:        {} tokens at {:.2} tokens/sec", report.gen_tokens, report.gen_tokens_per_sec);
+    Ok(())
+}
+
+/// Load the model once and answer newline-delimited JSON requests from stdin until EOF
+async fn run_pipe(defaults: RunConfig, verbose: bool) -> Result<()> {
+    validate_args(&defaults)?;
+
+    let model_path = resolve_model_path(&defaults, true).await?;
+
+    init_llama_logs(verbose);
+
+    if verbose {
+        eprintln!("{} Loading model: {}", "Info:".blue().bold(), model_path.display());
+    }
+
+    let engine = InferenceEngine::load_with_params(&model_path, &build_model_params(&defaults))?
+        .with_batch_size(defaults.batch_size);
+    let mut ctx = engine.new_context(build_context_params(&defaults, defaults.flash_attn)?)?;
+
+    if verbose {
+        eprintln!("{} Ready, reading requests from stdin", "Info:".blue().bold());
+    }
+
+    for line in io::stdin().lock().lines() {
+        if interrupt::interrupted() {
+            break;
+        }
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let start_time = Instant::now();
+        let response = match PipeRequest::parse(&line) {
+            Ok(request) => {
+                let id = request.id.clone();
+                let config = request.apply(&defaults);
+                match pipe_generate(&engine, &mut ctx, &config) {
+                    Ok((prompt_tokens, generation)) => PipeResponse {
+                        id,
+                        prompt_tokens,
+                        tokens_generated: generation.tokens_generated,
+                        avg_log_prob: Some(generation.avg_log_prob()).filter(|p| p.is_finite()),
+                        text: Some(generation.text),
+                        error: None,
+                    },
+                    Err(e) => PipeResponse::error(id, &e),
+                }
+            }
+            Err(e) => PipeResponse::error(None, &e),
+        };
+
+        if verbose {
+            eprintln!(
+                "{} Request done in {:.2}s: {} tokens{}",
+                "Info:".blue().bold(),
+                start_time.elapsed().as_secs_f64(),
+                response.tokens_generated,
+                response.error.as_deref().map(|e| format!(" (error: {})", e)).unwrap_or_default()
+            );
+        }
+
+        let mut stdout = io::stdout().lock();
+        writeln!(stdout, "{}", response.to_line())?;
+        stdout.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Answer one pipe request on a cleared KV cache, returning the prompt length and the generation
+fn pipe_generate<'a>(engine: &'a InferenceEngine, ctx: &mut LlamaContext<'a>, config: &RunConfig) -> Result<(usize, Generation)> {
+    validate_args(config)?;
+    let model = engine.model();
+
+    // Each request starts from an empty context
+    ctx.clear_kv_cache();
+
+    let tokens = model
+        .str_to_token(&config.prompt, AddBos::Always)
+        .map_err(|e| anyhow::anyhow!("Failed to tokenize prompt: {}", e))?;
+
+    let n_ctx = ctx.n_ctx() as usize;
+    let tokens = fit_prompt(model, tokens, n_ctx, config)?;
+    if tokens.len() + config.max_tokens > n_ctx {
+        return Err(anyhow::anyhow!(
+            "Prompt ({} tokens) plus max_tokens ({}) exceeds the context size of {} tokens",
+            tokens.len(),
+            config.max_tokens,
+            n_ctx
+        ));
+    }
+
+    let params = SamplingParams {
+        seed: Some(sampling_seed(config)),
+        ..sampling_params(config)?
+    };
+    let generation = generate_candidate(engine, ctx, &tokens, 0, config, &params, false)?;
+    Ok((tokens.len(), generation))
+}
+
+/// Apply --truncate to a prompt that leaves less than --max-tokens of the `available` context.
+/// Without a strategy, a prompt that can't fit at all is an error; one that merely eats into
+/// --max-tokens is left for generation to stop early.
+fn fit_prompt(model: &LlamaModel, tokens: Vec<LlamaToken>, available: usize, cli: &RunConfig) -> Result<Vec<LlamaToken>> {
+    let budget = available.saturating_sub(cli.max_tokens);
+    if tokens.len() <= budget {
+        return Ok(tokens);
+    }
+
+    let Some(strategy) = cli.truncate else {
+        if tokens.len() >= available {
+            return Err(anyhow::anyhow!(
+                "Prompt ({} tokens) does not fit in the context of {} tokens; use --truncate head|tail|middle or a larger --ctx-size",
+                tokens.len(),
+                available
+            ));
+        }
+        return Ok(tokens);
+    };
+
+    if budget == 0 {
+        return Err(anyhow::anyhow!(
+            "--max-tokens ({}) leaves no room for the prompt in a context of {} tokens",
+            cli.max_tokens,
+            available
+        ));
     }
 
-    // Resolve model path (download if necessary)
-    let model_path = if is_hf_model_id(&cli.model) {
+    let keep_prefix = usize::from(tokens.first() == Some(&model.token_bos()));
+    let kept = truncate::truncate_tokens(&tokens, budget, keep_prefix, strategy);
+    eprintln!(
+        "{} Prompt truncated ({}): dropped {} of {} tokens to leave room for {} generated tokens",
+        "Warning:".yellow().bold(),
+        format!("{:?}", strategy).to_lowercase(),
+        tokens.len() - kept.len(),
+        tokens.len(),
+        cli.max_tokens
+    );
+    Ok(kept)
+}
+
+/// Resolve the model argument to a local GGUF path, downloading from Hugging Face if needed.
+/// `quiet` keeps download status messages off stdout.
+async fn resolve_model_path(cli: &RunConfig, quiet: bool) -> Result<PathBuf> {
+    if is_hf_model_id(&cli.model) {
         // Download from Hugging Face
         if cli.verbose {
             println!(
@@ -370,11 +2698,20 @@ async fn run_inference(cli: RunConfig) -> Result<String> {
             );
         }
         
-        let downloader = ModelDownloader::new(cli.cache_dir.clone())?;
+        let downloader = ModelDownloader::new(cli.cache_dir.clone())?
+            .with_endpoints(cli.hf_endpoints.clone())
+            .with_verbose(cli.verbose)
+            .with_quiet(quiet)
+            .with_revision(cli.revision.clone())
+            .with_pinned_revision(cli.pin)
+            .with_verify(!cli.no_verify)
+            .with_token(cli.hf_token.clone());
         
         // If no specific filename provided, try to auto-detect
         let filename_to_download = if let Some(filename) = &cli.hf_filename {
             filename.clone()
+        } else if cli.auto_quant {
+            auto_select_quant(&downloader, &cli.model).await?
         } else {
             // List available files and try to find a suitable one
             if cli.verbose {
@@ -408,7 +2745,7 @@ async fn run_inference(cli: RunConfig) -> Result<String> {
             }
         };
         
-        downloader.download_model(&cli.model, &filename_to_download, cli.force_download).await?
+        downloader.download_model(&cli.model, &filename_to_download, cli.force_download).await
     } else {
         // Local file path
         let path = PathBuf::from(&cli.model);
@@ -424,55 +2761,111 @@ async fn run_inference(cli: RunConfig) -> Result<String> {
             );
             std::process::exit(1);
         }
-        path
-    };
-
-    if cli.verbose {
-        println!(
-            "{} Initializing llama.cpp backend...",
-            "Info:".blue().bold()
-        );
+        Ok(path)
     }
+}
 
-    // Initialize llama backend
-    let backend = LlamaBackend::init()
-        .map_err(|e| anyhow::anyhow!("Failed to initialize llama backend: {}", e))?;
-
-    if cli.verbose {
-        println!("{} Loading model: {}", "Info:".blue().bold(), model_path.display());
-    }
+/// The engine's sampling parameters for a run: every stage the CLI configures, with the grammar of
+/// --grammar-file or --json-schema loaded
+fn sampling_params(cli: &RunConfig) -> Result<SamplingParams> {
+    // llama.cpp masks the tokens that can't continue a string the grammar accepts
+    let grammar = match (&cli.grammar_file, &cli.json_schema) {
+        (Some(path), _) => Some(grammar::load_grammar(path)?),
+        (None, Some(path)) => Some(
+            json_schema::schema_to_grammar(&json_schema::load_schema(path)?)
+                .map_err(|e| anyhow::anyhow!("Can't convert JSON schema to a grammar: {}", e))?,
+        ),
+        (None, None) => None,
+    };
 
-    // Set up model parameters
-    let model_params = LlamaModelParams::default();
+    Ok(SamplingParams {
+        max_tokens: cli.max_tokens,
+        min_tokens: cli.min_tokens,
+        ignore_eos: cli.ignore_eos,
+        temperature: cli.temperature,
+        dynatemp_range: cli.dynatemp_range,
+        dynatemp_exponent: cli.dynatemp_exponent,
+        top_k: cli.top_k,
+        top_p: cli.top_p,
+        repeat_penalty: cli.repeat_penalty,
+        repeat_last_n: cli.repeat_last_n,
+        prompt_affinity: cli.prompt_affinity,
+        mirostat: cli.mirostat,
+        mirostat_tau: cli.mirostat_tau,
+        mirostat_eta: cli.mirostat_eta,
+        seed: cli.seed,
+        stop: cli.stop.clone(),
+        stop_tokens: cli.stop_tokens.clone(),
+        ban_words: cli.ban_words.clone(),
+        logit_bias: cli.logit_bias.clone(),
+        grammar,
+        single_line: cli.single_line,
+        stop_on_repeat: cli.stop_on_repeat,
+        n_keep: cli.n_keep,
+        // The saved state lists the tokens behind every cached position, which a shift breaks
+        context_shift: cli.dump_state.is_none(),
+    })
+}
 
-    // Load the model with progress indication
-    let loading_msg = format!("Loading model: {}", model_path.display());
-    let pb = if !cli.no_color {
-        let pb = ProgressBar::new_spinner();
-        pb.set_style(
-            ProgressStyle::default_spinner()
-                .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ")
-                .template("{spinner:.green} {msg}")
-                .unwrap(),
-        );
-        pb.set_message(loading_msg);
-        pb.enable_steady_tick(std::time::Duration::from_millis(100));
-        Some(pb)
-    } else {
-        println!("Loading model...");
-        None
-    };
+/// Seed for the sampling RNG: --seed if given, derived from the prompt with --seed-from-prompt,
+/// random otherwise
+fn sampling_seed(cli: &RunConfig) -> u64 {
+    cli.seed.unwrap_or_else(|| if cli.seed_from_prompt { derive_prompt_seed(cli) } else { rand::random() })
+}
 
-    let model = LlamaModel::load_from_file(&backend, model_path.to_string_lossy().as_ref(), &model_params)
-        .map_err(|e| anyhow::anyhow!("Failed to load model: {}", e))?;
+/// Derive a reproducible seed from the prompt and the parameters that shape sampling,
+/// so the same request always yields the same seed while different prompts vary
+pub fn derive_prompt_seed(cli: &RunConfig) -> u64 {
+    let mut hasher = Sha256::new();
+    hasher.update(cli.prompt.as_bytes());
+    hasher.update(cli.temperature.to_le_bytes());
+    hasher.update((cli.top_k as u64).to_le_bytes());
+    hasher.update(cli.top_p.to_le_bytes());
+    hasher.update((cli.max_tokens as u64).to_le_bytes());
+
+    let digest = hasher.finalize();
+    let mut seed_bytes = [0u8; 8];
+    seed_bytes.copy_from_slice(&digest[..8]);
+    u64::from_le_bytes(seed_bytes)
+}
 
-    if let Some(pb) = &pb {
-        pb.finish_with_message("Model loaded successfully ✓".green().to_string());
+/// Context size for --auto-ctx: the needed tokens rounded up to AUTO_CTX_ALIGN,
+/// capped at the model's trained context length when it is known
+pub fn auto_ctx_size(needed: usize, n_ctx_train: u32) -> u32 {
+    let rounded = needed.div_ceil(AUTO_CTX_ALIGN) * AUTO_CTX_ALIGN;
+    if n_ctx_train > 0 {
+        rounded.min(n_ctx_train as usize) as u32
     } else {
-        println!("Model loaded successfully");
+        rounded as u32
     }
+}
+
+/// Build llama.cpp model parameters from the run configuration
+fn build_model_params(cli: &RunConfig) -> LlamaModelParams {
+    LlamaModelParams::default()
+        .with_use_mmap(!cli.no_mmap)
+        .with_use_mlock(cli.mlock)
+}
 
-    // Set up context parameters
+/// Send llama.cpp's log to stderr: warnings and errors always, and its info and debug lines (load
+/// details, timings) only with `verbose`. The first call decides for the rest of the process.
+fn init_llama_logs(verbose: bool) {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        send_logs_to_tracing(LogOptions::default());
+        let level = if verbose { LevelFilter::DEBUG } else { LevelFilter::WARN };
+        // Fails only if a subscriber is already installed, which then receives the log instead
+        let _ = tracing_subscriber::fmt()
+            .with_max_level(level)
+            .with_writer(io::stderr)
+            .without_time()
+            .with_target(false)
+            .try_init();
+    });
+}
+
+/// Build llama.cpp context parameters from the run configuration
+fn build_context_params(cli: &RunConfig, flash_attn: bool) -> Result<LlamaContextParams> {
     let mut ctx_params = LlamaContextParams::default();
 
     if let Some(ctx_size) = cli.ctx_size {
@@ -480,170 +2873,529 @@ async fn run_inference(cli: RunConfig) -> Result<String> {
             ctx_params = ctx_params.with_n_ctx(Some(non_zero_ctx));
         }
     } else {
-        ctx_params = ctx_params.with_n_ctx(Some(NonZeroU32::new(2048).unwrap()));
+        ctx_params = ctx_params.with_n_ctx(NonZeroU32::new(DEFAULT_CTX_SIZE));
     }
 
     if let Some(threads) = cli.threads {
         ctx_params = ctx_params.with_n_threads(threads);
     }
 
-    if cli.verbose {
-        println!("{} Creating context...", "Info:".blue().bold());
+    if let Some(type_k) = &cli.kv_cache_type_k {
+        ctx_params = ctx_params.with_type_k(kv_cache::parse_kv_cache_type(type_k)?);
     }
 
-    // Create context from model
-    let mut ctx = model
-        .new_context(&backend, ctx_params)
-        .map_err(|e| anyhow::anyhow!("Failed to create context: {}", e))?;
+    if let Some(type_v) = &cli.kv_cache_type_v {
+        ctx_params = ctx_params.with_type_v(kv_cache::parse_kv_cache_type(type_v)?);
+    }
 
-    if cli.verbose {
-        println!(
-            "{} Context created with {} tokens",
-            "Info:".blue().bold(),
-            ctx.n_ctx()
-        );
+    if flash_attn {
+        ctx_params = ctx_params.with_flash_attention(true);
     }
 
-    // Tokenize the prompt
-    let tokens = model
-        .str_to_token(&cli.prompt, AddBos::Always)
-        .map_err(|e| anyhow::anyhow!("Failed to tokenize prompt: {}", e))?;
+    // llama.cpp rejects a decode of more tokens than the context's batch size
+    ctx_params = ctx_params.with_n_batch(cli.batch_size as u32);
 
-    if cli.verbose {
-        println!(
-            "{} Prompt tokenized: {} tokens",
-            "Info:".blue().bold(),
-            tokens.len()
-        );
+    // llama.cpp's own perf timings duplicate --stats, so they are only kept with --verbose
+    ctx_params = ctx_params.with_no_perf(cli.no_perf || !cli.verbose);
+
+    Ok(ctx_params)
+}
+
+/// Replace a file's contents via a temporary file and rename, so readers never see a partial write
+fn write_atomic(path: &str, contents: &str) -> Result<()> {
+    let temp_path = format!("{}.tmp", path);
+    fs::write(&temp_path, contents)
+        .and_then(|_| fs::rename(&temp_path, path))
+        .map_err(|e| anyhow::anyhow!("Failed to write '{}': {}", path, e))
+}
+
+/// Whether a single streamed completion writes --output-file piece by piece: always for a FIFO,
+/// and for a config task's output_file
+fn streams_output_file(cli: &RunConfig) -> bool {
+    cli.best_of == 1
+        && cli.beams == 1
+        && cli.temp_range.is_none()
+        && cli
+            .output_file
+            .as_deref()
+            .is_some_and(|path| cli.stream_output_file || fifo::is_fifo(Path::new(path)))
+}
+
+/// Open --output-file for streaming: a FIFO as is, a regular file under an exclusive lock
+fn open_streamed_output(path: &str, cli: &RunConfig) -> Result<io::BufWriter<fs::File>> {
+    let path = Path::new(path);
+    if fifo::is_fifo(path) {
+        fifo::open_writer(path)
+    } else {
+        file_lock::open_locked(path, cli.append, cli.output_lock_wait).map(io::BufWriter::new)
+    }
+}
+
+/// The live tokens/sec line needs a color terminal for both the text and itself, and stays off
+/// when other output (verbose logs, --show-probs annotations, JSON) would be interleaved with it
+fn shows_live_progress(cli: &RunConfig) -> bool {
+    !cli.no_color
+        && !cli.quiet
+        && !cli.verbose
+        && !cli.show_probs
+        && cli.format == OutputFormat::Text
+        && io::stdout().is_terminal()
+        && io::stderr().is_terminal()
+}
+
+/// Fail once a task's deadline has passed; checked between decode steps so a timed-out task stops promptly
+fn check_deadline(cli: &RunConfig) -> Result<()> {
+    match cli.deadline {
+        Some(deadline) if Instant::now() >= deadline => {
+            Err(anyhow::anyhow!("Timed out: generation exceeded the task timeout"))
+        }
+        _ => Ok(()),
     }
+}
 
-    // Create batch for processing tokens
-    let mut batch = LlamaBatch::new(512, 1);
+/// A single completed generation and the score accumulated while sampling it
+pub struct Generation {
+    pub text: String,
+    pub tokens_generated: usize,
+    /// Sum of the natural-log probabilities of every sampled token, including a terminating EOS
+    pub log_prob_sum: f64,
+    /// Number of sampled tokens contributing to `log_prob_sum`
+    pub scored_tokens: usize,
+    /// Each emitted piece with its log-probability (only recorded with `--show-probs`)
+    pub token_log_probs: Vec<(String, f32)>,
+    /// Generated token IDs, excluding the stop token or newline that ended generation
+    pub tokens: Vec<LlamaToken>,
+}
 
-    // Add prompt tokens to batch
-    for (i, &token) in tokens.iter().enumerate() {
-        let is_last = i == tokens.len() - 1;
-        batch
-            .add(token, i as i32, &[0], is_last)
-            .map_err(|e| anyhow::anyhow!("Failed to add token to batch: {}", e))?;
+impl Generation {
+    /// Average log-probability per sampled token, the metric used by `--best-of`
+    pub fn avg_log_prob(&self) -> f64 {
+        if self.scored_tokens == 0 {
+            f64::NEG_INFINITY
+        } else {
+            self.log_prob_sum / self.scored_tokens as f64
+        }
     }
+}
 
+/// Index of the generation with the highest average token log-probability
+pub fn best_generation_index(generations: &[Generation]) -> Option<usize> {
+    generations
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.avg_log_prob().total_cmp(&b.avg_log_prob()))
+        .map(|(i, _)| i)
+}
+
+/// Progress bar for decoding a prompt of `len` tokens. Only prompts spanning several batches are
+/// slow enough to need one.
+fn prompt_progress(len: usize, cli: &RunConfig) -> Option<ProgressBar> {
     if cli.verbose {
         println!("{} Processing prompt...", "Info:".blue().bold());
     }
 
-    // Process the prompt
-    ctx.decode(&mut batch)
-        .map_err(|e| anyhow::anyhow!("Failed to process prompt: {}", e))?;
+    if cli.no_color || cli.quiet || len <= cli.batch_size {
+        return None;
+    }
+    let pb = ProgressBar::new(len as u64);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} Processing prompt [{bar:30.cyan/blue}] {pos}/{len} tokens")
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+    Some(pb)
+}
 
-    // Print prompt if not verbose (so user sees what they're generating from)
-    if !cli.verbose {
-        if !cli.no_color {
-            print!("{}", cli.prompt.bright_blue());
-        } else {
-            print!("{}", cli.prompt);
+/// After each decoded prompt chunk: stop past the deadline and advance the progress bar
+fn prompt_chunk_done(pb: Option<&ProgressBar>, done: usize, cli: &RunConfig) -> Result<()> {
+    check_deadline(cli)?;
+    if let Some(pb) = pb {
+        pb.set_position(done as u64);
+    }
+    Ok(())
+}
+
+/// Decode prompt tokens starting at position `n_past`
+fn ingest_tokens(
+    engine: &InferenceEngine,
+    ctx: &mut LlamaContext<'_>,
+    tokens: &[LlamaToken],
+    n_past: i32,
+    cli: &RunConfig,
+) -> Result<()> {
+    let pb = prompt_progress(tokens.len(), cli);
+    engine.decode_prompt(ctx, tokens, n_past, |done| prompt_chunk_done(pb.as_ref(), done, cli))?;
+    if let Some(pb) = pb {
+        pb.finish_and_clear();
+    }
+    Ok(())
+}
+
+/// Ingest the prompt (starting at position `n_past`) and generate a single completion, scoring each sampled token
+fn generate_candidate<'a>(
+    engine: &'a InferenceEngine,
+    ctx: &mut LlamaContext<'a>,
+    tokens: &[LlamaToken],
+    n_past: i32,
+    cli: &RunConfig,
+    params: &SamplingParams,
+    stream_output: bool,
+) -> Result<Generation> {
+    let model = engine.model();
+
+    // Token IDs are appended so every --best-of candidate gets its own section
+    let mut token_dump = match &cli.dump_tokens_file {
+        Some(path) => {
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| anyhow::anyhow!("Failed to open token dump file '{}': {}", path, e))?;
+            let mut writer = io::BufWriter::new(file);
+            for token in tokens {
+                writeln!(writer, "{}", token.0)?;
+            }
+            writeln!(writer)?;
+            Some(writer)
         }
+        None => None,
+    };
+
+    // A FIFO --output-file, or a config task's output_file, gets each piece as it is generated
+    // instead of the whole text at the end, so the output can be followed with `tail -f`
+    let mut streamed_output = match &cli.output_file {
+        Some(path) if stream_output && streams_output_file(cli) => Some(open_streamed_output(path, cli)?),
+        _ => None,
+    };
+
+    // --json-schema's grammar is in `params`; the schema itself checks the finished output
+    let schema = cli.json_schema.as_deref().map(json_schema::load_schema).transpose()?;
+
+    let pb = prompt_progress(tokens.len(), cli);
+    let explain_steps = if cli.verbose { cli.explain.unwrap_or(0) } else { 0 };
+    let mut stream = engine
+        .stream_in(&mut *ctx, tokens, n_past, params, |done| prompt_chunk_done(pb.as_ref(), done, cli))?
+        .explain(explain_steps, EXPLAIN_TOP_CANDIDATES);
+    if let Some(pb) = pb {
+        pb.finish_and_clear();
     }
 
-    // Generate tokens
-    let start_time = Instant::now();
-    let mut generated_text = String::new();
-    let mut n_cur = tokens.len() as i32;
-    let mut tokens_generated = 0;
-
-    println!(); // New line after prompt
-
-    for _ in 0..cli.max_tokens {
-        // Sample next token using greedy sampling (simplest approach)
-        // For the first iteration, get logits from the last position of the prompt
-        // For subsequent iterations, get logits from position 0 (the current token)
-        let logit_index = if tokens_generated == 0 {
-            // First generation - get from the last prompt token
-            (tokens.len() - 1) as i32
-        } else {
-            // Subsequent generations - get from position 0
-            0
+    let mut progress = (stream_output && shows_live_progress(cli)).then(LiveProgress::start);
+    let mut token_log_probs = Vec::new();
+
+    let _generating = interrupt::generating();
+    loop {
+        check_deadline(cli)?;
+        if interrupt::interrupted() {
+            if let Some(progress) = progress.take() {
+                progress.finish();
+            }
+            eprintln!("\n{} Interrupted, stopping generation", "Warning:".yellow().bold());
+            break;
+        }
+
+        let step = stream.tokens_generated() + 1;
+        let Some(generated) = stream.next_token()? else {
+            break;
         };
 
-        let candidates: Vec<_> = ctx.candidates_ith(logit_index).collect();
+        if let Some(trace) = &generated.trace {
+            let sampled = SampledToken {
+                token: generated.token,
+                log_prob: generated.log_prob,
+            };
+            print_explanation(model, step, trace, sampled, cli);
+        }
 
-        // Find the token with highest logit (greedy sampling for simplicity)
-        let token = candidates
-            .iter()
-            .max_by(|a, b| a.logit().partial_cmp(&b.logit()).unwrap())
-            .map(|c| c.id())
-            .unwrap_or(model.token_eos());
+        if let Some(writer) = token_dump.as_mut() {
+            writeln!(writer, "{}", generated.token.0)?;
+        }
 
-        // Check for end of generation
-        if token == model.token_eos() {
-            if cli.verbose {
-                println!("\n{} Reached end-of-sequence token", "Info:".blue().bold());
+        if generated.shifted > 0 && cli.verbose {
+            println!(
+                "\n{} Context full: discarded {} tokens after the first {}",
+                "Info:".blue().bold(),
+                generated.shifted,
+                stream.n_keep()
+            );
+        }
+
+        // The stream holds back a tail that could still become a --stop string
+        if stream_output && !cli.quiet && !generated.text.is_empty() {
+            match progress.as_mut() {
+                Some(progress) => progress.print(&generated.text),
+                None => {
+                    print_piece(&generated.text, cli.show_probs.then_some(generated.log_prob), cli);
+                    io::stdout().flush().unwrap();
+                }
             }
-            break;
+        }
+        if let Some(pipe) = streamed_output.as_mut() {
+            pipe.write_all(generated.text.as_bytes())?;
+            pipe.flush()?;
+        }
+        if cli.show_probs && !generated.piece.is_empty() {
+            token_log_probs.push((generated.piece, generated.log_prob));
+        }
+        if let Some(progress) = &progress {
+            progress.inc();
         }
 
-        // Convert token to string
-        if let Ok(piece) = model.token_to_str(token, Special::Tokenize) {
-            generated_text.push_str(&piece);
-            if !cli.no_color {
-                print!("{}", piece.green());
-            } else {
-                print!("{}", piece);
+        // Periodic checkpoint of a long single-candidate run
+        if let (Some(every), Some(path), true, None) = (cli.save_every, &cli.output_file, stream_output, &streamed_output) {
+            if stream.stop_reason().is_none() && stream.tokens_generated() % every == 0 {
+                write_atomic(path, stream.text())?;
             }
-            io::stdout().flush().unwrap();
         }
+    }
 
-        batch.clear();
-        // Add token to batch for next iteration
-        batch
-            .add(token, n_cur, &[0], true)
-            .map_err(|e| anyhow::anyhow!("Failed to add generated token to batch: {}", e))?;
-        ctx.decode(&mut batch)
-            .map_err(|e| anyhow::anyhow!("Failed to decode batch: {}", e))?;
+    if let Some(progress) = progress {
+        progress.finish();
+    }
 
-        n_cur += 1;
-        tokens_generated += 1;
+    match stream.stop_reason() {
+        Some(StopReason::StopToken(token)) if cli.verbose => {
+            println!("\n{} Reached stop token {}", "Info:".blue().bold(), token.0);
+        }
+        Some(StopReason::StopString) if cli.verbose => println!("\n{} Reached stop string", "Info:".blue().bold()),
+        Some(StopReason::EndOfLine) if cli.verbose => println!("\n{} Reached end of line", "Info:".blue().bold()),
+        Some(StopReason::Repetition(ngram)) => eprintln!(
+            "\n{} Stopped due to repetition loop ({}-token sequence repeated {} times)",
+            "Warning:".yellow().bold(),
+            ngram,
+            params.stop_on_repeat.unwrap_or_default()
+        ),
+        _ => {}
     }
 
-    let generation_time = start_time.elapsed();
+    let generation = Generation {
+        text: stream.text().to_string(),
+        tokens_generated: stream.tokens_generated(),
+        log_prob_sum: stream.log_prob_sum(),
+        scored_tokens: stream.scored_tokens(),
+        token_log_probs,
+        tokens: stream.generated().to_vec(),
+    };
 
-    println!(); // New line after generation
+    if let Some(mut writer) = token_dump {
+        writeln!(writer)?;
+        writer.flush()?;
+    }
 
-    // Show statistics if requested
-    if cli.stats {
-        print_stats(tokens_generated, generation_time, &cli);
+    // The grammar guarantees the syntax, but a truncated document or a length or range limit
+    // can still fail the schema
+    if let Some(schema) = &schema {
+        if let Err(e) = json_schema::validate(schema, &generation.text) {
+            eprintln!(
+                "\n{} Output doesn't conform to the JSON schema: {}",
+                "Warning:".yellow().bold(),
+                e
+            );
+        }
+    }
+
+    Ok(generation)
+}
+
+/// Beam search over `--beams` sequences, keeping the one with the highest cumulative log-probability
+fn generate_beams<'a>(
+    engine: &'a InferenceEngine,
+    ctx: &mut LlamaContext<'a>,
+    tokens: &[LlamaToken],
+    n_past: i32,
+    cli: &RunConfig,
+    params: &SamplingParams,
+) -> Result<Generation> {
+    let pb = prompt_progress(tokens.len(), cli);
+    let mut search = engine.beam_search_in(&mut *ctx, tokens, n_past, params, cli.beams, |done| {
+        prompt_chunk_done(pb.as_ref(), done, cli)
+    })?;
+    if let Some(pb) = pb {
+        pb.finish_and_clear();
     }
 
+    let _generating = interrupt::generating();
+    loop {
+        check_deadline(cli)?;
+        if interrupt::interrupted() {
+            eprintln!("\n{} Interrupted, keeping the best beam so far", "Warning:".yellow().bold());
+            break;
+        }
+        if !search.step()? {
+            break;
+        }
+    }
+
+    let best = search.best();
     if cli.verbose {
-        println!("{} Generation completed!", "Success:".green().bold());
+        for (i, beam) in search.beams().iter().enumerate() {
+            println!(
+                "{} Beam {}: {} tokens, score {:.4}{}",
+                "Info:".blue().bold(),
+                i + 1,
+                beam.tokens.len(),
+                beam.score,
+                if beam.finished { " (finished)" } else { "" }
+            );
+        }
+    }
+
+    Ok(Generation {
+        text: search.text(&best),
+        tokens_generated: best.tokens.len(),
+        log_prob_sum: best.score,
+        scored_tokens: best.scored_tokens(),
+        token_log_probs: if cli.show_probs { search.pieces(&best) } else { Vec::new() },
+        tokens: best.tokens,
+    })
+}
+
+/// Print one --explain step to stderr, keeping stdout for the generated text
+fn print_explanation(model: &LlamaModel, step: usize, trace: &FilterTrace, sampled: SampledToken, cli: &RunConfig) {
+    let piece = |token: LlamaToken| {
+        model
+            .token_to_str(token, Special::Tokenize)
+            .map(|piece| format!("{:?}", piece))
+            .unwrap_or_else(|_| format!("<{}>", token.0))
+    };
+
+    let header = format!("[explain] step {}", step);
+    if !cli.no_color {
+        eprintln!("\n{}", header.cyan().bold());
+    } else {
+        eprintln!("\n{}", header);
+    }
+    eprintln!(
+        "  {} candidates → top-k {} → {} left → top-p {:.2} → {} left → temperature {:.2}",
+        trace.total, cli.top_k, trace.after_top_k, cli.top_p, trace.after_top_p, trace.temperature
+    );
+    for (rank, (token, prob)) in trace.top.iter().enumerate() {
+        eprintln!("  {:>2}. {:<20} p={:.3}", rank + 1, piece(*token), prob);
+    }
+    eprintln!("  chosen: {} (p={:.3})", piece(sampled.token), sampled.log_prob.exp());
+}
+
+/// Print a generated piece, optionally annotated with its probability
+fn print_piece(piece: &str, log_prob: Option<f32>, cli: &RunConfig) {
+    if !cli.no_color {
+        print!("{}", piece.green());
+    } else {
+        print!("{}", piece);
+    }
+
+    if let Some(log_prob) = log_prob {
+        let annotation = format!("({:.2})", log_prob.exp());
+        if !cli.no_color {
+            print!("{}", annotation.bright_black());
+        } else {
+            print!("{}", annotation);
+        }
+    }
+}
+
+/// Print a condensed description of the loaded model
+fn print_model_summary(summary: &ModelSummary, cli: &RunConfig) {
+    let lines = [
+        ("Model:", summary.name.clone().unwrap_or_else(|| cli.model.clone())),
+        (
+            "Architecture:",
+            format!(
+                "{} ({} params, {})",
+                summary.architecture,
+                format_param_count(summary.parameters),
+                format_file_size(summary.size_bytes)
+            ),
+        ),
+        ("Quantization:", summary.quantization.clone()),
+        (
+            "Vocab / Context:",
+            format!("{} tokens / {} trained", summary.vocab_size, summary.context_length),
+        ),
+    ];
+
+    for (label, value) in lines {
+        if !cli.no_color {
+            println!("{} {}", label.cyan().bold(), value);
+        } else {
+            println!("{} {}", label, value);
+        }
     }
+}
 
-    Ok(generated_text)
+/// Echo the prompt so the user sees what the completion continues from
+fn print_prompt(cli: &RunConfig) {
+    if !cli.no_color {
+        print!("{}", cli.prompt.bright_blue());
+    } else {
+        print!("{}", cli.prompt);
+    }
 }
 
-async fn handle_model_commands(command: ModelCommands) -> Result<()> {
+async fn handle_model_commands(command: ModelCommands, assume_yes: bool) -> Result<()> {
     match command {
-        ModelCommands::Pull { model_id, filename, cache_dir, force, verbose } => {
-            pull_model(model_id, filename, cache_dir, force, verbose).await
+        ModelCommands::Pull { model_id, filename, cache_dir, force, hf_endpoints, hf_token, no_interactive, auto_quant, revision, pin, no_verify, verbose } => {
+            let downloader = ModelDownloader::new(cache_dir)?
+                .with_endpoints(hf_endpoints)
+                .with_verbose(verbose)
+                .with_revision(revision)
+                .with_pinned_revision(pin)
+                .with_verify(!no_verify)
+                .with_token(hf_token);
+            let filename = if auto_quant {
+                Some(auto_select_quant(&downloader, &model_id).await?)
+            } else {
+                filename
+            };
+            pull_model(&downloader, model_id, filename, force, !no_interactive, verbose).await
         }
-        ModelCommands::List { cache_dir, verbose } => {
-            list_models(cache_dir, verbose).await
+        ModelCommands::List { cache_dir, since, before, sort, verbose } => {
+            list_models(cache_dir, since, before, sort, verbose).await
         }
         ModelCommands::Remove { model_id, cache_dir, force, verbose } => {
-            remove_models(model_id, cache_dir, force, verbose).await
+            remove_models(model_id, cache_dir, force || assume_yes, verbose).await
         }
         ModelCommands::Usage { cache_dir } => {
             show_disk_usage(cache_dir).await
         }
+        ModelCommands::Prune { older_than, dry_run, cache_dir } => {
+            prune_models(older_than, dry_run, cache_dir, assume_yes).await
+        }
+        ModelCommands::Pin { model_id, cache_dir } => {
+            set_model_pinned(model_id, cache_dir, true).await
+        }
+        ModelCommands::Unpin { model_id, cache_dir } => {
+            set_model_pinned(model_id, cache_dir, false).await
+        }
+        ModelCommands::Rename { old_id, new_id, cache_dir } => {
+            rename_model(old_id, new_id, cache_dir).await
+        }
+        ModelCommands::RepairMetadata { model_id, cache_dir, offline } => {
+            repair_metadata(model_id, cache_dir, offline).await
+        }
+        ModelCommands::Stat { model_id, cache_dir, no_verify } => {
+            stat_model(model_id, cache_dir, !no_verify).await
+        }
+        ModelCommands::Info { model, cache_dir } => {
+            model_info(model, cache_dir)
+        }
+        ModelCommands::Doctor { cache_dir, quarantine } => {
+            doctor_models(cache_dir, quarantine)
+        }
     }
 }
 
-async fn pull_model(model_id: String, filename: Option<String>, cache_dir: Option<String>, force: bool, verbose: bool) -> Result<()> {
+async fn pull_model(
+    downloader: &ModelDownloader,
+    model_id: String,
+    filename: Option<String>,
+    force: bool,
+    interactive: bool,
+    verbose: bool,
+) -> Result<()> {
     if verbose {
         println!("{} Pulling model: {}", "Info:".blue().bold(), model_id.green());
     }
-
-    let downloader = ModelDownloader::new(cache_dir)?;
     
     let filename_to_download = if let Some(filename) = filename {
         filename
@@ -652,10 +3404,13 @@ async fn pull_model(model_id: String, filename: Option<String>, cache_dir: Optio
             println!("{} No filename specified, detecting available files...", "Info:".blue().bold());
         }
         
-        match downloader.list_model_files(&model_id).await {
-            Ok(files) => {
+        match downloader.list_model_file_infos(&model_id).await {
+            Ok(file_infos) => {
+                let files: Vec<String> = file_infos.iter().map(|f| f.rfilename.clone()).collect();
                 if files.len() == 1 {
                     files[0].clone()
+                } else if files.len() > 1 && interactive && io::stdin().is_terminal() && io::stdout().is_terminal() {
+                    select_model_file(&model_id, &file_infos)?
                 } else if files.len() > 1 {
                     println!("{} Available files for {}:", "Info:".blue().bold(), model_id.green());
                     for (i, file) in files.iter().enumerate() {
@@ -687,68 +3442,196 @@ async fn pull_model(model_id: String, filename: Option<String>, cache_dir: Optio
     Ok(())
 }
 
-async fn list_models(cache_dir: Option<String>, verbose: bool) -> Result<()> {
-    let downloader = ModelDownloader::new(cache_dir)?;
-    let cache_path = downloader.get_cache_dir();
-    
-    if !cache_path.exists() {
-        println!("{} No models cached. Use 'rustlama models pull <model>' to download models.", "Info:".blue().bold());
-        return Ok(());
+/// Choose the largest GGUF quant of a repository that fits in available memory, explaining the choice
+async fn auto_select_quant(downloader: &ModelDownloader, model_id: &str) -> Result<String> {
+    let files = downloader
+        .list_model_file_infos(model_id)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to list model files: {}", e))?;
+
+    let available = quant::available_memory();
+    let budget = quant::memory_budget(available);
+
+    println!(
+        "{} Available memory: {}, budget for model weights: {} ({:.0}% headroom)",
+        "Info:".blue().bold(),
+        format_file_size(available),
+        format_file_size(budget),
+        quant::MEMORY_HEADROOM * 100.0
+    );
+
+    match quant::select_quant(&files, budget) {
+        Some(file) => {
+            let size = file.size.unwrap_or(0);
+            println!(
+                "{} Auto-selected quant: {} ({}), the largest of {} GGUF files that fits",
+                "Info:".blue().bold(),
+                file.rfilename.green(),
+                format_file_size(size),
+                files.len()
+            );
+            Ok(file.rfilename.clone())
+        }
+        None => {
+            let smallest = files.iter().filter_map(|f| f.size).min();
+            Err(anyhow::anyhow!(
+                "No GGUF file of {} fits in {} (smallest known size: {})",
+                model_id,
+                format_file_size(budget),
+                smallest.map(format_file_size).unwrap_or_else(|| "unknown".to_string())
+            ))
+        }
+    }
+}
+
+/// Show a numbered menu of model files and read the user's choice from stdin
+fn select_model_file(model_id: &str, files: &[HfFile]) -> Result<String> {
+    println!("{} Available files for {}:", "Info:".blue().bold(), model_id.green());
+    for (i, file) in files.iter().enumerate() {
+        let size = file
+            .size
+            .map(format_file_size)
+            .unwrap_or_else(|| "size unknown".to_string());
+        println!("  {:>2}. {} ({})", i + 1, file.rfilename, size.yellow());
+    }
+
+    loop {
+        print!("Select a file to download [1-{}]: ", files.len());
+        io::stdout().flush()?;
+
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input)? == 0 || input.trim().is_empty() {
+            return Err(anyhow::anyhow!("No file selected"));
+        }
+
+        match parse_selection(&input, files.len()) {
+            Some(index) => return Ok(files[index].rfilename.clone()),
+            None => println!("{} Please enter a number between 1 and {}", "Error:".red().bold(), files.len()),
+        }
+    }
+}
+
+/// Parse a 1-based menu selection into a 0-based index
+pub fn parse_selection(input: &str, count: usize) -> Option<usize> {
+    match input.trim().parse::<usize>() {
+        Ok(n) if n >= 1 && n <= count => Some(n - 1),
+        _ => None,
+    }
+}
+
+async fn list_models(
+    cache_dir: Option<String>,
+    since: Option<String>,
+    before: Option<String>,
+    sort: ListSort,
+    verbose: bool,
+) -> Result<()> {
+    let now = chrono::Utc::now();
+    let since = since.map(|value| date_filter::parse_time_bound(&value, now)).transpose()?;
+    let before = before.map(|value| date_filter::parse_time_bound(&value, now)).transpose()?;
+
+    let downloader = ModelDownloader::new(cache_dir)?;
+    let cache_path = downloader.get_models_dir();
+    
+    if !cache_path.exists() {
+        println!("{} No models cached. Use 'rustlama models pull <model>' to download models.", "Info:".blue().bold());
+        return Ok(());
+    }
+
+    println!("{} Cached models in: {}", "Models:".green().bold(), cache_path.display());
+    println!();
+
+    struct CachedModel {
+        name: String,
+        dir: OsString,
+        pinned: bool,
+        files: Vec<(String, u64)>,
+        size: u64,
+        downloaded_at: Option<chrono::DateTime<chrono::Utc>>,
+    }
+
+    let mut models = Vec::new();
+    for entry in fs::read_dir(&cache_path)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+
+        let model_dir = entry.path();
+        let model_metadata = ModelMetadata::load(&model_dir).unwrap_or_default();
+        let mut model = CachedModel {
+            name: metadata::display_model_id(&model_dir),
+            dir: entry.file_name(),
+            // Reports unreadable metadata
+            pinned: metadata::is_pinned(&model_dir),
+            files: Vec::new(),
+            size: 0,
+            // Recorded download times, falling back to file modification times below
+            downloaded_at: model_metadata.files.values().map(|record| record.downloaded_at).max(),
+        };
+        let mut newest_modified = None;
+
+        for model_file in fs::read_dir(&model_dir)? {
+            let model_file = model_file?;
+            if model_file.file_type()?.is_file() && model_file.file_name() != METADATA_FILE {
+                let file_metadata = model_file.metadata()?;
+                model.size += file_metadata.len();
+                model.files.push((model_file.file_name().to_string_lossy().into_owned(), file_metadata.len()));
+                if let Ok(modified) = file_metadata.modified() {
+                    newest_modified = newest_modified.max(Some(chrono::DateTime::<chrono::Utc>::from(modified)));
+                }
+            }
+        }
+        model.downloaded_at = model.downloaded_at.or(newest_modified);
+
+        let in_window = model
+            .downloaded_at
+            .map_or(since.is_none() && before.is_none(), |date| date_filter::in_window(date, since, before));
+        if in_window {
+            models.push(model);
+        }
     }
 
-    println!("{} Cached models in: {}", "Models:".green().bold(), cache_path.display());
-    println!();
+    match sort {
+        ListSort::Name => models.sort_by_key(|model| model.name.to_lowercase()),
+        ListSort::Size => models.sort_by(|a, b| b.size.cmp(&a.size)),
+        ListSort::Date => models.sort_by(|a, b| b.downloaded_at.cmp(&a.downloaded_at)),
+    }
 
+    // Dates matter when they are what the listing is filtered or sorted by
+    let show_dates = verbose || since.is_some() || before.is_some() || matches!(sort, ListSort::Date);
     let mut total_size = 0u64;
-    let mut model_count = 0;
 
-    for entry in fs::read_dir(&cache_path)? {
-        let entry = entry?;
-        if entry.file_type()?.is_dir() {
-            let model_dir = entry.path();
-            let model_name = model_dir.file_name().unwrap().to_string_lossy();
-            
-            // Convert back from filesystem safe name
-            let display_name = model_name.replace("--", "/");
-            
-            println!("📦 {}", display_name.cyan().bold());
-            
-            if verbose {
-                for model_file in fs::read_dir(&model_dir)? {
-                    let model_file = model_file?;
-                    if model_file.file_type()?.is_file() {
-                        let metadata = model_file.metadata()?;
-                        let size = metadata.len();
-                        total_size += size;
-                        
-                        println!("   └─ {} ({})", 
-                            model_file.file_name().to_string_lossy(),
-                            format_file_size(size).yellow()
-                        );
-                    }
-                }
-            } else {
-                // Just count files and sizes without verbose output
-                for model_file in fs::read_dir(&model_dir)? {
-                    let model_file = model_file?;
-                    if model_file.file_type()?.is_file() {
-                        let metadata = model_file.metadata()?;
-                        total_size += metadata.len();
-                    }
+    for model in &models {
+        let date = match (show_dates, model.downloaded_at) {
+            (true, Some(date)) => format!(" {}", date.format("%Y-%m-%d %H:%M").to_string().bright_black()),
+            _ => String::new(),
+        };
+        if model.pinned {
+            println!("📦 {} {}{}", model.name.cyan().bold(), "📌 pinned".yellow(), date);
+        } else {
+            println!("📦 {}{}", model.name.cyan().bold(), date);
+        }
+
+        if verbose {
+            for (filename, size) in &model.files {
+                match file_quantization(&cache_path.join(&model.dir).join(filename)) {
+                    Some(quant) => println!("   └─ {} ({}, {})", filename, format_file_size(*size).yellow(), quant.cyan()),
+                    None => println!("   └─ {} ({})", filename, format_file_size(*size).yellow()),
                 }
             }
-            model_count += 1;
         }
+        total_size += model.size;
     }
 
     println!();
     println!("{} {} models, {} total", 
         "Summary:".green().bold(),
-        model_count, 
+        models.len(), 
         format_file_size(total_size).yellow()
     );
     
-    if !verbose && model_count > 0 {
+    if !verbose && !models.is_empty() {
         println!("{} Use --verbose for detailed information", "Tip:".blue().bold());
     }
 
@@ -757,7 +3640,7 @@ async fn list_models(cache_dir: Option<String>, verbose: bool) -> Result<()> {
 
 async fn remove_models(model_id: String, cache_dir: Option<String>, force: bool, verbose: bool) -> Result<()> {
     let downloader = ModelDownloader::new(cache_dir)?;
-    let cache_path = downloader.get_cache_dir();
+    let cache_path = downloader.get_models_dir();
     
     if !cache_path.exists() {
         println!("{} No cached models found.", "Info:".blue().bold());
@@ -768,25 +3651,16 @@ async fn remove_models(model_id: String, cache_dir: Option<String>, force: bool,
         return remove_all_models(cache_path.clone(), force, verbose).await;
     }
 
-    // Convert model ID to filesystem safe name
-    let safe_model_name = model_id.replace("/", "--");
-    let model_path = cache_path.join(&safe_model_name);
+    let model_path = downloader.get_model_dir(&model_id);
 
     if !model_path.exists() {
         println!("{} Model '{}' not found in cache.", "Error:".red().bold(), model_id);
         return Ok(());
     }
 
-    if !force {
-        print!("Remove model '{}'? [y/N]: ", model_id.yellow());
-        io::stdout().flush()?;
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-        
-        if !input.trim().to_lowercase().starts_with('y') {
-            println!("Cancelled.");
-            return Ok(());
-        }
+    if !confirm(&format!("Remove model '{}'?", model_id.yellow()), force)? {
+        println!("Cancelled.");
+        return Ok(());
     }
 
     if verbose {
@@ -799,17 +3673,31 @@ async fn remove_models(model_id: String, cache_dir: Option<String>, force: bool,
     Ok(())
 }
 
+/// Ask a yes/no question on the terminal. `assume_yes` (--yes/--force) skips the prompt;
+/// without it a non-interactive stdin is an error rather than a hang.
+fn confirm(question: &str, assume_yes: bool) -> Result<bool> {
+    if assume_yes {
+        return Ok(true);
+    }
+
+    if !io::stdin().is_terminal() {
+        return Err(anyhow::anyhow!(
+            "Confirmation required but stdin is not a terminal; pass --yes to proceed"
+        ));
+    }
+
+    print!("{} [y/N]: ", question);
+    io::stdout().flush()?;
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+
+    Ok(input.trim().to_lowercase().starts_with('y'))
+}
+
 async fn remove_all_models(cache_path: PathBuf, force: bool, verbose: bool) -> Result<()> {
-    if !force {
-        print!("Remove ALL cached models? This cannot be undone! [y/N]: ");
-        io::stdout().flush()?;
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-        
-        if !input.trim().to_lowercase().starts_with('y') {
-            println!("Cancelled.");
-            return Ok(());
-        }
+    if !confirm("Remove ALL cached models? This cannot be undone!", force)? {
+        println!("Cancelled.");
+        return Ok(());
     }
 
     if verbose {
@@ -823,9 +3711,69 @@ async fn remove_all_models(cache_path: PathBuf, force: bool, verbose: bool) -> R
     Ok(())
 }
 
+/// Remove abandoned .tmp downloads and, with --older-than, unused model directories
+async fn prune_models(older_than: Option<u64>, dry_run: bool, cache_dir: Option<String>, assume_yes: bool) -> Result<()> {
+    let downloader = ModelDownloader::new(cache_dir)?;
+    let cache_path = downloader.get_models_dir();
+
+    if !cache_path.exists() {
+        println!("{} No cached models found.", "Info:".blue().bold());
+        return Ok(());
+    }
+
+    let older_than = older_than.map(|days| std::time::Duration::from_secs(days * 24 * 60 * 60));
+    let candidates = prune::find_prunable(&cache_path, older_than, SystemTime::now())?;
+    if candidates.is_empty() {
+        println!("{} Nothing to prune.", "Info:".blue().bold());
+        return Ok(());
+    }
+
+    for candidate in &candidates {
+        let description = match &candidate.kind {
+            PruneKind::TempFile => format!(
+                "{} (incomplete download in {})",
+                candidate.path.file_name().unwrap_or_default().to_string_lossy(),
+                candidate.path.parent().map(metadata::display_model_id).unwrap_or_default()
+            ),
+            PruneKind::StaleModel { last_used } => format!(
+                "{} (unused for {} days)",
+                metadata::display_model_id(&candidate.path),
+                SystemTime::now().duration_since(*last_used).unwrap_or_default().as_secs() / (24 * 60 * 60)
+            ),
+        };
+        println!("{:>12} {}", format_file_size(candidate.size).yellow(), description.cyan());
+    }
+
+    let total: u64 = candidates.iter().map(|candidate| candidate.size).sum();
+    if dry_run {
+        println!("{} Dry run: would free {}", "Info:".blue().bold(), format_file_size(total));
+        return Ok(());
+    }
+
+    if !confirm(&format!("Remove {} item(s), freeing {}?", candidates.len(), format_file_size(total)), assume_yes)? {
+        println!("Cancelled.");
+        return Ok(());
+    }
+
+    let mut reclaimed = 0;
+    for candidate in &candidates {
+        let removed = match candidate.kind {
+            PruneKind::TempFile => fs::remove_file(&candidate.path),
+            PruneKind::StaleModel { .. } => fs::remove_dir_all(&candidate.path),
+        };
+        match removed {
+            Ok(()) => reclaimed += candidate.size,
+            Err(e) => eprintln!("{} Failed to remove '{}': {}", "Warning:".yellow().bold(), candidate.path.display(), e),
+        }
+    }
+
+    println!("{} Reclaimed {}", "Success:".green().bold(), format_file_size(reclaimed));
+    Ok(())
+}
+
 async fn show_disk_usage(cache_dir: Option<String>) -> Result<()> {
     let downloader = ModelDownloader::new(cache_dir)?;
-    let cache_path = downloader.get_cache_dir();
+    let cache_path = downloader.get_models_dir();
     
     if !cache_path.exists() {
         println!("{} No cached models found.", "Info:".blue().bold());
@@ -842,8 +3790,7 @@ async fn show_disk_usage(cache_dir: Option<String>) -> Result<()> {
         let entry = entry?;
         if entry.file_type()?.is_dir() {
             let model_dir = entry.path();
-            let model_name = model_dir.file_name().unwrap().to_string_lossy();
-            let display_name = model_name.replace("--", "/");
+            let display_name = metadata::display_model_id(&model_dir);
             
             let mut model_size = 0u64;
             for model_file in fs::read_dir(&model_dir)? {
@@ -855,7 +3802,7 @@ async fn show_disk_usage(cache_dir: Option<String>) -> Result<()> {
             }
             
             total_size += model_size;
-            models.push((display_name.to_string(), model_size));
+            models.push((display_name, model_size));
         }
     }
 
@@ -872,6 +3819,351 @@ async fn show_disk_usage(cache_dir: Option<String>) -> Result<()> {
     Ok(())
 }
 
+async fn set_model_pinned(model_id: String, cache_dir: Option<String>, pinned: bool) -> Result<()> {
+    let downloader = ModelDownloader::new(cache_dir)?;
+    let model_dir = downloader.get_model_dir(&model_id);
+
+    if !model_dir.exists() {
+        return Err(anyhow::anyhow!("Model '{}' not found in cache", model_id));
+    }
+
+    let mut model_metadata = ModelMetadata::load_or_warn(&model_dir);
+    model_metadata.model_id.get_or_insert_with(|| model_id.clone());
+    model_metadata.pinned = pinned;
+    model_metadata.save(&model_dir)?;
+
+    if pinned {
+        println!("{} Model '{}' pinned; cleanup commands will skip it.", "Success:".green().bold(), model_id);
+    } else {
+        println!("{} Model '{}' unpinned.", "Success:".green().bold(), model_id);
+    }
+
+    Ok(())
+}
+
+/// The quantization recorded in a model file's GGUF header, else the one named in its filename
+fn file_quantization(path: &Path) -> Option<String> {
+    ModelSummary::from_file(path)
+        .ok()
+        .map(|summary| summary.quantization)
+        .filter(|quant| quant != "unknown")
+        .or_else(|| quant::quant_from_filename(&path.file_name()?.to_string_lossy()))
+}
+
+/// Print the GGUF header summary of a model file, or of every GGUF file of a cached model
+fn model_info(model: String, cache_dir: Option<String>) -> Result<()> {
+    let path = PathBuf::from(&model);
+    let files = if path.is_file() {
+        println!("📦 {}", model.cyan().bold());
+        vec![path]
+    } else {
+        let model_dir = ModelDownloader::new(cache_dir)?.get_model_dir(&model);
+        if !model_dir.exists() {
+            return Err(anyhow::anyhow!("Model '{}' not found in cache or as a file", model));
+        }
+        println!("📦 {}", metadata::display_model_id(&model_dir).cyan().bold());
+
+        let mut files: Vec<PathBuf> = fs::read_dir(&model_dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gguf")))
+            .collect();
+        files.sort();
+        if files.is_empty() {
+            return Err(anyhow::anyhow!("Model '{}' has no GGUF files", model));
+        }
+        files
+    };
+
+    for path in files {
+        let field = |label: &str, value: String| println!("      {} {}", format!("{:<13}", label).cyan(), value);
+        let filename = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+
+        println!();
+        println!("   {}", filename.bold());
+        match ModelSummary::from_file(&path) {
+            Ok(summary) => {
+                if let Some(name) = summary.name {
+                    field("Name:", name);
+                }
+                field("Architecture:", summary.architecture);
+                field("Parameters:", format_param_count(summary.parameters));
+                field("Quant:", summary.quantization);
+                field("Context:", format!("{} tokens (trained)", summary.context_length));
+                field("Vocab:", format!("{} tokens", summary.vocab_size));
+                field("Size:", format_file_size(summary.size_bytes).yellow().to_string());
+            }
+            Err(e) => println!("      {} {}", "Error:".red().bold(), e),
+        }
+    }
+
+    Ok(())
+}
+
+async fn stat_model(model_id: String, cache_dir: Option<String>, verify: bool) -> Result<()> {
+    let downloader = ModelDownloader::new(cache_dir)?;
+    let model_dir = downloader.get_model_dir(&model_id);
+
+    if !model_dir.exists() {
+        return Err(anyhow::anyhow!("Model '{}' not found in cache", model_id));
+    }
+
+    let model_metadata = ModelMetadata::load_or_warn(&model_dir);
+    let display_name = metadata::display_model_id(&model_dir);
+
+    if model_metadata.pinned {
+        println!("📦 {} {}", display_name.cyan().bold(), "📌 pinned".yellow());
+    } else {
+        println!("📦 {}", display_name.cyan().bold());
+    }
+    println!("   {} {}", "Directory:".cyan(), model_dir.display());
+    if let Some(revision) = &model_metadata.revision {
+        println!("   {} {}", "Revision:".cyan(), revision);
+    }
+
+    let mut files: Vec<_> = fs::read_dir(&model_dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()) && entry.file_name() != METADATA_FILE)
+        .collect();
+    files.sort_by_key(|entry| entry.file_name());
+
+    let mut failures = 0;
+    for entry in files {
+        let filename = entry.file_name().to_string_lossy().into_owned();
+        let path = entry.path();
+        let record = model_metadata.files.get(&filename);
+        let unknown = || "unknown".bright_black().to_string();
+        let field = |label: &str, value: String| println!("      {} {}", format!("{:<11}", label).cyan(), value);
+
+        println!();
+        println!("   {}", filename.bold());
+        field("Size:", format_file_size(entry.metadata()?.len()).yellow().to_string());
+        field("Quant:", file_quantization(&path).unwrap_or_else(unknown));
+        field("SHA256:", record.map(|r| r.sha256.clone()).unwrap_or_else(unknown));
+        field(
+            "Downloaded:",
+            record
+                .map(|r| r.downloaded_at.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+                .unwrap_or_else(unknown),
+        );
+        field("Revision:", record.and_then(|r| r.revision.clone()).unwrap_or_else(unknown));
+
+        let status = if !verify {
+            "not checked".bright_black().to_string()
+        } else {
+            match metadata::verify_file(&model_metadata, &filename, &path)? {
+                Verification::Verified => "✓ verified".green().to_string(),
+                Verification::SizeMismatch => {
+                    failures += 1;
+                    "✗ size differs from download".red().to_string()
+                }
+                Verification::ChecksumMismatch => {
+                    failures += 1;
+                    "✗ checksum mismatch".red().to_string()
+                }
+                Verification::Unrecorded => "no checksum recorded".bright_black().to_string(),
+            }
+        };
+        field("Status:", status);
+    }
+
+    if failures > 0 {
+        return Err(anyhow::anyhow!("{} file(s) failed verification", failures));
+    }
+
+    Ok(())
+}
+
+/// Check every model directory in the cache, returning each problem with the directory it was found in
+fn scan_model_cache(cache_dir: Option<String>) -> Result<Vec<(PathBuf, String, CacheProblem)>> {
+    let downloader = ModelDownloader::new(cache_dir)?;
+    let cache_path = downloader.get_models_dir();
+    if !cache_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut model_dirs: Vec<PathBuf> = fs::read_dir(&cache_path)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+        .map(|entry| entry.path())
+        .collect();
+    model_dirs.sort();
+
+    let mut issues = Vec::new();
+    for model_dir in model_dirs {
+        for (filename, problem) in metadata::check_model_dir(&model_dir)? {
+            issues.push((model_dir.clone(), filename, problem));
+        }
+    }
+    Ok(issues)
+}
+
+/// `--check-cache` for `pipe` and `serve`: one sweep before the model is loaded, failing if anything
+/// is wrong. Findings go to stderr since `pipe`'s stdout carries only responses.
+fn check_cache_before_start(cache_dir: Option<String>) -> Result<()> {
+    let issues = scan_model_cache(cache_dir)?;
+    for (model_dir, filename, problem) in &issues {
+        eprintln!(
+            "{} {}/{}: {}",
+            "Error:".red().bold(),
+            metadata::display_model_id(model_dir),
+            filename,
+            problem.describe()
+        );
+    }
+    if !issues.is_empty() {
+        return Err(anyhow::anyhow!(
+            "{} problem(s) found in the model cache; run 'rustlama models doctor --quarantine' to move bad files aside",
+            issues.len()
+        ));
+    }
+    Ok(())
+}
+
+fn doctor_models(cache_dir: Option<String>, quarantine: bool) -> Result<()> {
+    println!("{} Checking cached models...", "Info:".blue().bold());
+    let issues = scan_model_cache(cache_dir)?;
+
+    if issues.is_empty() {
+        println!("{} All cached files match their recorded checksums", "Success:".green().bold());
+        return Ok(());
+    }
+
+    for (model_dir, filename, problem) in &issues {
+        println!(
+            "  {} {}/{}: {}",
+            "✗".red(),
+            metadata::display_model_id(model_dir).cyan(),
+            filename,
+            problem.describe().red()
+        );
+
+        // A missing file has nothing to move
+        if quarantine && *problem != CacheProblem::Missing {
+            let destination = metadata::quarantine(model_dir, filename)?;
+            println!("    {} Moved to {}", "→".yellow(), destination.display());
+        }
+    }
+
+    if !quarantine {
+        println!(
+            "{} Run 'rustlama models doctor --quarantine' to move bad files aside, or re-pull the affected models",
+            "Info:".blue().bold()
+        );
+    }
+
+    Err(anyhow::anyhow!("{} problem(s) found in the model cache", issues.len()))
+}
+
+async fn repair_metadata(model_id: Option<String>, cache_dir: Option<String>, offline: bool) -> Result<()> {
+    let downloader = ModelDownloader::new(cache_dir)?.with_quiet(true);
+
+    // A named model is always rebuilt; otherwise only metadata that is missing or unreadable
+    let model_dirs: Vec<PathBuf> = match &model_id {
+        Some(model_id) => {
+            let model_dir = downloader.get_model_dir(model_id);
+            if !model_dir.exists() {
+                return Err(anyhow::anyhow!("Model '{}' not found in cache", model_id));
+            }
+            vec![model_dir]
+        }
+        None => {
+            let cache_path = downloader.get_models_dir();
+            if !cache_path.exists() {
+                println!("{} No cached models found.", "Info:".blue().bold());
+                return Ok(());
+            }
+            let mut dirs = Vec::new();
+            for entry in fs::read_dir(&cache_path)? {
+                let entry = entry?;
+                let path = entry.path();
+                if entry.file_type()?.is_dir() && (!ModelMetadata::path(&path).exists() || ModelMetadata::load(&path).is_err()) {
+                    dirs.push(path);
+                }
+            }
+            dirs
+        }
+    };
+
+    if model_dirs.is_empty() {
+        println!("{} All metadata files are readable; nothing to repair.", "Info:".blue().bold());
+        return Ok(());
+    }
+
+    for model_dir in model_dirs {
+        let previous = ModelMetadata::load(&model_dir).ok();
+        let display_name = metadata::display_model_id(&model_dir);
+        println!("{} Rebuilding metadata for {}", "Info:".blue().bold(), display_name.cyan());
+
+        let mut regenerated = metadata::regenerate(&model_dir, previous.as_ref())?;
+
+        // A file whose checksum matches the Hub's current copy came from the current commit
+        if !offline {
+            match downloader.get_model_info(&display_name).await {
+                Ok(info) => {
+                    for (filename, record) in regenerated.files.iter_mut() {
+                        let matches_hub = info.siblings.iter().any(|sibling| {
+                            sibling.rfilename == *filename
+                                && sibling.lfs.as_ref().is_some_and(|lfs| lfs.sha256 == record.sha256)
+                        });
+                        if matches_hub && info.sha.is_some() {
+                            record.revision.clone_from(&info.sha);
+                            regenerated.revision.clone_from(&info.sha);
+                        }
+                    }
+                }
+                Err(e) => println!(
+                    "{} Could not look up the revision ({}); keeping local information only",
+                    "Warning:".yellow().bold(),
+                    e
+                ),
+            }
+        }
+
+        regenerated.save(&model_dir)?;
+        println!(
+            "{} Rebuilt metadata for {} ({} files)",
+            "Success:".green().bold(),
+            display_name,
+            regenerated.files.len()
+        );
+    }
+
+    Ok(())
+}
+
+async fn rename_model(old_id: String, new_id: String, cache_dir: Option<String>) -> Result<()> {
+    if !is_hf_model_id(&new_id) || new_id.split('/').any(|part| part.is_empty()) {
+        return Err(anyhow::anyhow!(
+            "'{}' is not a valid model ID; expected owner/name (e.g., TheBloke/Llama-2-7B-Chat-GGUF)",
+            new_id
+        ));
+    }
+
+    let downloader = ModelDownloader::new(cache_dir)?;
+    let old_dir = downloader.get_model_dir(&old_id);
+    let new_dir = downloader.get_model_dir(&new_id);
+
+    if !old_dir.exists() {
+        return Err(anyhow::anyhow!("Model '{}' not found in cache", old_id));
+    }
+
+    if new_dir.exists() {
+        return Err(anyhow::anyhow!("A cached model named '{}' already exists", new_id));
+    }
+
+    fs::rename(&old_dir, &new_dir)
+        .map_err(|e| anyhow::anyhow!("Failed to rename '{}': {}", old_dir.display(), e))?;
+
+    let mut model_metadata = ModelMetadata::load_or_warn(&new_dir);
+    model_metadata.model_id = Some(new_id.clone());
+    model_metadata.save(&new_dir)?;
+
+    println!("{} Renamed '{}' to '{}'", "Success:".green().bold(), old_id, new_id);
+
+    Ok(())
+}
+
 fn format_file_size(size: u64) -> String {
     const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
     let mut size = size as f64;
@@ -889,16 +4181,30 @@ fn format_file_size(size: u64) -> String {
     }
 }
 
+/// How `config` runs its tasks, from the command line
+struct ConfigRunOptions {
+    dry_run: bool,
+    continue_on_error: bool,
+    /// Seconds after which a task fails, unless it sets its own `timeout`
+    task_timeout: Option<u64>,
+    /// Re-run changed tasks each time the config file is saved
+    watch: bool,
+    /// Wait for another process to release a task's output_file instead of failing
+    lock_wait: bool,
+    /// Threads running inference tasks; the config's `defaults.parallel` if unset
+    parallel: Option<usize>,
+    verbose: bool,
+}
+
 async fn handle_config_command(
     file: Option<PathBuf>,
-    dry_run: bool,
     generate_sample: bool,
     output: PathBuf,
-    continue_on_error: bool,
     only_tasks: Option<String>,
     skip_tasks: Option<String>,
-    verbose: bool,
+    options: &ConfigRunOptions,
 ) -> Result<()> {
+
     // Generate sample configuration if requested
     if generate_sample {
         let sample_config = YamlConfig::generate_sample();
@@ -915,7 +4221,7 @@ async fn handle_config_command(
     })?;
 
     // Load configuration from file
-    if verbose {
+    if options.verbose {
         println!("{} Loading configuration from: {}", 
                  "Info:".blue().bold(), 
                  config_file.display());
@@ -923,7 +4229,7 @@ async fn handle_config_command(
 
     let config = YamlConfig::load_from_file(&config_file)?;
 
-    if verbose {
+    if options.verbose {
         if let Some(name) = &config.name {
             println!("{} Configuration: {}", "Info:".blue().bold(), name);
         }
@@ -941,9 +4247,160 @@ async fn handle_config_command(
         tasks.split(',').map(|s| s.trim().to_string()).collect()
     });
 
+    let filter = TaskFilter {
+        only: only_task_names,
+        skip: skip_task_names,
+        run_models: true,
+    };
+    let result = run_config_tasks(&config, &filter, options).await;
+    if !options.watch {
+        return result;
+    }
+    if let Err(e) = result {
+        eprintln!("{} {}", "Error:".red().bold(), e);
+    }
+
+    watch_config(&config_file, &config, &filter, options).await
+}
+
+/// Which parts of a config to run
+struct TaskFilter {
+    /// Only run inference and dataset tasks with these names
+    only: Option<Vec<String>>,
+    /// Never run inference and dataset tasks with these names
+    skip: Option<Vec<String>>,
+    run_models: bool,
+}
+
+/// Whether a name given to --only-tasks/--skip-tasks picks out a task. The name of a task
+/// with a matrix picks out all of its runs.
+pub fn selects_task(selector: &str, task_name: &str) -> bool {
+    task_name
+        .strip_prefix(selector)
+        .is_some_and(|rest| rest.is_empty() || (rest.starts_with('[') && rest.ends_with(']')))
+}
+
+/// Re-run the tasks whose effective settings changed each time the config file is saved
+async fn watch_config(config_file: &Path, config: &YamlConfig, filter: &TaskFilter, options: &ConfigRunOptions) -> Result<()> {
+    use notify::{RecursiveMode, Watcher};
+
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event {
+            if !event.kind.is_access() {
+                let _ = tx.send(event.paths);
+            }
+        }
+    })
+    .map_err(|e| anyhow::anyhow!("Failed to start file watcher: {}", e))?;
+
+    // Watch the directory rather than the file: many editors save by replacing the file
+    let config_path = fs::canonicalize(config_file)?;
+    let watch_dir = config_path.parent().unwrap_or(Path::new("."));
+    watcher
+        .watch(watch_dir, RecursiveMode::NonRecursive)
+        .map_err(|e| anyhow::anyhow!("Failed to watch '{}': {}", watch_dir.display(), e))?;
+
+    let mut fingerprints = config.task_fingerprints();
+    println!(
+        "\n{} Watching {} for changes (Ctrl-C to stop)",
+        "Info:".blue().bold(),
+        config_file.display()
+    );
+
+    while let Some(paths) = rx.recv().await {
+        if !paths.iter().any(|path| path.file_name() == config_path.file_name()) {
+            continue;
+        }
+        while let Ok(Some(_)) = tokio::time::timeout(WATCH_DEBOUNCE, rx.recv()).await {}
+
+        let config = match YamlConfig::load_from_file(config_file) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("{} {}", "Error:".red().bold(), e);
+                continue;
+            }
+        };
+
+        let updated = config.task_fingerprints();
+        let changed: Vec<&String> = updated
+            .keys()
+            .filter(|key| fingerprints.get(*key) != updated.get(*key))
+            .collect();
+        if changed.is_empty() {
+            if options.verbose {
+                println!("{} No task settings changed", "Info:".blue().bold());
+            }
+            fingerprints = updated;
+            continue;
+        }
+
+        let changed_names: Vec<String> = changed
+            .iter()
+            .filter_map(|key| key.strip_prefix("task:").or_else(|| key.strip_prefix("dataset:")))
+            .filter(|name| filter.only.as_ref().map_or(true, |only| only.iter().any(|o| selects_task(o, name))))
+            .map(str::to_string)
+            .collect();
+        println!(
+            "\n{} {} changed; re-running {}",
+            "Info:".blue().bold(),
+            config_file.display(),
+            if changed_names.is_empty() { "model tasks".to_string() } else { changed_names.join(", ") }
+        );
+
+        let rerun = TaskFilter {
+            only: Some(changed_names),
+            skip: filter.skip.clone(),
+            run_models: changed.iter().any(|key| *key == "models"),
+        };
+        if let Err(e) = run_config_tasks(&config, &rerun, options).await {
+            eprintln!("{} {}", "Error:".red().bold(), e);
+        }
+        fingerprints = updated;
+    }
+
+    Ok(())
+}
+
+/// Execute the model, inference and dataset tasks of a loaded config
+async fn run_config_tasks(config: &YamlConfig, filter: &TaskFilter, options: &ConfigRunOptions) -> Result<()> {
+    let &ConfigRunOptions { dry_run, continue_on_error, task_timeout, lock_wait, parallel, verbose, .. } = options;
+    let only_task_names = filter.only.as_ref();
+    let skip_task_names = filter.skip.as_ref();
+    let parallel = parallel
+        .or_else(|| config.defaults.as_ref().and_then(|defaults| defaults.parallel))
+        .unwrap_or(1);
+    if parallel == 0 {
+        return Err(anyhow::anyhow!("--parallel must be at least 1"));
+    }
+
     // Execute model management tasks
-    if !config.models.is_empty() {
+    if filter.run_models && !config.models.is_empty() {
         println!("{} Executing model management tasks...", "Info:".blue().bold());
+
+        // Look up every model to pull at once rather than one round-trip per task
+        let info_cache = ModelInfoCache::default();
+        if !dry_run {
+            let mut pull_ids: Vec<String> = config
+                .models
+                .iter()
+                .filter(|task| task.action == "pull")
+                .filter_map(|task| task.model_id.clone())
+                .collect();
+            pull_ids.sort();
+            pull_ids.dedup();
+            if pull_ids.len() > 1 {
+                if verbose {
+                    println!("{} Fetching model info for {} models", "Info:".blue().bold(), pull_ids.len());
+                }
+                ModelDownloader::new(None)?
+                    .with_endpoints(downloader::env_endpoints())
+                    .with_info_cache(info_cache.clone())
+                    .prefetch_model_info(&pull_ids)
+                    .await;
+            }
+        }
         
         for (i, model_task) in config.models.iter().enumerate() {
             if let Some(desc) = &model_task.description {
@@ -960,7 +4417,7 @@ async fn handle_config_command(
                 continue;
             }
 
-            if let Err(e) = execute_model_task(model_task).await {
+            if let Err(e) = execute_model_task_with_retries(model_task, &info_cache).await {
                 eprintln!("{} Model task {} failed: {}", 
                           "Error:".red().bold(), i + 1, e);
                 if !continue_on_error {
@@ -977,54 +4434,76 @@ async fn handle_config_command(
         
         let mut executed_count = 0;
         let mut failed_count = 0;
+        let mut runnable = Vec::new();
 
         for mut task in tasks {
             // Apply default settings
             config.apply_defaults(&mut task);
+            if task.timeout.is_none() {
+                task.timeout = task_timeout;
+            }
 
             // Check task filters
             if let Some(ref only_names) = only_task_names {
-                if !only_names.contains(&task.name) {
+                if !only_names.iter().any(|name| selects_task(name, &task.name)) {
                     continue;
                 }
             }
 
             if let Some(ref skip_names) = skip_task_names {
-                if skip_names.contains(&task.name) {
+                if skip_names.iter().any(|name| selects_task(name, &task.name)) {
                     if verbose {
                         println!("{} Skipping task: {}", "Info:".blue().bold(), task.name);
                     }
-                    continue;
-                }
-            }
-
-            if verbose {
-                println!("{} Executing task: {}", "Info:".blue().bold(), task.name);
-                if let Some(desc) = &task.description {
-                    println!("  {}", desc);
+                    continue;
                 }
             }
 
             if dry_run {
-                println!("  {} Would run: {} with model {:?}", 
-                         "DRY RUN:".yellow().bold(),
-                         task.name,
-                         task.model.as_deref().unwrap_or("default"));
+                if verbose {
+                    print_task_header(&task);
+                }
+                match task_run_config(&task, lock_wait, verbose, false) {
+                    Ok(run_config) => print_resolved_task(&task, &run_config),
+                    Err(e) => {
+                        eprintln!("{} {}", "Error:".red().bold(), e);
+                        if !continue_on_error {
+                            return Err(e);
+                        }
+                    }
+                }
                 continue;
             }
 
-            match execute_inference_task(&task, verbose).await {
-                Ok(()) => {
-                    executed_count += 1;
-                    println!("{} Task '{}' completed successfully", 
-                             "Success:".green().bold(), task.name);
-                }
-                Err(e) => {
-                    failed_count += 1;
-                    eprintln!("{} Task '{}' failed: {}", 
-                              "Error:".red().bold(), task.name, e);
-                    if !continue_on_error {
-                        return Err(e);
+            runnable.push(task);
+        }
+
+        // Each model is loaded once for all of its tasks
+        let groups = group_by_model(runnable);
+        if parallel > 1 {
+            (executed_count, failed_count) =
+                run_inference_tasks_parallel(groups, parallel, continue_on_error, lock_wait, verbose).await?;
+        } else {
+            'groups: for group in groups {
+                let mut loaded = None;
+                for task in group {
+                    if interrupt::interrupted() {
+                        break 'groups;
+                    }
+                    match execute_inference_task_with_retries(&task, &mut loaded, lock_wait, verbose, false).await {
+                        Ok(()) => {
+                            executed_count += 1;
+                            println!("{} Task '{}' completed successfully", 
+                                     "Success:".green().bold(), task.name);
+                        }
+                        Err(e) => {
+                            failed_count += 1;
+                            eprintln!("{} Task '{}' failed: {}", 
+                                      "Error:".red().bold(), task.name, e);
+                            if !continue_on_error {
+                                return Err(e);
+                            }
+                        }
                     }
                 }
             }
@@ -1048,6 +4527,10 @@ async fn handle_config_command(
         let mut dataset_failed = 0;
 
         for mut dataset in datasets {
+            if interrupt::interrupted() {
+                break;
+            }
+
             // Apply default settings to dataset task
             config.apply_dataset_defaults(&mut dataset);
 
@@ -1114,16 +4597,127 @@ async fn handle_config_command(
     Ok(())
 }
 
-async fn execute_model_task(task: &ModelTask) -> Result<()> {
+fn list_devices(no_color: bool) -> Result<()> {
+    init_llama_logs(false);
+
+    let backend = LlamaBackend::init()
+        .map_err(|e| anyhow::anyhow!("Failed to initialize llama backend: {}", e))?;
+
+    let devices = llama_cpp_2::list_llama_ggml_backend_devices();
+
+    let header = format!(
+        "{:<4} {:<10} {:<14} {:<20} {:>20}  {}",
+        "#", "Backend", "Type", "Name", "Memory (free/total)", "Description"
+    );
+    if no_color {
+        println!("{}", header);
+    } else {
+        println!("{}", header.cyan().bold());
+    }
+
+    for device in &devices {
+        let memory = if device.memory_total > 0 {
+            format!(
+                "{} / {}",
+                format_file_size(device.memory_free as u64),
+                format_file_size(device.memory_total as u64)
+            )
+        } else {
+            "-".to_string()
+        };
+        let device_type = format!("{:?}", device.device_type);
+
+        println!(
+            "{:<4} {:<10} {:<14} {:<20} {:>20}  {}",
+            device.index,
+            device.backend,
+            device_type,
+            device.name,
+            memory,
+            device.description
+        );
+    }
+
+    if devices.is_empty() {
+        println!("No devices found");
+    }
+
+    println!();
+    println!(
+        "{} GPU offload supported: {}",
+        "Info:".blue().bold(),
+        if backend.supports_gpu_offload() { "yes" } else { "no" }
+    );
+
+    Ok(())
+}
+
+fn print_version(json: bool) -> Result<()> {
+    init_llama_logs(false);
+
+    let backend = LlamaBackend::init()
+        .map_err(|e| anyhow::anyhow!("Failed to initialize llama backend: {}", e))?;
+    let devices = llama_cpp_2::list_llama_ggml_backend_devices();
+    let info = BuildInfo::new(devices.into_iter().map(|device| device.backend), backend.supports_gpu_offload());
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+        return Ok(());
+    }
+
+    println!("rustlama {}", info.version);
+    println!("llama-cpp-2: {}", info.llama_cpp_bindings);
+    println!("Target:      {} ({})", info.target, info.profile);
+    println!("Backends:    {}", if info.backends.is_empty() { "none".to_string() } else { info.backends.join(", ") });
+    println!("GPU offload: {}", if info.gpu_offload { "yes" } else { "no" });
+
+    Ok(())
+}
+
+fn lint_config_file(file: PathBuf) -> Result<()> {
+    let mut config = YamlConfig::parse_file(&file)?;
+    config.interpolate_env()?;
+    config.render_prompts()?;
+    let report = lint::lint_config(&config);
+
+    println!("{} Linting: {}", "Info:".blue().bold(), file.display());
+
+    for warning in &report.warnings {
+        println!("  {} {}", "Warning:".yellow().bold(), warning);
+    }
+    for error in &report.errors {
+        println!("  {} {}", "Error:".red().bold(), error);
+    }
+
+    println!(
+        "\n{} {} error(s), {} warning(s)",
+        "Summary:".green().bold(),
+        report.errors.len(),
+        report.warnings.len()
+    );
+
+    if report.is_ok() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("Configuration has {} error(s)", report.errors.len()))
+    }
+}
+
+async fn execute_model_task(task: &ModelTask, info_cache: &ModelInfoCache) -> Result<()> {
     match task.action.as_str() {
         "pull" => {
             let model_id = task.model_id.as_ref()
                 .ok_or_else(|| anyhow::anyhow!("Model ID is required for pull action"))?;
+            let downloader = ModelDownloader::new(task.cache_dir.clone())?
+                .with_endpoints(downloader::env_endpoints())
+                .with_verbose(task.verbose)
+                .with_info_cache(info_cache.clone());
             pull_model(
+                &downloader,
                 model_id.clone(),
                 task.filename.clone(),
-                task.cache_dir.clone(),
                 task.force,
+                false, // Batch runs never prompt
                 task.verbose,
             ).await
         }
@@ -1138,7 +4732,7 @@ async fn execute_model_task(task: &ModelTask) -> Result<()> {
             ).await
         }
         "list" => {
-            list_models(task.cache_dir.clone(), task.verbose).await
+            list_models(task.cache_dir.clone(), None, None, ListSort::Name, task.verbose).await
         }
         "usage" => {
             show_disk_usage(task.cache_dir.clone()).await
@@ -1147,34 +4741,302 @@ async fn execute_model_task(task: &ModelTask) -> Result<()> {
     }
 }
 
-async fn execute_inference_task(task: &InferenceTask, global_verbose: bool) -> Result<()> {
-    let model = task.model.as_ref()
-        .ok_or_else(|| anyhow::anyhow!("Model is required for inference task '{}'", task.name))?;
+/// Run groups of inference tasks from [`group_by_model`] on up to `parallel` threads, returning
+/// how many tasks succeeded and failed. A single thread works through each group in order,
+/// so a model is never loaded twice at the same time. Without `continue_on_error`, the first
+/// failure stops tasks that haven't started yet and is returned once the running ones finish.
+async fn run_inference_tasks_parallel(
+    groups: VecDeque<Vec<InferenceTask>>,
+    parallel: usize,
+    continue_on_error: bool,
+    lock_wait: bool,
+    verbose: bool,
+) -> Result<(usize, usize)> {
+    if verbose {
+        println!(
+            "{} Running {} model group(s) on up to {} threads",
+            "Info:".blue().bold(),
+            groups.len(),
+            parallel
+        );
+    }
 
-    // Create RunConfig from the task
-    let run_config = RunConfig {
-        model: model.clone(),
-        hf_filename: task.hf_filename.clone(),
-        cache_dir: task.cache_dir.clone(),
-        force_download: task.force_download,
-        prompt: task.prompt.clone(),
-        max_tokens: task.max_tokens.unwrap_or(1024),
-        temperature: task.temperature.unwrap_or(0.8),
-        top_k: task.top_k.unwrap_or(40),
-        top_p: task.top_p.unwrap_or(0.95),
-        ctx_size: task.ctx_size,
-        threads: task.threads,
-        no_color: task.no_color,
-        stats: task.stats,
-        verbose: task.verbose || global_verbose,
+    let workers = parallel.min(groups.len());
+    let queue = Arc::new(Mutex::new(groups));
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let runtime = tokio::runtime::Handle::current();
+
+    // Generation blocks its thread, so each worker gets a blocking thread of its own
+    let handles: Vec<_> = (0..workers)
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            let cancelled = Arc::clone(&cancelled);
+            let runtime = runtime.clone();
+            tokio::task::spawn_blocking(move || {
+                let mut results = Vec::new();
+                loop {
+                    let next = queue.lock().unwrap_or_else(|e| e.into_inner()).pop_front();
+                    let Some(group) = next else { break };
+                    let mut loaded = None;
+                    for task in group {
+                        if cancelled.load(Ordering::SeqCst) || interrupt::interrupted() {
+                            return results;
+                        }
+                        let result =
+                            runtime.block_on(execute_inference_task_with_retries(&task, &mut loaded, lock_wait, verbose, true));
+                        match &result {
+                            Ok(()) => println!("{} Task '{}' completed successfully", "Success:".green().bold(), task.name),
+                            Err(e) => {
+                                eprintln!("{} Task '{}' failed: {}", "Error:".red().bold(), task.name, e);
+                                if !continue_on_error {
+                                    cancelled.store(true, Ordering::SeqCst);
+                                }
+                            }
+                        }
+                        results.push(result);
+                    }
+                }
+                results
+            })
+        })
+        .collect();
+
+    let mut executed_count = 0;
+    let mut failed_count = 0;
+    let mut first_error = None;
+    for handle in handles {
+        for result in handle.await? {
+            match result {
+                Ok(()) => executed_count += 1,
+                Err(e) => {
+                    failed_count += 1;
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+    }
+
+    match first_error {
+        Some(e) if !continue_on_error => Err(e),
+        _ => Ok((executed_count, failed_count)),
+    }
+}
+
+/// Split tasks into groups that use the same model, in order of first use, keeping each
+/// group's tasks in config order
+fn group_by_model(tasks: Vec<InferenceTask>) -> VecDeque<Vec<InferenceTask>> {
+    let mut groups: VecDeque<Vec<InferenceTask>> = VecDeque::new();
+    for task in tasks {
+        let same_model = |group: &&mut Vec<InferenceTask>| {
+            let first = &group[0];
+            first.model == task.model
+                && first.hf_filename == task.hf_filename
+                && first.cache_dir == task.cache_dir
+                && first.revision == task.revision
+        };
+        match groups.iter_mut().find(same_model) {
+            Some(group) => group.push(task),
+            None => groups.push_back(vec![task]),
+        }
+    }
+    groups
+}
+
+fn print_task_header(task: &InferenceTask) {
+    println!("{} Executing task: {}", "Info:".blue().bold(), task.name);
+    if let Some(desc) = &task.description {
+        println!("  {}", desc);
+    }
+}
+
+/// Print everything a config task would run with, defaults included, for --dry-run
+fn print_resolved_task(task: &InferenceTask, run_config: &RunConfig) {
+    println!("  {} Would run: {}", "DRY RUN:".yellow().bold(), task.name);
+    let field = |name: &str, value: String| println!("    {:<16} {}", format!("{}:", name), value);
+
+    field("model", run_config.model.clone());
+    if let Some(filename) = &run_config.hf_filename {
+        field("hf_filename", filename.clone());
+    }
+    if let Some(revision) = &run_config.revision {
+        field("revision", revision.clone());
+    }
+    match &task.prompt_file {
+        Some(path) => field("prompt_file", format!("{} ({} chars)", path, run_config.prompt.chars().count())),
+        None => field("prompt", text_preview(&run_config.prompt)),
+    }
+    if let Some(system) = &run_config.system {
+        field("system", text_preview(system));
+    }
+
+    field("max_tokens", run_config.max_tokens.to_string());
+    if run_config.min_tokens > 0 {
+        field("min_tokens", run_config.min_tokens.to_string());
+    }
+    field("temperature", run_config.temperature.to_string());
+    if run_config.dynatemp_range > 0.0 {
+        field("dynatemp_range", format!("{} (exponent {})", run_config.dynatemp_range, run_config.dynatemp_exponent));
+    }
+    field("top_k", run_config.top_k.to_string());
+    field("top_p", run_config.top_p.to_string());
+    field(
+        "repeat_penalty",
+        format!("{} (last {} tokens)", run_config.repeat_penalty, run_config.repeat_last_n),
+    );
+    if run_config.mirostat > 0 {
+        field(
+            "mirostat",
+            format!("{} (tau {}, eta {})", run_config.mirostat, run_config.mirostat_tau, run_config.mirostat_eta),
+        );
+    }
+    field("seed", run_config.seed.map_or("random".to_string(), |seed| seed.to_string()));
+    if run_config.beams > 1 {
+        field("beams", run_config.beams.to_string());
+    }
+    if !run_config.stop.is_empty() {
+        field("stop", format!("{:?}", run_config.stop));
+    }
+    if let Some(path) = &run_config.grammar_file {
+        field("grammar_file", path.display().to_string());
+    }
+    if let Some(path) = &run_config.json_schema {
+        field("json_schema", path.display().to_string());
+    }
+
+    field(
+        "ctx_size",
+        match run_config.ctx_size {
+            Some(ctx_size) => ctx_size.to_string(),
+            None if run_config.auto_ctx => "auto".to_string(),
+            None => DEFAULT_CTX_SIZE.to_string(),
+        },
+    );
+    field("threads", run_config.threads.map_or("auto".to_string(), |threads| threads.to_string()));
+    field("batch_size", run_config.batch_size.to_string());
+
+    field(
+        "output_file",
+        match &run_config.output_file {
+            Some(path) if run_config.append => format!("{} (append)", path),
+            Some(path) => path.clone(),
+            None => "none".to_string(),
+        },
+    );
+    if let Some(timeout) = task.timeout {
+        field("timeout", format!("{}s", timeout));
+    }
+    if let Some(retries) = task.retries.filter(|&retries| retries > 0) {
+        let delay = retry_delay(task.retry_delay_secs, 1);
+        field("retries", format!("{} (first after {}s)", retries, delay.as_secs()));
+    }
+    if let Some(command) = &task.post_hook {
+        field("post_hook", command.clone());
+    }
+}
+
+/// The first line of a prompt, shortened for display, with its length if anything was cut
+pub fn text_preview(text: &str) -> String {
+    const PREVIEW_CHARS: usize = 60;
+    let first_line = text.lines().next().unwrap_or_default();
+    let preview: String = first_line.chars().take(PREVIEW_CHARS).collect();
+    if preview.len() == text.len() {
+        format!("{:?}", preview)
+    } else {
+        format!("{:?}... ({} chars)", preview, text.chars().count())
+    }
+}
+
+/// Seconds before a task's first retry when retry_delay_secs isn't set
+const DEFAULT_RETRY_DELAY_SECS: u64 = 5;
+
+/// Wait before retry number `attempt` (from 1): the base delay, doubled for each retry after the first
+pub fn retry_delay(base_secs: Option<u64>, attempt: u32) -> std::time::Duration {
+    let base = base_secs.unwrap_or(DEFAULT_RETRY_DELAY_SECS);
+    std::time::Duration::from_secs(base.saturating_mul(1 << attempt.saturating_sub(1).min(16)))
+}
+
+/// Report a failed attempt and wait before retrying it
+async fn wait_to_retry(what: &str, error: &anyhow::Error, attempt: u32, retries: u32, delay_secs: Option<u64>) {
+    let delay = retry_delay(delay_secs, attempt);
+    eprintln!(
+        "{} {} failed: {}; retrying in {}s ({}/{})",
+        "Warning:".yellow().bold(),
+        what,
+        error,
+        delay.as_secs(),
+        attempt,
+        retries
+    );
+    tokio::time::sleep(delay).await;
+}
+
+/// Run a config task, retrying it up to its `retries` times before it counts as failed
+async fn execute_inference_task_with_retries(
+    task: &InferenceTask,
+    loaded: &mut Option<LoadedModel>,
+    lock_wait: bool,
+    global_verbose: bool,
+    quiet: bool,
+) -> Result<()> {
+    let retries = task.retries.unwrap_or(0);
+    let mut attempt = 0;
+    loop {
+        match execute_inference_task(task, loaded, lock_wait, global_verbose, quiet).await {
+            Err(e) if attempt < retries && !interrupt::interrupted() => {
+                attempt += 1;
+                wait_to_retry(&format!("Task '{}'", task.name), &e, attempt, retries, task.retry_delay_secs).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Run a model task, retrying it up to its `retries` times before it counts as failed
+async fn execute_model_task_with_retries(task: &ModelTask, info_cache: &ModelInfoCache) -> Result<()> {
+    let retries = task.retries.unwrap_or(0);
+    let mut attempt = 0;
+    loop {
+        match execute_model_task(task, info_cache).await {
+            Err(e) if attempt < retries && !interrupt::interrupted() => {
+                attempt += 1;
+                wait_to_retry(&format!("Model task '{}'", task.action), &e, attempt, retries, task.retry_delay_secs).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Run one config task. `loaded` carries the model between tasks that share it.
+async fn execute_inference_task(
+    task: &InferenceTask,
+    loaded: &mut Option<LoadedModel>,
+    lock_wait: bool,
+    global_verbose: bool,
+    quiet: bool,
+) -> Result<()> {
+    if global_verbose {
+        print_task_header(task);
+    }
+
+    let mut run_config = task_run_config(task, lock_wait, global_verbose, quiet)?;
+    let timeout = task.timeout.map(std::time::Duration::from_secs);
+    run_config.deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+    let start_time = Instant::now();
+    let streamed = streams_output_file(&run_config);
+
+    // The deadline stops the token loop; the timeout covers awaits such as downloads
+    let generation = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, run_inference(run_config, loaded))
+            .await
+            .map_err(|_| anyhow::anyhow!("Task '{}' timed out after {}s", task.name, timeout.as_secs()))??,
+        None => run_inference(run_config, loaded).await?,
     };
 
-    // Capture output if output_file is specified
-    if let Some(output_file) = &task.output_file {
-        let generated_text = run_inference(run_config).await?;
-        
-        // Save the generated text to file
-        match fs::write(output_file, &generated_text) {
+    // Save output if output_file is specified and wasn't streamed to as it was generated
+    if let Some(output_file) = task.output_file.as_ref().filter(|_| !streamed) {
+        // Save the generated text to file, locked against other processes writing the same path
+        match file_lock::write_locked(Path::new(output_file), &generation.text, task.append, lock_wait) {
             Ok(()) => {
                 if global_verbose {
                     println!("  {} Output saved to: {}", 
@@ -1187,12 +5049,116 @@ async fn execute_inference_task(task: &InferenceTask, global_verbose: bool) -> R
                 return Err(anyhow::anyhow!("Failed to save output to file: {}", e));
             }
         }
-        
-        Ok(())
-    } else {
-        let _generated_text = run_inference(run_config).await?;
-        Ok(())
     }
+
+    // Hook failures fail the task, so continue_on_error applies to them too
+    if let Some(command) = &task.post_hook {
+        if global_verbose {
+            println!("  {} Running post hook: {}", "Info:".blue().bold(), command);
+        }
+        hooks::run_post_hook(
+            command,
+            task.output_file.as_deref(),
+            generation.tokens_generated,
+            start_time.elapsed(),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// The settings a config task runs with: its own, then built-in defaults for anything
+/// neither it nor the config's defaults set
+fn task_run_config(task: &InferenceTask, lock_wait: bool, global_verbose: bool, quiet: bool) -> Result<RunConfig> {
+    let model = task.model.as_ref()
+        .ok_or_else(|| anyhow::anyhow!("Model is required for inference task '{}'", task.name))?;
+
+    let prompt = match &task.prompt_file {
+        Some(path) => fifo::read_prompt_file(Path::new(path)).map_err(|e| anyhow::anyhow!("Task '{}': {}", task.name, e))?,
+        None => task.prompt.clone(),
+    };
+
+    Ok(RunConfig {
+        model: model.clone(),
+        hf_filename: task.hf_filename.clone(),
+        cache_dir: task.cache_dir.clone(),
+        force_download: task.force_download,
+        prompt,
+        max_tokens: task.max_tokens.unwrap_or(1024),
+        temperature: task.temperature.unwrap_or(0.8),
+        top_k: task.top_k.unwrap_or(40),
+        top_p: task.top_p.unwrap_or(0.95),
+        best_of: 1,
+        ctx_size: task.ctx_size,
+        threads: task.threads,
+        no_color: task.no_color,
+        stats: task.stats,
+        no_perf: false,
+        image: None,
+        mmproj: None,
+        kv_cache_type_k: task.kv_cache_type_k.clone(),
+        kv_cache_type_v: task.kv_cache_type_v.clone(),
+        flash_attn: task.flash_attn,
+        show_probs: false,
+        seed_from_prompt: false,
+        show_model_info: false,
+        hf_endpoints: downloader::env_endpoints(),
+        dump_tokens_file: None,
+        stop_tokens: task.stop_tokens.clone(),
+        ban_words: task.ban_words.clone(),
+        auto_quant: false,
+        auto_ctx: task.auto_ctx,
+        deadline: None,
+        min_tokens: task.min_tokens.unwrap_or(0),
+        system: task.system.clone(),
+        chat: task.chat,
+        chat_template: task.chat_template.clone(),
+        output_file: task.output_file.clone(),
+        save_every: task.save_every,
+        prompt_affinity: task.prompt_affinity.unwrap_or(0.0),
+        interactive_first: false,
+        revision: task.revision.clone(),
+        pin: task.pin,
+        explain: None,
+        beams: task.beams.unwrap_or(1),
+        temp_range: None,
+        single_line: task.single_line,
+        truncate: task.truncate,
+        dump_state: None,
+        load_state: None,
+        stop_on_repeat: task.stop_on_repeat,
+        dynatemp_range: task.dynatemp_range.unwrap_or(0.0),
+        dynatemp_exponent: task.dynatemp_exponent.unwrap_or(1.0),
+        messages_file: None,
+        seed: task.seed,
+        repeat_penalty: task.repeat_penalty.unwrap_or(1.1),
+        repeat_last_n: task.repeat_last_n.unwrap_or(64),
+        stop: task.stop.clone(),
+        batch_size: task.batch_size.unwrap_or(DEFAULT_BATCH_SIZE),
+        no_verify: false,
+        hf_token: None,
+        append: task.append,
+        output_lock_wait: lock_wait,
+        stream_output_file: true,
+        format: OutputFormat::Text,
+        quiet,
+        n_keep: None,
+        mirostat: task.mirostat.unwrap_or(0),
+        mirostat_tau: task.mirostat_tau.unwrap_or(5.0),
+        mirostat_eta: task.mirostat_eta.unwrap_or(0.1),
+        grammar_file: task.grammar_file.as_ref().map(PathBuf::from),
+        json_schema: task.json_schema.as_ref().map(PathBuf::from),
+        ignore_eos: task.ignore_eos,
+        logit_bias: task
+            .logit_bias
+            .iter()
+            .map(|(token, &bias)| LogitBiasEntry { token: token.clone(), bias })
+            .collect(),
+        no_mmap: task.no_mmap,
+        mlock: task.mlock,
+        context: None,
+        verbose: task.verbose || global_verbose,
+    })
 }
 
 async fn execute_dataset_task(dataset: &DatasetTask, global_verbose: bool) -> Result<usize> {
@@ -1252,8 +5218,12 @@ async fn execute_dataset_task(dataset: &DatasetTask, global_verbose: bool) -> Re
         println!("  {} Generated {} unique prompts", "Info:".blue().bold(), prompts_to_generate.len());
     }
 
-    // Generate samples
+    // Generate samples, loading the model once for all of them
+    let mut loaded = None;
     for (i, prompt) in prompts_to_generate.iter().enumerate() {
+        if interrupt::interrupted() {
+            break;
+        }
         if global_verbose || dataset.verbose {
             println!("  {} Generating sample {}/{}", "Progress:".cyan(), i + 1, dataset.count);
         }
@@ -1269,16 +5239,77 @@ async fn execute_dataset_task(dataset: &DatasetTask, global_verbose: bool) -> Re
             temperature: dataset.temperature,
             top_k: dataset.top_k.unwrap_or(40),
             top_p: dataset.top_p.unwrap_or(0.95),
+            best_of: 1,
             ctx_size: Some(dataset.ctx_size),
             threads: dataset.threads,
             no_color: true, // Suppress colored output for batch processing
             stats: false,   // Suppress stats for batch processing
+            no_perf: false,
+            image: None,
+            mmproj: None,
+            kv_cache_type_k: None,
+            kv_cache_type_v: None,
+            flash_attn: false,
+            show_probs: false,
+            seed_from_prompt: false,
+            show_model_info: false,
+            hf_endpoints: downloader::env_endpoints(),
+            dump_tokens_file: None,
+            stop_tokens: Vec::new(),
+            ban_words: Vec::new(),
+            auto_quant: false,
+            auto_ctx: false,
+            deadline: None,
+            min_tokens: 0,
+            system: None,
+            chat: false,
+            chat_template: None,
+            output_file: None,
+            save_every: None,
+            prompt_affinity: 0.0,
+            interactive_first: false,
+            revision: None,
+            pin: false,
+            explain: None,
+            beams: 1,
+            temp_range: None,
+            single_line: false,
+            truncate: None,
+            dump_state: None,
+            load_state: None,
+            stop_on_repeat: None,
+            dynatemp_range: 0.0,
+            dynatemp_exponent: 1.0,
+            messages_file: None,
+            seed: None,
+            repeat_penalty: 1.1,
+            repeat_last_n: 64,
+            stop: Vec::new(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            no_verify: false,
+            hf_token: None,
+            append: false,
+            output_lock_wait: false,
+            stream_output_file: false,
+            format: OutputFormat::Text,
+            quiet: false,
+            n_keep: None,
+            mirostat: 0,
+            mirostat_tau: 5.0,
+            mirostat_eta: 0.1,
+            grammar_file: None,
+            json_schema: None,
+            ignore_eos: false,
+            logit_bias: Vec::new(),
+            no_mmap: false,
+            mlock: false,
+            context: None,
             verbose: false, // Suppress inference verbosity for cleaner output
         };
 
-        match run_inference(run_config).await {
-            Ok(generated_text) => {
-                let cleaned_text = generated_text.trim();
+        match run_inference(run_config, &mut loaded).await {
+            Ok(generation) => {
+                let cleaned_text = generation.text.trim();
                 
                 // Basic quality checks if enabled
                 if dataset.quality_checks {
@@ -1411,14 +5442,115 @@ pub fn validate_args(cli: &RunConfig) -> Result<()> {
         return Err(anyhow::anyhow!("Temperature must be between 0.0 and 2.0"));
     }
 
+    if cli.dynatemp_range < 0.0 {
+        return Err(anyhow::anyhow!("Dynamic temperature range must not be negative"));
+    }
+
+    if cli.dynatemp_exponent <= 0.0 {
+        return Err(anyhow::anyhow!("Dynamic temperature exponent must be greater than 0"));
+    }
+
+    if cli.mirostat > 2 {
+        return Err(anyhow::anyhow!("Mirostat must be 0 (off), 1 or 2"));
+    }
+
+    if cli.mirostat > 0 {
+        if cli.mirostat_tau <= 0.0 || cli.mirostat_eta <= 0.0 {
+            return Err(anyhow::anyhow!("Mirostat tau and eta must be greater than 0"));
+        }
+        if cli.beams > 1 {
+            return Err(anyhow::anyhow!("--mirostat can't be combined with --beams"));
+        }
+        if cli.explain.is_some() {
+            return Err(anyhow::anyhow!("--explain traces top-k and top-p, so it can't be combined with --mirostat"));
+        }
+    }
+
+    if cli.grammar_file.is_some() && cli.beams > 1 {
+        return Err(anyhow::anyhow!("--grammar-file can't be combined with --beams"));
+    }
+
+    if cli.json_schema.is_some() {
+        if cli.grammar_file.is_some() {
+            return Err(anyhow::anyhow!("--json-schema and --grammar-file can't be combined"));
+        }
+        if cli.beams > 1 {
+            return Err(anyhow::anyhow!("--json-schema can't be combined with --beams"));
+        }
+    }
+
+    if cli.repeat_penalty <= 0.0 {
+        return Err(anyhow::anyhow!("Repeat penalty must be greater than 0"));
+    }
+
     if cli.top_p < 0.0 || cli.top_p > 1.0 {
         return Err(anyhow::anyhow!("Top-p must be between 0.0 and 1.0"));
     }
 
+    if cli.batch_size == 0 {
+        return Err(anyhow::anyhow!("Batch size must be greater than 0"));
+    }
+
     if cli.max_tokens == 0 {
         return Err(anyhow::anyhow!("Max tokens must be greater than 0"));
     }
 
+    if cli.save_every == Some(0) {
+        return Err(anyhow::anyhow!("--save-every must be greater than 0"));
+    }
+
+    if cli.save_every.is_some() && cli.output_file.is_none() {
+        return Err(anyhow::anyhow!("--save-every requires --output-file"));
+    }
+
+    if cli.min_tokens > cli.max_tokens {
+        return Err(anyhow::anyhow!("Min tokens ({}) cannot exceed max tokens ({})", cli.min_tokens, cli.max_tokens));
+    }
+
+    if cli.best_of == 0 {
+        return Err(anyhow::anyhow!("Best-of must be at least 1"));
+    }
+
+    for kv_type in [&cli.kv_cache_type_k, &cli.kv_cache_type_v].into_iter().flatten() {
+        kv_cache::parse_kv_cache_type(kv_type)?;
+    }
+
+    if cli.ban_words.iter().any(|word| word.trim().is_empty()) {
+        return Err(anyhow::anyhow!("Banned words must not be empty"));
+    }
+
+    if let Some(id) = cli.stop_tokens.iter().find(|&&id| id < 0) {
+        return Err(anyhow::anyhow!("Stop token IDs must be non-negative, got {}", id));
+    }
+
+    if cli.beams == 0 {
+        return Err(anyhow::anyhow!("Beams must be at least 1"));
+    }
+
+    if cli.beams > 1 && cli.best_of > 1 {
+        return Err(anyhow::anyhow!("--beams cannot be combined with --best-of"));
+    }
+
+    if cli.stop_on_repeat.is_some_and(|repeats| repeats < 2) {
+        return Err(anyhow::anyhow!("--stop-on-repeat must be at least 2"));
+    }
+
+    if cli.dump_state.is_some() && (cli.best_of > 1 || cli.beams > 1 || cli.temp_range.is_some()) {
+        return Err(anyhow::anyhow!("--dump-state needs a single completion; it cannot be combined with --best-of, --beams or --temp-range"));
+    }
+
+    if cli.image.is_some() && (cli.dump_state.is_some() || cli.load_state.is_some()) {
+        return Err(anyhow::anyhow!("--dump-state and --load-state do not support --image"));
+    }
+
+    if cli.auto_quant && !is_hf_model_id(&cli.model) {
+        return Err(anyhow::anyhow!("--auto-quant only applies to Hugging Face model IDs"));
+    }
+
+    if cli.image.is_some() && cli.mmproj.is_none() {
+        return Err(anyhow::anyhow!("--image requires --mmproj <PATH> to load the multimodal projector"));
+    }
+
     Ok(())
 }
 
@@ -1437,6 +5569,12 @@ fn print_banner(cli: &RunConfig) {
         println!("{} {}", "Temperature:".cyan().bold(), cli.temperature);
         println!("{} {}", "Top-k:".cyan().bold(), cli.top_k);
         println!("{} {}", "Top-p:".cyan().bold(), cli.top_p);
+        if cli.best_of > 1 {
+            println!("{} {}", "Best-of:".cyan().bold(), cli.best_of);
+        }
+        if cli.beams > 1 {
+            println!("{} {}", "Beams:".cyan().bold(), cli.beams);
+        }
         if let Some(ctx_size) = cli.ctx_size {
             println!("{} {}", "Context Size:".cyan().bold(), ctx_size);
         }
@@ -1452,11 +5590,27 @@ fn print_banner(cli: &RunConfig) {
         println!("Temperature: {}", cli.temperature);
         println!("Top-k: {}", cli.top_k);
         println!("Top-p: {}", cli.top_p);
+        if cli.best_of > 1 {
+            println!("Best-of: {}", cli.best_of);
+        }
+        if cli.beams > 1 {
+            println!("Beams: {}", cli.beams);
+        }
     }
 }
 
-fn print_stats(tokens_generated: usize, duration: std::time::Duration, cli: &RunConfig) {
-    let tokens_per_sec = tokens_generated as f64 / duration.as_secs_f64();
+/// Throughput of `tokens` processed in `duration`, or 0 if no time was measured
+pub fn tokens_per_sec(tokens: usize, duration: std::time::Duration) -> f64 {
+    let secs = duration.as_secs_f64();
+    if secs > 0.0 {
+        tokens as f64 / secs
+    } else {
+        0.0
+    }
+}
+
+fn print_stats(tokens_generated: usize, duration: std::time::Duration, beam_score: Option<f64>, cli: &RunConfig) {
+    let tokens_per_sec = tokens_per_sec(tokens_generated, duration);
 
     if !cli.no_color {
         println!("\n{}", "📊 Generation Statistics".bright_cyan().bold());
@@ -1464,11 +5618,23 @@ fn print_stats(tokens_generated: usize, duration: std::time::Duration, cli: &Run
         println!("{} {}", "Tokens Generated:".cyan(), tokens_generated);
         println!("{} {:.2}s", "Time Taken:".cyan(), duration.as_secs_f64());
         println!("{} {:.2} tokens/sec", "Speed:".cyan(), tokens_per_sec);
+        if let Some(score) = beam_score {
+            println!("{} {:.4} (cumulative log-prob)", "Beam Score:".cyan(), score);
+        }
+        if let Some(seed) = cli.seed {
+            println!("{} {}", "Seed:".cyan(), seed);
+        }
         println!("{}", "━".repeat(30).bright_black());
     } else {
         println!("\nGeneration Statistics");
         println!("Tokens Generated: {}", tokens_generated);
         println!("Time Taken: {:.2}s", duration.as_secs_f64());
         println!("Speed: {:.2} tokens/sec", tokens_per_sec);
+        if let Some(score) = beam_score {
+            println!("Beam Score: {:.4} (cumulative log-prob)", score);
+        }
+        if let Some(seed) = cli.seed {
+            println!("Seed: {}", seed);
+        }
     }
 }
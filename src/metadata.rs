@@ -0,0 +1,428 @@
+/*!
+# Cached Model Metadata
+
+Each cached model directory may contain a `metadata.json` file recording
+information that can't be derived from the GGUF files themselves, such as
+whether the model is pinned against cleanup and the checksum of each
+downloaded file.
+
+A missing file is treated as default metadata, so models downloaded before
+this file existed keep working. A file that can't be parsed is reported and
+otherwise treated the same way; `models repair-metadata` rebuilds it from the
+files on disk.
+
+`models doctor` checks every cached file against its record and can move bad
+files into a `.corrupt/` directory inside the model directory, where they are
+no longer picked up as model files.
+*/
+
+use crate::downloader::denormalize_model_id;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use colored::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Name of the metadata file inside a model's cache directory
+pub const METADATA_FILE: &str = "metadata.json";
+
+/// Directory inside a model's cache directory that quarantined files are moved to
+pub const QUARANTINE_DIR: &str = ".corrupt";
+
+/// Per-model metadata stored alongside the downloaded files
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModelMetadata {
+    /// Original Hugging Face model ID
+    #[serde(default)]
+    pub model_id: Option<String>,
+
+    /// Pinned models are never removed by cleanup commands
+    #[serde(default)]
+    pub pinned: bool,
+
+    /// Commit SHA the downloaded files came from
+    #[serde(default)]
+    pub revision: Option<String>,
+
+    /// Downloaded files, keyed by filename
+    #[serde(default)]
+    pub files: BTreeMap<String, FileRecord>,
+}
+
+/// What was recorded about a file when it was downloaded
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileRecord {
+    pub size: u64,
+    /// Hex-encoded SHA256 of the file contents
+    pub sha256: String,
+    pub downloaded_at: DateTime<Utc>,
+    /// Commit SHA the file was downloaded from
+    #[serde(default)]
+    pub revision: Option<String>,
+}
+
+/// Result of checking a file on disk against its download record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verification {
+    Verified,
+    SizeMismatch,
+    ChecksumMismatch,
+    /// Downloaded before checksums were recorded
+    Unrecorded,
+}
+
+/// Something wrong with a cached file, found by [`check_model_dir`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CacheProblem {
+    SizeMismatch,
+    ChecksumMismatch,
+    /// A `.tmp` file left behind by an interrupted download
+    Incomplete,
+    /// Recorded in the metadata but no longer on disk
+    Missing,
+}
+
+impl CacheProblem {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            CacheProblem::SizeMismatch => "size differs from download",
+            CacheProblem::ChecksumMismatch => "checksum mismatch",
+            CacheProblem::Incomplete => "incomplete download",
+            CacheProblem::Missing => "recorded but missing",
+        }
+    }
+}
+
+impl FileRecord {
+    /// Compare a file against this record; the size is checked first to avoid hashing needlessly
+    pub fn verify(&self, path: &Path) -> Result<Verification> {
+        let size = fs::metadata(path)
+            .map_err(|e| anyhow!("Failed to read '{}': {}", path.display(), e))?
+            .len();
+        if size != self.size {
+            return Ok(Verification::SizeMismatch);
+        }
+
+        if sha256_file(path)? == self.sha256 {
+            Ok(Verification::Verified)
+        } else {
+            Ok(Verification::ChecksumMismatch)
+        }
+    }
+}
+
+/// Rebuild a model directory's metadata from the files in it: sizes and checksums are recomputed
+/// and the modification time stands in for the download date. Anything still readable from
+/// `previous` (ID, pin, revisions) is kept.
+pub fn regenerate(model_dir: &Path, previous: Option<&ModelMetadata>) -> Result<ModelMetadata> {
+    let mut regenerated = ModelMetadata {
+        model_id: Some(display_model_id_from(model_dir, previous)),
+        pinned: previous.is_some_and(|metadata| metadata.pinned),
+        revision: previous.and_then(|metadata| metadata.revision.clone()),
+        files: BTreeMap::new(),
+    };
+
+    for entry in fs::read_dir(model_dir)? {
+        let entry = entry?;
+        let filename = entry.file_name().to_string_lossy().into_owned();
+        if !entry.file_type()?.is_file() || filename == METADATA_FILE || filename.ends_with(".tmp") {
+            continue;
+        }
+
+        let file_metadata = entry.metadata()?;
+        let downloaded_at = file_metadata
+            .modified()
+            .map(DateTime::<Utc>::from)
+            .unwrap_or_else(|_| Utc::now());
+        let revision = previous
+            .and_then(|metadata| metadata.files.get(&filename))
+            .and_then(|record| record.revision.clone());
+
+        regenerated.files.insert(
+            filename,
+            FileRecord {
+                size: file_metadata.len(),
+                sha256: sha256_file(&entry.path())?,
+                downloaded_at,
+                revision,
+            },
+        );
+    }
+
+    Ok(regenerated)
+}
+
+/// Verify a file against the record for it in `metadata`, if any
+pub fn verify_file(metadata: &ModelMetadata, filename: &str, path: &Path) -> Result<Verification> {
+    match metadata.files.get(filename) {
+        Some(record) => record.verify(path),
+        None => Ok(Verification::Unrecorded),
+    }
+}
+
+/// Check every file in a model directory against its download record, sorted by filename.
+/// Files downloaded before checksums were recorded can't be checked and aren't reported.
+pub fn check_model_dir(model_dir: &Path) -> Result<Vec<(String, CacheProblem)>> {
+    let metadata = ModelMetadata::load_or_warn(model_dir);
+    let mut problems = Vec::new();
+
+    for entry in fs::read_dir(model_dir)? {
+        let entry = entry?;
+        let filename = entry.file_name().to_string_lossy().into_owned();
+        if !entry.file_type()?.is_file() || filename == METADATA_FILE {
+            continue;
+        }
+
+        if filename.ends_with(".tmp") {
+            problems.push((filename, CacheProblem::Incomplete));
+            continue;
+        }
+
+        match verify_file(&metadata, &filename, &entry.path())? {
+            Verification::SizeMismatch => problems.push((filename, CacheProblem::SizeMismatch)),
+            Verification::ChecksumMismatch => problems.push((filename, CacheProblem::ChecksumMismatch)),
+            Verification::Verified | Verification::Unrecorded => {}
+        }
+    }
+
+    for filename in metadata.files.keys() {
+        if !model_dir.join(filename).exists() {
+            problems.push((filename.clone(), CacheProblem::Missing));
+        }
+    }
+
+    problems.sort();
+    Ok(problems)
+}
+
+/// Move a file into the model's quarantine directory and drop its record, so it is neither loaded
+/// nor reported again. Returns the new path.
+pub fn quarantine(model_dir: &Path, filename: &str) -> Result<PathBuf> {
+    let quarantine_dir = model_dir.join(QUARANTINE_DIR);
+    fs::create_dir_all(&quarantine_dir)
+        .map_err(|e| anyhow!("Failed to create '{}': {}", quarantine_dir.display(), e))?;
+
+    let destination = quarantine_dir.join(filename);
+    fs::rename(model_dir.join(filename), &destination)
+        .map_err(|e| anyhow!("Failed to quarantine '{}': {}", filename, e))?;
+
+    let mut metadata = ModelMetadata::load_or_warn(model_dir);
+    if metadata.files.remove(filename).is_some() {
+        metadata.save(model_dir)?;
+    }
+
+    Ok(destination)
+}
+
+/// Hex-encoded SHA256 of a file, streamed so multi-gigabyte models aren't read into memory
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path)
+        .map_err(|e| anyhow!("Failed to open '{}': {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)
+        .map_err(|e| anyhow!("Failed to read '{}': {}", path.display(), e))?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+impl ModelMetadata {
+    /// Path of the metadata file for a model directory
+    pub fn path(model_dir: &Path) -> PathBuf {
+        model_dir.join(METADATA_FILE)
+    }
+
+    /// Load metadata for a model directory, returning defaults if none exists
+    pub fn load(model_dir: &Path) -> Result<Self> {
+        let path = Self::path(model_dir);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Failed to read '{}': {}", path.display(), e))?;
+
+        serde_json::from_str(&content)
+            .map_err(|e| anyhow!("Failed to parse '{}': {}", path.display(), e))
+    }
+
+    /// Load metadata for a model directory, warning about and ignoring a corrupt file
+    pub fn load_or_warn(model_dir: &Path) -> Self {
+        Self::load(model_dir).unwrap_or_else(|e| {
+            eprintln!(
+                "{} {}; falling back to information from the files. Run 'rustlama models repair-metadata' to regenerate it.",
+                "Warning:".yellow().bold(),
+                e
+            );
+            Self::default()
+        })
+    }
+
+    /// Write metadata into a model directory
+    pub fn save(&self, model_dir: &Path) -> Result<()> {
+        let path = Self::path(model_dir);
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| anyhow!("Failed to serialize model metadata: {}", e))?;
+
+        fs::write(&path, content)
+            .map_err(|e| anyhow!("Failed to write '{}': {}", path.display(), e))
+    }
+}
+
+/// Model ID to show for a cache directory: the recorded ID, or one derived from the directory name
+pub fn display_model_id(model_dir: &Path) -> String {
+    display_model_id_from(model_dir, ModelMetadata::load(model_dir).ok().as_ref())
+}
+
+fn display_model_id_from(model_dir: &Path, metadata: Option<&ModelMetadata>) -> String {
+    if let Some(model_id) = metadata.and_then(|metadata| metadata.model_id.clone()) {
+        return model_id;
+    }
+
+    let dir_name = model_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    denormalize_model_id(&dir_name)
+}
+
+/// Whether a model directory is pinned; unreadable metadata is reported and counts as unpinned
+pub fn is_pinned(model_dir: &Path) -> bool {
+    ModelMetadata::load_or_warn(model_dir).pinned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_missing_metadata_is_default() -> Result<()> {
+        let dir = TempDir::new()?;
+        let metadata = ModelMetadata::load(dir.path())?;
+        assert!(!metadata.pinned);
+        assert!(metadata.model_id.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pinned_round_trip() -> Result<()> {
+        let dir = TempDir::new()?;
+        let metadata = ModelMetadata {
+            model_id: Some("TheBloke/Llama-2-7B-Chat-GGUF".to_string()),
+            pinned: true,
+            revision: Some("0123456789abcdef0123456789abcdef01234567".to_string()),
+            files: BTreeMap::new(),
+        };
+        metadata.save(dir.path())?;
+
+        assert!(is_pinned(dir.path()));
+        let loaded = ModelMetadata::load(dir.path())?;
+        assert_eq!(loaded.model_id, metadata.model_id);
+        assert_eq!(loaded.revision, metadata.revision);
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_model_id_prefers_recorded_id() -> Result<()> {
+        let cache = TempDir::new()?;
+        let model_id = "some--org/model--v2-GGUF";
+        let model_dir = cache.path().join(crate::downloader::normalize_model_id(model_id));
+        fs::create_dir_all(&model_dir)?;
+
+        // Without metadata the owner's `--` is lost
+        assert_ne!(display_model_id(&model_dir), model_id);
+
+        ModelMetadata {
+            model_id: Some(model_id.to_string()),
+            ..Default::default()
+        }
+        .save(&model_dir)?;
+        assert_eq!(display_model_id(&model_dir), model_id);
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_metadata_falls_back_and_regenerates() -> Result<()> {
+        let cache = TempDir::new()?;
+        let model_dir = cache.path().join("TheBloke--Llama-2-7B-Chat-GGUF");
+        fs::create_dir_all(&model_dir)?;
+        fs::write(model_dir.join("model.Q4_K_M.gguf"), b"hello")?;
+        fs::write(ModelMetadata::path(&model_dir), "{\"model_id\": \"TheBlo")?;
+
+        assert!(ModelMetadata::load(&model_dir).is_err());
+        assert!(!ModelMetadata::load_or_warn(&model_dir).pinned);
+        assert_eq!(display_model_id(&model_dir), "TheBloke/Llama-2-7B-Chat-GGUF");
+
+        let regenerated = regenerate(&model_dir, None)?;
+        regenerated.save(&model_dir)?;
+        let loaded = ModelMetadata::load(&model_dir)?;
+        assert_eq!(loaded.model_id.as_deref(), Some("TheBloke/Llama-2-7B-Chat-GGUF"));
+        assert_eq!(loaded.files.len(), 1);
+        assert_eq!(
+            verify_file(&loaded, "model.Q4_K_M.gguf", &model_dir.join("model.Q4_K_M.gguf"))?,
+            Verification::Verified
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_file() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("model.gguf");
+        fs::write(&path, b"hello")?;
+
+        let mut metadata = ModelMetadata::default();
+        assert_eq!(verify_file(&metadata, "model.gguf", &path)?, Verification::Unrecorded);
+
+        metadata.files.insert(
+            "model.gguf".to_string(),
+            FileRecord {
+                size: 5,
+                sha256: "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824".to_string(),
+                downloaded_at: Utc::now(),
+                revision: None,
+            },
+        );
+        assert_eq!(verify_file(&metadata, "model.gguf", &path)?, Verification::Verified);
+
+        fs::write(&path, b"jello")?;
+        assert_eq!(verify_file(&metadata, "model.gguf", &path)?, Verification::ChecksumMismatch);
+
+        fs::write(&path, b"hello!")?;
+        assert_eq!(verify_file(&metadata, "model.gguf", &path)?, Verification::SizeMismatch);
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_model_dir_and_quarantine() -> Result<()> {
+        let model_dir = TempDir::new()?;
+        let model_dir = model_dir.path();
+        for (filename, contents) in [("good.gguf", "hello"), ("bad.gguf", "hello"), ("gone.gguf", "hello")] {
+            fs::write(model_dir.join(filename), contents)?;
+        }
+        regenerate(model_dir, None)?.save(model_dir)?;
+
+        fs::write(model_dir.join("bad.gguf"), "jello")?;
+        fs::remove_file(model_dir.join("gone.gguf"))?;
+        fs::write(model_dir.join("partial.tmp"), "hel")?;
+
+        assert_eq!(
+            check_model_dir(model_dir)?,
+            vec![
+                ("bad.gguf".to_string(), CacheProblem::ChecksumMismatch),
+                ("gone.gguf".to_string(), CacheProblem::Missing),
+                ("partial.tmp".to_string(), CacheProblem::Incomplete),
+            ]
+        );
+
+        let moved = quarantine(model_dir, "bad.gguf")?;
+        assert_eq!(moved, model_dir.join(QUARANTINE_DIR).join("bad.gguf"));
+        assert!(!model_dir.join("bad.gguf").exists());
+        assert!(!ModelMetadata::load(model_dir)?.files.contains_key("bad.gguf"));
+        assert_eq!(check_model_dir(model_dir)?.len(), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+/*!
+# Pipe Mode
+
+`rustlama pipe` keeps a model loaded and serves newline-delimited JSON over
+stdin/stdout: each input line is one request, each output line is the
+matching response. Requests only need a `prompt`; any other field overrides
+the defaults given on the command line.
+
+```text
+{"id": 1, "prompt": "The capital of France is", "max_tokens": 8}
+{"id":1,"text":" Paris.","prompt_tokens":7,"tokens_generated":3,"avg_log_prob":-0.41}
+```
+
+A request that fails produces a response with an `error` field instead of
+ending the session.
+*/
+
+use crate::RunConfig;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// One line of input
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PipeRequest {
+    /// Opaque value echoed back in the response
+    #[serde(default)]
+    pub id: Option<Value>,
+    pub prompt: String,
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub top_k: Option<usize>,
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    #[serde(default)]
+    pub stop_tokens: Option<Vec<i32>>,
+    #[serde(default)]
+    pub stop: Option<Vec<String>>,
+    #[serde(default)]
+    pub ban_words: Option<Vec<String>>,
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+/// One line of output
+#[derive(Debug, Default, Serialize)]
+pub struct PipeResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    pub prompt_tokens: usize,
+    pub tokens_generated: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avg_log_prob: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl PipeRequest {
+    /// Parse a request line
+    pub fn parse(line: &str) -> Result<Self> {
+        serde_json::from_str(line).map_err(|e| anyhow!("Invalid request: {}", e))
+    }
+
+    /// The run configuration for this request: the session defaults with this request's overrides
+    pub fn apply(self, defaults: &RunConfig) -> RunConfig {
+        let mut config = defaults.clone();
+        config.prompt = self.prompt;
+        if let Some(max_tokens) = self.max_tokens {
+            config.max_tokens = max_tokens;
+        }
+        if let Some(temperature) = self.temperature {
+            config.temperature = temperature;
+        }
+        if let Some(top_k) = self.top_k {
+            config.top_k = top_k;
+        }
+        if let Some(top_p) = self.top_p {
+            config.top_p = top_p;
+        }
+        if let Some(stop_tokens) = self.stop_tokens {
+            config.stop_tokens = stop_tokens;
+        }
+        if let Some(stop) = self.stop {
+            config.stop = stop;
+        }
+        if let Some(ban_words) = self.ban_words {
+            config.ban_words = ban_words;
+        }
+        if let Some(seed) = self.seed {
+            config.seed = Some(seed);
+        }
+        config
+    }
+}
+
+impl PipeResponse {
+    /// A response reporting a failed request
+    pub fn error(id: Option<Value>, error: &anyhow::Error) -> Self {
+        Self {
+            id,
+            error: Some(error.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Serialize as a single output line
+    pub fn to_line(&self) -> String {
+        serde_json::to_string(self).unwrap_or_else(|e| format!("{{\"error\":\"{}\"}}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        let request = PipeRequest::parse(r#"{"id": "a", "prompt": "Hi", "max_tokens": 5}"#).unwrap();
+        assert_eq!(request.prompt, "Hi");
+        assert_eq!(request.max_tokens, Some(5));
+        assert_eq!(request.id, Some(Value::from("a")));
+        assert!(request.temperature.is_none());
+
+        assert!(PipeRequest::parse(r#"{"max_tokens": 5}"#).is_err());
+        assert!(PipeRequest::parse(r#"{"prompt": "Hi", "maxtokens": 5}"#).is_err());
+    }
+
+    #[test]
+    fn test_error_response_line() {
+        let response = PipeResponse::error(Some(Value::from(3)), &anyhow!("boom"));
+        assert_eq!(
+            response.to_line(),
+            r#"{"id":3,"prompt_tokens":0,"tokens_generated":0,"error":"boom"}"#
+        );
+    }
+}
@@ -0,0 +1,62 @@
+/*!
+# Live Progress
+
+While a completion streams to a color terminal, a status line on stderr shows
+how many tokens have been generated and the rolling tokens/sec. The line is
+drawn under the text, so the text isn't printed straight to stdout: finished
+lines are, while the line still being written is shown as part of the
+progress display until it ends (or generation does).
+*/
+
+use colored::Colorize;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::io::{self, Write};
+
+/// Status line under the streamed text
+pub struct LiveProgress {
+    bar: ProgressBar,
+    /// Generated text after the last newline, not yet printed to stdout
+    partial: String,
+}
+
+impl LiveProgress {
+    /// Show the status line, empty until the first token
+    pub fn start() -> Self {
+        let bar = ProgressBar::with_draw_target(None, ProgressDrawTarget::stderr());
+        bar.set_style(
+            ProgressStyle::default_spinner()
+                .template("{msg}\n{spinner:.green} {pos} tokens ({per_sec})")
+                .unwrap(),
+        );
+        Self {
+            bar,
+            partial: String::new(),
+        }
+    }
+
+    /// Count one generated token
+    pub fn inc(&self) {
+        self.bar.inc(1);
+    }
+
+    /// Show a piece of generated text, printing every line it completes
+    pub fn print(&mut self, piece: &str) {
+        self.partial.push_str(piece);
+        if let Some(newline) = self.partial.rfind('\n') {
+            let rest = self.partial.split_off(newline + 1);
+            let done = std::mem::replace(&mut self.partial, rest);
+            self.bar.suspend(|| {
+                print!("{}", done.green());
+                io::stdout().flush().unwrap();
+            });
+        }
+        self.bar.set_message(self.partial.green().to_string());
+    }
+
+    /// Remove the status line and print the unfinished line
+    pub fn finish(self) {
+        self.bar.finish_and_clear();
+        print!("{}", self.partial.green());
+        io::stdout().flush().unwrap();
+    }
+}
@@ -0,0 +1,170 @@
+/*!
+# Cache Pruning
+
+`models prune` finds what can be deleted from the model cache: `.tmp` files
+left behind by interrupted downloads and, with `--older-than DAYS`, whole model
+directories whose GGUF files haven't been read or written in that long.
+Pinned models are never selected, though their leftover `.tmp` files are.
+
+A `.tmp` file written to within the last hour is assumed to belong to a
+download that is still running and is left alone.
+*/
+
+use crate::metadata;
+use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How recently a `.tmp` file may have been written and still be an active download
+pub const TEMP_FILE_GRACE: Duration = Duration::from_secs(60 * 60);
+
+/// Something `models prune` would delete
+#[derive(Debug, Clone, PartialEq)]
+pub struct PruneCandidate {
+    pub path: PathBuf,
+    pub size: u64,
+    pub kind: PruneKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PruneKind {
+    /// A partial download
+    TempFile,
+    /// A model directory unused for longer than --older-than
+    StaleModel { last_used: SystemTime },
+}
+
+/// Everything prunable under `cache_path`, as of `now`
+pub fn find_prunable(cache_path: &Path, older_than: Option<Duration>, now: SystemTime) -> Result<Vec<PruneCandidate>> {
+    let mut candidates = Vec::new();
+    let mut model_dirs: Vec<PathBuf> = fs::read_dir(cache_path)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    model_dirs.sort();
+
+    for model_dir in model_dirs {
+        let stale_since = older_than.and_then(|age| {
+            let last_used = last_used(&model_dir)?;
+            let unused = now.duration_since(last_used).unwrap_or_default() >= age;
+            (unused && !metadata::is_pinned(&model_dir)).then_some(last_used)
+        });
+
+        // A stale model goes as a whole, partial downloads included
+        if let Some(last_used) = stale_since {
+            candidates.push(PruneCandidate {
+                size: dir_size(&model_dir),
+                path: model_dir,
+                kind: PruneKind::StaleModel { last_used },
+            });
+            continue;
+        }
+
+        for entry in fs::read_dir(&model_dir)?.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            let Ok(file) = entry.metadata() else { continue };
+            let in_progress = file
+                .modified()
+                .is_ok_and(|modified| now.duration_since(modified).unwrap_or_default() < TEMP_FILE_GRACE);
+            if file.is_file() && path.extension().is_some_and(|ext| ext == "tmp") && !in_progress {
+                candidates.push(PruneCandidate {
+                    path,
+                    size: file.len(),
+                    kind: PruneKind::TempFile,
+                });
+            }
+        }
+    }
+
+    Ok(candidates)
+}
+
+/// The latest access or modification time of a GGUF file in a model directory. metadata.json
+/// is left out, since listing or pinning models touches it.
+pub fn last_used(model_dir: &Path) -> Option<SystemTime> {
+    fs::read_dir(model_dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gguf")))
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|file| file.is_file())
+        .filter_map(|file| {
+            let modified = file.modified().ok();
+            // Access times are unavailable on some platforms and mounts
+            file.accessed().ok().max(modified)
+        })
+        .max()
+}
+
+/// Total size of the files under `path`
+pub fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(path) else { return 0 };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| match entry.metadata() {
+            Ok(file) if file.is_dir() => dir_size(&entry.path()),
+            Ok(file) => file.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{File, FileTimes};
+    use tempfile::TempDir;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn write_file(path: &Path, len: usize, time: SystemTime) {
+        fs::write(path, vec![0u8; len]).unwrap();
+        let times = FileTimes::new().set_accessed(time).set_modified(time);
+        File::options().write(true).open(path).unwrap().set_times(times).unwrap();
+    }
+
+    #[test]
+    fn test_find_prunable() {
+        let cache = TempDir::new().unwrap();
+        let now = SystemTime::now();
+
+        let fresh = cache.path().join("owner--fresh");
+        fs::create_dir(&fresh).unwrap();
+        write_file(&fresh.join("model.gguf"), 10, now);
+        write_file(&fresh.join("other.tmp"), 5, now - 2 * DAY);
+        // Still being downloaded
+        write_file(&fresh.join("model.Q8_0.tmp"), 7, now);
+
+        let stale = cache.path().join("owner--stale");
+        fs::create_dir(&stale).unwrap();
+        write_file(&stale.join("model.gguf"), 20, now - 40 * DAY);
+        write_file(&stale.join("model.Q8_0.tmp"), 3, now - 40 * DAY);
+
+        // Without --older-than only the abandoned .tmp files go
+        let candidates = find_prunable(cache.path(), None, now).unwrap();
+        let paths: Vec<_> = candidates.iter().map(|c| c.path.clone()).collect();
+        assert_eq!(paths, vec![fresh.join("other.tmp"), stale.join("model.Q8_0.tmp")]);
+        assert_eq!(candidates[0].kind, PruneKind::TempFile);
+
+        // A stale model is removed whole, partial downloads included
+        let candidates = find_prunable(cache.path(), Some(30 * DAY), now).unwrap();
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[1].path, stale);
+        assert_eq!(candidates[1].size, 23);
+        assert!(matches!(candidates[1].kind, PruneKind::StaleModel { .. }));
+
+        assert_eq!(find_prunable(cache.path(), Some(50 * DAY), now).unwrap().len(), 2);
+
+        // Pinned models are kept, but not their partial downloads
+        let metadata = metadata::ModelMetadata {
+            pinned: true,
+            ..Default::default()
+        };
+        metadata.save(&stale).unwrap();
+        let candidates = find_prunable(cache.path(), Some(30 * DAY), now).unwrap();
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[1].kind, PruneKind::TempFile);
+    }
+}
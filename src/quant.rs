@@ -0,0 +1,90 @@
+/*!
+# Automatic Quant Selection
+
+Hugging Face GGUF repositories usually ship the same model at several
+quantization levels. `--auto-quant` picks the largest file that fits in the
+memory currently available, keeping some headroom for the KV cache, compute
+buffers and the rest of the system.
+*/
+
+use crate::downloader::HfFile;
+use sysinfo::System;
+
+/// Fraction of available memory kept free when choosing a quant
+pub const MEMORY_HEADROOM: f64 = 0.2;
+
+/// Memory currently available to new processes, in bytes
+pub fn available_memory() -> u64 {
+    let mut system = System::new();
+    system.refresh_memory();
+    system.available_memory()
+}
+
+/// Largest model size that still leaves the configured headroom free
+pub fn memory_budget(available: u64) -> u64 {
+    (available as f64 * (1.0 - MEMORY_HEADROOM)) as u64
+}
+
+/// Pick the largest GGUF file whose size fits within the budget; files of unknown size are skipped
+pub fn select_quant(files: &[HfFile], budget: u64) -> Option<&HfFile> {
+    files
+        .iter()
+        .filter(|f| f.rfilename.ends_with(".gguf"))
+        .filter(|f| f.size.is_some_and(|size| size <= budget))
+        .max_by_key(|f| f.size)
+}
+
+/// Quantization named in a GGUF filename, e.g. `Q4_K_M` in `llama-2-7b-chat.Q4_K_M.gguf`
+pub fn quant_from_filename(filename: &str) -> Option<String> {
+    let stem = filename.strip_suffix(".gguf").unwrap_or(filename);
+    stem.rsplit(['.', '-'])
+        .map(|part| part.to_uppercase())
+        .find(|part| {
+            let digits = part
+                .strip_prefix("IQ")
+                .or_else(|| part.strip_prefix("BF"))
+                .or_else(|| part.strip_prefix('Q'))
+                .or_else(|| part.strip_prefix('F'));
+            digits.is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str, size: Option<u64>) -> HfFile {
+        HfFile {
+            rfilename: name.to_string(),
+            size,
+            lfs: None,
+        }
+    }
+
+    #[test]
+    fn test_select_quant_picks_largest_that_fits() {
+        let files = vec![
+            file("model.Q2_K.gguf", Some(2_000)),
+            file("model.Q4_K_M.gguf", Some(4_000)),
+            file("model.Q8_0.gguf", Some(8_000)),
+            file("model.F16.gguf", None),
+        ];
+
+        let chosen = select_quant(&files, 5_000).unwrap();
+        assert_eq!(chosen.rfilename, "model.Q4_K_M.gguf");
+        assert!(select_quant(&files, 1_000).is_none());
+    }
+
+    #[test]
+    fn test_quant_from_filename() {
+        assert_eq!(quant_from_filename("llama-2-7b-chat.Q4_K_M.gguf").as_deref(), Some("Q4_K_M"));
+        assert_eq!(quant_from_filename("Meta-Llama-3-8B-Instruct-IQ3_XXS.gguf").as_deref(), Some("IQ3_XXS"));
+        assert_eq!(quant_from_filename("phi-2.f16.gguf").as_deref(), Some("F16"));
+        assert_eq!(quant_from_filename("mistral-7b-instruct.gguf"), None);
+    }
+
+    #[test]
+    fn test_memory_budget_keeps_headroom() {
+        assert_eq!(memory_budget(10_000), 8_000);
+    }
+}
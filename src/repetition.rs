@@ -0,0 +1,60 @@
+/*!
+# Repetition Loops
+
+`--stop-on-repeat N` ends generation once the output is stuck in a loop: the
+most recent tokens are the same n-gram (up to [`MAX_NGRAM`] tokens long)
+repeated N times back to back. Greedy decoding in particular can fall into
+such loops and never produce a stop token.
+*/
+
+/// Longest n-gram checked for repetition
+pub const MAX_NGRAM: usize = 8;
+
+/// If `tokens` ends with an n-gram of length 1..=`MAX_NGRAM` repeated `repeats` consecutive
+/// times, return the n-gram length (the shortest one, so `a a a a` reports 1, not 2)
+pub fn repeating_ngram<T: PartialEq>(tokens: &[T], repeats: usize) -> Option<usize> {
+    if repeats < 2 {
+        return None;
+    }
+
+    (1..=MAX_NGRAM).find(|&n| {
+        let span = n * repeats;
+        if tokens.len() < span {
+            return false;
+        }
+
+        let tail = &tokens[tokens.len() - span..];
+        let ngram = &tail[..n];
+        tail.chunks(n).all(|chunk| chunk == ngram)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeating_ngram_on_looping_stream() {
+        // A prefix followed by the 3-gram [7, 8, 9] looping; feed it one token at a time
+        let stream: Vec<i32> = [1, 2, 3].into_iter().chain([7, 8, 9].repeat(4)).collect();
+
+        let stopped_at = (1..=stream.len()).find(|&len| repeating_ngram(&stream[..len], 4).is_some());
+        assert_eq!(stopped_at, Some(stream.len()));
+        assert_eq!(repeating_ngram(&stream, 4), Some(3));
+
+        // Three repeats aren't enough for N = 4, but are for N = 3
+        assert_eq!(repeating_ngram(&stream[..stream.len() - 3], 4), None);
+        assert_eq!(repeating_ngram(&stream[..stream.len() - 3], 3), Some(3));
+    }
+
+    #[test]
+    fn test_repeating_ngram_limits() {
+        assert_eq!(repeating_ngram(&[5, 5, 5, 5], 4), Some(1));
+        assert_eq!(repeating_ngram(&[1, 2, 1, 2, 1, 3], 2), None);
+
+        // A 9-gram loop is longer than MAX_NGRAM
+        let long: Vec<i32> = (0..9).collect::<Vec<_>>().repeat(3);
+        assert_eq!(repeating_ngram(&long, 3), None);
+        assert_eq!(repeating_ngram(&long, 1), None);
+    }
+}
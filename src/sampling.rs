@@ -0,0 +1,668 @@
+/*!
+# Token Sampling
+
+Helpers used by the generation loop to pick the next token from the
+candidate distribution produced by llama.cpp, and to score that choice.
+
+Candidates are first cut to the `top_k` most likely, then to the nucleus whose
+probability mass reaches `top_p`, and a token is drawn from the softmax of the
+remaining logits divided by the temperature, using a seeded RNG so a run can
+be reproduced; temperature 0 is greedy. With Mirostat, the top-k and top-p
+cuts are replaced by one that adapts each step to keep the surprise of the
+sampled tokens near a target.
+
+Scores are natural-log probabilities of the chosen token under the softmax
+of all candidate logits at that step, whatever the temperature, so they stay
+comparable between runs.
+*/
+
+use llama_cpp_2::token::data::LlamaTokenData;
+use llama_cpp_2::token::LlamaToken;
+use rand::Rng;
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// A token chosen by the sampler together with its log-probability
+#[derive(Debug, Clone, Copy)]
+pub struct SampledToken {
+    pub token: LlamaToken,
+    pub log_prob: f32,
+}
+
+/// Compute `log(sum(exp(logit)))` over all candidates in a numerically stable way
+pub fn log_sum_exp(candidates: &[LlamaTokenData]) -> f32 {
+    let max_logit = candidates
+        .iter()
+        .map(|c| c.logit())
+        .fold(f32::NEG_INFINITY, f32::max);
+
+    if !max_logit.is_finite() {
+        return max_logit;
+    }
+
+    let sum: f32 = candidates
+        .iter()
+        .map(|c| (c.logit() - max_logit).exp())
+        .sum();
+
+    max_logit + sum.ln()
+}
+
+/// Add `bias` to the logit of every candidate in `tokens`
+pub fn apply_token_bias(candidates: &mut [LlamaTokenData], tokens: &HashSet<LlamaToken>, bias: f32) {
+    for candidate in candidates.iter_mut() {
+        if tokens.contains(&candidate.id()) {
+            candidate.set_logit(candidate.logit() + bias);
+        }
+    }
+}
+
+/// Make every candidate in `recent` less likely: positive logits are divided by `penalty` and
+/// negative ones multiplied by it, as in llama.cpp
+pub fn apply_repeat_penalty(candidates: &mut [LlamaTokenData], recent: &HashSet<LlamaToken>, penalty: f32) {
+    for candidate in candidates.iter_mut() {
+        if recent.contains(&candidate.id()) {
+            let logit = candidate.logit();
+            candidate.set_logit(if logit > 0.0 { logit / penalty } else { logit * penalty });
+        }
+    }
+}
+
+/// Pick the candidate with the highest logit and report its log-probability
+pub fn sample_greedy(candidates: &[LlamaTokenData]) -> Option<SampledToken> {
+    let best = candidates
+        .iter()
+        .max_by(|a, b| a.logit().partial_cmp(&b.logit()).unwrap())?;
+
+    Some(SampledToken {
+        token: best.id(),
+        log_prob: best.logit() - log_sum_exp(candidates),
+    })
+}
+
+/// Draw a candidate from the softmax of `logit / temperature`; temperature 0 (or less) is greedy.
+/// Masked (`-inf`) candidates are never drawn. The score is `logit - log_norm`: pass the
+/// [`log_sum_exp`] of the candidates before any filtering to score under the full distribution.
+pub fn sample_temperature(
+    candidates: &[LlamaTokenData],
+    temperature: f32,
+    log_norm: f32,
+    rng: &mut impl Rng,
+) -> Option<SampledToken> {
+    let score = |chosen: &LlamaTokenData| SampledToken {
+        token: chosen.id(),
+        log_prob: chosen.logit() - log_norm,
+    };
+
+    let best = candidates.iter().max_by(|a, b| a.logit().total_cmp(&b.logit()))?;
+    if temperature <= 0.0 || !best.logit().is_finite() {
+        return Some(score(best));
+    }
+
+    let weights: Vec<f32> = candidates
+        .iter()
+        .map(|c| ((c.logit() - best.logit()) / temperature).exp())
+        .collect();
+    let mut target = rng.gen::<f32>() * weights.iter().sum::<f32>();
+
+    // Fall back to the last drawable candidate if rounding leaves `target` past the end
+    let mut chosen = best;
+    for (candidate, weight) in candidates.iter().zip(&weights) {
+        if *weight > 0.0 {
+            chosen = candidate;
+            if target < *weight {
+                break;
+            }
+            target -= weight;
+        }
+    }
+
+    Some(score(chosen))
+}
+
+/// Nucleus filtering: keep the smallest set of most likely candidates whose probability (at
+/// temperature 1, over the candidates passed in) reaches `p`, most likely first. At least one
+/// candidate always survives; `p >= 1` keeps all of them.
+pub fn apply_top_p(candidates: &mut Vec<LlamaTokenData>, p: f32) {
+    if p >= 1.0 || candidates.is_empty() {
+        return;
+    }
+
+    candidates.sort_by(|a, b| b.logit().total_cmp(&a.logit()));
+    let sorted: Vec<&LlamaTokenData> = candidates.iter().collect();
+    let keep = nucleus_len(&softmax(&sorted, 1.0), p);
+    candidates.truncate(keep);
+}
+
+/// Keep the `k` highest-logit candidates, most likely first. `k == 0` or a `k` of at least the
+/// number of candidates keeps all of them (unsorted); `k == 1` leaves only the greedy choice.
+pub fn apply_top_k(candidates: &mut Vec<LlamaTokenData>, k: usize) {
+    if k == 0 || k >= candidates.len() {
+        return;
+    }
+
+    let by_logit_desc = |a: &LlamaTokenData, b: &LlamaTokenData| b.logit().total_cmp(&a.logit());
+    candidates.select_nth_unstable_by(k - 1, by_logit_desc);
+    candidates.truncate(k);
+    candidates.sort_by(by_logit_desc);
+}
+
+/// The `n` highest-logit candidates, most likely first, with their log-probabilities
+pub fn top_candidates(candidates: &[LlamaTokenData], n: usize) -> Vec<SampledToken> {
+    let mut sorted: Vec<&LlamaTokenData> = candidates
+        .iter()
+        .filter(|c| c.logit().is_finite())
+        .collect();
+    sorted.sort_by(|a, b| b.logit().partial_cmp(&a.logit()).unwrap());
+
+    let normalizer = log_sum_exp(candidates);
+    sorted
+        .into_iter()
+        .take(n)
+        .map(|c| SampledToken {
+            token: c.id(),
+            log_prob: c.logit() - normalizer,
+        })
+        .collect()
+}
+
+/// An inclusive `start:stop:step` temperature sweep for `--temp-range`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TempRange {
+    pub start: f32,
+    pub stop: f32,
+    pub step: f32,
+}
+
+impl TempRange {
+    /// Every temperature in the sweep, `stop` included when the steps land on it
+    pub fn values(&self) -> Vec<f32> {
+        // Small tolerance so 0.2:1.0:0.2 reaches 1.0 despite float rounding
+        let steps = ((self.stop - self.start) / self.step + 1e-4).floor() as usize;
+        (0..=steps).map(|i| self.start + i as f32 * self.step).collect()
+    }
+}
+
+impl FromStr for TempRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let [start, stop, step] = parts[..] else {
+            return Err(format!("expected START:STOP:STEP, got '{}'", s));
+        };
+        let parse = |value: &str| {
+            value
+                .trim()
+                .parse::<f32>()
+                .map_err(|_| format!("'{}' is not a number", value))
+        };
+        let range = TempRange {
+            start: parse(start)?,
+            stop: parse(stop)?,
+            step: parse(step)?,
+        };
+
+        if !(0.0..=2.0).contains(&range.start) || !(0.0..=2.0).contains(&range.stop) {
+            return Err("temperatures must be between 0.0 and 2.0".to_string());
+        }
+        if range.start > range.stop {
+            return Err("start must not be greater than stop".to_string());
+        }
+        if range.step <= 0.0 {
+            return Err("step must be greater than 0".to_string());
+        }
+        Ok(range)
+    }
+}
+
+/// How the top-k, top-p and temperature stages narrow one step's candidates, for `--explain`
+#[derive(Debug, Clone)]
+pub struct FilterTrace {
+    pub total: usize,
+    pub after_top_k: usize,
+    pub after_top_p: usize,
+    /// Temperature the survivors were scaled with
+    pub temperature: f32,
+    /// Most likely survivors with their temperature-scaled probabilities
+    pub top: Vec<(LlamaToken, f32)>,
+}
+
+/// Run the candidates through top-k, then top-p (at temperature 1), then temperature scaling,
+/// in llama.cpp's order. Masked (`-inf`) candidates never survive; `top_k == 0` keeps all.
+pub fn trace_filters(
+    candidates: &[LlamaTokenData],
+    top_k: usize,
+    top_p: f32,
+    temperature: f32,
+    show: usize,
+) -> FilterTrace {
+    let mut survivors: Vec<&LlamaTokenData> = candidates
+        .iter()
+        .filter(|c| c.logit().is_finite())
+        .collect();
+    survivors.sort_by(|a, b| b.logit().partial_cmp(&a.logit()).unwrap());
+
+    if top_k > 0 {
+        survivors.truncate(top_k);
+    }
+    let after_top_k = survivors.len();
+
+    let keep = nucleus_len(&softmax(&survivors, 1.0), top_p);
+    survivors.truncate(keep);
+    let after_top_p = survivors.len();
+
+    let top = survivors
+        .iter()
+        .zip(softmax(&survivors, temperature))
+        .take(show)
+        .map(|(c, p)| (c.id(), p))
+        .collect();
+
+    FilterTrace {
+        total: candidates.len(),
+        after_top_k,
+        after_top_p,
+        temperature,
+        top,
+    }
+}
+
+/// Dynamic temperature (`--dynatemp-range`): the normalized entropy of the candidate distribution
+/// (0 when one token has all the mass, 1 when uniform), raised to `exponent`, picks a temperature
+/// between `temperature - range` (floored at 0) and `temperature + range`. Confident steps are
+/// sampled cooler and uncertain ones hotter. A zero range returns `temperature` unchanged.
+pub fn dynamic_temperature(candidates: &[LlamaTokenData], temperature: f32, range: f32, exponent: f32) -> f32 {
+    if range <= 0.0 {
+        return temperature;
+    }
+
+    let min_temp = (temperature - range).max(0.0);
+    let max_temp = temperature + range;
+
+    let finite = candidates.iter().filter(|c| c.logit().is_finite()).count();
+    if finite <= 1 {
+        return min_temp;
+    }
+
+    let lse = log_sum_exp(candidates);
+    let entropy: f32 = candidates
+        .iter()
+        .filter(|c| c.logit().is_finite())
+        .map(|c| {
+            let log_prob = c.logit() - lse;
+            -log_prob.exp() * log_prob
+        })
+        .sum();
+    let normalized = (entropy / (finite as f32).ln()).clamp(0.0, 1.0);
+
+    min_temp + (max_temp - min_temp) * normalized.powf(exponent)
+}
+
+/// Number of top candidates Mirostat 1 estimates the distribution's Zipf exponent from
+const MIROSTAT_M: usize = 100;
+
+/// Mirostat adaptive sampling (`--mirostat 1|2`). Each step keeps only the candidates whose
+/// surprise (`-log2 p`, in bits) is plausible under the current limit `mu`, then nudges `mu` by
+/// `eta` times how far the sampled token's surprise was from `tau`. Version 1 turns `mu` into a
+/// top-k cut by estimating how the distribution falls off; version 2 drops every candidate more
+/// surprising than `mu`. Either way generation settles at a roughly constant perplexity.
+#[derive(Debug, Clone)]
+pub struct Mirostat {
+    version: u8,
+    tau: f32,
+    eta: f32,
+    mu: f32,
+}
+
+impl Mirostat {
+    /// Start a generation's Mirostat state, with `mu` at `2 * tau` as in the paper
+    pub fn new(version: u8, tau: f32, eta: f32) -> Self {
+        Self { version, tau, eta, mu: 2.0 * tau }
+    }
+
+    /// The current surprise limit, in bits
+    pub fn mu(&self) -> f32 {
+        self.mu
+    }
+
+    /// Cut `candidates` to Mirostat's selection and draw from the softmax of `logit / temperature`
+    /// over it, most likely first; temperature 0 (or less) is greedy. Scores are as for
+    /// [`sample_temperature`].
+    pub fn sample(
+        &mut self,
+        candidates: &mut Vec<LlamaTokenData>,
+        temperature: f32,
+        log_norm: f32,
+        rng: &mut impl Rng,
+    ) -> Option<SampledToken> {
+        candidates.retain(|c| c.logit().is_finite());
+        if temperature <= 0.0 {
+            return sample_temperature(candidates, temperature, log_norm, rng);
+        }
+
+        candidates.sort_by(|a, b| b.logit().total_cmp(&a.logit()));
+        let sorted: Vec<&LlamaTokenData> = candidates.iter().collect();
+        let probs = softmax(&sorted, temperature);
+        let keep = match self.version {
+            1 => self.zipf_top_k(&probs),
+            _ => probs.iter().take_while(|&&p| -p.log2() <= self.mu).count().max(1),
+        }
+        .min(probs.len());
+        let kept = &probs[..keep];
+
+        let total: f32 = kept.iter().sum();
+        let mut target = rng.gen::<f32>() * total;
+        let mut index = keep.checked_sub(1)?;
+        for (i, p) in kept.iter().enumerate() {
+            if target < *p {
+                index = i;
+                break;
+            }
+            target -= p;
+        }
+
+        let surprise = -(kept[index] / total).log2();
+        self.mu -= self.eta * (surprise - self.tau);
+
+        let chosen = &candidates[index];
+        Some(SampledToken {
+            token: chosen.id(),
+            log_prob: chosen.logit() - log_norm,
+        })
+    }
+
+    /// Mirostat 1's top-k for the current `mu`, from the Zipf exponent estimated over the top
+    /// `MIROSTAT_M` probabilities (sorted highest first)
+    fn zipf_top_k(&self, probs: &[f32]) -> usize {
+        let n = probs.len();
+        let (mut numerator, mut denominator) = (0.0f32, 0.0f32);
+        for i in 0..MIROSTAT_M.min(n.saturating_sub(1)) {
+            let t = ((i + 2) as f32 / (i + 1) as f32).ln();
+            let b = (probs[i] / probs[i + 1]).ln();
+            if b.is_finite() {
+                numerator += t * b;
+                denominator += t * t;
+            }
+        }
+
+        let s_hat = numerator / denominator;
+        let epsilon_hat = s_hat - 1.0;
+        let k = ((epsilon_hat * 2f32.powf(self.mu)) / (1.0 - (n as f32).powf(-epsilon_hat))).powf(1.0 / s_hat);
+        if k.is_finite() {
+            (k as usize).max(1)
+        } else {
+            n
+        }
+    }
+}
+
+/// Length of the smallest prefix of `probs` (sorted highest first) whose mass reaches `top_p`,
+/// always at least one token
+fn nucleus_len(probs: &[f32], top_p: f32) -> usize {
+    let mut cumulative = 0.0;
+    for (i, p) in probs.iter().enumerate() {
+        cumulative += p;
+        if cumulative >= top_p {
+            return i + 1;
+        }
+    }
+    probs.len().max(1)
+}
+
+/// Softmax of logits sorted highest first; temperature 0 puts all mass on the first
+fn softmax(sorted: &[&LlamaTokenData], temperature: f32) -> Vec<f32> {
+    if temperature <= 0.0 {
+        return (0..sorted.len()).map(|i| if i == 0 { 1.0 } else { 0.0 }).collect();
+    }
+
+    let Some(max_logit) = sorted.first().map(|c| c.logit()) else {
+        return Vec::new();
+    };
+    let weights: Vec<f32> = sorted
+        .iter()
+        .map(|c| ((c.logit() - max_logit) / temperature).exp())
+        .collect();
+    let sum: f32 = weights.iter().sum();
+    weights.into_iter().map(|w| w / sum).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates(logits: &[f32]) -> Vec<LlamaTokenData> {
+        logits
+            .iter()
+            .enumerate()
+            .map(|(i, &logit)| LlamaTokenData::new(LlamaToken::new(i as i32), logit, 0.0))
+            .collect()
+    }
+
+    #[test]
+    fn test_sample_greedy_picks_highest_logit() {
+        let sampled = sample_greedy(&candidates(&[0.5, 3.0, 1.0])).unwrap();
+        assert_eq!(sampled.token, LlamaToken::new(1));
+    }
+
+    #[test]
+    fn test_sample_greedy_log_prob_uniform() {
+        // Four equal logits give each token probability 1/4
+        let sampled = sample_greedy(&candidates(&[2.0, 2.0, 2.0, 2.0])).unwrap();
+        assert!((sampled.log_prob - 0.25f32.ln()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_apply_token_bias() {
+        let mut biased = candidates(&[1.0, 2.0, 1.5]);
+        let prompt_tokens: HashSet<LlamaToken> = [LlamaToken::new(0), LlamaToken::new(2)].into_iter().collect();
+
+        apply_token_bias(&mut biased, &prompt_tokens, 1.0);
+        assert_eq!(biased[0].logit(), 2.0);
+        assert_eq!(biased[1].logit(), 2.0);
+        assert_eq!(sample_greedy(&biased).unwrap().token, LlamaToken::new(2));
+
+        apply_token_bias(&mut biased, &prompt_tokens, -3.0);
+        assert_eq!(sample_greedy(&biased).unwrap().token, LlamaToken::new(1));
+    }
+
+    #[test]
+    fn test_apply_repeat_penalty() {
+        let mut penalized = candidates(&[2.0, -1.0, 1.8]);
+        let recent: HashSet<LlamaToken> = [LlamaToken::new(0), LlamaToken::new(1)].into_iter().collect();
+
+        apply_repeat_penalty(&mut penalized, &recent, 1.25);
+        assert_eq!(penalized[0].logit(), 1.6);
+        assert_eq!(penalized[1].logit(), -1.25);
+        assert_eq!(penalized[2].logit(), 1.8);
+        assert_eq!(sample_greedy(&penalized).unwrap().token, LlamaToken::new(2));
+    }
+
+    #[test]
+    fn test_top_candidates() {
+        let top = top_candidates(&candidates(&[1.0, 3.0, f32::NEG_INFINITY, 2.0]), 3);
+        let ids: Vec<i32> = top.iter().map(|t| t.token.0).collect();
+        assert_eq!(ids, vec![1, 3, 0]);
+        let greedy = sample_greedy(&candidates(&[1.0, 3.0, 2.0])).unwrap();
+        assert!((top[0].log_prob - greedy.log_prob).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_temp_range() {
+        let range: TempRange = "0.2:1.0:0.2".parse().unwrap();
+        let values = range.values();
+        assert_eq!(values.len(), 5);
+        assert!((values[4] - 1.0).abs() < 1e-5);
+
+        assert_eq!("0.5:0.5:0.1".parse::<TempRange>().unwrap().values(), vec![0.5]);
+        assert!("0.2:1.0".parse::<TempRange>().is_err());
+        assert!("0.2:2.5:0.5".parse::<TempRange>().is_err());
+        assert!("1.0:0.2:0.2".parse::<TempRange>().is_err());
+        assert!("0.2:1.0:0".parse::<TempRange>().is_err());
+    }
+
+    #[test]
+    fn test_trace_filters_stages() {
+        // ln(4), ln(2), ln(1), ln(1): probabilities 0.5, 0.25, 0.125, 0.125, plus one masked token
+        let mut logits = vec![4f32.ln(), 2f32.ln(), 0.0, 0.0];
+        logits.push(f32::NEG_INFINITY);
+        let candidates = candidates(&logits);
+
+        let trace = trace_filters(&candidates, 3, 0.7, 1.0, 2);
+        assert_eq!(trace.total, 5);
+        assert_eq!(trace.after_top_k, 3);
+        assert_eq!(trace.after_top_p, 2);
+        assert_eq!(trace.top.len(), 2);
+        assert_eq!(trace.top[0].0, LlamaToken::new(0));
+        assert!((trace.top[0].1 - 2.0 / 3.0).abs() < 1e-5);
+
+        // No top-k, and temperature 0 is greedy
+        let trace = trace_filters(&candidates, 0, 1.0, 0.0, 10);
+        assert_eq!(trace.after_top_k, 4);
+        assert_eq!(trace.top[0].1, 1.0);
+    }
+
+    #[test]
+    fn test_dynamic_temperature_follows_entropy() {
+        let uniform = candidates(&[1.0, 1.0, 1.0, 1.0]);
+        let peaked = candidates(&[10.0, 0.0, 0.0, 0.0]);
+        let mixed = candidates(&[2.0, 1.0, 0.0, f32::NEG_INFINITY]);
+
+        assert!((dynamic_temperature(&uniform, 0.8, 0.5, 1.0) - 1.3).abs() < 1e-5);
+        let cool = dynamic_temperature(&peaked, 0.8, 0.5, 1.0);
+        assert!(cool > 0.3 && cool < 0.35);
+        let middle = dynamic_temperature(&mixed, 0.8, 0.5, 1.0);
+        assert!(cool < middle && middle < 1.3);
+
+        // A larger exponent pulls partial entropy toward the low end
+        assert!(dynamic_temperature(&mixed, 0.8, 0.5, 2.0) < middle);
+        // The lower bound is floored at 0, and a zero range is a no-op
+        assert_eq!(dynamic_temperature(&candidates(&[1.0]), 0.2, 0.5, 1.0), 0.0);
+        assert_eq!(dynamic_temperature(&uniform, 0.8, 0.0, 1.0), 0.8);
+    }
+
+    #[test]
+    fn test_sample_temperature() {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+
+        let logits = candidates(&[1.0, 0.8, 0.6, 0.4, f32::NEG_INFINITY]);
+        let draw = |seed: u64, temperature: f32| -> Vec<i32> {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..32)
+                .map(|_| sample_temperature(&logits, temperature, log_sum_exp(&logits), &mut rng).unwrap().token.0)
+                .collect()
+        };
+
+        // Temperature 0 reproduces greedy decoding regardless of the seed
+        assert!(draw(1, 0.0).iter().all(|&token| token == 0));
+        assert_eq!(draw(1, 0.0), draw(2, 0.0));
+
+        // At 1.2 the draws vary, but the same seed repeats them exactly; masked tokens never appear
+        let hot = draw(1, 1.2);
+        assert!(hot.iter().any(|&token| token != hot[0]));
+        assert!(hot.iter().all(|&token| token != 4));
+        assert_eq!(hot, draw(1, 1.2));
+        assert_ne!(hot, draw(2, 1.2));
+
+        // The score is the untempered log-probability
+        let mut rng = StdRng::seed_from_u64(3);
+        let sampled = sample_temperature(&logits, 1.2, log_sum_exp(&logits), &mut rng).unwrap();
+        let expected = logits[sampled.token.0 as usize].logit() - log_sum_exp(&logits);
+        assert!((sampled.log_prob - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_apply_top_k() {
+        let ids = |candidates: &[LlamaTokenData]| candidates.iter().map(|c| c.id().0).collect::<Vec<_>>();
+
+        let mut top = candidates(&[0.1, 2.0, 0.5, 3.0, 1.0]);
+        apply_top_k(&mut top, 3);
+        assert_eq!(ids(&top), vec![3, 1, 4]);
+
+        // 0 and anything past the vocabulary keep every candidate
+        let mut all = candidates(&[0.1, 2.0, 0.5]);
+        apply_top_k(&mut all, 0);
+        assert_eq!(all.len(), 3);
+        apply_top_k(&mut all, 50_000);
+        assert_eq!(all.len(), 3);
+
+        // k = 1 is greedy even at a high temperature
+        let mut one = candidates(&[0.1, 2.0, 0.5]);
+        let log_norm = log_sum_exp(&one);
+        apply_top_k(&mut one, 1);
+        let mut rng = rand::thread_rng();
+        for _ in 0..10 {
+            let sampled = sample_temperature(&one, 2.0, log_norm, &mut rng).unwrap();
+            assert_eq!(sampled.token, LlamaToken::new(1));
+            // Still scored under the unfiltered distribution
+            assert!(sampled.log_prob < 0.0);
+        }
+    }
+
+    #[test]
+    fn test_apply_top_p() {
+        // Probabilities 0.125, 0.5, 0.125, 0.25
+        let logits = [0.0, 4f32.ln(), 0.0, 2f32.ln()];
+        let ids = |candidates: &[LlamaTokenData]| candidates.iter().map(|c| c.id().0).collect::<Vec<_>>();
+
+        let mut focused = candidates(&logits);
+        apply_top_p(&mut focused, 0.1);
+        assert_eq!(ids(&focused), vec![1]);
+
+        let mut nucleus = candidates(&logits);
+        apply_top_p(&mut nucleus, 0.6);
+        assert_eq!(ids(&nucleus), vec![1, 3]);
+
+        let mut all = candidates(&logits);
+        apply_top_p(&mut all, 1.0);
+        assert_eq!(all.len(), 4);
+
+        // Composes with top-k: over the two survivors token 1 alone has 2/3 of the mass
+        let mut both = candidates(&logits);
+        apply_top_k(&mut both, 2);
+        apply_top_p(&mut both, 0.6);
+        assert_eq!(ids(&both), vec![1]);
+    }
+
+    #[test]
+    fn test_mirostat() {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+
+        // Probabilities 0.5, 0.25, 0.125, 0.125: surprises of 1, 2, 3 and 3 bits
+        let logits = [8f32.ln(), 4f32.ln(), 2f32.ln(), 2f32.ln(), f32::NEG_INFINITY];
+        let mut rng = StdRng::seed_from_u64(3);
+
+        // Mirostat 2 starts at mu = 2 * tau = 2 bits, so only the first two tokens survive
+        let mut mirostat = Mirostat::new(2, 1.0, 0.5);
+        let mut cut = candidates(&logits);
+        let sampled = mirostat.sample(&mut cut, 1.0, log_sum_exp(&cut), &mut rng).unwrap();
+        assert_eq!(cut.len(), 2);
+        assert!(sampled.token.0 <= 1);
+        assert!((sampled.log_prob - 0.5f32.ln()).abs() < 1e-5 || (sampled.log_prob - 0.25f32.ln()).abs() < 1e-5);
+        // Over the survivors the surprise is below 1 bit (token 0) or above it (token 1)
+        if sampled.token.0 == 0 {
+            assert!(mirostat.mu() > 2.0);
+        } else {
+            assert!(mirostat.mu() < 2.0);
+        }
+
+        // Mirostat 1 always keeps at least one candidate and never a masked one
+        let mut mirostat = Mirostat::new(1, 0.1, 0.1);
+        for _ in 0..16 {
+            let mut cut = candidates(&logits);
+            let sampled = mirostat.sample(&mut cut, 0.8, log_sum_exp(&cut), &mut rng).unwrap();
+            assert!(!cut.is_empty() && sampled.token.0 < 4);
+        }
+
+        // Temperature 0 is greedy
+        let mut cut = candidates(&logits);
+        assert_eq!(Mirostat::new(2, 5.0, 0.1).sample(&mut cut, 0.0, 0.0, &mut rng).unwrap().token.0, 0);
+    }
+
+    #[test]
+    fn test_sample_greedy_empty() {
+        assert!(sample_greedy(&[]).is_none());
+    }
+}
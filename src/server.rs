@@ -0,0 +1,371 @@
+/*!
+# OpenAI-Compatible Server
+
+`rustlama serve` loads a model once and answers HTTP requests shaped like
+OpenAI's completions API, so existing clients and tooling can use RustLlama
+as a local backend by pointing their base URL at it:
+
+```text
+POST /v1/completions  {"prompt": "The capital of France is", "max_tokens": 8, "stop": ["\n"]}
+GET  /v1/models
+```
+
+Requests may set `prompt`, `max_tokens`, `temperature`, `top_p`, `stop` and
+`seed`; anything else a client sends is ignored, and unset fields fall back
+to the defaults given on the command line. Every other sampling stage works
+as in `rustlama run`. Requests are answered one at a time, each in a fresh
+context.
+
+With `"stream": true` the completion is sent as server-sent events: one
+`data:` chunk per piece of text as it is generated, a final chunk carrying
+the `finish_reason`, then `data: [DONE]`. If the client disconnects, the
+request's generation stops at the next token.
+
+A prompt the model can't take (too long for the context, or empty with no BOS
+token) is answered with a 400 error, streamed or not.
+*/
+
+use anyhow::{anyhow, Result};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::sse::{Event, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use colored::*;
+use rustlama::engine::{InferenceEngine, SamplingParams};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+/// Body of `POST /v1/completions`
+#[derive(Debug, Deserialize)]
+pub struct CompletionRequest {
+    pub prompt: String,
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    #[serde(default)]
+    pub stop: Option<StopSequences>,
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub stream: bool,
+}
+
+/// OpenAI accepts a single stop string or a list of them
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum StopSequences {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Response to `POST /v1/completions`
+#[derive(Debug, Serialize)]
+pub struct CompletionResponse {
+    pub id: String,
+    pub object: &'static str,
+    pub created: i64,
+    pub model: String,
+    pub choices: Vec<CompletionChoice>,
+    pub usage: Usage,
+}
+
+/// One server-sent event of a streamed completion
+#[derive(Debug, Serialize)]
+pub struct CompletionChunk<'a> {
+    pub id: &'a str,
+    pub object: &'static str,
+    pub created: i64,
+    pub model: &'a str,
+    pub choices: [CompletionChoice; 1],
+}
+
+#[derive(Debug, Serialize)]
+pub struct CompletionChoice {
+    /// The whole completion, or in a streamed chunk the text generated since the previous one
+    pub text: String,
+    pub index: usize,
+    pub logprobs: Option<Value>,
+    /// `"length"` if generation hit `max_tokens`, else `"stop"`; null until a stream's last chunk
+    pub finish_reason: Option<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Usage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub total_tokens: usize,
+}
+
+impl CompletionRequest {
+    /// The sampling parameters for this request: the server defaults with this request's overrides
+    pub fn sampling_params(&self, defaults: &SamplingParams) -> SamplingParams {
+        let mut params = defaults.clone();
+        if let Some(max_tokens) = self.max_tokens {
+            params.max_tokens = max_tokens;
+        }
+        if let Some(temperature) = self.temperature {
+            params.temperature = temperature;
+        }
+        if let Some(top_p) = self.top_p {
+            params.top_p = top_p;
+        }
+        match &self.stop {
+            Some(StopSequences::One(stop)) => params.stop = vec![stop.clone()],
+            Some(StopSequences::Many(stops)) => params.stop = stops.clone(),
+            None => {}
+        }
+        if self.seed.is_some() {
+            params.seed = self.seed;
+        }
+        params
+    }
+}
+
+/// The loaded model and what every request shares
+struct ServerState {
+    engine: Mutex<InferenceEngine>,
+    /// Name reported in responses and by `/v1/models`
+    model_name: String,
+    defaults: SamplingParams,
+    verbose: bool,
+}
+
+/// How a generation ended
+struct Finished {
+    usage: Usage,
+    finish_reason: &'static str,
+}
+
+impl ServerState {
+    /// Generate a completion, calling `on_start` once the prompt is accepted and handing each
+    /// piece to `on_piece`, stopping early if it returns false. Waits for any request already
+    /// being answered.
+    fn generate(
+        &self,
+        prompt: &str,
+        params: &SamplingParams,
+        on_start: impl FnOnce(),
+        mut on_piece: impl FnMut(String) -> bool,
+    ) -> Result<Finished> {
+        let mut engine = self.engine.lock().unwrap_or_else(|e| e.into_inner());
+        let mut stream = engine.generate_stream(prompt, params)?;
+        on_start();
+        for piece in stream.by_ref() {
+            if !on_piece(piece?) {
+                break;
+            }
+        }
+
+        Ok(Finished {
+            usage: Usage {
+                prompt_tokens: stream.prompt_tokens(),
+                completion_tokens: stream.tokens_generated(),
+                total_tokens: stream.prompt_tokens() + stream.tokens_generated(),
+            },
+            finish_reason: if stream.reached_max_tokens() { "length" } else { "stop" },
+        })
+    }
+
+    /// Generate a whole completion
+    fn complete(&self, prompt: &str, params: &SamplingParams) -> Result<CompletionResponse> {
+        let mut text = String::new();
+        let finished = self.generate(prompt, params, || {}, |piece| {
+            text.push_str(&piece);
+            true
+        })?;
+
+        Ok(CompletionResponse {
+            id: completion_id(),
+            object: "text_completion",
+            created: chrono::Utc::now().timestamp(),
+            model: self.model_name.clone(),
+            choices: vec![CompletionChoice {
+                text,
+                index: 0,
+                logprobs: None,
+                finish_reason: Some(finished.finish_reason),
+            }],
+            usage: finished.usage,
+        })
+    }
+
+    /// Generate a completion as server-sent events, sent to `events` until the client goes away.
+    /// `started` gets the error if the prompt is rejected before anything is generated.
+    fn stream(
+        &self,
+        prompt: &str,
+        params: &SamplingParams,
+        started: tokio::sync::oneshot::Sender<Result<(), String>>,
+        events: &tokio::sync::mpsc::Sender<Event>,
+    ) {
+        let id = completion_id();
+        let created = chrono::Utc::now().timestamp();
+        let chunk = |text: String, finish_reason: Option<&'static str>| {
+            let chunk = CompletionChunk {
+                id: &id,
+                object: "text_completion",
+                created,
+                model: &self.model_name,
+                choices: [CompletionChoice { text, index: 0, logprobs: None, finish_reason }],
+            };
+            Event::default().data(serde_json::to_string(&chunk).unwrap_or_default())
+        };
+
+        // A failed send means the client disconnected and the response was dropped
+        let mut started = Some(started);
+        let result = self.generate(
+            prompt,
+            params,
+            || {
+                if let Some(started) = started.take() {
+                    let _ = started.send(Ok(()));
+                }
+            },
+            |piece| events.blocking_send(chunk(piece, None)).is_ok(),
+        );
+        if let (Some(started), Err(e)) = (started, &result) {
+            let _ = started.send(Err(e.to_string()));
+            return;
+        }
+        let last = match result {
+            Ok(finished) => chunk(String::new(), Some(finished.finish_reason)),
+            Err(e) => Event::default().data(error_body(&e.to_string(), "invalid_request_error").to_string()),
+        };
+        if events.blocking_send(last).is_ok() {
+            let _ = events.blocking_send(Event::default().data("[DONE]"));
+        }
+    }
+}
+
+fn completion_id() -> String {
+    format!("cmpl-{:016x}", rand::random::<u64>())
+}
+
+/// Serve the API on `addr` until the process is stopped
+pub async fn serve(
+    engine: InferenceEngine,
+    model_name: String,
+    defaults: SamplingParams,
+    addr: SocketAddr,
+    verbose: bool,
+) -> Result<()> {
+    let state = Arc::new(ServerState {
+        engine: Mutex::new(engine),
+        model_name,
+        defaults,
+        verbose,
+    });
+    let app = Router::new()
+        .route("/v1/completions", post(completions))
+        .route("/v1/models", get(models))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .map_err(|e| anyhow!("Failed to listen on {}: {}", addr, e))?;
+    eprintln!("{} Listening on http://{}/v1", "Info:".blue().bold(), addr);
+
+    axum::serve(listener, app).await.map_err(|e| anyhow!("Server error: {}", e))
+}
+
+async fn completions(State(state): State<Arc<ServerState>>, Json(request): Json<CompletionRequest>) -> Response {
+    let params = request.sampling_params(&state.defaults);
+    if state.verbose {
+        eprintln!(
+            "{} Completion: {} prompt bytes, max_tokens {}{}",
+            "Info:".blue().bold(),
+            request.prompt.len(),
+            params.max_tokens,
+            if request.stream { ", streamed" } else { "" }
+        );
+    }
+
+    // Generation blocks, so it runs off the async workers
+    let worker = Arc::clone(&state);
+    if request.stream {
+        let (events, receiver) = tokio::sync::mpsc::channel(16);
+        let (started, accepted) = tokio::sync::oneshot::channel();
+        tokio::task::spawn_blocking(move || worker.stream(&request.prompt, &params, started, &events));
+        // Rejected prompts get a plain error response rather than an event stream
+        match accepted.await {
+            Ok(Ok(())) => {}
+            Ok(Err(message)) => return error_response(StatusCode::BAD_REQUEST, &message),
+            Err(_) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Generation failed"),
+        }
+        let stream = futures_util::stream::unfold(receiver, |mut receiver| async move {
+            let event = receiver.recv().await?;
+            Some((Ok::<_, Infallible>(event), receiver))
+        });
+        return Sse::new(stream).into_response();
+    }
+
+    match tokio::task::spawn_blocking(move || worker.complete(&request.prompt, &params)).await {
+        Ok(Ok(response)) => Json(response).into_response(),
+        Ok(Err(e)) => error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &format!("Generation failed: {}", e)),
+    }
+}
+
+async fn models(State(state): State<Arc<ServerState>>) -> Json<Value> {
+    Json(json!({
+        "object": "list",
+        "data": [{"id": state.model_name, "object": "model", "owned_by": "rustlama"}],
+    }))
+}
+
+/// An error in OpenAI's shape
+fn error_response(status: StatusCode, message: &str) -> Response {
+    let kind = if status.is_client_error() { "invalid_request_error" } else { "server_error" };
+    (status, Json(error_body(message, kind))).into_response()
+}
+
+fn error_body(message: &str, kind: &str) -> Value {
+    json!({"error": {"message": message, "type": kind}})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completion_request_overrides_defaults() {
+        let defaults = SamplingParams::default();
+
+        let request: CompletionRequest = serde_json::from_str(
+            r#"{"model": "ignored", "prompt": "Hi", "max_tokens": 8, "stop": "\n", "n": 1}"#,
+        )
+        .unwrap();
+        let params = request.sampling_params(&defaults);
+        assert_eq!(params.max_tokens, 8);
+        assert_eq!(params.stop, vec!["\n".to_string()]);
+        assert_eq!(params.temperature, defaults.temperature);
+        assert!(!request.stream);
+
+        let chunk = CompletionChunk {
+            id: "cmpl-1",
+            object: "text_completion",
+            created: 0,
+            model: "m",
+            choices: [CompletionChoice { text: " Paris".to_string(), index: 0, logprobs: None, finish_reason: None }],
+        };
+        let chunk: Value = serde_json::to_value(&chunk).unwrap();
+        assert_eq!(chunk["choices"][0]["text"], " Paris");
+        assert!(chunk["choices"][0]["finish_reason"].is_null());
+
+        let request: CompletionRequest =
+            serde_json::from_str(r#"{"prompt": "Hi", "stop": ["a", "b"], "top_p": 0.5}"#).unwrap();
+        let params = request.sampling_params(&defaults);
+        assert_eq!(params.stop, vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(params.top_p, 0.5);
+
+        assert!(serde_json::from_str::<CompletionRequest>(r#"{"max_tokens": 8}"#).is_err());
+    }
+}
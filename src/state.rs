@@ -0,0 +1,142 @@
+/*!
+# Context State Files
+
+`--dump-state` saves the KV cache and the token history after a run, and
+`--load-state` restores them in place of prompt processing, so a long prompt
+only has to be evaluated once.
+
+The llama.cpp session file is only valid for the exact model it was written
+with, so a small `<path>.json` sidecar records a fingerprint of the model and
+is checked before the session is loaded.
+*/
+
+use crate::gguf::ModelSummary;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use llama_cpp_2::context::LlamaContext;
+use llama_cpp_2::token::LlamaToken;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Sidecar describing a saved context state
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateInfo {
+    pub model_fingerprint: String,
+    pub n_tokens: usize,
+    pub saved_at: DateTime<Utc>,
+}
+
+/// Fingerprint of a loaded model's GGUF metadata and tensor layout
+pub fn model_fingerprint(summary: &ModelSummary) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(summary.architecture.as_bytes());
+    hasher.update(summary.quantization.as_bytes());
+    hasher.update(summary.parameters.to_le_bytes());
+    hasher.update(summary.vocab_size.to_le_bytes());
+    hasher.update(summary.context_length.to_le_bytes());
+    hasher.update(summary.size_bytes.to_le_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// Path of the sidecar written next to a state file
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".json");
+    PathBuf::from(sidecar)
+}
+
+/// Check that a state saved with `info` can be loaded into the model fingerprinted as `fingerprint`
+pub fn check_compatible(info: &StateInfo, fingerprint: &str, n_ctx: usize) -> Result<()> {
+    if info.model_fingerprint != fingerprint {
+        return Err(anyhow!(
+            "State was saved with a different model (fingerprint {} != {})",
+            short(&info.model_fingerprint),
+            short(fingerprint)
+        ));
+    }
+
+    if info.n_tokens > n_ctx {
+        return Err(anyhow!(
+            "State holds {} tokens but the context only has room for {}; use a larger --ctx-size",
+            info.n_tokens,
+            n_ctx
+        ));
+    }
+
+    Ok(())
+}
+
+/// Save the context's KV cache and `tokens` (everything it holds) to `path`
+pub fn save(ctx: &LlamaContext<'_>, path: &Path, tokens: &[LlamaToken], fingerprint: &str) -> Result<()> {
+    ctx.save_session_file(path, tokens)
+        .map_err(|e| anyhow!("Failed to save state to '{}': {}", path.display(), e))?;
+
+    let info = StateInfo {
+        model_fingerprint: fingerprint.to_string(),
+        n_tokens: tokens.len(),
+        saved_at: Utc::now(),
+    };
+    fs::write(sidecar_path(path), serde_json::to_string_pretty(&info)?)?;
+    Ok(())
+}
+
+/// Restore a state saved with [`save`], returning the tokens it holds
+pub fn load(ctx: &mut LlamaContext<'_>, path: &Path, fingerprint: &str) -> Result<Vec<LlamaToken>> {
+    let sidecar = sidecar_path(path);
+    let info: StateInfo = serde_json::from_str(
+        &fs::read_to_string(&sidecar)
+            .map_err(|e| anyhow!("Failed to read state info '{}': {}", sidecar.display(), e))?,
+    )
+    .map_err(|e| anyhow!("Invalid state info '{}': {}", sidecar.display(), e))?;
+
+    let n_ctx = ctx.n_ctx() as usize;
+    check_compatible(&info, fingerprint, n_ctx)?;
+
+    ctx.load_session_file(path, n_ctx)
+        .map_err(|e| anyhow!("Failed to load state from '{}': {}", path.display(), e))
+}
+
+fn short(fingerprint: &str) -> &str {
+    &fingerprint[..fingerprint.len().min(12)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary() -> ModelSummary {
+        ModelSummary {
+            name: Some("Tiny".to_string()),
+            architecture: "llama".to_string(),
+            parameters: 1_100_000_000,
+            quantization: "Q4_K_M".to_string(),
+            vocab_size: 32000,
+            context_length: 2048,
+            size_bytes: 668_788_096,
+        }
+    }
+
+    #[test]
+    fn test_state_compatibility() {
+        let fingerprint = model_fingerprint(&summary());
+        let info = StateInfo {
+            model_fingerprint: fingerprint.clone(),
+            n_tokens: 100,
+            saved_at: Utc::now(),
+        };
+
+        assert!(check_compatible(&info, &fingerprint, 2048).is_ok());
+        assert!(check_compatible(&info, &fingerprint, 64).is_err());
+
+        let other = model_fingerprint(&ModelSummary {
+            quantization: "Q8_0".to_string(),
+            ..summary()
+        });
+        assert_ne!(other, fingerprint);
+        assert!(check_compatible(&info, &other, 2048).is_err());
+
+        assert_eq!(sidecar_path(Path::new("/tmp/ctx.bin")), PathBuf::from("/tmp/ctx.bin.json"));
+    }
+}
@@ -0,0 +1,61 @@
+/*!
+# Stop Strings
+
+`--stop TEXT` ends generation as soon as the generated text contains TEXT,
+which may span several tokens. The stop string and anything after it are left
+out of the output.
+
+While streaming, text that could be the start of a stop string is held back
+until the next tokens show whether it completes one, so a stop string is never
+printed.
+*/
+
+/// Byte offset of the earliest stop string in `text`
+pub fn find_stop(text: &str, stops: &[String]) -> Option<usize> {
+    stops
+        .iter()
+        .filter(|stop| !stop.is_empty())
+        .filter_map(|stop| text.find(stop.as_str()))
+        .min()
+}
+
+/// Length of the longest suffix of `text` that is the start of a stop string,
+/// i.e. how much of it can't be emitted yet
+pub fn partial_stop_len(text: &str, stops: &[String]) -> usize {
+    text.char_indices()
+        .map(|(i, _)| &text[i..])
+        .find(|suffix| stops.iter().any(|stop| stop.starts_with(suffix)))
+        .map_or(0, str::len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stop_spanning_tokens() {
+        let stops = vec!["\n\n".to_string(), "</s>".to_string()];
+        let pieces = ["Hello", " world", "\n", "\nNext", " line"];
+
+        // Feed the pieces one at a time, emitting what can't be part of a stop string
+        let mut text = String::new();
+        let mut emitted = 0;
+        let mut output = String::new();
+        for piece in pieces {
+            text.push_str(piece);
+            if let Some(stop) = find_stop(&text[emitted..], &stops) {
+                output.push_str(&text[emitted..emitted + stop]);
+                break;
+            }
+            let safe = text.len() - partial_stop_len(&text[emitted..], &stops);
+            output.push_str(&text[emitted..safe]);
+            emitted = safe;
+        }
+
+        assert_eq!(output, "Hello world");
+        assert_eq!(partial_stop_len("done </", &stops), 2);
+        assert_eq!(partial_stop_len("dóne", &stops), 0);
+        assert_eq!(find_stop("a</s>b\n\nc", &stops), Some(1));
+        assert_eq!(find_stop("anything", &[String::new()]), None);
+    }
+}
@@ -0,0 +1,178 @@
+/*!
+# Stop Tokens
+
+Chat and instruct models often end a turn with a dedicated special token
+(`<|eot_id|>`, `<|im_end|>`, `<end_of_turn>`, ...) rather than the model's
+single end-of-sequence token. Generation stops on any token in the set built
+here: the model's EOS, every token llama.cpp flags as end-of-generation (EOG),
+end-of-turn tokens found in its metadata or vocabulary, and any IDs passed
+with `--stop-token`.
+*/
+
+use anyhow::{anyhow, Result};
+use llama_cpp_2::model::{AddBos, LlamaModel, Special};
+use llama_cpp_2::token::data::LlamaTokenData;
+use llama_cpp_2::token::LlamaToken;
+use std::collections::HashSet;
+
+/// GGUF metadata keys that hold end-of-turn token IDs
+const END_OF_TURN_METADATA_KEYS: &[&str] = &[
+    "tokenizer.ggml.eot_token_id",
+    "tokenizer.ggml.eom_token_id",
+];
+
+/// Common end-of-turn special tokens across chat template families
+pub const END_OF_TURN_TOKENS: &[&str] = &[
+    "<|eot_id|>",
+    "<|eom_id|>",
+    "<|end_of_text|>",
+    "<|im_end|>",
+    "<|endoftext|>",
+    "<|end|>",
+    "<end_of_turn>",
+];
+
+/// Set of tokens that end generation
+#[derive(Debug, Clone)]
+pub struct StopTokens {
+    tokens: HashSet<LlamaToken>,
+}
+
+impl StopTokens {
+    /// Stop on the model's EOS token plus the given token IDs
+    pub fn new(eos: LlamaToken, extra: &[i32]) -> Self {
+        let mut tokens = HashSet::new();
+        tokens.insert(eos);
+        tokens.extend(extra.iter().map(|&id| LlamaToken::new(id)));
+        Self { tokens }
+    }
+
+    /// Build the stop set for a loaded model, auto-detecting its end-of-turn tokens
+    pub fn for_model(model: &LlamaModel, extra: &[i32]) -> Result<Self> {
+        let n_vocab = model.n_vocab();
+        if let Some(&id) = extra.iter().find(|&&id| id >= n_vocab) {
+            return Err(anyhow!(
+                "Stop token {} is out of range for a vocabulary of {} tokens",
+                id,
+                n_vocab
+            ));
+        }
+
+        let mut stop_tokens = Self::new(model.token_eos(), extra);
+        stop_tokens.tokens.extend(detect_end_of_turn_tokens(model));
+        Ok(stop_tokens)
+    }
+
+    /// Whether the token ends generation
+    pub fn contains(&self, token: LlamaToken) -> bool {
+        self.tokens.contains(&token)
+    }
+
+    /// Mask every stop token while fewer than `min_tokens` tokens have been generated (--min-tokens)
+    pub fn suppress_below_floor(
+        &self,
+        tokens_generated: usize,
+        min_tokens: usize,
+        candidates: &mut [LlamaTokenData],
+    ) {
+        if tokens_generated >= min_tokens {
+            return;
+        }
+
+        for candidate in candidates.iter_mut() {
+            if self.contains(candidate.id()) {
+                candidate.set_logit(f32::NEG_INFINITY);
+            }
+        }
+    }
+
+    /// Token IDs in the set, sorted
+    pub fn ids(&self) -> Vec<i32> {
+        let mut ids: Vec<i32> = self.tokens.iter().map(|t| t.0).collect();
+        ids.sort_unstable();
+        ids
+    }
+}
+
+/// Find end-of-turn tokens: those llama.cpp flags as end-of-generation, plus any from GGUF
+/// metadata or well-known special token strings that it doesn't
+pub fn detect_end_of_turn_tokens(model: &LlamaModel) -> Vec<LlamaToken> {
+    let n_vocab = model.n_vocab();
+    let mut found: Vec<LlamaToken> = (0..n_vocab)
+        .map(LlamaToken::new)
+        .filter(|&token| model.is_eog_token(token))
+        .collect();
+
+    for key in END_OF_TURN_METADATA_KEYS {
+        if let Some(id) = model.meta_val_str(key).ok().as_deref().and_then(parse_token_id) {
+            if id < n_vocab {
+                found.push(LlamaToken::new(id));
+            }
+        }
+    }
+
+    // A special token string only counts if the vocabulary has it as a single token
+    for text in END_OF_TURN_TOKENS {
+        if let Ok(tokens) = model.str_to_token(text, AddBos::Never) {
+            if let [token] = tokens.as_slice() {
+                if model.token_to_str(*token, Special::Tokenize).ok().as_deref() == Some(*text) {
+                    found.push(*token);
+                }
+            }
+        }
+    }
+
+    found
+}
+
+/// Parse a token ID stored as a GGUF metadata string
+pub fn parse_token_id(value: &str) -> Option<i32> {
+    value.trim().parse::<i32>().ok().filter(|&id| id >= 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stop_tokens_include_eos_and_extra() {
+        let stop_tokens = StopTokens::new(LlamaToken::new(2), &[128009, 2]);
+        assert!(stop_tokens.contains(LlamaToken::new(2)));
+        assert!(stop_tokens.contains(LlamaToken::new(128009)));
+        assert!(!stop_tokens.contains(LlamaToken::new(3)));
+        assert_eq!(stop_tokens.ids(), vec![2, 128009]);
+    }
+
+    #[test]
+    fn test_stop_tokens_suppressed_below_floor() {
+        let stop_tokens = StopTokens::new(LlamaToken::new(0), &[]);
+        let candidates = || -> Vec<LlamaTokenData> {
+            vec![
+                LlamaTokenData::new(LlamaToken::new(0), 5.0, 0.0),
+                LlamaTokenData::new(LlamaToken::new(1), 1.0, 0.0),
+            ]
+        };
+
+        // Below the floor EOS can't win even with the highest logit
+        let mut below = candidates();
+        stop_tokens.suppress_below_floor(2, 3, &mut below);
+        assert_eq!(below[0].logit(), f32::NEG_INFINITY);
+        assert_eq!(
+            crate::sampling::sample_greedy(&below).unwrap().token,
+            LlamaToken::new(1)
+        );
+
+        // At the floor it is left alone
+        let mut at_floor = candidates();
+        stop_tokens.suppress_below_floor(3, 3, &mut at_floor);
+        assert_eq!(at_floor[0].logit(), 5.0);
+    }
+
+    #[test]
+    fn test_parse_token_id() {
+        assert_eq!(parse_token_id("128009"), Some(128009));
+        assert_eq!(parse_token_id(" 7 "), Some(7));
+        assert_eq!(parse_token_id("-1"), None);
+        assert_eq!(parse_token_id("eot"), None);
+    }
+}
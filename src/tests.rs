@@ -1,7 +1,13 @@
 #[cfg(test)]
 mod tests {
-    use crate::{RunConfig, validate_args};
-    use crate::downloader::is_hf_model_id;
+    use crate::{auto_ctx_size, default_to_run, group_by_model, numbered_output_path, Cli, Commands, best_generation_index, derive_prompt_seed, parse_selection, retry_delay, sampling_seed, selects_task, text_preview, with_context, Generation, RunConfig, validate_args};
+    use crate::downloader::{denormalize_model_id, is_hf_model_id, normalize_model_id, read_token_file, HfFile, HfLfsInfo, HfModelInfo, ModelDownloader, ModelInfoCache};
+    use crate::config::{InferenceTask, YamlConfig};
+    use crate::json_output::{RunOutput, SweepOutput};
+    use clap::Parser;
+    use llama_cpp_2::token::LlamaToken;
+    use std::ffi::OsString;
+    use std::time::Duration;
 
     fn create_test_run_config() -> RunConfig {
         RunConfig {
@@ -14,10 +20,71 @@ mod tests {
             temperature: 0.8,
             top_k: 40,
             top_p: 0.95,
+            best_of: 1,
             ctx_size: None,
             threads: None,
             no_color: false,
             stats: false,
+            no_perf: false,
+            image: None,
+            mmproj: None,
+            kv_cache_type_k: None,
+            kv_cache_type_v: None,
+            flash_attn: false,
+            show_probs: false,
+            seed_from_prompt: false,
+            show_model_info: false,
+            hf_endpoints: Vec::new(),
+            dump_tokens_file: None,
+            stop_tokens: Vec::new(),
+            ban_words: Vec::new(),
+            auto_quant: false,
+            auto_ctx: false,
+            deadline: None,
+            min_tokens: 0,
+            system: None,
+            chat: false,
+            chat_template: None,
+            output_file: None,
+            save_every: None,
+            prompt_affinity: 0.0,
+            interactive_first: false,
+            revision: None,
+            pin: false,
+            explain: None,
+            beams: 1,
+            temp_range: None,
+            single_line: false,
+            truncate: None,
+            dump_state: None,
+            load_state: None,
+            stop_on_repeat: None,
+            dynatemp_range: 0.0,
+            dynatemp_exponent: 1.0,
+            messages_file: None,
+            seed: None,
+            repeat_penalty: 1.1,
+            repeat_last_n: 64,
+            stop: Vec::new(),
+            batch_size: 512,
+            no_verify: false,
+            hf_token: None,
+            append: false,
+            output_lock_wait: false,
+            stream_output_file: false,
+            format: OutputFormat::Text,
+            quiet: false,
+            n_keep: None,
+            mirostat: 0,
+            mirostat_tau: 5.0,
+            mirostat_eta: 0.1,
+            grammar_file: None,
+            json_schema: None,
+            ignore_eos: false,
+            logit_bias: Vec::new(),
+            no_mmap: false,
+            mlock: false,
+            context: None,
             verbose: false,
         }
     }
@@ -106,6 +173,20 @@ mod tests {
         assert!(result.unwrap_err().to_string().contains("Max tokens"));
     }
 
+    #[test]
+    fn test_validate_args_mirostat() {
+        let mut config = create_test_run_config();
+        config.mirostat = 2;
+        assert!(validate_args(&config).is_ok());
+
+        config.mirostat_tau = 0.0;
+        assert!(validate_args(&config).unwrap_err().to_string().contains("tau"));
+
+        config.mirostat_tau = 5.0;
+        config.beams = 4;
+        assert!(validate_args(&config).unwrap_err().to_string().contains("--beams"));
+    }
+
     #[test]
     fn test_validate_args_valid_max_tokens() {
         let mut config = create_test_run_config();
@@ -119,6 +200,201 @@ mod tests {
         assert!(validate_args(&config).is_ok());
     }
 
+    #[test]
+    fn test_validate_args_zero_best_of() {
+        let mut config = create_test_run_config();
+        config.best_of = 0;
+
+        let result = validate_args(&config);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("Best-of"));
+    }
+
+    #[test]
+    fn test_validate_args_image_requires_mmproj() {
+        let mut config = create_test_run_config();
+        config.image = Some("cat.png".to_string());
+
+        let result = validate_args(&config);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("--mmproj"));
+
+        config.mmproj = Some("mmproj.gguf".to_string());
+        assert!(validate_args(&config).is_ok());
+    }
+
+    #[test]
+    fn test_validate_args_beams() {
+        let mut config = create_test_run_config();
+        config.beams = 4;
+        assert!(validate_args(&config).is_ok());
+
+        config.best_of = 2;
+        assert!(validate_args(&config).unwrap_err().to_string().contains("--beams"));
+
+        config.best_of = 1;
+        config.beams = 0;
+        assert!(validate_args(&config).unwrap_err().to_string().contains("Beams"));
+    }
+
+    #[test]
+    fn test_best_generation_index_uses_average_log_prob() {
+        let generation = |log_prob_sum: f64, scored_tokens: usize| Generation {
+            text: String::new(),
+            tokens_generated: scored_tokens,
+            log_prob_sum,
+            scored_tokens,
+            token_log_probs: Vec::new(),
+            tokens: Vec::new(),
+        };
+
+        // The longer candidate has a lower total but a better per-token average
+        let generations = vec![
+            generation(-2.0, 2),   // avg -1.0
+            generation(-3.0, 10),  // avg -0.3
+            generation(0.0, 0),    // nothing scored
+        ];
+        assert_eq!(best_generation_index(&generations), Some(1));
+        assert_eq!(best_generation_index(&[]), None);
+    }
+
+    #[test]
+    fn test_derive_prompt_seed_is_stable_per_prompt() {
+        let config = create_test_run_config();
+        let same = create_test_run_config();
+        assert_eq!(derive_prompt_seed(&config), derive_prompt_seed(&same));
+
+        let mut other_prompt = create_test_run_config();
+        other_prompt.prompt = "another prompt".to_string();
+        assert_ne!(derive_prompt_seed(&config), derive_prompt_seed(&other_prompt));
+
+        let mut other_temperature = create_test_run_config();
+        other_temperature.temperature = 1.2;
+        assert_ne!(derive_prompt_seed(&config), derive_prompt_seed(&other_temperature));
+    }
+
+    #[test]
+    fn test_check_download() {
+        let sha256 = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+        let file = HfFile {
+            rfilename: "model.Q4_K_M.gguf".to_string(),
+            size: Some(4),
+            lfs: Some(HfLfsInfo { sha256: sha256.to_string(), size: 4 }),
+        };
+        assert!(file.check_download(4, sha256).is_ok());
+        assert!(file.check_download(3, sha256).unwrap_err().to_string().contains("expected 4 bytes"));
+        assert!(file.check_download(4, &"0".repeat(64)).unwrap_err().to_string().contains("SHA256"));
+
+        // Without LFS details only the size can be checked
+        let plain = HfFile { lfs: None, ..file };
+        assert!(plain.check_download(4, &"0".repeat(64)).is_ok());
+        assert!(plain.check_download(5, sha256).is_err());
+    }
+
+    #[test]
+    fn test_explicit_seed_wins() {
+        let mut config = create_test_run_config();
+        config.seed = Some(42);
+        assert_eq!(sampling_seed(&config), 42);
+
+        config.seed = None;
+        config.seed_from_prompt = true;
+        assert_eq!(sampling_seed(&config), derive_prompt_seed(&config));
+    }
+
+    #[test]
+    fn test_default_to_run() {
+        let args = |list: &[&str]| list.iter().map(OsString::from).collect::<Vec<_>>();
+
+        assert_eq!(
+            default_to_run(args(&["rustlama", "--model", "m.gguf", "--prompt", "Hi"])),
+            args(&["rustlama", "run", "--model", "m.gguf", "--prompt", "Hi"])
+        );
+        assert_eq!(default_to_run(args(&["rustlama", "-y", "-m", "m.gguf"])), args(&["rustlama", "-y", "run", "-m", "m.gguf"]));
+
+        // Explicit subcommands, help and version are left alone
+        for unchanged in [&["rustlama", "run", "-m", "m.gguf"][..], &["rustlama", "models", "ls"], &["rustlama", "--help"], &["rustlama"]] {
+            assert_eq!(default_to_run(args(unchanged)), args(unchanged));
+        }
+
+        let cli = Cli::try_parse_from(default_to_run(args(&["rustlama", "--model", "m.gguf", "--prompt", "Hi"]))).unwrap();
+        assert!(matches!(cli.command, Commands::Run { .. }));
+    }
+
+    #[test]
+    fn test_json_output() {
+        let mut config = create_test_run_config();
+        config.seed = Some(7);
+        config.show_probs = true;
+        let generation = Generation {
+            text: " Paris.".to_string(),
+            tokens_generated: 2,
+            log_prob_sum: -0.5,
+            scored_tokens: 2,
+            token_log_probs: vec![(" Paris".to_string(), -0.25), (".".to_string(), -0.25)],
+            tokens: Vec::new(),
+        };
+
+        let output = RunOutput::new(&generation, 2, Duration::from_millis(500), None, &config);
+        let value: serde_json::Value = serde_json::from_str(&output.to_json().unwrap()).unwrap();
+        assert_eq!(value["prompt"], config.prompt.as_str());
+        assert_eq!(value["text"], " Paris.");
+        assert_eq!(value["tokens_per_sec"], 4.0);
+        assert_eq!(value["sampling"]["seed"], 7);
+        assert_eq!(value["token_log_probs"][0]["text"], " Paris");
+        assert!(value.get("beam_score").is_none());
+
+        // A --temp-range sweep keys each object by its temperature
+        let sweep = SweepOutput { temperature: 0.4, output };
+        let value = serde_json::to_value(&sweep).unwrap();
+        assert_eq!(value["temperature"].as_f64().unwrap() as f32, 0.4);
+        assert_eq!(value["text"], " Paris.");
+
+        // JSON on stdout can't be mixed with verbose or interactive output
+        let args = ["rustlama", "run", "-m", "m.gguf", "--prompt", "Hi", "--format", "json"];
+        assert!(Cli::try_parse_from(args).is_ok());
+        assert!(Cli::try_parse_from(args.iter().chain(&["--verbose"])).is_err());
+        assert!(Cli::try_parse_from(args.iter().chain(&["--temp-range", "0.2:1.0:0.4"])).is_ok());
+    }
+
+    #[test]
+    fn test_tokenize_add_bos_flag() {
+        let add_bos = |extra: &[&str]| {
+            let args = ["rustlama", "tokenize", "-m", "m.gguf", "-p", "Hi"].iter().chain(extra);
+            match Cli::try_parse_from(args).map(|cli| cli.command) {
+                Ok(Commands::Tokenize { add_bos, .. }) => Some(add_bos),
+                _ => None,
+            }
+        };
+
+        assert_eq!(add_bos(&[]), Some(true));
+        assert_eq!(add_bos(&["--add-bos", "false"]), Some(false));
+        assert_eq!(add_bos(&["--add-bos", "maybe"]), None);
+    }
+
+    #[test]
+    fn test_group_by_model_keeps_config_order() {
+        let template = YamlConfig::generate_sample().tasks.remove(0);
+        let task = |name: &str, model: &str| InferenceTask {
+            name: name.to_string(),
+            model: Some(model.to_string()),
+            ..template.clone()
+        };
+
+        let groups = group_by_model(vec![task("a", "m1.gguf"), task("b", "m2.gguf"), task("c", "m1.gguf")]);
+        let names: Vec<Vec<&str>> = groups.iter().map(|group| group.iter().map(|task| task.name.as_str()).collect()).collect();
+        assert_eq!(names, vec![vec!["a", "c"], vec!["b"]]);
+    }
+
+    #[test]
+    fn test_parse_selection() {
+        assert_eq!(parse_selection("1\n", 3), Some(0));
+        assert_eq!(parse_selection(" 3 ", 3), Some(2));
+        assert_eq!(parse_selection("0", 3), None);
+        assert_eq!(parse_selection("4", 3), None);
+        assert_eq!(parse_selection("q4_k_m", 3), None);
+    }
+
     #[test]
     fn test_is_hf_model_id_valid() {
         // Test valid Hugging Face model IDs
@@ -205,4 +481,110 @@ mod tests {
         assert!(is_hf_model_id("user/")); // Function currently accepts this
         assert!(!is_hf_model_id("/repo")); // Function rejects this (starts with /)
     }
+
+    #[test]
+    fn test_auto_ctx_size() {
+        assert_eq!(auto_ctx_size(1000, 4096), 1024);
+        assert_eq!(auto_ctx_size(1024, 4096), 1024);
+        assert_eq!(auto_ctx_size(5000, 4096), 4096);
+        assert_eq!(auto_ctx_size(5000, 0), 5120);
+    }
+
+    #[test]
+    fn test_numbered_output_path() {
+        assert_eq!(numbered_output_path("out.txt", 2), "out.2.txt");
+        assert_eq!(numbered_output_path("results/answer.md", 10), "results/answer.10.md");
+        assert_eq!(numbered_output_path("out", 1), "out.1.txt");
+    }
+
+    #[test]
+    fn test_selects_task() {
+        assert!(selects_task("story", "story"));
+        assert!(selects_task("story", "story[space,formal]"));
+        assert!(!selects_task("story", "story-2"));
+        assert!(!selects_task("story[space,formal]", "story"));
+    }
+
+    #[test]
+    fn test_text_preview() {
+        assert_eq!(text_preview("Short prompt"), "\"Short prompt\"");
+        assert_eq!(text_preview("First line\nsecond"), "\"First line\"... (17 chars)");
+        assert_eq!(text_preview(&"a".repeat(70)), format!("{:?}... (70 chars)", "a".repeat(60)));
+    }
+
+    #[test]
+    fn test_with_context() {
+        assert_eq!(with_context("Report text.\n\n", "Summarize it."), "Report text.\n\nSummarize it.");
+        assert_eq!(with_context("Report text.\n", ""), "Report text.");
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(None, 1), Duration::from_secs(5));
+        assert_eq!(retry_delay(Some(2), 1), Duration::from_secs(2));
+        assert_eq!(retry_delay(Some(2), 3), Duration::from_secs(8));
+        assert_eq!(retry_delay(Some(0), 4), Duration::ZERO);
+        assert_eq!(retry_delay(Some(u64::MAX), 2), Duration::from_secs(u64::MAX));
+    }
+
+    #[test]
+    fn test_model_id_normalization_round_trip() {
+        for model_id in ["TheBloke/Llama-2-7B-Chat-GGUF", "user/model--v2", "org/a--b--c"] {
+            let dir_name = normalize_model_id(model_id);
+            assert!(!dir_name.contains('/'));
+            assert_eq!(denormalize_model_id(&dir_name), model_id);
+        }
+
+        // An owner containing `--` is ambiguous; its real ID comes from metadata.json
+        assert_eq!(normalize_model_id("my--org/model"), "my--org--model");
+        assert_ne!(denormalize_model_id("my--org--model"), "my--org/model");
+    }
+
+    #[test]
+    fn test_read_token_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token");
+        assert_eq!(read_token_file(&path), None);
+
+        std::fs::write(&path, "hf_abc123\n").unwrap();
+        assert_eq!(read_token_file(&path).as_deref(), Some("hf_abc123"));
+
+        std::fs::write(&path, "  \n").unwrap();
+        assert_eq!(read_token_file(&path), None);
+    }
+
+    #[tokio::test]
+    async fn test_model_info_cache_skips_round_trip() {
+        let cache_dir = tempfile::TempDir::new().unwrap();
+        let info_cache = ModelInfoCache::default();
+        info_cache.lock().unwrap().insert(
+            "user/model-GGUF@".to_string(),
+            HfModelInfo {
+                id: "user/model-GGUF".to_string(),
+                sha: Some("abc123".to_string()),
+                siblings: Vec::new(),
+            },
+        );
+
+        // The endpoint is unreachable, so only a cache hit can succeed
+        let downloader = ModelDownloader::new(Some(cache_dir.path().to_string_lossy().into_owned()))
+            .unwrap()
+            .with_endpoints(vec!["http://127.0.0.1:9".to_string()])
+            .with_info_cache(info_cache);
+        let info = downloader.get_model_info("user/model-GGUF").await.unwrap();
+        assert_eq!(info.sha.as_deref(), Some("abc123"));
+        assert!(downloader.get_model_info("user/other-GGUF").await.is_err());
+    }
+
+    #[test]
+    fn test_cli_definition_and_completions() {
+        use clap::CommandFactory;
+
+        Cli::command().debug_assert();
+
+        let mut script = Vec::new();
+        clap_complete::generate(clap_complete::Shell::Bash, &mut Cli::command(), "rustlama", &mut script);
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.contains("--max-tokens"));
+    }
 }
@@ -0,0 +1,79 @@
+/*!
+# Prompt Truncation
+
+`--truncate` fits an over-long prompt into the context left over after
+reserving room for `--max-tokens`, instead of failing. The strategy decides
+which tokens are dropped:
+
+- `head` drops from the start, keeping the most recent text
+- `tail` drops from the end, keeping the beginning
+- `middle` drops from the middle, keeping both ends
+
+A leading BOS token is always kept in place.
+*/
+
+use serde::{Deserialize, Serialize};
+
+/// Which part of an over-long prompt to drop
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum TruncateStrategy {
+    Head,
+    Tail,
+    Middle,
+}
+
+/// Shorten `tokens` to at most `budget` tokens, never dropping the first `keep_prefix` tokens
+/// (e.g. BOS). Returns the tokens to keep.
+pub fn truncate_tokens<T: Copy>(tokens: &[T], budget: usize, keep_prefix: usize, strategy: TruncateStrategy) -> Vec<T> {
+    if tokens.len() <= budget {
+        return tokens.to_vec();
+    }
+
+    let keep_prefix = keep_prefix.min(budget).min(tokens.len());
+    let (prefix, body) = tokens.split_at(keep_prefix);
+    let body_budget = budget - keep_prefix;
+
+    let mut kept = prefix.to_vec();
+    match strategy {
+        TruncateStrategy::Head => kept.extend_from_slice(&body[body.len() - body_budget..]),
+        TruncateStrategy::Tail => kept.extend_from_slice(&body[..body_budget]),
+        TruncateStrategy::Middle => {
+            let front = body_budget.div_ceil(2);
+            let back = body_budget - front;
+            kept.extend_from_slice(&body[..front]);
+            kept.extend_from_slice(&body[body.len() - back..]);
+        }
+    }
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Token 0 plays the role of BOS
+    const PROMPT: [i32; 10] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+
+    #[test]
+    fn test_truncate_head_keeps_the_end() {
+        assert_eq!(truncate_tokens(&PROMPT, 5, 1, TruncateStrategy::Head), vec![0, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn test_truncate_tail_keeps_the_start() {
+        assert_eq!(truncate_tokens(&PROMPT, 5, 1, TruncateStrategy::Tail), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_truncate_middle_keeps_both_ends() {
+        assert_eq!(truncate_tokens(&PROMPT, 6, 1, TruncateStrategy::Middle), vec![0, 1, 2, 3, 8, 9]);
+        assert_eq!(truncate_tokens(&PROMPT, 5, 0, TruncateStrategy::Middle), vec![0, 1, 2, 8, 9]);
+    }
+
+    #[test]
+    fn test_truncate_within_budget_is_unchanged() {
+        assert_eq!(truncate_tokens(&PROMPT, 10, 1, TruncateStrategy::Head), PROMPT.to_vec());
+        assert_eq!(truncate_tokens(&PROMPT, 1, 1, TruncateStrategy::Middle), vec![0]);
+    }
+}
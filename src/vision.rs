@@ -0,0 +1,84 @@
+/*!
+# Multimodal (Vision) Support
+
+LLaVA-style GGUF models accept images through a separate multimodal projector
+(`mmproj`) file. The projector encodes an image into embeddings that are fed
+into the KV cache alongside ordinary text tokens.
+
+The image is placed where the media marker (`<__media__>`) appears in the
+prompt, or in front of the prompt when no marker is present. Everything up to
+and including the image is evaluated through the projector; the text after it
+is handed back to the normal generation loop so sampling works unchanged.
+*/
+
+use anyhow::{anyhow, Result};
+use llama_cpp_2::context::LlamaContext;
+use llama_cpp_2::model::LlamaModel;
+use llama_cpp_2::mtmd::{mtmd_default_marker, MtmdBitmap, MtmdContext, MtmdContextParams, MtmdInputText};
+use std::ffi::CString;
+
+/// Split a prompt at the first media marker into the text before and after the image
+pub fn split_at_marker(prompt: &str) -> (&str, &str) {
+    let marker = mtmd_default_marker();
+    match prompt.find(marker) {
+        Some(pos) => (&prompt[..pos], &prompt[pos + marker.len()..]),
+        None => ("", prompt),
+    }
+}
+
+/// Evaluate `text_before` followed by the image into sequence 0 of the context.
+///
+/// Returns the number of KV positions consumed, i.e. the position at which the
+/// remaining prompt tokens should start.
+pub fn eval_image_prefix(
+    model: &LlamaModel,
+    ctx: &LlamaContext<'_>,
+    mmproj: &str,
+    image: &str,
+    text_before: &str,
+    n_threads: Option<i32>,
+    n_batch: i32,
+) -> Result<i32> {
+    let params = MtmdContextParams {
+        use_gpu: true,
+        print_timings: false,
+        n_threads: n_threads.unwrap_or(4),
+        media_marker: CString::new(mtmd_default_marker())?,
+    };
+
+    let mtmd_ctx = MtmdContext::init_from_file(mmproj, model, &params)
+        .map_err(|e| anyhow!("Failed to load multimodal projector '{}': {}", mmproj, e))?;
+
+    let bitmap = MtmdBitmap::from_file(&mtmd_ctx, image)
+        .map_err(|e| anyhow!("Failed to load image '{}': {}", image, e))?;
+
+    let text = MtmdInputText {
+        text: format!("{}{}", text_before, mtmd_default_marker()),
+        add_special: true,
+        parse_special: true,
+    };
+
+    let chunks = mtmd_ctx
+        .tokenize(text, &[&bitmap])
+        .map_err(|e| anyhow!("Failed to tokenize image prompt: {}", e))?;
+
+    chunks
+        .eval_chunks(&mtmd_ctx, ctx, 0, 0, n_batch, false)
+        .map_err(|e| anyhow!("Failed to encode image: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_at_marker() {
+        let prompt = format!("USER: {}\nDescribe this image", mtmd_default_marker());
+        assert_eq!(split_at_marker(&prompt), ("USER: ", "\nDescribe this image"));
+    }
+
+    #[test]
+    fn test_split_without_marker_puts_image_first() {
+        assert_eq!(split_at_marker("Describe this image"), ("", "Describe this image"));
+    }
+}